@@ -0,0 +1,37 @@
+#![no_main]
+
+use coding_challenge_redis_adorow::protocol::{RespDecoder, RespObject, RespObjectParseError};
+use libfuzzer_sys::fuzz_target;
+
+fn drain(decoder: &mut RespDecoder, frames: &mut Vec<RespObject>) -> Result<(), RespObjectParseError> {
+    while let Some(frame) = decoder.next_frame()? {
+        frames.push(frame);
+    }
+    Ok(())
+}
+
+// any input must either parse or be reported as an error, never panic
+fuzz_target!(|data: &[u8]| {
+    let _ = RespObject::from_bytes(data);
+
+    let mut whole = RespDecoder::new();
+    let mut decoded_whole = Vec::new();
+    whole.feed(data);
+    let whole_result = drain(&mut whole, &mut decoded_whole);
+
+    // the same bytes fed in two reads must decode to the same frames
+    let split = data.first().map_or(0, |first| *first as usize % (data.len() + 1));
+    let mut parts = RespDecoder::new();
+    let mut decoded_in_parts = Vec::new();
+    parts.feed(&data[..split]);
+    let parts_result = drain(&mut parts, &mut decoded_in_parts).and_then(|_| {
+        parts.feed(&data[split..]);
+        drain(&mut parts, &mut decoded_in_parts)
+    });
+
+    if whole_result.is_ok() {
+        assert!(parts_result.is_ok());
+        assert_eq!(decoded_in_parts, decoded_whole);
+        assert_eq!(parts.buffered_len(), whole.buffered_len());
+    }
+});
@@ -0,0 +1,471 @@
+use crate::command::Command;
+use crate::engine::{Snapshot, StorageEngine};
+use crate::protocol::RespDecoder;
+use crate::protocol::RespObject::{Array, BulkString};
+use crate::session::Session;
+
+use std::fmt::Display;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// how often 'everysec' flushes the file to disk
+const FSYNC_INTERVAL: Duration = Duration::from_secs(1);
+// how much of the file is read at once when replaying it
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// When the append only file is flushed to disk, as set with 'appendfsync'
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub enum FsyncPolicy {
+    /// after every write: nothing acknowledged is ever lost, at the cost of a disk flush per write
+    Always,
+    /// once per second: at most a second worth of writes is lost on a crash
+    #[default]
+    EverySec,
+    /// whenever the operating system decides to
+    No,
+}
+
+impl FsyncPolicy {
+    pub fn parse(policy: &str) -> Option<FsyncPolicy> {
+        match policy.to_lowercase().as_str() {
+            "always" => Some(FsyncPolicy::Always),
+            "everysec" => Some(FsyncPolicy::EverySec),
+            "no" => Some(FsyncPolicy::No),
+            _ => None,
+        }
+    }
+}
+
+impl Display for FsyncPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FsyncPolicy::Always => write!(f, "always"),
+            FsyncPolicy::EverySec => write!(f, "everysec"),
+            FsyncPolicy::No => write!(f, "no"),
+        }
+    }
+}
+
+/// The append only file (AOF): every write that succeeds is appended to it as the command that does it (in RESP, the
+/// way clients send commands), so that replaying the file on startup rebuilds the data.
+///
+/// The file only ever grows, so BGREWRITEAOF replaces it with the shortest commands that rebuild the current data.
+/// The rewrite is written by a thread of its own, from a snapshot; whatever is logged meanwhile is also kept aside,
+/// and appended to the rewritten file before it takes the place of the old one.
+pub struct AppendOnlyFile {
+    path: PathBuf,
+    file: File,
+    fsync: FsyncPolicy,
+    // the database the commands in the file currently apply to, so that SELECT is only logged when it changes
+    db: Option<usize>,
+    // between the MULTI and EXEC of a transaction, whether MULTI made it into the file (with the first write)
+    transaction: Option<bool>,
+    // whether something was written since the last flush to disk, and when that flush was
+    unsynced: bool,
+    last_fsync: Instant,
+    rewrite: Option<Rewrite>,
+}
+
+// A rewrite in progress
+struct Rewrite {
+    // what was logged since the snapshot was taken, which the rewritten file misses
+    buffer: Vec<u8>,
+    writer: JoinHandle<io::Result<()>>,
+}
+
+impl AppendOnlyFile {
+    /// Opens the file at 'path' for appending, creating it when missing.
+    pub fn open(path: impl Into<PathBuf>, fsync: FsyncPolicy) -> io::Result<AppendOnlyFile> {
+        let path = path.into();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(AppendOnlyFile {
+            path,
+            file,
+            fsync,
+            db: None,
+            transaction: None,
+            unsynced: false,
+            last_fsync: Instant::now(),
+            rewrite: None,
+        })
+    }
+
+    pub fn fsync(&self) -> FsyncPolicy {
+        self.fsync
+    }
+
+    pub fn set_fsync(&mut self, fsync: FsyncPolicy) {
+        self.fsync = fsync;
+    }
+
+    /// Appends a command that wrote to the given database.
+    pub fn log(&mut self, db: usize, arguments: &[Vec<u8>]) -> io::Result<()> {
+        let mut bytes = vec![];
+        if self.transaction == Some(false) {
+            bytes.extend(command_bytes(&[b"MULTI".to_vec()]));
+            self.transaction = Some(true);
+        }
+        if self.db != Some(db) {
+            bytes.extend(command_bytes(&[b"SELECT".to_vec(), db.to_string().into_bytes()]));
+            self.db = Some(db);
+        }
+        bytes.extend(command_bytes(arguments));
+        self.write(&bytes)
+    }
+
+    /// Has the writes that follow, up to 'end_transaction', replayed all at once: they are wrapped in MULTI/EXEC,
+    /// unless there are none.
+    pub fn begin_transaction(&mut self) {
+        self.transaction = Some(false);
+    }
+
+    pub fn end_transaction(&mut self) -> io::Result<()> {
+        match self.transaction.take() {
+            Some(true) => self.write(&command_bytes(&[b"EXEC".to_vec()])),
+            _ => Ok(()),
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if let Some(rewrite) = &mut self.rewrite {
+            rewrite.buffer.extend_from_slice(bytes);
+        }
+        self.file.write_all(bytes)?;
+        self.unsynced = true;
+        if self.fsync == FsyncPolicy::Always {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.file.sync_data()?;
+        self.unsynced = false;
+        self.last_fsync = Instant::now();
+        Ok(())
+    }
+
+    pub fn is_rewriting(&self) -> bool {
+        self.rewrite.is_some()
+    }
+
+    /// Starts writing the commands that rebuild the snapshot to a new file, unless a rewrite is already in progress.
+    /// Returns whether it started.
+    pub fn start_rewrite(&mut self, snapshot: Snapshot) -> bool {
+        if self.rewrite.is_some() {
+            return false;
+        }
+
+        let rewrite_path = rewrite_path(&self.path);
+        let writer = thread::spawn(move || {
+            let mut out = BufWriter::new(File::create(rewrite_path)?);
+            snapshot.write_commands(&mut out)?;
+            out.get_ref().sync_all()
+        });
+
+        // the rewritten file ends in whichever database the snapshot ends in, and a transaction whose MULTI was
+        // already logged goes on after it
+        self.db = None;
+        let buffer = match self.transaction {
+            Some(true) => command_bytes(&[b"MULTI".to_vec()]),
+            _ => vec![],
+        };
+        self.rewrite = Some(Rewrite { buffer, writer });
+        true
+    }
+
+    /// What is due every now and then: the flush to disk of 'everysec', and putting a finished rewrite in place.
+    pub fn cron(&mut self) -> io::Result<()> {
+        if self.fsync == FsyncPolicy::EverySec && self.unsynced && self.last_fsync.elapsed() >= FSYNC_INTERVAL {
+            self.sync()?;
+        }
+        if self.rewrite.as_ref().is_some_and(|rewrite| rewrite.writer.is_finished()) {
+            self.finish_rewrite()?;
+        }
+        Ok(())
+    }
+
+    // appends what was logged during the rewrite to the rewritten file, which then replaces the old one; when the
+    // rewrite failed, the old file simply stays
+    fn finish_rewrite(&mut self) -> io::Result<()> {
+        let Some(rewrite) = self.rewrite.take() else {
+            return Ok(());
+        };
+        let rewrite_path = rewrite_path(&self.path);
+        let written = rewrite.writer.join()
+            .unwrap_or_else(|_| Err(io::Error::other("the rewrite thread panicked")));
+        if let Err(e) = written {
+            let _ = fs::remove_file(&rewrite_path);
+            return Err(e);
+        }
+
+        let mut file = OpenOptions::new().append(true).open(&rewrite_path)?;
+        file.write_all(&rewrite.buffer)?;
+        file.sync_all()?;
+        fs::rename(&rewrite_path, &self.path)?;
+        // the new file is written to from now on, under the name of the old one
+        self.file = file;
+        self.unsynced = false;
+        Ok(())
+    }
+}
+
+// where a rewrite is written to, until it is complete
+fn rewrite_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".rewrite");
+    path.with_file_name(name)
+}
+
+fn command_bytes(arguments: &[Vec<u8>]) -> Vec<u8> {
+    Array(arguments.iter().cloned().map(BulkString).collect()).to_bytes()
+}
+
+/// Rebuilds the data by running the commands of the append only file at 'path', returning how many there were. A
+/// missing file is just an empty one.
+///
+/// A command that was cut short (e.g. by a crash in the middle of writing it) can only be the last one, and it is
+/// removed from the file along with a transaction that never got to its EXEC, as neither was ever acknowledged.
+/// Anything else that is not a command means the file is corrupt, and nothing is loaded past it.
+pub fn replay(path: &Path, engine: &mut StorageEngine) -> io::Result<usize> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+
+    // keys are not expired while loading, as the commands that follow in the file may still use them
+    engine.set_loading(true);
+    let replayed = run_commands(&mut file, engine);
+    engine.set_loading(false);
+
+    let (commands, complete_len) = replayed?;
+    let file_len = file.metadata()?.len();
+    if complete_len < file_len {
+        eprintln!("The append only file ends in an incomplete command, truncating it from {file_len} to {complete_len} bytes");
+        OpenOptions::new().write(true).open(path)?.set_len(complete_len)?;
+    }
+    Ok(commands)
+}
+
+// runs every command of the file, returning how many ran and up to where the file holds complete commands
+fn run_commands(file: &mut File, engine: &mut StorageEngine) -> io::Result<(usize, u64)> {
+    let mut decoder = RespDecoder::new();
+    // the commands run as a client of their own, which keeps track of SELECT and MULTI
+    let mut session = Session::new();
+    let mut chunk = vec![0u8; READ_CHUNK_SIZE];
+    let mut read_len = 0u64;
+    let mut commands = 0;
+    let mut complete_len = 0u64;
+    // where the transaction in progress started, if any
+    let mut transaction_start = None;
+
+    loop {
+        let read = file.read(&mut chunk)?;
+        if read == 0 {
+            return Ok((commands, transaction_start.unwrap_or(complete_len)));
+        }
+        read_len += read as u64;
+        decoder.feed(&chunk[..read]);
+
+        loop {
+            let frame = decoder.next_frame()
+                .map_err(|e| io::Error::new(ErrorKind::InvalidData, format!("Bad file format reading the append only file: {}", e.message)))?;
+            let Some(frame) = frame else {
+                break;
+            };
+            let command = Command::from(frame)
+                .map_err(|e| io::Error::new(ErrorKind::InvalidData, format!("Bad command in the append only file: {e}")))?;
+
+            let start = complete_len;
+            command.execute(&mut session, engine);
+            commands += 1;
+            complete_len = read_len - decoder.buffered_len() as u64;
+
+            transaction_start = match transaction_start {
+                None if session.in_transaction() => Some(start),
+                _ if !session.in_transaction() => None,
+                start => start,
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod aof_tests {
+    use super::*;
+    use crate::protocol::RespObject;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("aof-{}-{name}.aof", std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn execute(session: &mut Session, engine: &mut StorageEngine, arguments: &[&str]) -> RespObject {
+        let arguments = arguments.iter().map(|argument| BulkString(argument.as_bytes().to_vec())).collect();
+        Command::from(Array(arguments)).unwrap().execute(session, engine)
+    }
+
+    fn replayed(path: &Path) -> StorageEngine {
+        let mut engine = StorageEngine::new();
+        replay(path, &mut engine).unwrap();
+        // left in whichever database the file ended in
+        engine.select_db(0);
+        engine
+    }
+
+    #[test]
+    fn replays_the_writes_that_succeeded() {
+        let path = temp_path("replay");
+        let mut engine = StorageEngine::new();
+        engine.set_aof(Some(AppendOnlyFile::open(&path, FsyncPolicy::Always).unwrap()));
+        let mut session = Session::new();
+
+        execute(&mut session, &mut engine, &["set", "a", "1"]);
+        execute(&mut session, &mut engine, &["get", "a"]);
+        execute(&mut session, &mut engine, &["lpush", "a", "x"]);
+        execute(&mut session, &mut engine, &["select", "3"]);
+        execute(&mut session, &mut engine, &["rpush", "list", "x", "y", "z"]);
+        execute(&mut session, &mut engine, &["sadd", "set", "m"]);
+        execute(&mut session, &mut engine, &["spop", "set"]);
+        execute(&mut session, &mut engine, &["multi"]);
+        execute(&mut session, &mut engine, &["lpop", "list"]);
+        execute(&mut session, &mut engine, &["incr", "counter"]);
+        execute(&mut session, &mut engine, &["exec"]);
+
+        let logged = fs::read(&path).unwrap();
+        let expected: Vec<u8> = [
+            &["SELECT", "0"][..], &["set", "a", "1"], &["SELECT", "3"], &["rpush", "list", "x", "y", "z"],
+            &["sadd", "set", "m"], &["SREM", "set", "m"], &["MULTI"], &["lpop", "list"], &["incr", "counter"], &["EXEC"],
+        ].iter()
+            .flat_map(|command| command_bytes(&command.iter().map(|argument| argument.as_bytes().to_vec()).collect::<Vec<_>>()))
+            .collect();
+        assert_eq!(logged.escape_ascii().to_string(), expected.escape_ascii().to_string());
+
+        let mut engine = replayed(&path);
+        assert_eq!(engine.get(b"a"), Ok(Some(&b"1".to_vec())));
+        engine.select_db(3);
+        assert_eq!(engine.list_range(b"list", 0, -1).unwrap(), vec![&b"y".to_vec(), &b"z".to_vec()]);
+        assert_eq!(engine.get(b"counter"), Ok(Some(&b"1".to_vec())));
+        assert!(!engine.exists(b"set"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn logs_what_was_random_or_relative_to_the_time_as_it_turned_out() {
+        let path = temp_path("explicit");
+        let mut engine = StorageEngine::new();
+        engine.set_aof(Some(AppendOnlyFile::open(&path, FsyncPolicy::No).unwrap()));
+        let mut session = Session::new();
+        let mut blocked = Session::new();
+
+        let BulkString(id) = execute(&mut session, &mut engine, &["xadd", "stream", "maxlen", "5", "*", "f", "v"]) else {
+            panic!("XADD should reply with the ID");
+        };
+        execute(&mut session, &mut engine, &["set", "a", "1", "ex", "100"]);
+        execute(&mut session, &mut engine, &["set", "b", "1"]);
+        execute(&mut session, &mut engine, &["expire", "b", "-1"]);
+        execute(&mut blocked, &mut engine, &["brpop", "list", "0"]);
+        execute(&mut session, &mut engine, &["lpush", "list", "x"]);
+
+        let logged = String::from_utf8(fs::read(&path).unwrap()).unwrap();
+        let id = String::from_utf8(id).unwrap();
+        assert!(logged.contains(&format!("$6\r\nstream\r\n$6\r\nmaxlen\r\n$1\r\n5\r\n${}\r\n{id}\r\n", id.len())));
+        assert!(logged.contains("$9\r\nPEXPIREAT\r\n$1\r\na\r\n"));
+        assert!(logged.contains("$3\r\nDEL\r\n$1\r\nb\r\n"));
+        assert!(logged.ends_with("$4\r\nRPOP\r\n$4\r\nlist\r\n"));
+
+        let mut engine = replayed(&path);
+        assert_eq!(engine.stream_len(b"stream"), Ok(1));
+        assert!(engine.exists(b"a") && !engine.exists(b"b") && !engine.exists(b"list"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn drops_an_incomplete_command_or_transaction_at_the_end() {
+        let path = temp_path("truncated");
+        let complete = [command_bytes(&[b"SET".to_vec(), b"a".to_vec(), b"1".to_vec()])].concat();
+        fs::write(&path, [&complete[..], b"*3\r\n$3\r\nSET\r\n$1\r\nb"].concat()).unwrap();
+
+        let mut engine = replayed(&path);
+        assert_eq!(engine.get(b"a"), Ok(Some(&b"1".to_vec())));
+        assert!(!engine.exists(b"b"));
+        assert_eq!(fs::read(&path).unwrap(), complete);
+
+        let unfinished = [
+            command_bytes(&[b"MULTI".to_vec()]),
+            command_bytes(&[b"SET".to_vec(), b"b".to_vec(), b"2".to_vec()]),
+        ].concat();
+        fs::write(&path, [&complete[..], &unfinished].concat()).unwrap();
+
+        let mut engine = replayed(&path);
+        assert!(!engine.exists(b"b"));
+        assert_eq!(fs::read(&path).unwrap(), complete);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn refuses_a_corrupt_file() {
+        let path = temp_path("corrupt");
+        fs::write(&path, b"*1\r\n$4\r\nPING\r\n*1\r\n+OK\r\n").unwrap();
+
+        let error = replay(&path, &mut StorageEngine::new()).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rewrites_the_file_with_the_current_data() {
+        let path = temp_path("rewrite");
+        let mut engine = StorageEngine::new();
+        engine.set_aof(Some(AppendOnlyFile::open(&path, FsyncPolicy::No).unwrap()));
+        let mut session = Session::new();
+
+        for value in ["1", "2", "3"] {
+            execute(&mut session, &mut engine, &["rpush", "list", value]);
+        }
+        execute(&mut session, &mut engine, &["lpop", "list"]);
+        execute(&mut session, &mut engine, &["zadd", "zset", "1.5", "a", "-inf", "b"]);
+        execute(&mut session, &mut engine, &["hset", "hash", "f", "v"]);
+        execute(&mut session, &mut engine, &["xadd", "stream", "1-1", "f", "v"]);
+        execute(&mut session, &mut engine, &["xadd", "stream", "2-1", "f", "v"]);
+        execute(&mut session, &mut engine, &["xdel", "stream", "2-1"]);
+        execute(&mut session, &mut engine, &["xgroup", "create", "stream", "group", "0"]);
+        execute(&mut session, &mut engine, &["xreadgroup", "group", "group", "alice", "streams", "stream", ">"]);
+        execute(&mut session, &mut engine, &["set", "expiring", "v", "ex", "100"]);
+
+        assert_eq!(execute(&mut session, &mut engine, &["bgrewriteaof"]), RespObject::SimpleString("Background append only file rewriting started".to_string()));
+        // logged while the rewrite is in progress
+        execute(&mut session, &mut engine, &["select", "1"]);
+        execute(&mut session, &mut engine, &["set", "late", "v"]);
+        while engine.aof().is_some_and(AppendOnlyFile::is_rewriting) {
+            engine.aof_mut().unwrap().cron().unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        let logged = String::from_utf8(fs::read(&path).unwrap()).unwrap();
+        assert!(!logged.contains("LPOP") && !logged.contains("lpop"));
+
+        let mut engine = replayed(&path);
+        let mut session = Session::new();
+        assert_eq!(engine.list_range(b"list", 0, -1).unwrap(), vec![&b"2".to_vec(), &b"3".to_vec()]);
+        assert_eq!(execute(&mut session, &mut engine, &["zrange", "zset", "0", "-1", "withscores"]), Array(vec![
+            BulkString("b".into()), BulkString("-inf".into()), BulkString("a".into()), BulkString("1.5".into()),
+        ]));
+        assert!(matches!(engine.expire_time(b"expiring"), crate::engine::ExpireTime::AtUnixTimeMillis(_)));
+        // the stream keeps its last ID, and its group its pending entry
+        assert_eq!(execute(&mut session, &mut engine, &["xadd", "stream", "2-1", "f", "v"]),
+                   RespObject::Error("The ID specified in XADD is equal or smaller than the target stream top item".to_string()));
+        assert_eq!(execute(&mut session, &mut engine, &["xpending", "stream", "group"]), Array(vec![
+            RespObject::Integer(1), BulkString("1-1".into()), BulkString("1-1".into()),
+            Array(vec![Array(vec![BulkString("alice".into()), BulkString("1".into())])]),
+        ]));
+        execute(&mut session, &mut engine, &["select", "1"]);
+        assert_eq!(engine.get(b"late"), Ok(Some(&b"v".to_vec())));
+        fs::remove_file(&path).unwrap();
+    }
+}
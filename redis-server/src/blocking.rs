@@ -0,0 +1,158 @@
+use crate::command::Command;
+use crate::protocol::RespObject;
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::mpsc::Sender;
+
+/// The clients waiting in blocking commands (e.g. BLPOP), by the keys they wait on.
+///
+/// Writes to those keys mark them as ready. Once the command that wrote them is done (for a transaction, once EXEC
+/// is), the clients blocked on each ready key are given a chance to retry their commands, in the order they blocked.
+#[derive(Default)]
+pub struct BlockedClients {
+    // database and key -> the ids of the clients blocked on it, longest waiting first
+    queues: HashMap<(usize, Vec<u8>), VecDeque<u64>>,
+    clients: HashMap<u64, BlockedClient>,
+    // the keys written to while clients wait on them, in the order they were first written
+    ready: VecDeque<(usize, Vec<u8>)>,
+}
+
+/// A client waiting in a blocking command, as reached from the connection that ends up serving it
+pub struct BlockedClient {
+    db: usize,
+    keys: Vec<Vec<u8>>,
+    command: Command,
+    reply: Sender<RespObject>,
+}
+
+impl BlockedClient {
+    pub fn command(&self) -> &Command {
+        &self.command
+    }
+
+    /// Hands the reply over to the connection of the client, which stops waiting.
+    pub fn send(self, reply: RespObject) {
+        // a client that is gone cannot be served, and it stops waiting as it goes
+        let _ = self.reply.send(reply);
+    }
+}
+
+impl BlockedClients {
+    pub fn new() -> BlockedClients {
+        BlockedClients::default()
+    }
+
+    /// Has the client wait on the keys of a database, behind the clients that already wait on them.
+    pub fn block(&mut self, client_id: u64, db: usize, keys: &[Vec<u8>], command: Command, reply: Sender<RespObject>) {
+        let mut unique_keys: Vec<Vec<u8>> = vec![];
+        for key in keys {
+            if !unique_keys.contains(key) {
+                self.queues.entry((db, key.clone())).or_default().push_back(client_id);
+                unique_keys.push(key.clone());
+            }
+        }
+        self.clients.insert(client_id, BlockedClient { db, keys: unique_keys, command, reply });
+    }
+
+    /// Stops the client from waiting on any key, returning it if it was still waiting.
+    pub fn unblock(&mut self, client_id: u64) -> Option<BlockedClient> {
+        let client = self.clients.remove(&client_id)?;
+        for key in &client.keys {
+            let queue_key = (client.db, key.clone());
+            if let Some(queue) = self.queues.get_mut(&queue_key) {
+                queue.retain(|id| *id != client_id);
+                if queue.is_empty() {
+                    self.queues.remove(&queue_key);
+                }
+            }
+        }
+        Some(client)
+    }
+
+    pub fn is_blocked(&self, client_id: u64) -> bool {
+        self.clients.contains_key(&client_id)
+    }
+
+    pub fn client(&self, client_id: u64) -> Option<&BlockedClient> {
+        self.clients.get(&client_id)
+    }
+
+    /// The clients waiting on the key, longest waiting first.
+    pub fn waiting_on(&self, db: usize, key: &[u8]) -> Vec<u64> {
+        self.queues.get(&(db, key.to_vec()))
+            .map_or(vec![], |queue| queue.iter().copied().collect())
+    }
+
+    /// Marks the key as ready, if some client waits on it.
+    pub fn key_modified(&mut self, db: usize, key: &[u8]) {
+        let queue_key = (db, key.to_vec());
+        if self.queues.contains_key(&queue_key) && !self.ready.contains(&queue_key) {
+            self.ready.push_back(queue_key);
+        }
+    }
+
+    /// Marks every key clients wait on in the database as ready, for operations that replace a whole database.
+    pub fn all_modified(&mut self, db: usize) {
+        let keys: Vec<Vec<u8>> = self.queues.keys()
+            .filter(|(key_db, _)| *key_db == db)
+            .map(|(_, key)| key.clone())
+            .collect();
+        for key in keys {
+            self.key_modified(db, &key);
+        }
+    }
+
+    pub fn take_ready_key(&mut self) -> Option<(usize, Vec<u8>)> {
+        self.ready.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod blocked_clients_tests {
+    use super::*;
+    use crate::protocol::RespObject::{Array, BulkString};
+    use std::sync::mpsc::channel;
+
+    fn blpop(keys: &[&str]) -> Command {
+        let mut arguments = vec![BulkString("blpop".into())];
+        arguments.extend(keys.iter().map(|key| BulkString(key.as_bytes().to_vec())));
+        arguments.push(BulkString("0".into()));
+        Command::from(Array(arguments)).unwrap()
+    }
+
+    #[test]
+    fn queues_clients_in_the_order_they_block() {
+        let mut blocked = BlockedClients::new();
+        let (reply, _replies) = channel();
+        blocked.block(7, 0, &[b"a".to_vec(), b"b".to_vec()], blpop(&["a", "b"]), reply.clone());
+        blocked.block(3, 0, &[b"b".to_vec(), b"b".to_vec()], blpop(&["b", "b"]), reply.clone());
+        blocked.block(5, 1, &[b"b".to_vec()], blpop(&["b"]), reply);
+
+        assert_eq!(blocked.waiting_on(0, b"b"), vec![7, 3]);
+        assert_eq!(blocked.waiting_on(1, b"b"), vec![5]);
+
+        // a client waiting on several keys leaves all of them at once
+        assert!(blocked.unblock(7).is_some());
+        assert!(blocked.unblock(7).is_none());
+        assert_eq!(blocked.waiting_on(0, b"a"), Vec::<u64>::new());
+        assert_eq!(blocked.waiting_on(0, b"b"), vec![3]);
+        assert!(blocked.is_blocked(3));
+    }
+
+    #[test]
+    fn marks_keys_ready_only_when_clients_wait_on_them() {
+        let mut blocked = BlockedClients::new();
+        let (reply, _replies) = channel();
+        blocked.block(1, 0, &[b"a".to_vec(), b"b".to_vec()], blpop(&["a", "b"]), reply);
+
+        blocked.key_modified(0, b"other");
+        blocked.key_modified(1, b"a");
+        blocked.key_modified(0, b"b");
+        blocked.key_modified(0, b"b");
+        blocked.all_modified(0);
+
+        assert_eq!(blocked.take_ready_key(), Some((0, b"b".to_vec())));
+        assert_eq!(blocked.take_ready_key(), Some((0, b"a".to_vec())));
+        assert_eq!(blocked.take_ready_key(), None);
+    }
+}
@@ -1,99 +1,248 @@
-use crate::engine::{StorageEngine, TimeToLive};
-use crate::protocol::RespObject;
-use crate::protocol::RespObject::{Array, BulkString, Error, Integer, NullBulkString, SimpleString};
+use crate::aof::{AppendOnlyFile, FsyncPolicy};
+use crate::engine::{AutoClaimOptions, ClaimOptions, ExpireAt, ExpireConditions, ExpireConfig, ExpireTime, GroupStartId, InsertPosition, LexBound, LexRange, ListEnd, NewStreamId, NotifyFlags, PendingFilter, ScoreRange, SetCondition, SetExpiry, SetOperation, SetOptions, SortedSetRange, SortedSetUpdateConditions, StorageEngine, StreamFields, StreamId, StreamTrim, TimeToLive, TrimStrategy};
+use crate::glob::glob_match;
+use crate::protocol::{ProtocolVersion, RespObject, MAX_ARRAY_LENGTH};
+use crate::protocol::RespObject::{Array, BulkString, Error, Integer, Map, Null, NullArray, NullBulkString, Push, SimpleString};
+use crate::pubsub::Subscriber;
+use crate::rdb::SaveRules;
+use crate::session::Session;
+
+use std::sync::mpsc::channel;
+use std::time::Duration;
 
 // public struct to explicitly hide implementation details from enum RespCommand and its children
 // enums can only have public components, and I want some of those details to be hidden
-#[derive(Debug, Eq, PartialEq)]
-pub struct Command(RespCommand);
+#[derive(Debug, Clone)]
+pub struct Command {
+    command: RespCommand,
+    // the command as the client sent it, which is how the AOF logs most writes
+    arguments: Vec<Vec<u8>>,
+}
+
+// commands are the same when they do the same, however the client spelled them (e.g. 'get' vs 'GET')
+impl PartialEq for Command {
+    fn eq(&self, other: &Self) -> bool {
+        self.command == other.command
+    }
+}
 
 impl Command {
     pub fn from(input: RespObject) -> Result<Command, String> {
+        let arguments = match &input {
+            Array(entries) => entries.iter()
+                .filter_map(|entry| if let BulkString(bytes) = entry { Some(bytes.clone()) } else { None })
+                .collect(),
+            _ => vec![],
+        };
         RespCommand::from(input)
-            .map(|inner| Self(inner))
+            .map(|command| Command { command, arguments })
+    }
+
+    // a command that did not come from a client, so that there are no arguments to log
+    fn parsed(command: RespCommand) -> Command {
+        Command { command, arguments: vec![] }
+    }
+
+    // parses a request of the client, for which a request that is not even a valid command dooms the transaction
+    // in progress, like any other error while queueing
+    pub fn from_request(input: RespObject, session: &mut Session) -> Result<Command, String> {
+        if session.in_subscribed_mode() {
+            let name = match &input {
+                Array(entries) => match entries.first() {
+                    Some(BulkString(name)) => String::from_utf8_lossy(name).to_lowercase(),
+                    _ => String::new(),
+                },
+                _ => String::new(),
+            };
+            if !SUBSCRIBED_MODE_COMMANDS.contains(&name.as_str()) {
+                return Err(format!("Can't execute '{name}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context"));
+            }
+        }
+
+        Self::from(input).inspect_err(|_| session.fail_transaction())
     }
 
     // TODO: can create some specific functions to create the different commands, eg: ping(), echo(String), etc ...
 
+    // runs the command as a client that has just connected would
     pub fn execute_on(&self, engine: &mut StorageEngine) -> RespObject {
-        self.0.execute_on(engine)
+        self.execute(&mut Session::new(), engine)
+    }
+
+    // the session carries the state of the client's connection, which some commands (e.g. HELLO) change
+    pub fn execute(&self, session: &mut Session, engine: &mut StorageEngine) -> RespObject {
+        let reply = self.run(session, engine);
+        // clients blocked on keys the command wrote to are served once it is done, which for EXEC means after the
+        // whole transaction
+        serve_blocked_clients(engine);
+        reply
+    }
+
+    fn run(&self, session: &mut Session, engine: &mut StorageEngine) -> RespObject {
+        // the engine is shared by all clients, so it is pointed at the database of this one for every command
+        engine.select_db(session.db());
+
+        if session.in_transaction() {
+            match self.command {
+                // these are about the connection rather than the data, so they are not queued
+                RespCommand::Exec | RespCommand::Discard | RespCommand::Quit | RespCommand::Reset => {}
+                RespCommand::Multi | RespCommand::Watch { .. } => {
+                    session.fail_transaction();
+                    return Error("Command not allowed inside a transaction".to_string());
+                }
+                _ => {
+                    session.queue_command(self.clone());
+                    return SimpleString("QUEUED".to_string());
+                }
+            }
+        }
+
+        let dirty = engine.dirty();
+        let reply = self.command.execute_on(session, engine);
+        // writes are logged once they succeed, and only if they did change something
+        if engine.dirty() != dirty && self.command.is_write() && !matches!(reply, Error(_)) {
+            for arguments in self.propagated(engine, &reply) {
+                engine.propagate(&arguments);
+            }
+        }
+        reply
+    }
+
+    // the commands that have the same effect when replayed as this one had, given its reply: most are logged as
+    // they came, but what was random or relative to the current time is made explicit
+    fn propagated(&self, engine: &mut StorageEngine, reply: &RespObject) -> Vec<Vec<Vec<u8>>> {
+        let command = |name: &[u8], key: &[u8], rest: &[&Vec<u8>]| {
+            let mut command = vec![name.to_vec(), key.to_vec()];
+            command.extend(rest.iter().map(|argument| argument.to_vec()));
+            command
+        };
+        let pop = |end: &ListEnd| if *end == ListEnd::Left { b"LPOP".as_slice() } else { b"RPOP".as_slice() };
+        let push = |end: &ListEnd| if *end == ListEnd::Left { b"LPUSH".as_slice() } else { b"RPUSH".as_slice() };
+
+        match (&self.command, reply) {
+            // the blocking pops are logged as the pops they ended up doing, which never block
+            (RespCommand::Blpop { .. } | RespCommand::Brpop { .. }, Array(popped)) => match popped.as_slice() {
+                [BulkString(key), _] => {
+                    let end = if matches!(self.command, RespCommand::Blpop { .. }) { ListEnd::Left } else { ListEnd::Right };
+                    vec![command(pop(&end), key, &[])]
+                }
+                _ => vec![],
+            },
+            (RespCommand::Blmpop { end, .. }, Array(popped)) => match popped.as_slice() {
+                [BulkString(key), Array(elements)] => vec![command(pop(end), key, &[&elements.len().to_string().into_bytes()])],
+                _ => vec![],
+            },
+            (RespCommand::Bzpopmin { .. } | RespCommand::Bzpopmax { .. }, Array(popped)) => match popped.as_slice() {
+                [BulkString(key), BulkString(member), _] => vec![command(b"ZREM", key, &[member])],
+                _ => vec![],
+            },
+            (RespCommand::Blmove { source, destination, from, to, .. }, BulkString(element)) => {
+                vec![command(pop(from), source, &[]), command(push(to), destination, &[element])]
+            }
+            (RespCommand::Spop { key, .. }, BulkString(member)) => vec![command(b"SREM", key, &[member])],
+            (RespCommand::Spop { key, .. }, Array(members)) => {
+                let members: Vec<&Vec<u8>> = members.iter()
+                    .filter_map(|member| if let BulkString(member) = member { Some(member) } else { None })
+                    .collect();
+                vec![command(b"SREM", key, &members)]
+            }
+            // the ID of the entry, which may have been generated
+            (RespCommand::Xadd { fields, .. }, BulkString(id)) => {
+                let mut arguments = self.arguments.clone();
+                let id_index = arguments.len() - 2 * fields.len() - 1;
+                arguments[id_index] = id.clone();
+                vec![arguments]
+            }
+            // an expiry is logged as a unix time, and one in the past as the deletion it was
+            (RespCommand::Set(SetCommand { key, .. }) | RespCommand::Getex { key, .. } | RespCommand::Expire { key, .. }, _) => {
+                match engine.expire_time(key) {
+                    ExpireTime::KeyDoesNotExist => vec![command(b"DEL", key, &[])],
+                    ExpireTime::DoesNotExpire => vec![self.arguments.clone()],
+                    ExpireTime::AtUnixTimeMillis(millis) => {
+                        vec![self.arguments.clone(), command(b"PEXPIREAT", key, &[&millis.to_string().into_bytes()])]
+                    }
+                }
+            }
+            _ => vec![self.arguments.clone()],
+        }
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 struct GetCommand {
-    key: String,
+    key: Vec<u8>,
 }
 
 impl GetCommand {
-    pub fn from(key: String) -> GetCommand {
+    pub fn from(key: Vec<u8>) -> GetCommand {
         GetCommand { key }
     }
 
-    fn execute_on<'a>(&self, engine: &'a mut StorageEngine) -> Result<Option<&'a String>, String> {
+    fn execute_on<'a>(&self, engine: &'a mut StorageEngine) -> Result<Option<&'a Vec<u8>>, String> {
         engine.get(&self.key)
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 struct SetCommand {
-    key: String,
-    value: String,
-    expiry_seconds: Option<u64>,
+    key: Vec<u8>,
+    value: Vec<u8>,
+    options: SetOptions,
 }
 
 impl SetCommand {
-    pub fn from_key_value(key_value: (String, String)) -> SetCommand {
-        SetCommand { key: key_value.0, value: key_value.1, expiry_seconds: None }
+    pub fn from_key_value(key_value: (Vec<u8>, Vec<u8>)) -> SetCommand {
+        SetCommand::with_options(key_value, SetOptions::default())
     }
 
-    pub fn from(key_value: (String, String), expiry_seconds: Option<u64>) -> SetCommand {
-        SetCommand { key: key_value.0, value: key_value.1, expiry_seconds }
+    pub fn with_options(key_value: (Vec<u8>, Vec<u8>), options: SetOptions) -> SetCommand {
+        SetCommand { key: key_value.0, value: key_value.1, options }
     }
 
-    fn execute_on(&self, engine: &mut StorageEngine) -> Result<(), String> {
+    // whether the value was written, and the previous value if the GET option was given
+    fn execute_on(&self, engine: &mut StorageEngine) -> Result<(bool, Option<Vec<u8>>), String> {
         // todo: find something more efficient, so .clone() doesn't have to be called here
-        engine.set(self.key.clone(), self.value.clone(), self.expiry_seconds.clone())?;
-        Ok(())
+        engine.set_with_options(self.key.clone(), self.value.clone(), self.options)
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 struct MsetCommand {
     commands: Vec<SetCommand>,
 }
 
 impl MsetCommand {
-    pub fn from_key_values(key_values: Vec<(String, String)>) -> MsetCommand {
+    pub fn from_key_values(key_values: Vec<(Vec<u8>, Vec<u8>)>) -> MsetCommand {
         let commands = key_values.into_iter()
-            .map(|kv| SetCommand::from_key_value(kv))
+            .map(SetCommand::from_key_value)
             .collect();
         MsetCommand { commands }
     }
 
     fn execute_on(&self, engine: &mut StorageEngine) -> Result<(), String> {
-        self.commands.iter()
-            .for_each(|cmd| cmd.execute_on(engine).unwrap());
+        for cmd in &self.commands {
+            cmd.execute_on(engine)?;
+        }
         Ok(())
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 struct MgetCommand {
     commands: Vec<GetCommand>,
 }
 
 impl MgetCommand {
-    pub fn from_keys(keys: Vec<String>) -> MgetCommand {
+    pub fn from_keys(keys: Vec<Vec<u8>>) -> MgetCommand {
         MgetCommand {
-            commands: keys.into_iter().map(|k| GetCommand::from(k)).collect()
+            commands: keys.into_iter().map(GetCommand::from).collect()
         }
     }
 
-    fn execute_on(&self, engine: &mut StorageEngine) -> Vec<Option<String>> {
+    fn execute_on(&self, engine: &mut StorageEngine) -> Vec<Option<Vec<u8>>> {
         self.commands.iter()
             // todo: maybe there's a better solution, but for now _must_ clone and
-            //  return Option<String> instead of Option<&String>;
+            //  return Option<Vec<u8>> instead of Option<&String>;
             //  problem is that calling in loop, technically the reference returned e.g. in the first loop
             //  will not exist anymore after the second loop (because that second call may deallocate it)
             //  a solution may be to implement the multi_get into Engine at a low level
@@ -106,13 +255,13 @@ impl MgetCommand {
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 struct DelCommand {
-    keys: Vec<String>,
+    keys: Vec<Vec<u8>>,
 }
 
 impl DelCommand {
-    pub fn from_keys(keys: Vec<String>) -> DelCommand {
+    pub fn from_keys(keys: Vec<Vec<u8>>) -> DelCommand {
         DelCommand { keys }
     }
 
@@ -124,13 +273,13 @@ impl DelCommand {
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, Eq, PartialEq, Clone)]
 struct ExistsCommand {
-    keys: Vec<String>,
+    keys: Vec<Vec<u8>>,
 }
 
 impl ExistsCommand {
-    pub fn from_keys(keys: Vec<String>) -> ExistsCommand {
+    pub fn from_keys(keys: Vec<Vec<u8>>) -> ExistsCommand {
         ExistsCommand { keys }
     }
 
@@ -142,18 +291,136 @@ impl ExistsCommand {
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
+// not 'Eq', because sorted set scores are floats
+#[derive(Debug, PartialEq, Clone)]
 enum RespCommand {
     Ping,
+    Quit,
+    Reset,
+    Hello { protocol: Option<ProtocolVersion>, auth: Option<(Vec<u8>, Vec<u8>)>, client_name: Option<Vec<u8>> },
     // TODO: review: do these commands really need to own this data (particularly the Strings)?
-    Echo { message: String },
+    Echo { message: Vec<u8> },
+    ConfigGet { parameters: Vec<Vec<u8>> },
+    ConfigSet { parameters: Vec<(Vec<u8>, Vec<u8>)> },
+    Info { sections: Vec<Vec<u8>> },
+    Bgrewriteaof,
+    Save,
+    Bgsave,
+    Lastsave,
     Set(SetCommand),
     Get(GetCommand),
-    Ttl { key: String },
+    Ttl { key: Vec<u8> },
+    Pttl { key: Vec<u8> },
+    Expire { key: Vec<u8>, at: ExpireAt, conditions: ExpireConditions },
+    Expiretime { key: Vec<u8> },
+    Pexpiretime { key: Vec<u8> },
+    Persist { key: Vec<u8> },
+    Keys { pattern: Vec<u8> },
+    Scan { cursor: u64, pattern: Option<Vec<u8>>, count: usize, value_type: Option<Vec<u8>> },
+    Randomkey,
+    Dbsize,
+    Select { db: usize },
+    Subscribe { channels: Vec<Vec<u8>> },
+    Unsubscribe { channels: Vec<Vec<u8>> },
+    Psubscribe { patterns: Vec<Vec<u8>> },
+    Punsubscribe { patterns: Vec<Vec<u8>> },
+    Publish { channel: Vec<u8>, message: Vec<u8> },
+    PubsubChannels { pattern: Option<Vec<u8>> },
+    PubsubNumsub { channels: Vec<Vec<u8>> },
+    PubsubNumpat,
+    Multi,
+    Exec,
+    Discard,
+    Watch { keys: Vec<Vec<u8>> },
+    Unwatch,
+    Swapdb { first: usize, second: usize },
+    Move { key: Vec<u8>, db: usize },
+    Flushdb { asynchronously: bool },
+    Flushall { asynchronously: bool },
+    Rename { key: Vec<u8>, new_key: Vec<u8>, only_if_new: bool },
+    Copy { source: Vec<u8>, destination: Vec<u8>, destination_db: Option<usize>, replace: bool },
+    Touch { keys: Vec<Vec<u8>> },
+    Type { key: Vec<u8> },
+    ObjectEncoding { key: Vec<u8> },
+    ObjectIdletime { key: Vec<u8> },
+    ObjectFreq { key: Vec<u8> },
+    ObjectRefcount { key: Vec<u8> },
+    Append { key: Vec<u8>, value: Vec<u8> },
+    Strlen { key: Vec<u8> },
+    Getrange { key: Vec<u8>, start: i64, end: i64 },
+    Setrange { key: Vec<u8>, offset: usize, value: Vec<u8> },
+    Getdel { key: Vec<u8> },
+    Getex { key: Vec<u8>, expiry: SetExpiry },
+    Setnx { key: Vec<u8>, value: Vec<u8> },
+    Msetnx { key_values: Vec<(Vec<u8>, Vec<u8>)> },
+    Incrby { key: Vec<u8>, increment: i64 },
+    Incrbyfloat { key: Vec<u8>, increment: f64 },
     Mset(MsetCommand),
     Mget(MgetCommand),
     Del(DelCommand),
     Exists(ExistsCommand),
+    Lpush { key: Vec<u8>, values: Vec<Vec<u8>> },
+    Rpush { key: Vec<u8>, values: Vec<Vec<u8>> },
+    Lpop { key: Vec<u8>, count: Option<usize> },
+    Rpop { key: Vec<u8>, count: Option<usize> },
+    Llen { key: Vec<u8> },
+    Lrange { key: Vec<u8>, start: i64, stop: i64 },
+    Lindex { key: Vec<u8>, index: i64 },
+    Lset { key: Vec<u8>, index: i64, value: Vec<u8> },
+    Lrem { key: Vec<u8>, count: i64, value: Vec<u8> },
+    Ltrim { key: Vec<u8>, start: i64, stop: i64 },
+    Linsert { key: Vec<u8>, position: InsertPosition, pivot: Vec<u8>, value: Vec<u8> },
+    // the blocking commands wait 'timeout' at most, or for as long as it takes when there is none
+    Blpop { keys: Vec<Vec<u8>>, timeout: Option<Duration> },
+    Brpop { keys: Vec<Vec<u8>>, timeout: Option<Duration> },
+    Blmove { source: Vec<u8>, destination: Vec<u8>, from: ListEnd, to: ListEnd, timeout: Option<Duration> },
+    Blmpop { keys: Vec<Vec<u8>>, end: ListEnd, count: usize, timeout: Option<Duration> },
+    Hset { key: Vec<u8>, field_values: Vec<(Vec<u8>, Vec<u8>)> },
+    Hsetnx { key: Vec<u8>, field: Vec<u8>, value: Vec<u8> },
+    Hget { key: Vec<u8>, field: Vec<u8> },
+    Hmget { key: Vec<u8>, fields: Vec<Vec<u8>> },
+    Hdel { key: Vec<u8>, fields: Vec<Vec<u8>> },
+    Hgetall { key: Vec<u8> },
+    Hkeys { key: Vec<u8> },
+    Hvals { key: Vec<u8> },
+    Hlen { key: Vec<u8> },
+    Hexists { key: Vec<u8>, field: Vec<u8> },
+    Hincrby { key: Vec<u8>, field: Vec<u8>, increment: i64 },
+    Sadd { key: Vec<u8>, members: Vec<Vec<u8>> },
+    Srem { key: Vec<u8>, members: Vec<Vec<u8>> },
+    Smembers { key: Vec<u8> },
+    Sismember { key: Vec<u8>, member: Vec<u8> },
+    Smismember { key: Vec<u8>, members: Vec<Vec<u8>> },
+    Scard { key: Vec<u8> },
+    Spop { key: Vec<u8>, count: Option<usize> },
+    Srandmember { key: Vec<u8>, count: Option<i64> },
+    SetAlgebra { operation: SetOperation, keys: Vec<Vec<u8>> },
+    SetAlgebraStore { operation: SetOperation, destination: Vec<u8>, keys: Vec<Vec<u8>> },
+    Zadd { key: Vec<u8>, entries: Vec<(f64, Vec<u8>)>, conditions: SortedSetUpdateConditions, count_changed: bool, increment: bool },
+    Zincrby { key: Vec<u8>, increment: f64, member: Vec<u8> },
+    Zrem { key: Vec<u8>, members: Vec<Vec<u8>> },
+    Zcard { key: Vec<u8> },
+    Zscore { key: Vec<u8>, member: Vec<u8> },
+    Zrank { key: Vec<u8>, member: Vec<u8>, reverse: bool, with_score: bool },
+    Zcount { key: Vec<u8>, range: ScoreRange },
+    Zrange { key: Vec<u8>, range: SortedSetRange, reverse: bool, limit: Option<(i64, i64)>, with_scores: bool },
+    Bzpopmin { keys: Vec<Vec<u8>>, timeout: Option<Duration> },
+    Bzpopmax { keys: Vec<Vec<u8>>, timeout: Option<Duration> },
+    Xadd { key: Vec<u8>, id: NewStreamId, fields: StreamFields, trim: Option<StreamTrim>, no_create: bool },
+    Xrange { key: Vec<u8>, start: StreamId, end: StreamId, reverse: bool, count: Option<usize> },
+    Xlen { key: Vec<u8> },
+    Xtrim { key: Vec<u8>, trim: StreamTrim },
+    Xdel { key: Vec<u8>, ids: Vec<StreamId> },
+    XgroupCreate { key: Vec<u8>, group: Vec<u8>, start: GroupStartId, make_stream: bool },
+    XgroupSetid { key: Vec<u8>, group: Vec<u8>, start: GroupStartId },
+    XgroupDestroy { key: Vec<u8>, group: Vec<u8> },
+    XgroupCreateConsumer { key: Vec<u8>, group: Vec<u8>, consumer: Vec<u8>, seen_time: Option<u64> },
+    XgroupDelConsumer { key: Vec<u8>, group: Vec<u8>, consumer: Vec<u8> },
+    Xreadgroup { group: Vec<u8>, consumer: Vec<u8>, streams: Vec<(Vec<u8>, Option<StreamId>)>, count: Option<usize>, no_ack: bool },
+    Xack { key: Vec<u8>, group: Vec<u8>, ids: Vec<StreamId> },
+    Xpending { key: Vec<u8>, group: Vec<u8>, filter: Option<PendingFilter> },
+    Xclaim { key: Vec<u8>, group: Vec<u8>, consumer: Vec<u8>, min_idle: u64, ids: Vec<StreamId>, options: ClaimOptions },
+    Xautoclaim { key: Vec<u8>, group: Vec<u8>, consumer: Vec<u8>, min_idle: u64, start: StreamId, options: AutoClaimOptions },
 }
 
 impl RespCommand {
@@ -165,22 +432,57 @@ impl RespCommand {
                 if entries.is_empty() {
                     return Err("Wrong number of arguments for command".to_string());
                 }
-                let entries = entries.iter()
-                    .map(|e| if let BulkString(str) = e {
-                        Ok(str.to_owned())
+                let entries = entries.into_iter()
+                    .map(|e| if let BulkString(bytes) = e {
+                        Ok(bytes)
                     } else {
                         Err(String::from("Array should only contain BulkStrings"))
-                    }).collect::<Result<Vec<String>, String>>()?;
+                    }).collect::<Result<Vec<Vec<u8>>, String>>()?;
 
                 let mut arguments = entries.into_iter();
 
+                // command names are text, while the arguments that follow are binary-safe
                 let cmd_name =
                     arguments.next()
-                        .map(|str| str.to_lowercase())
+                        .map(|bytes| String::from_utf8_lossy(&bytes).to_lowercase())
                         .ok_or_else(|| "Wrong number of arguments for command".to_string())?;
 
                 match cmd_name.as_str() {
                     "ping" => Ok(RespCommand::Ping),
+                    // like Redis, QUIT does not mind whatever follows it
+                    "quit" => Ok(RespCommand::Quit),
+                    "reset" => {
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        Ok(RespCommand::Reset)
+                    }
+                    "hello" => {
+                        let protocol = match arguments.next() {
+                            Some(version) => match parse_number::<i64>(&version) {
+                                Some(2) => Some(ProtocolVersion::Resp2),
+                                Some(3) => Some(ProtocolVersion::Resp3),
+                                Some(_) => return Err("NOPROTO unsupported protocol version".to_string()),
+                                None => return Err("Protocol version is not an integer or out of range".to_string()),
+                            },
+                            None => None,
+                        };
+
+                        let mut auth = None;
+                        let mut client_name = None;
+                        while let Some(option) = arguments.next() {
+                            match option.to_ascii_lowercase().as_slice() {
+                                b"auth" => {
+                                    let username = next_argument(&mut arguments, &cmd_name)?;
+                                    let password = next_argument(&mut arguments, &cmd_name)?;
+                                    auth = Some((username, password));
+                                }
+                                b"setname" => client_name = Some(next_argument(&mut arguments, &cmd_name)?),
+                                _ => return Err(format!("Syntax error in HELLO option '{}'", String::from_utf8_lossy(&option))),
+                            }
+                        }
+
+                        Ok(RespCommand::Hello { protocol, auth, client_name })
+                    }
                     "echo" => {
                         let msg = arguments.next()
                             .ok_or_else(|| "Not enough arguments for 'echo'".to_owned())?;
@@ -203,6 +505,48 @@ impl RespCommand {
 
                         Ok(RespCommand::Get(GetCommand::from(key.to_owned())))
                     }
+                    "config" => {
+                        let subcommand = String::from_utf8_lossy(&next_argument(&mut arguments, &cmd_name)?).to_lowercase();
+
+                        match subcommand.as_str() {
+                            "get" => {
+                                let parameters: Vec<Vec<u8>> = arguments.collect();
+                                if parameters.is_empty() {
+                                    return Err("Wrong number of arguments for 'config|get' command".to_string());
+                                }
+                                Ok(RespCommand::ConfigGet { parameters })
+                            }
+                            "set" => {
+                                let mut parameters = vec![];
+                                while let Some(name) = arguments.next() {
+                                    let value = next_argument(&mut arguments, "config|set")?;
+                                    parameters.push((name, value));
+                                }
+                                if parameters.is_empty() {
+                                    return Err("Wrong number of arguments for 'config|set' command".to_string());
+                                }
+                                Ok(RespCommand::ConfigSet { parameters })
+                            }
+                            _ => Err(format!("unknown subcommand '{subcommand}' for 'config' command")),
+                        }
+                    }
+                    "info" => Ok(RespCommand::Info { sections: arguments.collect() }),
+                    "bgrewriteaof" => {
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+                        Ok(RespCommand::Bgrewriteaof)
+                    }
+                    "save" => {
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+                        Ok(RespCommand::Save)
+                    }
+                    "bgsave" => {
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+                        Ok(RespCommand::Bgsave)
+                    }
+                    "lastsave" => {
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+                        Ok(RespCommand::Lastsave)
+                    }
                     "set" => {
                         let key = arguments.next()
                             .ok_or_else(|| "Wrong number of arguments for command".to_owned())?;
@@ -210,29 +554,154 @@ impl RespCommand {
                         let value = arguments.next()
                             .ok_or_else(|| "Wrong number of arguments for command".to_owned())?;
 
-                        let mut expiry_seconds = None;
+                        let mut options = SetOptions::default();
+                        // which of EX/PX/EXAT/PXAT/KEEPTTL was given, as they exclude each other
+                        let mut expiry_option: Option<Vec<u8>> = None;
 
                         // the next arguments have no specific order
-
                         while let Some(param) = arguments.next() {
-                            match param.to_lowercase().as_str() {
-                                // set expiry in seconds
-                                "ex" => {
-                                    expiry_seconds = {
-                                        let ex_value =
-                                            arguments.next()
-                                            .ok_or_else(|| "Wrong number of arguments for command".to_owned())?
-                                            .parse::<u64>()
-                                            .or_else(|_| Err("value is not an integer or out of range".to_owned()))?;
-
-                                        Some(ex_value)
+                            let param = param.to_ascii_lowercase();
+                            match param.as_slice() {
+                                b"nx" if options.condition != SetCondition::IfExists => options.condition = SetCondition::IfNotExists,
+                                b"xx" if options.condition != SetCondition::IfNotExists => options.condition = SetCondition::IfExists,
+                                b"get" => options.get = true,
+                                b"keepttl" | b"ex" | b"px" | b"exat" | b"pxat" => {
+                                    // repeating the same option is fine though, the last one wins
+                                    if expiry_option.as_ref().is_some_and(|previous| *previous != param) {
+                                        return Err(SYNTAX_ERROR.to_string());
                                     }
+                                    options.expiry = if param == b"keepttl" {
+                                        SetExpiry::Keep
+                                    } else {
+                                        let amount = parse_integer(&arguments.next().ok_or_else(|| SYNTAX_ERROR.to_string())?)?;
+                                        parse_expiry(&cmd_name, &param, amount)?
+                                    };
+                                    expiry_option = Some(param);
                                 }
-                                _ => return Err("Wrong number of arguments for command".to_owned())
+                                _ => return Err(SYNTAX_ERROR.to_string()),
+                            }
+                        }
+
+                        Ok(RespCommand::Set(SetCommand::with_options((key.to_owned(), value.to_owned()), options)))
+                    }
+                    "append" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let value = next_argument(&mut arguments, &cmd_name)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        Ok(RespCommand::Append { key, value })
+                    }
+                    "strlen" | "getdel" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        if cmd_name == "strlen" {
+                            Ok(RespCommand::Strlen { key })
+                        } else {
+                            Ok(RespCommand::Getdel { key })
+                        }
+                    }
+                    "getrange" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let start = parse_integer(&next_argument(&mut arguments, &cmd_name)?)?;
+                        let end = parse_integer(&next_argument(&mut arguments, &cmd_name)?)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        Ok(RespCommand::Getrange { key, start, end })
+                    }
+                    "setrange" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let offset = parse_integer(&next_argument(&mut arguments, &cmd_name)?)?
+                            .try_into()
+                            .map_err(|_| "offset is out of range".to_string())?;
+                        let value = next_argument(&mut arguments, &cmd_name)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        Ok(RespCommand::Setrange { key, offset, value })
+                    }
+                    "getex" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+
+                        // no option leaves the expiry as it is, and only one option can be given
+                        let expiry = match arguments.next().map(|option| option.to_ascii_lowercase()) {
+                            None => SetExpiry::Keep,
+                            Some(option) if option == b"persist" => SetExpiry::Never,
+                            Some(option) if matches!(option.as_slice(), b"ex" | b"px" | b"exat" | b"pxat") => {
+                                let amount = parse_integer(&arguments.next().ok_or_else(|| SYNTAX_ERROR.to_string())?)?;
+                                parse_expiry(&cmd_name, &option, amount)?
                             }
+                            Some(_) => return Err(SYNTAX_ERROR.to_string()),
+                        };
+                        if arguments.next().is_some() {
+                            return Err(SYNTAX_ERROR.to_string());
+                        }
+
+                        Ok(RespCommand::Getex { key, expiry })
+                    }
+                    // GETSET, SETEX and PSETEX are shorthands for SET with options
+                    "getset" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let value = next_argument(&mut arguments, &cmd_name)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        let options = SetOptions { get: true, ..SetOptions::default() };
+                        Ok(RespCommand::Set(SetCommand::with_options((key, value), options)))
+                    }
+                    "setex" | "psetex" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let amount = parse_integer(&next_argument(&mut arguments, &cmd_name)?)?;
+                        let value = next_argument(&mut arguments, &cmd_name)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        let unit: &[u8] = if cmd_name == "setex" { b"ex" } else { b"px" };
+                        let options = SetOptions { expiry: parse_expiry(&cmd_name, unit, amount)?, ..SetOptions::default() };
+                        Ok(RespCommand::Set(SetCommand::with_options((key, value), options)))
+                    }
+                    "setnx" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let value = next_argument(&mut arguments, &cmd_name)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        Ok(RespCommand::Setnx { key, value })
+                    }
+                    "msetnx" => {
+                        let mut key_values = Vec::new();
+                        while let Some(key) = arguments.next() {
+                            key_values.push((key, next_argument(&mut arguments, &cmd_name)?));
                         }
+                        if key_values.is_empty() {
+                            return Err(wrong_number_of_arguments(&cmd_name));
+                        }
+
+                        Ok(RespCommand::Msetnx { key_values })
+                    }
+                    "incr" | "decr" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        let increment = if cmd_name == "incr" { 1 } else { -1 };
+                        Ok(RespCommand::Incrby { key, increment })
+                    }
+                    "incrby" | "decrby" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let amount = parse_integer(&next_argument(&mut arguments, &cmd_name)?)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        let increment = if cmd_name == "incrby" {
+                            amount
+                        } else {
+                            amount.checked_neg().ok_or_else(|| "value is not an integer or out of range".to_string())?
+                        };
+                        Ok(RespCommand::Incrby { key, increment })
+                    }
+                    "incrbyfloat" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let increment = parse_number::<f64>(&next_argument(&mut arguments, &cmd_name)?)
+                            .filter(|increment| !increment.is_nan())
+                            .ok_or_else(|| "value is not a valid float".to_string())?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
 
-                        Ok(RespCommand::Set(SetCommand::from((key.to_owned(), value.to_owned()), expiry_seconds)))
+                        Ok(RespCommand::Incrbyfloat { key, increment })
                     }
                     "ttl" => {
                         let key = arguments.next()
@@ -245,8 +714,219 @@ impl RespCommand {
 
                         Ok(RespCommand::Ttl { key: key.to_owned() })
                     }
+                    "pttl" | "expiretime" | "pexpiretime" | "persist" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        Ok(match cmd_name.as_str() {
+                            "pttl" => RespCommand::Pttl { key },
+                            "expiretime" => RespCommand::Expiretime { key },
+                            "pexpiretime" => RespCommand::Pexpiretime { key },
+                            _ => RespCommand::Persist { key },
+                        })
+                    }
+                    "keys" => {
+                        let pattern = next_argument(&mut arguments, &cmd_name)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        Ok(RespCommand::Keys { pattern })
+                    }
+                    "scan" => {
+                        let cursor = parse_number::<u64>(&next_argument(&mut arguments, &cmd_name)?)
+                            .ok_or_else(|| "invalid cursor".to_string())?;
+
+                        let mut pattern = None;
+                        let mut count = 10;
+                        let mut value_type = None;
+                        while let Some(option) = arguments.next() {
+                            match option.to_ascii_lowercase().as_slice() {
+                                b"match" => pattern = Some(next_argument(&mut arguments, &cmd_name)?),
+                                b"count" => {
+                                    count = parse_positive_integer(&next_argument(&mut arguments, &cmd_name)?)?;
+                                    if count == 0 {
+                                        return Err(SYNTAX_ERROR.to_string());
+                                    }
+                                }
+                                b"type" => value_type = Some(next_argument(&mut arguments, &cmd_name)?),
+                                _ => return Err(SYNTAX_ERROR.to_string()),
+                            }
+                        }
+
+                        Ok(RespCommand::Scan { cursor, pattern, count, value_type })
+                    }
+                    "rename" | "renamenx" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let new_key = next_argument(&mut arguments, &cmd_name)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        Ok(RespCommand::Rename { key, new_key, only_if_new: cmd_name == "renamenx" })
+                    }
+                    "copy" => {
+                        let source = next_argument(&mut arguments, &cmd_name)?;
+                        let destination = next_argument(&mut arguments, &cmd_name)?;
+
+                        let mut destination_db = None;
+                        let mut replace = false;
+                        while let Some(option) = arguments.next() {
+                            match option.to_ascii_lowercase().as_slice() {
+                                b"replace" => replace = true,
+                                b"db" => destination_db = Some(parse_db_index(&next_argument(&mut arguments, &cmd_name)?)?),
+                                _ => return Err(SYNTAX_ERROR.to_string()),
+                            }
+                        }
+
+                        Ok(RespCommand::Copy { source, destination, destination_db, replace })
+                    }
+                    "touch" => {
+                        let keys: Vec<Vec<u8>> = arguments.collect();
+                        if keys.is_empty() {
+                            return Err(wrong_number_of_arguments(&cmd_name));
+                        }
+
+                        Ok(RespCommand::Touch { keys })
+                    }
+                    "type" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        Ok(RespCommand::Type { key })
+                    }
+                    "object" => {
+                        let subcommand = String::from_utf8_lossy(&next_argument(&mut arguments, &cmd_name)?).to_lowercase();
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        match subcommand.as_str() {
+                            "encoding" => Ok(RespCommand::ObjectEncoding { key }),
+                            "idletime" => Ok(RespCommand::ObjectIdletime { key }),
+                            "freq" => Ok(RespCommand::ObjectFreq { key }),
+                            "refcount" => Ok(RespCommand::ObjectRefcount { key }),
+                            _ => Err(format!("unknown subcommand '{subcommand}' for 'object' command")),
+                        }
+                    }
+                    "randomkey" | "dbsize" => {
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        Ok(if cmd_name == "randomkey" { RespCommand::Randomkey } else { RespCommand::Dbsize })
+                    }
+                    "multi" | "exec" | "discard" | "unwatch" => {
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        Ok(match cmd_name.as_str() {
+                            "multi" => RespCommand::Multi,
+                            "exec" => RespCommand::Exec,
+                            "discard" => RespCommand::Discard,
+                            _ => RespCommand::Unwatch,
+                        })
+                    }
+                    "subscribe" | "psubscribe" => {
+                        let names: Vec<Vec<u8>> = arguments.collect();
+                        if names.is_empty() {
+                            return Err(wrong_number_of_arguments(&cmd_name));
+                        }
+
+                        Ok(if cmd_name == "subscribe" { RespCommand::Subscribe { channels: names } } else { RespCommand::Psubscribe { patterns: names } })
+                    }
+                    // without arguments, these unsubscribe from everything
+                    "unsubscribe" => Ok(RespCommand::Unsubscribe { channels: arguments.collect() }),
+                    "punsubscribe" => Ok(RespCommand::Punsubscribe { patterns: arguments.collect() }),
+                    "publish" => {
+                        let channel = next_argument(&mut arguments, &cmd_name)?;
+                        let message = next_argument(&mut arguments, &cmd_name)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        Ok(RespCommand::Publish { channel, message })
+                    }
+                    "pubsub" => {
+                        let subcommand = String::from_utf8_lossy(&next_argument(&mut arguments, &cmd_name)?).to_lowercase();
+
+                        match subcommand.as_str() {
+                            "channels" => {
+                                let pattern = arguments.next();
+                                no_more_arguments(&mut arguments, &cmd_name)?;
+                                Ok(RespCommand::PubsubChannels { pattern })
+                            }
+                            "numsub" => Ok(RespCommand::PubsubNumsub { channels: arguments.collect() }),
+                            "numpat" => {
+                                no_more_arguments(&mut arguments, &cmd_name)?;
+                                Ok(RespCommand::PubsubNumpat)
+                            }
+                            _ => Err(format!("unknown subcommand '{subcommand}' for 'pubsub' command")),
+                        }
+                    }
+                    "watch" => {
+                        let keys: Vec<Vec<u8>> = arguments.collect();
+                        if keys.is_empty() {
+                            return Err(wrong_number_of_arguments(&cmd_name));
+                        }
+
+                        Ok(RespCommand::Watch { keys })
+                    }
+                    "select" => {
+                        let db = parse_db_index(&next_argument(&mut arguments, &cmd_name)?)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        Ok(RespCommand::Select { db })
+                    }
+                    "swapdb" => {
+                        let first = parse_db_index(&next_argument(&mut arguments, &cmd_name)?)?;
+                        let second = parse_db_index(&next_argument(&mut arguments, &cmd_name)?)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        Ok(RespCommand::Swapdb { first, second })
+                    }
+                    "move" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let db = parse_db_index(&next_argument(&mut arguments, &cmd_name)?)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        Ok(RespCommand::Move { key, db })
+                    }
+                    "flushdb" | "flushall" => {
+                        let asynchronously = match arguments.next() {
+                            None => false,
+                            Some(mode) => match mode.to_ascii_lowercase().as_slice() {
+                                b"async" => true,
+                                b"sync" => false,
+                                _ => return Err(SYNTAX_ERROR.to_string()),
+                            },
+                        };
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        Ok(if cmd_name == "flushdb" { RespCommand::Flushdb { asynchronously } } else { RespCommand::Flushall { asynchronously } })
+                    }
+                    "expire" | "pexpire" | "expireat" | "pexpireat" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let amount = parse_integer(&next_argument(&mut arguments, &cmd_name)?)?;
+
+                        let mut conditions = ExpireConditions::default();
+                        for option in arguments {
+                            match option.to_ascii_lowercase().as_slice() {
+                                b"nx" => conditions.only_without_expiry = true,
+                                b"xx" => conditions.only_with_expiry = true,
+                                b"gt" => conditions.only_greater = true,
+                                b"lt" => conditions.only_lower = true,
+                                _ => return Err(format!("Unsupported option {}", String::from_utf8_lossy(&option))),
+                            }
+                        }
+                        if conditions.only_without_expiry && (conditions.only_with_expiry || conditions.only_greater || conditions.only_lower) {
+                            return Err("NX and XX, GT or LT options at the same time are not compatible".to_string());
+                        }
+                        if conditions.only_greater && conditions.only_lower {
+                            return Err("GT and LT options at the same time are not compatible".to_string());
+                        }
+
+                        let millis = if cmd_name.starts_with('p') {
+                            amount
+                        } else {
+                            amount.checked_mul(1000)
+                                .ok_or_else(|| format!("invalid expire time in '{cmd_name}' command"))?
+                        };
+                        let at = if cmd_name.ends_with("at") { ExpireAt::UnixTimeMillis(millis) } else { ExpireAt::AfterMillis(millis) };
+                        Ok(RespCommand::Expire { key, at, conditions })
+                    }
                     "mset" => {
-                        let mut key_values: Vec<(String, String)> = vec![];
+                        let mut key_values: Vec<(Vec<u8>, Vec<u8>)> = vec![];
 
                         while let Some(key) = arguments.next() {
                             let value = arguments.next()
@@ -262,11 +942,7 @@ impl RespCommand {
                         Ok(RespCommand::Mset(MsetCommand::from_key_values(key_values)))
                     }
                     "mget" => {
-                        let mut keys: Vec<String> = vec![];
-
-                        while let Some(key) = arguments.next() {
-                            keys.push(key.to_owned());
-                        }
+                        let keys: Vec<Vec<u8>> = arguments.collect();
 
                         if keys.is_empty() {
                             return Err("Wrong number of arguments for 'mget' command".to_string());
@@ -274,25 +950,18 @@ impl RespCommand {
 
                         Ok(RespCommand::Mget(MgetCommand::from_keys(keys)))
                     }
-                    "del" => {
-                        let mut keys: Vec<String> = vec![];
-
-                        while let Some(key) = arguments.next() {
-                            keys.push(key.to_owned());
-                        }
+                    // keys are freed right away, so UNLINK is no different from DEL
+                    "del" | "unlink" => {
+                        let keys: Vec<Vec<u8>> = arguments.collect();
 
                         if keys.is_empty() {
-                            return Err("Wrong number of arguments for 'del' command".to_string());
+                            return Err(wrong_number_of_arguments(&cmd_name));
                         }
 
                         Ok(RespCommand::Del(DelCommand::from_keys(keys)))
                     }
                     "exists" => {
-                        let mut keys: Vec<String> = vec![];
-
-                        while let Some(key) = arguments.next() {
-                            keys.push(key.to_owned());
-                        }
+                        let keys: Vec<Vec<u8>> = arguments.collect();
 
                         if keys.is_empty() {
                             return Err("Wrong number of arguments for 'exists' command".to_string());
@@ -300,349 +969,4387 @@ impl RespCommand {
 
                         Ok(RespCommand::Exists(ExistsCommand::from_keys(keys)))
                     }
-                    _ => Err(format!("unknown command '{cmd_name}'")),
-                }
-            },
-            _ => Err("An Array of BulkStrings is expected".to_string()),
-        }
-    }
+                    "lpush" | "rpush" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let values: Vec<Vec<u8>> = arguments.collect();
 
-    pub fn execute_on(&self, engine: &mut StorageEngine) -> RespObject {
-        match self {
-            RespCommand::Ping => SimpleString("PONG".to_string()),
-            RespCommand::Echo { message} => SimpleString(message.clone()),
-            RespCommand::Get(cmd) => {
-                match cmd.execute_on(engine) {
-                    Ok(Some(value)) => BulkString(value.clone()),
-                    Ok(None) => NullBulkString,
-                    Err(e) => Error(e.to_string()),
-                }
-            },
-            RespCommand::Set(cmd) => {
-                match cmd.execute_on(engine) {
-                    Ok(_) => SimpleString("OK".to_string()),
-                    Err(e) => Error(e.to_string()),
-                }
-            },
-            RespCommand::Ttl { key } => {
-                match engine.time_to_live(key) {
-                    TimeToLive::KeyDoesNotExist => Integer(-2),
-                    TimeToLive::DoesNotExpire => Integer(-1),
-                    TimeToLive::ExpiresInSeconds(seconds) => Integer(seconds as i64)
-                }
-            },
-            RespCommand::Mset(cmd) => {
-                match cmd.execute_on(engine) {
-                    Ok(_) => SimpleString("OK".to_string()),
-                    Err(e) => Error(e.to_string()),
-                }
-            },
-            RespCommand::Mget(cmd) => {
-                let mget_results =
-                    cmd.execute_on(engine)
-                        .iter()
-                        .map(|option| {
-                            match option {
-                                Some(value) => BulkString(value.clone()),
-                                None => NullBulkString,
-                            }
-                        })
-                        .collect();
+                        if values.is_empty() {
+                            return Err(wrong_number_of_arguments(&cmd_name));
+                        }
 
-                Array(mget_results)
-            },
-            RespCommand::Del(cmd) => {
-                let deleted_count = cmd.execute_on(engine);
-                Integer(deleted_count as i64)
-            }
-            RespCommand::Exists(cmd) => {
-                let exists_count = cmd.execute_on(engine);
-                Integer(exists_count as i64)
-            }
-        }
-    }
-}
+                        if cmd_name == "lpush" {
+                            Ok(RespCommand::Lpush { key, values })
+                        } else {
+                            Ok(RespCommand::Rpush { key, values })
+                        }
+                    }
+                    "lpop" | "rpop" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let count = arguments.next()
+                            .map(|count| parse_positive_integer(&count))
+                            .transpose()?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
 
-#[cfg(test)]
-mod command_creation_tests {
-    use super::*;
-    use crate::protocol::RespObject::{Error, Integer, NullArray, NullBulkString, SimpleString};
+                        if cmd_name == "lpop" {
+                            Ok(RespCommand::Lpop { key, count })
+                        } else {
+                            Ok(RespCommand::Rpop { key, count })
+                        }
+                    }
+                    "llen" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
 
-    #[test]
-    fn create_ping_command() {
-        let cmd = Command::from(Array(vec![BulkString("ping".to_owned())]));
-        assert_eq!(cmd, Ok(Command(RespCommand::Ping)));
-    }
+                        Ok(RespCommand::Llen { key })
+                    }
+                    "lrange" | "ltrim" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let start = parse_integer(&next_argument(&mut arguments, &cmd_name)?)?;
+                        let stop = parse_integer(&next_argument(&mut arguments, &cmd_name)?)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
 
-    #[test]
-    fn create_ping_command_from_uppercase() {
-        let cmd = Command::from(Array(vec![BulkString("PING".to_owned())]));
-        assert_eq!(cmd, Ok(Command(RespCommand::Ping)));
-    }
+                        if cmd_name == "lrange" {
+                            Ok(RespCommand::Lrange { key, start, stop })
+                        } else {
+                            Ok(RespCommand::Ltrim { key, start, stop })
+                        }
+                    }
+                    "lindex" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let index = parse_integer(&next_argument(&mut arguments, &cmd_name)?)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
 
-    #[test]
-    fn create_ping_command_from_mixed_case() {
-        let cmd = Command::from(Array(vec![BulkString("PinG".to_owned())]));
-        assert_eq!(cmd, Ok(Command(RespCommand::Ping)));
-    }
+                        Ok(RespCommand::Lindex { key, index })
+                    }
+                    "lset" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let index = parse_integer(&next_argument(&mut arguments, &cmd_name)?)?;
+                        let value = next_argument(&mut arguments, &cmd_name)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
 
-    #[test]
-    fn create_echo_command() {
-        let cmd = Command::from(Array(vec![BulkString("echo".to_owned()), BulkString("\"Hello, world!\"".to_owned())]));
-        assert_eq!(cmd, Ok(Command(RespCommand::Echo { message: String::from("\"Hello, world!\"") })));
-    }
+                        Ok(RespCommand::Lset { key, index, value })
+                    }
+                    "lrem" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let count = parse_integer(&next_argument(&mut arguments, &cmd_name)?)?;
+                        let value = next_argument(&mut arguments, &cmd_name)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
 
-    #[test]
-    fn create_plain_set_command() {
-        let cmd = Command::from(Array(vec![BulkString("set".to_owned()), BulkString("Name".to_owned()), BulkString("Doe".to_owned())]));
-        assert_eq!(cmd, Ok(Command(RespCommand::Set(SetCommand::from_key_value((String::from("Name"), String::from("Doe")))))));
-    }
+                        Ok(RespCommand::Lrem { key, count, value })
+                    }
+                    "linsert" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let position = match next_argument(&mut arguments, &cmd_name)?.to_ascii_lowercase().as_slice() {
+                            b"before" => InsertPosition::Before,
+                            b"after" => InsertPosition::After,
+                            _ => return Err(SYNTAX_ERROR.to_string()),
+                        };
+                        let pivot = next_argument(&mut arguments, &cmd_name)?;
+                        let value = next_argument(&mut arguments, &cmd_name)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
 
-    #[test]
+                        Ok(RespCommand::Linsert { key, position, pivot, value })
+                    }
+                    "blpop" | "brpop" | "bzpopmin" | "bzpopmax" => {
+                        let mut keys: Vec<Vec<u8>> = arguments.collect();
+                        let timeout = match keys.pop() {
+                            Some(timeout) if !keys.is_empty() => parse_timeout(&timeout)?,
+                            _ => return Err(wrong_number_of_arguments(&cmd_name)),
+                        };
+
+                        match cmd_name.as_str() {
+                            "blpop" => Ok(RespCommand::Blpop { keys, timeout }),
+                            "brpop" => Ok(RespCommand::Brpop { keys, timeout }),
+                            "bzpopmin" => Ok(RespCommand::Bzpopmin { keys, timeout }),
+                            _ => Ok(RespCommand::Bzpopmax { keys, timeout }),
+                        }
+                    }
+                    "blmove" => {
+                        let source = next_argument(&mut arguments, &cmd_name)?;
+                        let destination = next_argument(&mut arguments, &cmd_name)?;
+                        let from = parse_list_end(&next_argument(&mut arguments, &cmd_name)?)?;
+                        let to = parse_list_end(&next_argument(&mut arguments, &cmd_name)?)?;
+                        let timeout = parse_timeout(&next_argument(&mut arguments, &cmd_name)?)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        Ok(RespCommand::Blmove { source, destination, from, to, timeout })
+                    }
+                    "blmpop" => {
+                        let timeout = parse_timeout(&next_argument(&mut arguments, &cmd_name)?)?;
+                        let key_count = parse_integer(&next_argument(&mut arguments, &cmd_name)?)?;
+                        if key_count <= 0 {
+                            return Err("numkeys should be greater than 0".to_string());
+                        }
+                        let keys = (0..key_count)
+                            .map(|_| next_argument(&mut arguments, &cmd_name))
+                            .collect::<Result<Vec<_>, _>>()?;
+                        let end = parse_list_end(&next_argument(&mut arguments, &cmd_name)?)?;
+                        let count = match arguments.next() {
+                            None => 1,
+                            Some(option) if option.eq_ignore_ascii_case(b"count") => {
+                                let count = parse_integer(&next_argument(&mut arguments, &cmd_name)?)?;
+                                usize::try_from(count)
+                                    .ok()
+                                    .filter(|count| *count > 0)
+                                    .ok_or_else(|| "count should be greater than 0".to_string())?
+                            }
+                            Some(_) => return Err(SYNTAX_ERROR.to_string()),
+                        };
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        Ok(RespCommand::Blmpop { keys, end, count, timeout })
+                    }
+                    "hset" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+
+                        let mut field_values: Vec<(Vec<u8>, Vec<u8>)> = vec![];
+                        while let Some(field) = arguments.next() {
+                            let value = next_argument(&mut arguments, &cmd_name)?;
+                            field_values.push((field, value));
+                        }
+
+                        if field_values.is_empty() {
+                            return Err(wrong_number_of_arguments(&cmd_name));
+                        }
+
+                        Ok(RespCommand::Hset { key, field_values })
+                    }
+                    "hsetnx" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let field = next_argument(&mut arguments, &cmd_name)?;
+                        let value = next_argument(&mut arguments, &cmd_name)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        Ok(RespCommand::Hsetnx { key, field, value })
+                    }
+                    "hget" | "hexists" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let field = next_argument(&mut arguments, &cmd_name)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        if cmd_name == "hget" {
+                            Ok(RespCommand::Hget { key, field })
+                        } else {
+                            Ok(RespCommand::Hexists { key, field })
+                        }
+                    }
+                    "hmget" | "hdel" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let fields: Vec<Vec<u8>> = arguments.collect();
+
+                        if fields.is_empty() {
+                            return Err(wrong_number_of_arguments(&cmd_name));
+                        }
+
+                        if cmd_name == "hmget" {
+                            Ok(RespCommand::Hmget { key, fields })
+                        } else {
+                            Ok(RespCommand::Hdel { key, fields })
+                        }
+                    }
+                    "hgetall" | "hkeys" | "hvals" | "hlen" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        match cmd_name.as_str() {
+                            "hgetall" => Ok(RespCommand::Hgetall { key }),
+                            "hkeys" => Ok(RespCommand::Hkeys { key }),
+                            "hvals" => Ok(RespCommand::Hvals { key }),
+                            _ => Ok(RespCommand::Hlen { key }),
+                        }
+                    }
+                    "hincrby" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let field = next_argument(&mut arguments, &cmd_name)?;
+                        let increment = parse_integer(&next_argument(&mut arguments, &cmd_name)?)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        Ok(RespCommand::Hincrby { key, field, increment })
+                    }
+                    "sadd" | "srem" | "smismember" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let members: Vec<Vec<u8>> = arguments.collect();
+
+                        if members.is_empty() {
+                            return Err(wrong_number_of_arguments(&cmd_name));
+                        }
+
+                        match cmd_name.as_str() {
+                            "sadd" => Ok(RespCommand::Sadd { key, members }),
+                            "srem" => Ok(RespCommand::Srem { key, members }),
+                            _ => Ok(RespCommand::Smismember { key, members }),
+                        }
+                    }
+                    "smembers" | "scard" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        if cmd_name == "smembers" {
+                            Ok(RespCommand::Smembers { key })
+                        } else {
+                            Ok(RespCommand::Scard { key })
+                        }
+                    }
+                    "sismember" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let member = next_argument(&mut arguments, &cmd_name)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        Ok(RespCommand::Sismember { key, member })
+                    }
+                    "spop" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let count = arguments.next()
+                            .map(|count| parse_positive_integer(&count))
+                            .transpose()?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        Ok(RespCommand::Spop { key, count })
+                    }
+                    "srandmember" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let count = arguments.next()
+                            .map(|count| parse_integer(&count))
+                            .transpose()?;
+                        // refuses counts it could never reply with, as a negative one is served by repeating members
+                        if count.is_some_and(|count| count.unsigned_abs() > MAX_ARRAY_LENGTH as u64) {
+                            return Err("value is out of range".to_string());
+                        }
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        Ok(RespCommand::Srandmember { key, count })
+                    }
+                    "sinter" | "sunion" | "sdiff" => {
+                        let keys: Vec<Vec<u8>> = arguments.collect();
+
+                        if keys.is_empty() {
+                            return Err(wrong_number_of_arguments(&cmd_name));
+                        }
+
+                        let operation = set_operation(&cmd_name[1..]);
+                        Ok(RespCommand::SetAlgebra { operation, keys })
+                    }
+                    "sinterstore" | "sunionstore" | "sdiffstore" => {
+                        let destination = next_argument(&mut arguments, &cmd_name)?;
+                        let keys: Vec<Vec<u8>> = arguments.collect();
+
+                        if keys.is_empty() {
+                            return Err(wrong_number_of_arguments(&cmd_name));
+                        }
+
+                        let operation = set_operation(&cmd_name[1..cmd_name.len() - "store".len()]);
+                        Ok(RespCommand::SetAlgebraStore { operation, destination, keys })
+                    }
+                    "zadd" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let mut arguments = arguments.peekable();
+
+                        let mut conditions = SortedSetUpdateConditions::default();
+                        let mut count_changed = false;
+                        let mut increment = false;
+
+                        // options come before the score/member pairs, and no option is a valid score
+                        while let Some(option) = arguments.next_if(|arg| parse_score(arg).is_err()) {
+                            match option.to_ascii_lowercase().as_slice() {
+                                b"nx" => conditions.only_new = true,
+                                b"xx" => conditions.only_existing = true,
+                                b"gt" => conditions.only_greater = true,
+                                b"lt" => conditions.only_lower = true,
+                                b"ch" => count_changed = true,
+                                b"incr" => increment = true,
+                                _ => return Err(SYNTAX_ERROR.to_string()),
+                            }
+                        }
+
+                        let mut entries: Vec<(f64, Vec<u8>)> = vec![];
+                        while let Some(score) = arguments.next() {
+                            let score = parse_score(&score)?;
+                            let member = next_argument(&mut arguments, &cmd_name)?;
+                            entries.push((score, member));
+                        }
+
+                        if entries.is_empty() {
+                            return Err(wrong_number_of_arguments(&cmd_name));
+                        }
+                        if conditions.only_new && conditions.only_existing {
+                            return Err("XX and NX options at the same time are not compatible".to_string());
+                        }
+                        let exclusive_conditions = [conditions.only_new, conditions.only_greater, conditions.only_lower];
+                        if exclusive_conditions.iter().filter(|it| **it).count() > 1 {
+                            return Err("GT, LT, and/or NX options at the same time are not compatible".to_string());
+                        }
+                        if increment && entries.len() > 1 {
+                            return Err("INCR option supports a single increment-element pair".to_string());
+                        }
+
+                        Ok(RespCommand::Zadd { key, entries, conditions, count_changed, increment })
+                    }
+                    "zincrby" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let increment = parse_score(&next_argument(&mut arguments, &cmd_name)?)?;
+                        let member = next_argument(&mut arguments, &cmd_name)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        Ok(RespCommand::Zincrby { key, increment, member })
+                    }
+                    "zrem" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let members: Vec<Vec<u8>> = arguments.collect();
+
+                        if members.is_empty() {
+                            return Err(wrong_number_of_arguments(&cmd_name));
+                        }
+
+                        Ok(RespCommand::Zrem { key, members })
+                    }
+                    "zcard" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        Ok(RespCommand::Zcard { key })
+                    }
+                    "zscore" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let member = next_argument(&mut arguments, &cmd_name)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        Ok(RespCommand::Zscore { key, member })
+                    }
+                    "zrank" | "zrevrank" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let member = next_argument(&mut arguments, &cmd_name)?;
+                        let with_score = match arguments.next() {
+                            None => false,
+                            Some(option) if option.eq_ignore_ascii_case(b"withscore") => true,
+                            Some(_) => return Err(SYNTAX_ERROR.to_string()),
+                        };
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        Ok(RespCommand::Zrank { key, member, reverse: cmd_name == "zrevrank", with_score })
+                    }
+                    "zcount" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let min = next_argument(&mut arguments, &cmd_name)?;
+                        let max = next_argument(&mut arguments, &cmd_name)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        Ok(RespCommand::Zcount { key, range: parse_score_range(&min, &max)? })
+                    }
+                    "zrange" | "zrangebyscore" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let start = next_argument(&mut arguments, &cmd_name)?;
+                        let stop = next_argument(&mut arguments, &cmd_name)?;
+
+                        let mut by_score = cmd_name == "zrangebyscore";
+                        let mut by_lex = false;
+                        let mut reverse = false;
+                        let mut limit = None;
+                        let mut with_scores = false;
+
+                        while let Some(option) = arguments.next() {
+                            match option.to_ascii_lowercase().as_slice() {
+                                b"byscore" if cmd_name == "zrange" => by_score = true,
+                                b"bylex" if cmd_name == "zrange" => by_lex = true,
+                                b"rev" if cmd_name == "zrange" => reverse = true,
+                                b"withscores" => with_scores = true,
+                                b"limit" => {
+                                    let offset = parse_integer(&next_argument(&mut arguments, &cmd_name)?)?;
+                                    let count = parse_integer(&next_argument(&mut arguments, &cmd_name)?)?;
+                                    limit = Some((offset, count));
+                                }
+                                _ => return Err(SYNTAX_ERROR.to_string()),
+                            }
+                        }
+
+                        if by_score && by_lex {
+                            return Err(SYNTAX_ERROR.to_string());
+                        }
+                        if limit.is_some() && !by_score && !by_lex {
+                            return Err("syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX".to_string());
+                        }
+                        if with_scores && by_lex {
+                            return Err("syntax error, WITHSCORES not supported in combination with BYLEX".to_string());
+                        }
+
+                        // when reversed, the range is given from the highest to the lowest end
+                        let (min, max) = if reverse { (stop, start) } else { (start, stop) };
+                        let range = if by_score {
+                            SortedSetRange::ByScore(parse_score_range(&min, &max)?)
+                        } else if by_lex {
+                            SortedSetRange::ByLex(LexRange { min: parse_lex_bound(&min)?, max: parse_lex_bound(&max)? })
+                        } else {
+                            let (start, stop) = if reverse { (max, min) } else { (min, max) };
+                            SortedSetRange::ByRank { start: parse_integer(&start)?, stop: parse_integer(&stop)? }
+                        };
+
+                        Ok(RespCommand::Zrange { key, range, reverse, limit, with_scores })
+                    }
+                    "xadd" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+
+                        let mut no_create = false;
+                        let mut trim = TrimOptions::default();
+                        // options come before the ID, which is then followed by the field-value pairs
+                        let id = loop {
+                            let argument = next_argument(&mut arguments, &cmd_name)?;
+                            if argument.eq_ignore_ascii_case(b"nomkstream") {
+                                no_create = true;
+                            } else if !trim.parse(&argument, &mut arguments, &cmd_name)? {
+                                break parse_new_stream_id(&argument)?;
+                            }
+                        };
+
+                        let mut fields = vec![];
+                        while let Some(field) = arguments.next() {
+                            let value = next_argument(&mut arguments, &cmd_name)?;
+                            fields.push((field, value));
+                        }
+                        if fields.is_empty() {
+                            return Err(wrong_number_of_arguments(&cmd_name));
+                        }
+
+                        Ok(RespCommand::Xadd { key, id, fields, trim: trim.build()?, no_create })
+                    }
+                    "xrange" | "xrevrange" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let reverse = cmd_name == "xrevrange";
+                        let first = next_argument(&mut arguments, &cmd_name)?;
+                        let second = next_argument(&mut arguments, &cmd_name)?;
+                        // XREVRANGE takes the end of the range first
+                        let (start, end) = if reverse { (second, first) } else { (first, second) };
+
+                        let count = match arguments.next() {
+                            None => None,
+                            Some(option) if option.eq_ignore_ascii_case(b"count") => {
+                                Some(parse_positive_integer(&next_argument(&mut arguments, &cmd_name)?)?)
+                            }
+                            Some(_) => return Err(SYNTAX_ERROR.to_string()),
+                        };
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        Ok(RespCommand::Xrange {
+                            key,
+                            start: parse_stream_range_bound(&start, false)?,
+                            end: parse_stream_range_bound(&end, true)?,
+                            reverse,
+                            count,
+                        })
+                    }
+                    "xlen" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        Ok(RespCommand::Xlen { key })
+                    }
+                    "xtrim" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+
+                        let mut trim = TrimOptions::default();
+                        while let Some(option) = arguments.next() {
+                            if !trim.parse(&option, &mut arguments, &cmd_name)? {
+                                return Err(SYNTAX_ERROR.to_string());
+                            }
+                        }
+
+                        match trim.build()? {
+                            Some(trim) => Ok(RespCommand::Xtrim { key, trim }),
+                            None => Err(SYNTAX_ERROR.to_string()),
+                        }
+                    }
+                    "xdel" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let ids = arguments.map(|id| parse_stream_id(&id, 0))
+                            .collect::<Result<Vec<StreamId>, String>>()?;
+
+                        if ids.is_empty() {
+                            return Err(wrong_number_of_arguments(&cmd_name));
+                        }
+
+                        Ok(RespCommand::Xdel { key, ids })
+                    }
+                    "xgroup" => {
+                        let subcommand = String::from_utf8_lossy(&next_argument(&mut arguments, &cmd_name)?).to_lowercase();
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let group = next_argument(&mut arguments, &cmd_name)?;
+
+                        let command = match subcommand.as_str() {
+                            "create" => {
+                                let start = parse_group_start_id(&next_argument(&mut arguments, &cmd_name)?)?;
+                                let make_stream = match arguments.next() {
+                                    None => false,
+                                    Some(option) if option.eq_ignore_ascii_case(b"mkstream") => true,
+                                    Some(_) => return Err(SYNTAX_ERROR.to_string()),
+                                };
+                                RespCommand::XgroupCreate { key, group, start, make_stream }
+                            }
+                            "setid" => {
+                                let start = parse_group_start_id(&next_argument(&mut arguments, &cmd_name)?)?;
+                                RespCommand::XgroupSetid { key, group, start }
+                            }
+                            "destroy" => RespCommand::XgroupDestroy { key, group },
+                            "createconsumer" => {
+                                let consumer = next_argument(&mut arguments, &cmd_name)?;
+                                // not in Redis: a rewritten append only file restores when each consumer was last seen
+                                let seen_time = match arguments.next() {
+                                    None => None,
+                                    Some(option) if option.eq_ignore_ascii_case(b"seentime") =>
+                                        Some(parse_positive_integer(&next_argument(&mut arguments, &cmd_name)?)? as u64),
+                                    Some(_) => return Err(SYNTAX_ERROR.to_string()),
+                                };
+                                RespCommand::XgroupCreateConsumer { key, group, consumer, seen_time }
+                            }
+                            "delconsumer" => {
+                                let consumer = next_argument(&mut arguments, &cmd_name)?;
+                                RespCommand::XgroupDelConsumer { key, group, consumer }
+                            }
+                            _ => return Err(format!("unknown subcommand '{subcommand}' for 'xgroup' command")),
+                        };
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        Ok(command)
+                    }
+                    "xreadgroup" => {
+                        if !next_argument(&mut arguments, &cmd_name)?.eq_ignore_ascii_case(b"group") {
+                            return Err(SYNTAX_ERROR.to_string());
+                        }
+                        let group = next_argument(&mut arguments, &cmd_name)?;
+                        let consumer = next_argument(&mut arguments, &cmd_name)?;
+
+                        let mut count = None;
+                        let mut no_ack = false;
+                        loop {
+                            let option = next_argument(&mut arguments, &cmd_name)?;
+                            match option.to_ascii_lowercase().as_slice() {
+                                b"count" => count = Some(parse_positive_integer(&next_argument(&mut arguments, &cmd_name)?)?),
+                                // blocking reads are not supported (yet): BLOCK behaves as if the timeout was reached at once
+                                b"block" => { parse_positive_integer(&next_argument(&mut arguments, &cmd_name)?)?; }
+                                b"noack" => no_ack = true,
+                                b"streams" => break,
+                                _ => return Err(SYNTAX_ERROR.to_string()),
+                            }
+                        }
+
+                        // the keys come first, and then one ID per key
+                        let mut keys: Vec<Vec<u8>> = arguments.collect();
+                        if keys.is_empty() || !keys.len().is_multiple_of(2) {
+                            return Err("Unbalanced 'xreadgroup' list of streams: for each stream key an ID or '>' must be specified.".to_string());
+                        }
+                        let ids = keys.split_off(keys.len() / 2);
+                        let streams = keys.into_iter()
+                            .zip(ids)
+                            .map(|(key, id)| match id.as_slice() {
+                                b">" => Ok((key, None)),
+                                _ => parse_stream_id(&id, 0).map(|id| (key, Some(id))),
+                            })
+                            .collect::<Result<Vec<(Vec<u8>, Option<StreamId>)>, String>>()?;
+
+                        Ok(RespCommand::Xreadgroup { group, consumer, streams, count, no_ack })
+                    }
+                    "xack" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let group = next_argument(&mut arguments, &cmd_name)?;
+                        let ids = arguments.map(|id| parse_stream_id(&id, 0))
+                            .collect::<Result<Vec<StreamId>, String>>()?;
+
+                        if ids.is_empty() {
+                            return Err(wrong_number_of_arguments(&cmd_name));
+                        }
+
+                        Ok(RespCommand::Xack { key, group, ids })
+                    }
+                    "xpending" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let group = next_argument(&mut arguments, &cmd_name)?;
+
+                        // without a range, XPENDING replies with a summary
+                        let Some(mut start) = arguments.next() else {
+                            return Ok(RespCommand::Xpending { key, group, filter: None });
+                        };
+                        let mut min_idle = None;
+                        if start.eq_ignore_ascii_case(b"idle") {
+                            min_idle = Some(parse_positive_integer(&next_argument(&mut arguments, &cmd_name)?)? as u64);
+                            start = next_argument(&mut arguments, &cmd_name)?;
+                        }
+                        let end = next_argument(&mut arguments, &cmd_name)?;
+                        // a negative count selects nothing
+                        let count = parse_integer(&next_argument(&mut arguments, &cmd_name)?)?.max(0) as usize;
+                        let consumer = arguments.next();
+                        no_more_arguments(&mut arguments, &cmd_name)?;
+
+                        let filter = PendingFilter {
+                            min_idle,
+                            start: parse_stream_range_bound(&start, false)?,
+                            end: parse_stream_range_bound(&end, true)?,
+                            count,
+                            consumer,
+                        };
+                        Ok(RespCommand::Xpending { key, group, filter: Some(filter) })
+                    }
+                    "xclaim" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let group = next_argument(&mut arguments, &cmd_name)?;
+                        let consumer = next_argument(&mut arguments, &cmd_name)?;
+                        let min_idle = parse_positive_integer(&next_argument(&mut arguments, &cmd_name)?)
+                            .map_err(|_| "Invalid min-idle-time argument for XCLAIM".to_string())? as u64;
+
+                        // the IDs are followed by the options: the first argument that is not an ID starts the options
+                        let mut ids = vec![];
+                        let mut next = arguments.next();
+                        while let Some(id) = next.as_deref().and_then(|id| parse_stream_id(id, 0).ok()) {
+                            ids.push(id);
+                            next = arguments.next();
+                        }
+                        if ids.is_empty() {
+                            return Err(wrong_number_of_arguments(&cmd_name));
+                        }
+
+                        let mut options = ClaimOptions::default();
+                        while let Some(option) = next {
+                            match option.to_ascii_lowercase().as_slice() {
+                                b"idle" => options.idle = Some(parse_positive_integer(&next_argument(&mut arguments, &cmd_name)?)? as u64),
+                                b"time" => options.time = Some(parse_positive_integer(&next_argument(&mut arguments, &cmd_name)?)? as u64),
+                                b"retrycount" => options.retry_count = Some(parse_positive_integer(&next_argument(&mut arguments, &cmd_name)?)? as u64),
+                                b"force" => options.force = true,
+                                b"justid" => options.just_id = true,
+                                b"lastid" => options.last_id = Some(parse_stream_id(&next_argument(&mut arguments, &cmd_name)?, 0)?),
+                                _ => return Err(format!("Unrecognized XCLAIM option '{}'", String::from_utf8_lossy(&option))),
+                            }
+                            next = arguments.next();
+                        }
+
+                        Ok(RespCommand::Xclaim { key, group, consumer, min_idle, ids, options })
+                    }
+                    "xautoclaim" => {
+                        let key = next_argument(&mut arguments, &cmd_name)?;
+                        let group = next_argument(&mut arguments, &cmd_name)?;
+                        let consumer = next_argument(&mut arguments, &cmd_name)?;
+                        let min_idle = parse_positive_integer(&next_argument(&mut arguments, &cmd_name)?)
+                            .map_err(|_| "Invalid min-idle-time argument for XAUTOCLAIM".to_string())? as u64;
+                        let start = parse_stream_range_bound(&next_argument(&mut arguments, &cmd_name)?, false)?;
+
+                        let mut options = AutoClaimOptions { count: 100, just_id: false };
+                        while let Some(option) = arguments.next() {
+                            match option.to_ascii_lowercase().as_slice() {
+                                b"count" => {
+                                    options.count = parse_positive_integer(&next_argument(&mut arguments, &cmd_name)?)?;
+                                    if options.count == 0 {
+                                        return Err("COUNT must be > 0".to_string());
+                                    }
+                                }
+                                b"justid" => options.just_id = true,
+                                _ => return Err(SYNTAX_ERROR.to_string()),
+                            }
+                        }
+
+                        Ok(RespCommand::Xautoclaim { key, group, consumer, min_idle, start, options })
+                    }
+                    _ => Err(format!("unknown command '{cmd_name}'")),
+                }
+            },
+            _ => Err("An Array of BulkStrings is expected".to_string()),
+        }
+    }
+
+    // whether the command may change the data, in which case what it did goes into the append only file
+    fn is_write(&self) -> bool {
+        matches!(self,
+            RespCommand::Set(_) | RespCommand::Expire { .. } | RespCommand::Persist { .. } | RespCommand::Swapdb { .. }
+            | RespCommand::Move { .. } | RespCommand::Flushdb { .. } | RespCommand::Flushall { .. } | RespCommand::Rename { .. }
+            | RespCommand::Copy { .. } | RespCommand::Append { .. } | RespCommand::Setrange { .. } | RespCommand::Getdel { .. }
+            | RespCommand::Getex { .. } | RespCommand::Setnx { .. } | RespCommand::Msetnx { .. } | RespCommand::Incrby { .. }
+            | RespCommand::Incrbyfloat { .. } | RespCommand::Mset(_) | RespCommand::Del(_) | RespCommand::Lpush { .. }
+            | RespCommand::Rpush { .. } | RespCommand::Lpop { .. } | RespCommand::Rpop { .. } | RespCommand::Lset { .. }
+            | RespCommand::Lrem { .. } | RespCommand::Ltrim { .. } | RespCommand::Linsert { .. } | RespCommand::Blpop { .. }
+            | RespCommand::Brpop { .. } | RespCommand::Blmove { .. } | RespCommand::Blmpop { .. } | RespCommand::Hset { .. }
+            | RespCommand::Hsetnx { .. } | RespCommand::Hdel { .. } | RespCommand::Hincrby { .. } | RespCommand::Sadd { .. }
+            | RespCommand::Srem { .. } | RespCommand::Spop { .. } | RespCommand::SetAlgebraStore { .. } | RespCommand::Zadd { .. }
+            | RespCommand::Zincrby { .. } | RespCommand::Zrem { .. } | RespCommand::Bzpopmin { .. } | RespCommand::Bzpopmax { .. }
+            | RespCommand::Xadd { .. } | RespCommand::Xtrim { .. } | RespCommand::Xdel { .. } | RespCommand::XgroupCreate { .. }
+            | RespCommand::XgroupSetid { .. } | RespCommand::XgroupDestroy { .. } | RespCommand::XgroupCreateConsumer { .. }
+            | RespCommand::XgroupDelConsumer { .. } | RespCommand::Xreadgroup { .. } | RespCommand::Xack { .. }
+            | RespCommand::Xclaim { .. } | RespCommand::Xautoclaim { .. }
+        )
+    }
+
+    // a blocking command replies right away when one of its keys has something to pop; otherwise the client blocks
+    // until a write of another client serves it, and the reply returned here is only sent if it times out first
+    fn execute_blocking(&self, session: &mut Session, engine: &mut StorageEngine, keys: &[Vec<u8>], timeout: Option<Duration>) -> RespObject {
+        if let Some(reply) = self.pop_blocking(engine, keys) {
+            return reply;
+        }
+
+        let (reply, replies) = channel();
+        engine.blocked_clients_mut().block(session.id(), session.db(), keys, Command::parsed(self.clone()), reply);
+        session.block(replies, timeout);
+        match self {
+            RespCommand::Blmove { .. } => NullBulkString,
+            _ => NullArray,
+        }
+    }
+
+    // what a blocking command pops from the first of the keys that has something, or nothing for it to keep waiting
+    fn pop_blocking(&self, engine: &mut StorageEngine, keys: &[Vec<u8>]) -> Option<RespObject> {
+        keys.iter().find_map(|key| match self {
+            RespCommand::Blpop { .. } => blocking_pop_reply(engine, key, ListEnd::Left, None),
+            RespCommand::Brpop { .. } => blocking_pop_reply(engine, key, ListEnd::Right, None),
+            RespCommand::Blmpop { end, count, .. } => blocking_pop_reply(engine, key, *end, Some(*count)),
+            RespCommand::Blmove { destination, from, to, .. } => match engine.list_move(key, destination, *from, *to) {
+                Ok(value) => value.map(BulkString),
+                Err(e) => Some(Error(e)),
+            },
+            RespCommand::Bzpopmin { .. } => blocking_sorted_set_pop_reply(engine, key, false),
+            RespCommand::Bzpopmax { .. } => blocking_sorted_set_pop_reply(engine, key, true),
+            _ => None,
+        })
+    }
+
+    pub fn execute_on(&self, session: &mut Session, engine: &mut StorageEngine) -> RespObject {
+        match self {
+            // a subscribed RESP2 client cannot tell a plain reply apart from a message, so it gets one shaped like them
+            RespCommand::Ping if session.in_subscribed_mode() => Array(vec![BulkString(b"pong".to_vec()), BulkString(vec![])]),
+            RespCommand::Ping => SimpleString("PONG".to_string()),
+            RespCommand::Quit => {
+                session.close();
+                SimpleString("OK".to_string())
+            }
+            RespCommand::Reset => {
+                session.reset(engine);
+                SimpleString("RESET".to_string())
+            }
+            RespCommand::Hello { protocol, auth, client_name } => {
+                // there are no users configured, so only the default one can authenticate, with any password
+                if auth.as_ref().is_some_and(|(username, _)| username != b"default") {
+                    return Error("WRONGPASS invalid username-password pair or user is disabled.".to_string());
+                }
+                if let Some(client_name) = client_name {
+                    if !client_name.iter().all(|byte| (b'!'..=b'~').contains(byte)) {
+                        return Error("Client names cannot contain spaces, newlines or special characters.".to_string());
+                    }
+                    session.set_client_name(client_name.clone());
+                }
+                if let Some(protocol) = protocol {
+                    session.set_protocol(*protocol);
+                    engine.broker_mut().set_protocol(session.id(), *protocol);
+                }
+
+                let proto = match session.protocol() {
+                    ProtocolVersion::Resp2 => 2,
+                    ProtocolVersion::Resp3 => 3,
+                };
+                Map(vec![
+                    (BulkString(b"server".to_vec()), BulkString(b"redis".to_vec())),
+                    (BulkString(b"version".to_vec()), BulkString(env!("CARGO_PKG_VERSION").as_bytes().to_vec())),
+                    (BulkString(b"proto".to_vec()), Integer(proto)),
+                    (BulkString(b"id".to_vec()), Integer(session.id() as i64)),
+                    (BulkString(b"mode".to_vec()), BulkString(b"standalone".to_vec())),
+                    (BulkString(b"role".to_vec()), BulkString(b"master".to_vec())),
+                    (BulkString(b"modules".to_vec()), Array(vec![])),
+                ])
+            }
+            RespCommand::Echo { message} => BulkString(message.clone()),
+            RespCommand::ConfigGet { parameters } => {
+                let mut values = vec![];
+                for name in CONFIG_PARAMETERS {
+                    if parameters.iter().any(|parameter| glob_match(parameter, name.as_bytes(), true)) {
+                        values.push((BulkString(name.as_bytes().to_vec()), BulkString(config_value(engine, name).into_bytes())));
+                    }
+                }
+                Map(values)
+            }
+            RespCommand::ConfigSet { parameters } => {
+                // either all the parameters are applied, or none of them is
+                let mut settings = Settings::of(engine);
+                for (name, value) in parameters {
+                    if let Err(e) = apply_config(&mut settings, name, value) {
+                        return Error(e);
+                    }
+                }
+                settings.apply_to(engine);
+                SimpleString("OK".to_string())
+            }
+            RespCommand::Info { sections } => BulkString(info_report(engine, sections).into_bytes()),
+            RespCommand::Bgrewriteaof => {
+                match engine.aof() {
+                    None => return Error("The append only file is not enabled, start the server with '--appendonly yes'".to_string()),
+                    Some(aof) if aof.is_rewriting() => return Error("Background append only file rewriting already in progress".to_string()),
+                    Some(_) => {}
+                }
+                let snapshot = engine.snapshot();
+                if let Some(aof) = engine.aof_mut() {
+                    aof.start_rewrite(snapshot);
+                }
+                SimpleString("Background append only file rewriting started".to_string())
+            }
+            RespCommand::Save => {
+                if engine.rdb().is_saving() {
+                    return Error("Background save already in progress".to_string());
+                }
+                let snapshot = engine.snapshot();
+                let dirty = engine.dirty();
+                match engine.rdb_mut().save(snapshot, dirty) {
+                    Ok(()) => SimpleString("OK".to_string()),
+                    Err(e) => Error(format!("Failed saving the DB: {e}")),
+                }
+            }
+            RespCommand::Bgsave => {
+                // checked before taking the snapshot, as copying the data only to throw it away is not cheap
+                if engine.rdb().is_saving() {
+                    return Error("Background save already in progress".to_string());
+                }
+                let snapshot = engine.snapshot();
+                let dirty = engine.dirty();
+                engine.rdb_mut().start_background_save(snapshot, dirty);
+                SimpleString("Background saving started".to_string())
+            }
+            RespCommand::Lastsave => Integer(engine.rdb().last_save() as i64),
+            RespCommand::Get(cmd) => {
+                match cmd.execute_on(engine) {
+                    Ok(Some(value)) => BulkString(value.clone()),
+                    Ok(None) => NullBulkString,
+                    Err(e) => Error(e.to_string()),
+                }
+            },
+            RespCommand::Set(cmd) => {
+                match cmd.execute_on(engine) {
+                    // with GET, the previous value is the reply whether the value was written or not
+                    Ok((_, previous)) if cmd.options.get => previous.map_or(NullBulkString, BulkString),
+                    Ok((true, _)) => SimpleString("OK".to_string()),
+                    // the NX/XX condition did not hold
+                    Ok((false, _)) => NullBulkString,
+                    Err(e) => Error(e.to_string()),
+                }
+            },
+            RespCommand::Ttl { key } => {
+                match engine.time_to_live(key) {
+                    TimeToLive::KeyDoesNotExist => Integer(-2),
+                    TimeToLive::DoesNotExpire => Integer(-1),
+                    // rounded to the closest second, like Redis does
+                    TimeToLive::ExpiresInMillis(millis) => Integer((millis as i64 + 500) / 1000)
+                }
+            },
+            RespCommand::Pttl { key } => {
+                match engine.time_to_live(key) {
+                    TimeToLive::KeyDoesNotExist => Integer(-2),
+                    TimeToLive::DoesNotExpire => Integer(-1),
+                    TimeToLive::ExpiresInMillis(millis) => Integer(millis as i64)
+                }
+            },
+            RespCommand::Expire { key, at, conditions } => boolean_reply(engine.expire(key, *at, *conditions)),
+            RespCommand::Expiretime { key } => {
+                match engine.expire_time(key) {
+                    ExpireTime::KeyDoesNotExist => Integer(-2),
+                    ExpireTime::DoesNotExpire => Integer(-1),
+                    ExpireTime::AtUnixTimeMillis(millis) => Integer((millis as i64 + 500) / 1000)
+                }
+            },
+            RespCommand::Pexpiretime { key } => {
+                match engine.expire_time(key) {
+                    ExpireTime::KeyDoesNotExist => Integer(-2),
+                    ExpireTime::DoesNotExpire => Integer(-1),
+                    ExpireTime::AtUnixTimeMillis(millis) => Integer(millis as i64)
+                }
+            },
+            RespCommand::Persist { key } => Integer(engine.persist(key) as i64),
+            RespCommand::Keys { pattern } => Array(engine.keys(pattern).into_iter().map(BulkString).collect()),
+            RespCommand::Scan { cursor, pattern, count, value_type } => {
+                let (next_cursor, keys) = engine.scan(*cursor, *count, pattern.as_deref(), value_type.as_deref());
+                Array(vec![
+                    BulkString(next_cursor.to_string().into_bytes()),
+                    Array(keys.into_iter().map(BulkString).collect()),
+                ])
+            }
+            RespCommand::Randomkey => engine.random_key().map_or(NullBulkString, BulkString),
+            RespCommand::Dbsize => Integer(engine.db_size() as i64),
+            RespCommand::Select { db } => {
+                if *db >= engine.db_count() {
+                    return Error("DB index is out of range".to_string());
+                }
+                session.select_db(*db);
+                engine.select_db(*db);
+                SimpleString("OK".to_string())
+            }
+            RespCommand::Subscribe { channels } => {
+                let replies = channels.iter().map(|channel| {
+                    if session.add_channel(channel) {
+                        engine.broker_mut().subscribe(channel, session.id(), Subscriber::new(session.outbox(), session.protocol()));
+                    }
+                    subscription_reply("subscribe", Some(channel), session.subscription_count())
+                }).collect();
+                send_subscription_replies(session, replies)
+            }
+            RespCommand::Unsubscribe { channels } => {
+                let channels = if channels.is_empty() { session.channels() } else { channels.clone() };
+                let mut replies: Vec<RespObject> = channels.iter().map(|channel| {
+                    if session.remove_channel(channel) {
+                        engine.broker_mut().unsubscribe(channel, session.id());
+                    }
+                    subscription_reply("unsubscribe", Some(channel), session.subscription_count())
+                }).collect();
+                if replies.is_empty() {
+                    replies.push(subscription_reply("unsubscribe", None, session.subscription_count()));
+                }
+                send_subscription_replies(session, replies)
+            }
+            RespCommand::Psubscribe { patterns } => {
+                let replies = patterns.iter().map(|pattern| {
+                    if session.add_pattern(pattern) {
+                        engine.broker_mut().psubscribe(pattern, session.id(), Subscriber::new(session.outbox(), session.protocol()));
+                    }
+                    subscription_reply("psubscribe", Some(pattern), session.subscription_count())
+                }).collect();
+                send_subscription_replies(session, replies)
+            }
+            RespCommand::Punsubscribe { patterns } => {
+                let patterns = if patterns.is_empty() { session.patterns() } else { patterns.clone() };
+                let mut replies: Vec<RespObject> = patterns.iter().map(|pattern| {
+                    if session.remove_pattern(pattern) {
+                        engine.broker_mut().punsubscribe(pattern, session.id());
+                    }
+                    subscription_reply("punsubscribe", Some(pattern), session.subscription_count())
+                }).collect();
+                if replies.is_empty() {
+                    replies.push(subscription_reply("punsubscribe", None, session.subscription_count()));
+                }
+                send_subscription_replies(session, replies)
+            }
+            RespCommand::Publish { channel, message } => Integer(engine.broker().publish(channel, message) as i64),
+            RespCommand::PubsubChannels { pattern } => {
+                Array(engine.broker().channels(pattern.as_deref()).into_iter().map(BulkString).collect())
+            }
+            RespCommand::PubsubNumsub { channels } => {
+                Array(channels.iter()
+                    .flat_map(|channel| [BulkString(channel.clone()), Integer(engine.broker().subscriber_count(channel) as i64)])
+                    .collect())
+            }
+            RespCommand::PubsubNumpat => Integer(engine.broker().pattern_count() as i64),
+            RespCommand::Multi => {
+                session.begin_transaction();
+                SimpleString("OK".to_string())
+            }
+            RespCommand::Exec => {
+                let Some(transaction) = session.take_transaction() else {
+                    return Error("EXEC without MULTI".to_string());
+                };
+                // the keys are checked before anything runs, as the transaction itself may well modify them
+                let watched_key_modified = session.watched_keys().iter()
+                    .any(|(db, key, version)| engine.watched_key_modified(*db, key, *version));
+                session.unwatch_all(engine);
+
+                if transaction.failed {
+                    return Error("EXECABORT Transaction discarded because of previous errors.".to_string());
+                }
+                if watched_key_modified {
+                    return NullArray;
+                }
+                // the engine stays locked while the commands run, so no other client sees them half done, and the
+                // writes are logged as a transaction too, so that they are replayed all or nothing
+                if let Some(aof) = engine.aof_mut() {
+                    aof.begin_transaction();
+                }
+                let replies = transaction.commands.iter().map(|command| {
+                    let reply = command.run(session, engine);
+                    // a transaction can't wait, so its blocking commands time out right away, as in Redis
+                    session.unblock(engine).unwrap_or(reply)
+                }).collect();
+                if let Some(Err(e)) = engine.aof_mut().map(AppendOnlyFile::end_transaction) {
+                    eprintln!("Error writing to the append only file: {e}");
+                }
+                Array(replies)
+            }
+            RespCommand::Discard => {
+                if session.take_transaction().is_none() {
+                    return Error("DISCARD without MULTI".to_string());
+                }
+                session.unwatch_all(engine);
+                SimpleString("OK".to_string())
+            }
+            RespCommand::Watch { keys } => {
+                for key in keys {
+                    if !session.is_watching(session.db(), key) {
+                        let version = engine.watch(key);
+                        session.watch(session.db(), key.clone(), version);
+                    }
+                }
+                SimpleString("OK".to_string())
+            }
+            RespCommand::Unwatch => {
+                session.unwatch_all(engine);
+                SimpleString("OK".to_string())
+            }
+            RespCommand::Swapdb { first, second } => match engine.swap_dbs(*first, *second) {
+                Ok(()) => SimpleString("OK".to_string()),
+                Err(e) => Error(e),
+            },
+            RespCommand::Move { key, db } => boolean_reply(engine.move_key(key, *db)),
+            RespCommand::Flushdb { asynchronously } => {
+                engine.flush_db(*asynchronously);
+                SimpleString("OK".to_string())
+            }
+            RespCommand::Flushall { asynchronously } => {
+                engine.flush_all(*asynchronously);
+                SimpleString("OK".to_string())
+            }
+            RespCommand::Rename { key, new_key, only_if_new } => {
+                match engine.rename(key, new_key, *only_if_new) {
+                    Ok(renamed) if *only_if_new => Integer(renamed as i64),
+                    Ok(_) => SimpleString("OK".to_string()),
+                    Err(e) => Error(e),
+                }
+            }
+            RespCommand::Copy { source, destination, destination_db, replace } => boolean_reply(engine.copy(source, destination, *destination_db, *replace)),
+            RespCommand::Touch { keys } => Integer(engine.touch(keys) as i64),
+            RespCommand::Type { key } => SimpleString(engine.key_type(key).unwrap_or("none").to_string()),
+            RespCommand::ObjectEncoding { key } => {
+                engine.object_info(key).map_or(NullBulkString, |info| BulkString(info.encoding.as_bytes().to_vec()))
+            }
+            RespCommand::ObjectIdletime { key } => engine.object_info(key).map_or(NullBulkString, |info| Integer(info.idle_seconds as i64)),
+            RespCommand::ObjectFreq { key } => engine.object_info(key).map_or(NullBulkString, |info| Integer(info.frequency as i64)),
+            RespCommand::ObjectRefcount { key } => engine.object_info(key).map_or(NullBulkString, |info| Integer(info.ref_count as i64)),
+            RespCommand::Append { key, value } => integer_reply(engine.append(key, value)),
+            RespCommand::Strlen { key } => integer_reply(engine.string_len(key)),
+            RespCommand::Getrange { key, start, end } => {
+                match engine.get_range(key, *start, *end) {
+                    Ok(value) => BulkString(value),
+                    Err(e) => Error(e),
+                }
+            }
+            RespCommand::Setrange { key, offset, value } => integer_reply(engine.set_range(key, *offset, value)),
+            RespCommand::Getdel { key } => optional_bulk_string_reply(engine.get_del(key)),
+            RespCommand::Getex { key, expiry } => optional_bulk_string_reply(engine.get_ex(key, *expiry)),
+            RespCommand::Setnx { key, value } => {
+                let options = SetOptions { condition: SetCondition::IfNotExists, ..SetOptions::default() };
+                boolean_reply(engine.set_with_options(key.clone(), value.clone(), options).map(|(written, _)| written))
+            }
+            RespCommand::Msetnx { key_values } => Integer(engine.set_all_if_none_exist(key_values) as i64),
+            RespCommand::Incrby { key, increment } => {
+                match engine.increment_by(key, *increment) {
+                    Ok(value) => Integer(value),
+                    Err(e) => Error(e),
+                }
+            }
+            RespCommand::Incrbyfloat { key, increment } => {
+                match engine.increment_by_float(key, *increment) {
+                    Ok(value) => BulkString(value),
+                    Err(e) => Error(e),
+                }
+            }
+            RespCommand::Mset(cmd) => {
+                match cmd.execute_on(engine) {
+                    Ok(_) => SimpleString("OK".to_string()),
+                    Err(e) => Error(e.to_string()),
+                }
+            },
+            RespCommand::Mget(cmd) => {
+                let mget_results =
+                    cmd.execute_on(engine)
+                        .iter()
+                        .map(|option| {
+                            match option {
+                                Some(value) => BulkString(value.clone()),
+                                None => NullBulkString,
+                            }
+                        })
+                        .collect();
+
+                Array(mget_results)
+            },
+            RespCommand::Del(cmd) => {
+                let deleted_count = cmd.execute_on(engine);
+                Integer(deleted_count as i64)
+            }
+            RespCommand::Exists(cmd) => {
+                let exists_count = cmd.execute_on(engine);
+                Integer(exists_count as i64)
+            }
+            RespCommand::Lpush { key, values } => integer_reply(engine.push(key, values, ListEnd::Left)),
+            RespCommand::Rpush { key, values } => integer_reply(engine.push(key, values, ListEnd::Right)),
+            RespCommand::Lpop { key, count } => pop_reply(engine, key, ListEnd::Left, *count),
+            RespCommand::Rpop { key, count } => pop_reply(engine, key, ListEnd::Right, *count),
+            RespCommand::Llen { key } => integer_reply(engine.list_len(key)),
+            RespCommand::Blpop { keys, timeout } | RespCommand::Brpop { keys, timeout } | RespCommand::Blmpop { keys, timeout, .. }
+            | RespCommand::Bzpopmin { keys, timeout } | RespCommand::Bzpopmax { keys, timeout } => {
+                self.execute_blocking(session, engine, keys, *timeout)
+            }
+            RespCommand::Blmove { source, timeout, .. } => {
+                self.execute_blocking(session, engine, std::slice::from_ref(source), *timeout)
+            }
+            RespCommand::Lrange { key, start, stop } => {
+                match engine.list_range(key, *start, *stop) {
+                    Ok(elements) => bulk_string_array(elements),
+                    Err(e) => Error(e),
+                }
+            }
+            RespCommand::Lindex { key, index } => {
+                match engine.list_index(key, *index) {
+                    Ok(Some(element)) => BulkString(element.clone()),
+                    Ok(None) => NullBulkString,
+                    Err(e) => Error(e),
+                }
+            }
+            RespCommand::Lset { key, index, value } => ok_reply(engine.list_set(key, *index, value)),
+            RespCommand::Lrem { key, count, value } => integer_reply(engine.list_remove(key, *count, value)),
+            RespCommand::Ltrim { key, start, stop } => ok_reply(engine.list_trim(key, *start, *stop)),
+            RespCommand::Linsert { key, position, pivot, value } => {
+                match engine.list_insert(key, *position, pivot, value) {
+                    Ok(result) => Integer(result),
+                    Err(e) => Error(e),
+                }
+            }
+            RespCommand::Hset { key, field_values } => integer_reply(engine.hash_set(key, field_values)),
+            RespCommand::Hsetnx { key, field, value } => boolean_reply(engine.hash_set_if_absent(key, field, value)),
+            RespCommand::Hget { key, field } => {
+                match engine.hash_get(key, field) {
+                    Ok(Some(value)) => BulkString(value.clone()),
+                    Ok(None) => NullBulkString,
+                    Err(e) => Error(e),
+                }
+            }
+            RespCommand::Hmget { key, fields } => {
+                match engine.hash_get_multiple(key, fields) {
+                    Ok(values) => Array(values.into_iter()
+                        .map(|value| value.map_or(NullBulkString, |value| BulkString(value.clone())))
+                        .collect()),
+                    Err(e) => Error(e),
+                }
+            }
+            RespCommand::Hdel { key, fields } => integer_reply(engine.hash_delete(key, fields)),
+            RespCommand::Hgetall { key } => {
+                match engine.hash_get_all(key) {
+                    Ok(field_values) => Map(field_values.into_iter()
+                        .map(|(field, value)| (BulkString(field.to_vec()), BulkString(value.to_vec())))
+                        .collect()),
+                    Err(e) => Error(e),
+                }
+            }
+            RespCommand::Hkeys { key } => {
+                match engine.hash_keys(key) {
+                    Ok(fields) => bulk_string_array(fields),
+                    Err(e) => Error(e),
+                }
+            }
+            RespCommand::Hvals { key } => {
+                match engine.hash_values(key) {
+                    Ok(values) => bulk_string_array(values),
+                    Err(e) => Error(e),
+                }
+            }
+            RespCommand::Hlen { key } => integer_reply(engine.hash_len(key)),
+            RespCommand::Hexists { key, field } => boolean_reply(engine.hash_exists(key, field)),
+            RespCommand::Hincrby { key, field, increment } => {
+                match engine.hash_increment_by(key, field, *increment) {
+                    Ok(value) => Integer(value),
+                    Err(e) => Error(e),
+                }
+            }
+            RespCommand::Sadd { key, members } => integer_reply(engine.set_add(key, members)),
+            RespCommand::Srem { key, members } => integer_reply(engine.set_remove(key, members)),
+            RespCommand::Smembers { key } => {
+                match engine.set_members(key) {
+                    Ok(members) => bulk_string_set(members),
+                    Err(e) => Error(e),
+                }
+            }
+            RespCommand::Sismember { key, member } => {
+                boolean_reply(engine.set_contains(key, std::slice::from_ref(member)).map(|found| found[0]))
+            }
+            RespCommand::Smismember { key, members } => {
+                match engine.set_contains(key, members) {
+                    Ok(found) => Array(found.into_iter().map(|found| Integer(found as i64)).collect()),
+                    Err(e) => Error(e),
+                }
+            }
+            RespCommand::Scard { key } => integer_reply(engine.set_len(key)),
+            RespCommand::Spop { key, count } => {
+                match (engine.set_pop(key, count.unwrap_or(1)), count) {
+                    (Ok(Some(mut popped)), None) => popped.pop().map_or(NullBulkString, BulkString),
+                    (Ok(None), None) => NullBulkString,
+                    (Ok(popped), Some(_)) => Array(popped.unwrap_or_default().into_iter().map(BulkString).collect()),
+                    (Err(e), _) => Error(e),
+                }
+            }
+            RespCommand::Srandmember { key, count } => {
+                match (engine.set_random_members(key, count.unwrap_or(1)), count) {
+                    (Ok(mut members), None) => members.pop().map_or(NullBulkString, |member| BulkString(member.clone())),
+                    (Ok(members), Some(_)) => bulk_string_array(members),
+                    (Err(e), _) => Error(e),
+                }
+            }
+            RespCommand::SetAlgebra { operation, keys } => {
+                match engine.set_combine(*operation, keys) {
+                    Ok(members) => bulk_string_set(members),
+                    Err(e) => Error(e),
+                }
+            }
+            RespCommand::SetAlgebraStore { operation, destination, keys } => {
+                integer_reply(engine.set_combine_and_store(*operation, destination, keys))
+            }
+            RespCommand::Zadd { key, entries, conditions, count_changed, increment } => {
+                if *increment {
+                    let (increment, member) = &entries[0];
+                    match engine.sorted_set_increment(key, member, *increment, *conditions) {
+                        Ok(Some(score)) => BulkString(format_score(score)),
+                        Ok(None) => NullBulkString,
+                        Err(e) => Error(e),
+                    }
+                } else {
+                    match engine.sorted_set_add(key, entries, *conditions) {
+                        Ok((added, updated)) if *count_changed => Integer((added + updated) as i64),
+                        Ok((added, _)) => Integer(added as i64),
+                        Err(e) => Error(e),
+                    }
+                }
+            }
+            RespCommand::Zincrby { key, increment, member } => {
+                match engine.sorted_set_increment(key, member, *increment, SortedSetUpdateConditions::default()) {
+                    Ok(score) => score.map_or(NullBulkString, |score| BulkString(format_score(score))),
+                    Err(e) => Error(e),
+                }
+            }
+            RespCommand::Zrem { key, members } => integer_reply(engine.sorted_set_remove(key, members)),
+            RespCommand::Zcard { key } => integer_reply(engine.sorted_set_len(key)),
+            RespCommand::Zscore { key, member } => {
+                match engine.sorted_set_score(key, member) {
+                    Ok(score) => score.map_or(NullBulkString, |score| BulkString(format_score(score))),
+                    Err(e) => Error(e),
+                }
+            }
+            RespCommand::Zrank { key, member, reverse, with_score } => {
+                match engine.sorted_set_rank(key, member, *reverse) {
+                    Ok(Some((rank, score))) if *with_score => Array(vec![Integer(rank as i64), BulkString(format_score(score))]),
+                    Ok(Some((rank, _))) => Integer(rank as i64),
+                    Ok(None) => NullBulkString,
+                    Err(e) => Error(e),
+                }
+            }
+            RespCommand::Zcount { key, range } => integer_reply(engine.sorted_set_count(key, range)),
+            RespCommand::Zrange { key, range, reverse, limit, with_scores } => {
+                let limit = match limit {
+                    // a negative offset never selects anything
+                    Some((offset, _)) if *offset < 0 => return Array(vec![]),
+                    // while a negative count means "all the remaining members"
+                    Some((offset, count)) => Some((*offset as usize, usize::try_from(*count).ok())),
+                    None => None,
+                };
+
+                match engine.sorted_set_range(key, range, *reverse, limit) {
+                    Ok(entries) => Array(entries.into_iter()
+                        .flat_map(|(member, score)| {
+                            let score = with_scores.then(|| BulkString(format_score(score)));
+                            std::iter::once(BulkString(member.clone())).chain(score)
+                        })
+                        .collect()),
+                    Err(e) => Error(e),
+                }
+            }
+            RespCommand::Xadd { key, id, fields, trim, no_create } => {
+                match engine.stream_add(key, *id, fields.clone(), *trim, *no_create) {
+                    Ok(id) => id.map_or(NullBulkString, |id| BulkString(id.to_string().into_bytes())),
+                    Err(e) => Error(e),
+                }
+            }
+            RespCommand::Xrange { key, start, end, reverse, count } => {
+                match engine.stream_range(key, *start, *end, *reverse, *count) {
+                    Ok(entries) => stream_entries_reply(entries),
+                    Err(e) => Error(e),
+                }
+            }
+            RespCommand::Xlen { key } => integer_reply(engine.stream_len(key)),
+            RespCommand::Xtrim { key, trim } => integer_reply(engine.stream_trim(key, trim)),
+            RespCommand::Xdel { key, ids } => integer_reply(engine.stream_delete(key, ids)),
+            RespCommand::XgroupCreate { key, group, start, make_stream } => ok_reply(engine.stream_group_create(key, group, *start, *make_stream)),
+            RespCommand::XgroupSetid { key, group, start } => ok_reply(engine.stream_group_set_id(key, group, *start)),
+            RespCommand::XgroupDestroy { key, group } => boolean_reply(engine.stream_group_destroy(key, group)),
+            RespCommand::XgroupCreateConsumer { key, group, consumer, seen_time } =>
+                boolean_reply(engine.stream_group_create_consumer(key, group, consumer, *seen_time)),
+            RespCommand::XgroupDelConsumer { key, group, consumer } => integer_reply(engine.stream_group_delete_consumer(key, group, consumer)),
+            RespCommand::Xreadgroup { group, consumer, streams, count, no_ack } => {
+                match engine.stream_read_group(group, consumer, streams, *count, *no_ack) {
+                    Ok(streams) if streams.is_empty() => NullArray,
+                    Ok(streams) => Array(streams.into_iter()
+                        .map(|(key, entries)| Array(vec![
+                            BulkString(key),
+                            Array(entries.into_iter()
+                                .map(|(id, fields)| stream_entry_reply(id, fields.as_ref()))
+                                .collect()),
+                        ]))
+                        .collect()),
+                    Err(e) => Error(e),
+                }
+            }
+            RespCommand::Xack { key, group, ids } => integer_reply(engine.stream_ack(key, group, ids)),
+            RespCommand::Xpending { key, group, filter: None } => {
+                match engine.stream_pending_summary(key, group) {
+                    Ok(summary) => {
+                        let (min, max) = match summary.ids {
+                            Some((min, max)) => (BulkString(min.to_string().into_bytes()), BulkString(max.to_string().into_bytes())),
+                            None => (NullBulkString, NullBulkString),
+                        };
+                        let consumers = if summary.consumers.is_empty() {
+                            NullArray
+                        } else {
+                            Array(summary.consumers.into_iter()
+                                .map(|(consumer, count)| Array(vec![BulkString(consumer), BulkString(count.to_string().into_bytes())]))
+                                .collect())
+                        };
+                        Array(vec![Integer(summary.count as i64), min, max, consumers])
+                    }
+                    Err(e) => Error(e),
+                }
+            }
+            RespCommand::Xpending { key, group, filter: Some(filter) } => {
+                match engine.stream_pending(key, group, filter) {
+                    Ok(entries) => Array(entries.into_iter()
+                        .map(|entry| Array(vec![
+                            BulkString(entry.id.to_string().into_bytes()),
+                            BulkString(entry.consumer),
+                            Integer(entry.idle as i64),
+                            Integer(entry.delivery_count as i64),
+                        ]))
+                        .collect()),
+                    Err(e) => Error(e),
+                }
+            }
+            RespCommand::Xclaim { key, group, consumer, min_idle, ids, options } => {
+                match engine.stream_claim(key, group, consumer, *min_idle, ids, options) {
+                    Ok(entries) if options.just_id => stream_ids_reply(entries.into_iter().map(|(id, _)| id)),
+                    Ok(entries) => stream_entries_reply(entries),
+                    Err(e) => Error(e),
+                }
+            }
+            RespCommand::Xautoclaim { key, group, consumer, min_idle, start, options } => {
+                match engine.stream_auto_claim(key, group, consumer, *min_idle, *start, options) {
+                    Ok((cursor, entries, deleted)) => {
+                        let entries = if options.just_id {
+                            stream_ids_reply(entries.into_iter().map(|(id, _)| id))
+                        } else {
+                            stream_entries_reply(entries)
+                        };
+                        Array(vec![BulkString(cursor.to_string().into_bytes()), entries, stream_ids_reply(deleted.into_iter())])
+                    }
+                    Err(e) => Error(e),
+                }
+            }
+        }
+    }
+}
+
+// maps the name of a set command, stripped of its 's' prefix and 'store' suffix, into the operation it performs
+fn set_operation(name: &str) -> SetOperation {
+    match name {
+        "inter" => SetOperation::Intersection,
+        "union" => SetOperation::Union,
+        _ => SetOperation::Difference,
+    }
+}
+
+// ===== Helpers for parsing arguments =====
+
+const SYNTAX_ERROR: &str = "syntax error";
+
+// what a RESP2 client can still run once it subscribed to something
+const SUBSCRIBED_MODE_COMMANDS: [&str; 7] = ["subscribe", "unsubscribe", "psubscribe", "punsubscribe", "ping", "quit", "reset"];
+
+// confirms a (un)subscription, with how many subscriptions the client is left with
+fn subscription_reply(kind: &str, name: Option<&Vec<u8>>, subscription_count: usize) -> RespObject {
+    Push(vec![
+        BulkString(kind.as_bytes().to_vec()),
+        name.map_or(Null, |name| BulkString(name.clone())),
+        Integer(subscription_count as i64),
+    ])
+}
+
+// Redis confirms each channel or pattern on its own: all but the last confirmation go out right away, ahead of the
+// last one, which is the reply of the command
+fn send_subscription_replies(session: &Session, mut replies: Vec<RespObject>) -> RespObject {
+    let last = replies.pop().expect("there is always at least one confirmation");
+    for reply in replies {
+        session.send(reply);
+    }
+    last
+}
+
+// the parameters CONFIG GET/SET know about
+const CONFIG_PARAMETERS: [&str; 8] = [
+    "hz", "active-expire-effort", "databases", "notify-keyspace-events", "appendonly", "appendfsync", "save", "dbfilename",
+];
+
+fn config_value(engine: &StorageEngine, name: &str) -> String {
+    let config = engine.expire_config();
+    match name {
+        "hz" => config.hz.to_string(),
+        "active-expire-effort" => config.effort.to_string(),
+        "databases" => engine.db_count().to_string(),
+        "notify-keyspace-events" => engine.notify_flags().to_string(),
+        "appendonly" => if engine.aof().is_some() { "yes" } else { "no" }.to_string(),
+        "appendfsync" => engine.aof().map_or(FsyncPolicy::default(), AppendOnlyFile::fsync).to_string(),
+        "save" => engine.rdb().rules().to_string(),
+        "dbfilename" => engine.rdb().path().display().to_string(),
+        _ => String::new(),
+    }
+}
+
+// what CONFIG SET can change, gathered first so that either all the parameters are applied, or none of them is
+struct Settings {
+    expire: ExpireConfig,
+    notify_flags: NotifyFlags,
+    fsync: FsyncPolicy,
+    save_rules: SaveRules,
+    dbfilename: String,
+}
+
+impl Settings {
+    fn of(engine: &StorageEngine) -> Settings {
+        Settings {
+            expire: engine.expire_config(),
+            notify_flags: engine.notify_flags(),
+            fsync: engine.aof().map_or(FsyncPolicy::default(), AppendOnlyFile::fsync),
+            save_rules: engine.rdb().rules().clone(),
+            dbfilename: engine.rdb().path().display().to_string(),
+        }
+    }
+
+    fn apply_to(self, engine: &mut StorageEngine) {
+        engine.set_expire_config(self.expire);
+        engine.set_notify_flags(self.notify_flags);
+        if let Some(aof) = engine.aof_mut() {
+            aof.set_fsync(self.fsync);
+        }
+        engine.rdb_mut().set_rules(self.save_rules);
+        engine.rdb_mut().set_path(self.dbfilename);
+    }
+}
+
+fn apply_config(settings: &mut Settings, name: &[u8], value: &[u8]) -> Result<(), String> {
+    let config = &mut settings.expire;
+    let name = String::from_utf8_lossy(name).to_lowercase();
+    let failed = |reason: &str| format!("CONFIG SET failed (possibly related to argument '{name}') - {reason}");
+    let number = parse_number::<i64>(value)
+        .ok_or_else(|| failed("argument couldn't be parsed into an integer"));
+
+    match name.as_str() {
+        // like Redis, an out of range hz is clamped rather than refused
+        "hz" => config.hz = number?.clamp(1, 500) as u32,
+        "active-expire-effort" => {
+            config.effort = u32::try_from(number?)
+                .ok()
+                .filter(|effort| (1..=10).contains(effort))
+                .ok_or_else(|| failed("argument must be between 1 and 10 inclusive"))?;
+        }
+        // the databases are created on startup, from the '--databases' argument, and the append only file is
+        // replayed on startup (from '--appendonly yes'), as only then it can be
+        "databases" | "appendonly" => return Err(failed("can't set immutable config")),
+        "notify-keyspace-events" => {
+            settings.notify_flags = std::str::from_utf8(value)
+                .ok()
+                .and_then(NotifyFlags::parse)
+                .ok_or_else(|| failed("Invalid event class character. Use 'Ag$lshzxeKEtmdn'."))?;
+        }
+        "appendfsync" => {
+            settings.fsync = std::str::from_utf8(value)
+                .ok()
+                .and_then(FsyncPolicy::parse)
+                .ok_or_else(|| failed("argument(s) must be one of the following: always, everysec, no"))?;
+        }
+        "save" => {
+            settings.save_rules = std::str::from_utf8(value)
+                .ok()
+                .and_then(SaveRules::parse)
+                .ok_or_else(|| failed("Invalid save parameters"))?;
+        }
+        "dbfilename" => {
+            // like Redis, just a file name, which goes in the directory the server runs in
+            settings.dbfilename = std::str::from_utf8(value)
+                .ok()
+                .filter(|filename| !filename.is_empty() && !filename.contains('/'))
+                .ok_or_else(|| failed("dbfilename can't be a path, just a filename"))?
+                .to_string();
+        }
+        _ => return Err(format!("Unknown option or number of arguments for CONFIG SET - '{name}'")),
+    }
+    Ok(())
+}
+
+// the sections of INFO, as 'name:value' lines under a '# Section' header; no sections means the default ones
+fn info_report(engine: &StorageEngine, sections: &[Vec<u8>]) -> String {
+    let wants = |section: &str| sections.is_empty() || sections.iter().any(|wanted| {
+        [section.as_bytes(), b"all", b"default", b"everything"].iter().any(|name| wanted.eq_ignore_ascii_case(name))
+    });
+
+    let mut report = String::new();
+    if wants("persistence") {
+        let rdb = engine.rdb();
+        report.push_str("# Persistence\r\n");
+        report.push_str(&format!("rdb_changes_since_last_save:{}\r\n", rdb.changes_since_save(engine.dirty())));
+        report.push_str(&format!("rdb_bgsave_in_progress:{}\r\n", rdb.is_saving() as u8));
+        report.push_str(&format!("rdb_last_save_time:{}\r\n", rdb.last_save()));
+        report.push_str(&format!("rdb_last_bgsave_status:{}\r\n", if rdb.last_save_ok() { "ok" } else { "err" }));
+        report.push_str(&format!("aof_enabled:{}\r\n", engine.aof().is_some() as u8));
+        report.push_str(&format!("aof_rewrite_in_progress:{}\r\n", engine.aof().is_some_and(AppendOnlyFile::is_rewriting) as u8));
+    }
+    if wants("stats") {
+        let stats = engine.expire_stats();
+        report.push_str("# Stats\r\n");
+        report.push_str(&format!("expired_keys:{}\r\n", stats.expired_keys));
+        report.push_str(&format!("expired_stale_perc:{:.2}\r\n", stats.expired_stale_perc));
+        report.push_str(&format!("expired_time_cap_reached_count:{}\r\n", stats.expired_time_cap_reached_count));
+    }
+    report
+}
+
+fn wrong_number_of_arguments(cmd_name: &str) -> String {
+    format!("Wrong number of arguments for '{cmd_name}' command")
+}
+
+fn next_argument(arguments: &mut impl Iterator<Item = Vec<u8>>, cmd_name: &str) -> Result<Vec<u8>, String> {
+    arguments.next()
+        .ok_or_else(|| wrong_number_of_arguments(cmd_name))
+}
+
+fn no_more_arguments(arguments: &mut impl Iterator<Item = Vec<u8>>, cmd_name: &str) -> Result<(), String> {
+    match arguments.next() {
+        Some(_) => Err(wrong_number_of_arguments(cmd_name)),
+        None => Ok(()),
+    }
+}
+
+// arguments are bytes, so numbers have to be valid UTF-8 text before they can be parsed
+fn parse_number<T: std::str::FromStr>(argument: &[u8]) -> Option<T> {
+    std::str::from_utf8(argument).ok()?.parse::<T>().ok()
+}
+
+fn parse_integer(argument: &[u8]) -> Result<i64, String> {
+    parse_number::<i64>(argument)
+        .ok_or_else(|| "value is not an integer or out of range".to_owned())
+}
+
+// turns the amount given to EX/PX/EXAT/PXAT into an expiry, which has to be in the future (or after the unix epoch)
+fn parse_expiry(cmd_name: &str, option: &[u8], amount: i64) -> Result<SetExpiry, String> {
+    let invalid_expire_time = || format!("invalid expire time in '{cmd_name}' command");
+    if amount <= 0 {
+        return Err(invalid_expire_time());
+    }
+
+    let millis = match option {
+        b"ex" | b"exat" => amount.checked_mul(1000).ok_or_else(invalid_expire_time)?,
+        _ => amount,
+    } as u64;
+
+    match option {
+        b"ex" | b"px" => Ok(SetExpiry::After(Duration::from_millis(millis))),
+        _ => Ok(SetExpiry::AtUnixTimeMillis(millis)),
+    }
+}
+
+// whether the index is within the configured databases is only known to the engine
+fn parse_db_index(argument: &[u8]) -> Result<usize, String> {
+    parse_integer(argument)?
+        .try_into()
+        .map_err(|_| "DB index is out of range".to_owned())
+}
+
+fn parse_positive_integer(argument: &[u8]) -> Result<usize, String> {
+    parse_integer(argument)?
+        .try_into()
+        .map_err(|_| "value is out of range, must be positive".to_owned())
+}
+
+// the timeout of blocking commands, in seconds (possibly fractional); 0 means waiting for as long as it takes
+fn parse_timeout(argument: &[u8]) -> Result<Option<Duration>, String> {
+    let seconds = parse_number::<f64>(argument)
+        .filter(|seconds| seconds.is_finite())
+        .ok_or_else(|| "timeout is not a float or out of range".to_owned())?;
+    if seconds < 0.0 {
+        return Err("timeout is negative".to_owned());
+    }
+    if seconds == 0.0 {
+        return Ok(None);
+    }
+    Duration::try_from_secs_f64(seconds)
+        .map(Some)
+        .map_err(|_| "timeout is not a float or out of range".to_owned())
+}
+
+fn parse_list_end(argument: &[u8]) -> Result<ListEnd, String> {
+    match argument.to_ascii_lowercase().as_slice() {
+        b"left" => Ok(ListEnd::Left),
+        b"right" => Ok(ListEnd::Right),
+        _ => Err(SYNTAX_ERROR.to_string()),
+    }
+}
+
+fn parse_score(argument: &[u8]) -> Result<f64, String> {
+    parse_number::<f64>(argument)
+        .filter(|score| !score.is_nan())
+        .ok_or_else(|| "value is not a valid float".to_owned())
+}
+
+// score range ends are inclusive by default, and exclusive when prefixed with '('
+fn parse_score_range(min: &[u8], max: &[u8]) -> Result<ScoreRange, String> {
+    let parse_bound = |bound: &[u8]| {
+        let (bound, exclusive) = match bound.strip_prefix(b"(") {
+            Some(bound) => (bound, true),
+            None => (bound, false),
+        };
+        parse_score(bound)
+            .map(|score| (score, exclusive))
+            .map_err(|_| "min or max is not a float".to_owned())
+    };
+
+    let (min, min_exclusive) = parse_bound(min)?;
+    let (max, max_exclusive) = parse_bound(max)?;
+    Ok(ScoreRange { min, max, min_exclusive, max_exclusive })
+}
+
+fn parse_lex_bound(bound: &[u8]) -> Result<LexBound, String> {
+    match bound {
+        b"-" => Ok(LexBound::NegativeInfinity),
+        b"+" => Ok(LexBound::PositiveInfinity),
+        _ => {
+            if let Some(bound) = bound.strip_prefix(b"[") {
+                Ok(LexBound::Inclusive(bound.to_vec()))
+            } else if let Some(bound) = bound.strip_prefix(b"(") {
+                Ok(LexBound::Exclusive(bound.to_vec()))
+            } else {
+                Err("min or max not valid string range item".to_owned())
+            }
+        }
+    }
+}
+
+// stream IDs are '<ms>-<seq>', where a missing sequence number defaults to 'default_seq'
+fn parse_stream_id(argument: &[u8], default_seq: u64) -> Result<StreamId, String> {
+    let invalid = || "Invalid stream ID specified as stream command argument".to_owned();
+
+    let (ms, seq) = match argument.iter().position(|byte| *byte == b'-') {
+        Some(dash) => (&argument[..dash], parse_number::<u64>(&argument[dash + 1..]).ok_or_else(invalid)?),
+        None => (argument, default_seq),
+    };
+    let ms = parse_number::<u64>(ms).ok_or_else(invalid)?;
+    Ok(StreamId::new(ms, seq))
+}
+
+// the ID of a new entry can be fully ('*') or partially ('<ms>-*') generated
+fn parse_new_stream_id(argument: &[u8]) -> Result<NewStreamId, String> {
+    if argument == b"*" {
+        return Ok(NewStreamId::Auto);
+    }
+
+    match argument.strip_suffix(b"-*") {
+        Some(ms) => parse_number::<u64>(ms)
+            .map(NewStreamId::AutoSequence)
+            .ok_or_else(|| "Invalid stream ID specified as stream command argument".to_owned()),
+        None => parse_stream_id(argument, 0).map(NewStreamId::Explicit),
+    }
+}
+
+// range ends are '-'/'+' for the smallest/greatest ID, are inclusive by default and exclusive when prefixed with '(',
+// and an ID without sequence number stands for the whole millisecond
+fn parse_stream_range_bound(bound: &[u8], is_end: bool) -> Result<StreamId, String> {
+    let default_seq = if is_end { u64::MAX } else { 0 };
+
+    match bound {
+        b"-" => Ok(StreamId::MIN),
+        b"+" => Ok(StreamId::MAX),
+        _ => match bound.strip_prefix(b"(") {
+            Some(bound) => {
+                let id = parse_stream_id(bound, default_seq)?;
+                let id = if is_end { id.previous() } else { id.next() };
+                id.ok_or_else(|| format!("invalid {} ID for the interval", if is_end { "end" } else { "start" }))
+            }
+            None => parse_stream_id(bound, default_seq),
+        },
+    }
+}
+
+// consumer groups start either at a given ID, or after the last entry ('$')
+fn parse_group_start_id(argument: &[u8]) -> Result<GroupStartId, String> {
+    match argument {
+        b"$" => Ok(GroupStartId::LastEntry),
+        _ => parse_stream_id(argument, 0).map(GroupStartId::Id),
+    }
+}
+
+/// The 'MAXLEN|MINID [=|~] threshold [LIMIT count]' options shared by XADD and XTRIM.
+#[derive(Default)]
+struct TrimOptions {
+    strategy: Option<TrimStrategy>,
+    approximate: bool,
+    limit: Option<usize>,
+}
+
+impl TrimOptions {
+    /// Parses the option (and its arguments) if it is a trim option, returning whether it was one.
+    fn parse(&mut self, option: &[u8], arguments: &mut impl Iterator<Item = Vec<u8>>, cmd_name: &str) -> Result<bool, String> {
+        match option.to_ascii_lowercase().as_slice() {
+            strategy @ (b"maxlen" | b"minid") => {
+                let mut threshold = next_argument(arguments, cmd_name)?;
+                if threshold == b"~" || threshold == b"=" {
+                    self.approximate = threshold == b"~";
+                    threshold = next_argument(arguments, cmd_name)?;
+                }
+
+                self.strategy = Some(if strategy == b"maxlen" {
+                    TrimStrategy::MaxLen(parse_positive_integer(&threshold)?)
+                } else {
+                    TrimStrategy::MinId(parse_stream_id(&threshold, 0)?)
+                });
+                Ok(true)
+            }
+            b"limit" => {
+                self.limit = Some(parse_positive_integer(&next_argument(arguments, cmd_name)?)?);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn build(self) -> Result<Option<StreamTrim>, String> {
+        if self.limit.is_some() && !self.approximate {
+            return Err("syntax error, LIMIT cannot be used without the special ~ option".to_string());
+        }
+
+        Ok(self.strategy.map(|strategy| StreamTrim { strategy, limit: self.limit }))
+    }
+}
+
+// ===== Helpers for building replies =====
+
+// Redis writes infinite scores as 'inf'/'-inf', and others like '%.17g' does, with the fewest digits that read back as
+// the same score: integral scores without decimal places, and very large or small ones with an exponent (e.g. 1e+20)
+fn format_score(score: f64) -> Vec<u8> {
+    if score.is_infinite() {
+        return if score > 0.0 { b"inf".to_vec() } else { b"-inf".to_vec() };
+    }
+    let scientific = format!("{score:e}");
+    let (mantissa, exponent) = scientific.split_once('e').expect("scientific notation has an exponent");
+    let exponent: i32 = exponent.parse().expect("the exponent is an integer");
+    let score = if (-4..17).contains(&exponent) {
+        score.to_string()
+    } else {
+        format!("{mantissa}e{}{:02}", if exponent < 0 { '-' } else { '+' }, exponent.unsigned_abs())
+    };
+    score.into_bytes()
+}
+
+fn ok_reply(result: Result<(), String>) -> RespObject {
+    match result {
+        Ok(_) => SimpleString("OK".to_string()),
+        Err(e) => Error(e),
+    }
+}
+
+fn integer_reply(result: Result<usize, String>) -> RespObject {
+    match result {
+        Ok(value) => Integer(value as i64),
+        Err(e) => Error(e),
+    }
+}
+
+// Redis replies with 1/0 for commands that tell whether something happened or not
+fn boolean_reply(result: Result<bool, String>) -> RespObject {
+    match result {
+        Ok(value) => Integer(value as i64),
+        Err(e) => Error(e),
+    }
+}
+
+// a missing value is replied as nil
+fn optional_bulk_string_reply(result: Result<Option<Vec<u8>>, String>) -> RespObject {
+    match result {
+        Ok(value) => value.map_or(NullBulkString, BulkString),
+        Err(e) => Error(e),
+    }
+}
+
+fn bulk_string_array(values: Vec<&Vec<u8>>) -> RespObject {
+    Array(values.into_iter().map(|value| BulkString(value.clone())).collect())
+}
+
+// set members come back as a RESP3 set, which RESP2 clients receive as a plain array
+fn bulk_string_set(values: Vec<&Vec<u8>>) -> RespObject {
+    RespObject::Set(values.into_iter().map(|value| BulkString(value.clone())).collect())
+}
+
+// LPOP/RPOP reply with a single element when called without 'count', and with an array otherwise
+fn pop_reply(engine: &mut StorageEngine, key: &[u8], end: ListEnd, count: Option<usize>) -> RespObject {
+    match (engine.pop(key, end, count.unwrap_or(1)), count) {
+        (Ok(Some(mut popped)), None) => popped.pop().map_or(NullBulkString, BulkString),
+        (Ok(Some(popped)), Some(_)) => Array(popped.into_iter().map(BulkString).collect()),
+        (Ok(None), None) => NullBulkString,
+        (Ok(None), Some(_)) => NullArray,
+        (Err(e), _) => Error(e),
+    }
+}
+
+// BLPOP/BRPOP reply with the key and the element, BLMPOP (which has a 'count') with the key and the elements;
+// nothing means there was nothing to pop
+fn blocking_pop_reply(engine: &mut StorageEngine, key: &[u8], end: ListEnd, count: Option<usize>) -> Option<RespObject> {
+    match engine.pop(key, end, count.unwrap_or(1)) {
+        Ok(Some(mut popped)) if !popped.is_empty() => {
+            let popped = match count {
+                None => BulkString(popped.remove(0)),
+                Some(_) => Array(popped.into_iter().map(BulkString).collect()),
+            };
+            Some(Array(vec![BulkString(key.to_vec()), popped]))
+        }
+        Ok(_) => None,
+        Err(e) => Some(Error(e)),
+    }
+}
+
+// BZPOPMIN/BZPOPMAX reply with the key, the member and its score
+fn blocking_sorted_set_pop_reply(engine: &mut StorageEngine, key: &[u8], max: bool) -> Option<RespObject> {
+    match engine.sorted_set_pop(key, max, 1) {
+        Ok(mut popped) => popped.pop().map(|(member, score)| {
+            Array(vec![BulkString(key.to_vec()), BulkString(member), BulkString(format_score(score))])
+        }),
+        Err(e) => Some(Error(e)),
+    }
+}
+
+// retries the blocking commands of the clients waiting on the keys that were written to, the longest waiting first;
+// serving a client may write to other keys (e.g. BLMOVE), which serves the clients waiting on those in turn
+fn serve_blocked_clients(engine: &mut StorageEngine) {
+    while let Some((db, key)) = engine.blocked_clients_mut().take_ready_key() {
+        engine.select_db(db);
+        for client_id in engine.blocked_clients().waiting_on(db, &key) {
+            let Some(command) = engine.blocked_clients().client(client_id).map(|client| client.command().clone()) else {
+                continue;
+            };
+            if let Some(reply) = command.command.pop_blocking(engine, std::slice::from_ref(&key)) {
+                if !matches!(reply, Error(_)) {
+                    for arguments in command.propagated(engine, &reply) {
+                        engine.propagate(&arguments);
+                    }
+                }
+                if let Some(client) = engine.blocked_clients_mut().unblock(client_id) {
+                    client.send(reply);
+                }
+            }
+        }
+    }
+}
+
+// a stream entry is replied as its ID followed by the flattened field-value pairs (or nil, for deleted entries)
+fn stream_entry_reply(id: StreamId, fields: Option<&StreamFields>) -> RespObject {
+    let fields = fields.map_or(NullArray, |fields| {
+        Array(fields.iter()
+            .flat_map(|(field, value)| [BulkString(field.clone()), BulkString(value.clone())])
+            .collect())
+    });
+    Array(vec![BulkString(id.to_string().into_bytes()), fields])
+}
+
+fn stream_entries_reply(entries: Vec<(StreamId, &StreamFields)>) -> RespObject {
+    Array(entries.into_iter().map(|(id, fields)| stream_entry_reply(id, Some(fields))).collect())
+}
+
+fn stream_ids_reply(ids: impl Iterator<Item = StreamId>) -> RespObject {
+    Array(ids.map(|id| BulkString(id.to_string().into_bytes())).collect())
+}
+
+#[cfg(test)]
+mod command_creation_tests {
+    use super::*;
+    use crate::protocol::RespObject::{Error, Integer, NullArray, NullBulkString, SimpleString};
+
+    #[test]
+    fn create_ping_command() {
+        let cmd = Command::from(Array(vec![BulkString("ping".into())]));
+        assert_eq!(cmd, Ok(Command::parsed(RespCommand::Ping)));
+    }
+
+    #[test]
+    fn create_ping_command_from_uppercase() {
+        let cmd = Command::from(Array(vec![BulkString("PING".into())]));
+        assert_eq!(cmd, Ok(Command::parsed(RespCommand::Ping)));
+    }
+
+    #[test]
+    fn create_ping_command_from_mixed_case() {
+        let cmd = Command::from(Array(vec![BulkString("PinG".into())]));
+        assert_eq!(cmd, Ok(Command::parsed(RespCommand::Ping)));
+    }
+
+    #[test]
+    fn create_hello_command() {
+        let hello = |args: &[&str]| Command::from(Array(args.iter().map(|arg| BulkString(arg.as_bytes().to_vec())).collect()));
+
+        assert_eq!(hello(&["hello"]), Ok(Command::parsed(RespCommand::Hello { protocol: None, auth: None, client_name: None })));
+        assert_eq!(
+            hello(&["HELLO", "3", "auth", "default", "pass", "SETNAME", "me"]),
+            Ok(Command::parsed(RespCommand::Hello {
+                protocol: Some(ProtocolVersion::Resp3),
+                auth: Some(("default".into(), "pass".into())),
+                client_name: Some("me".into()),
+            }))
+        );
+        assert_eq!(hello(&["hello", "4"]), Err("NOPROTO unsupported protocol version".to_string()));
+        assert_eq!(hello(&["hello", "three"]), Err("Protocol version is not an integer or out of range".to_string()));
+        assert_eq!(hello(&["hello", "3", "auth", "default"]), Err("Wrong number of arguments for 'hello' command".to_string()));
+        assert_eq!(hello(&["hello", "3", "nope"]), Err("Syntax error in HELLO option 'nope'".to_string()));
+    }
+
+    #[test]
+    fn create_echo_command() {
+        let cmd = Command::from(Array(vec![BulkString("echo".into()), BulkString("\"Hello, world!\"".into())]));
+        assert_eq!(cmd, Ok(Command::parsed(RespCommand::Echo { message: "\"Hello, world!\"".into() })));
+    }
+
+    #[test]
+    fn create_plain_set_command() {
+        let cmd = Command::from(Array(vec![BulkString("set".into()), BulkString("Name".into()), BulkString("Doe".into())]));
+        assert_eq!(cmd, Ok(Command::parsed(RespCommand::Set(SetCommand::from_key_value(("Name".into(), "Doe".into()))))));
+    }
+
+    #[test]
     fn create_set_command_with_expiry() {
-        let cmd = Command::from(Array(vec![BulkString("set".to_owned()), BulkString("Name".to_owned()), BulkString("Doe".to_owned()), BulkString("EX".to_owned()), BulkString("3600".to_owned())]));
-        assert_eq!(cmd, Ok(Command(RespCommand::Set( SetCommand::from((String::from("Name"), String::from("Doe")), Some(3600))))));
+        let cmd = Command::from(Array(vec![BulkString("set".into()), BulkString("Name".into()), BulkString("Doe".into()), BulkString("EX".into()), BulkString("3600".into())]));
+        assert_eq!(cmd, Ok(Command::parsed(RespCommand::Set( SetCommand::with_options(("Name".into(), "Doe".into()), SetOptions { expiry: SetExpiry::After(Duration::from_secs(3600)), ..SetOptions::default() })))));
+    }
+
+    #[test]
+    fn create_set_command_with_all_options() {
+        let set = |args: &[&str]| Command::from(Array(args.iter().map(|arg| BulkString(arg.as_bytes().to_vec())).collect()));
+        let with_options = |options: SetOptions| Ok(Command::parsed(RespCommand::Set(SetCommand::with_options(("lock".into(), "me".into()), options))));
+
+        assert_eq!(
+            set(&["SET", "lock", "me", "NX", "PX", "30000"]),
+            with_options(SetOptions { condition: SetCondition::IfNotExists, expiry: SetExpiry::After(Duration::from_millis(30000)), get: false })
+        );
+        assert_eq!(
+            set(&["set", "lock", "me", "xx", "keepttl", "get"]),
+            with_options(SetOptions { condition: SetCondition::IfExists, expiry: SetExpiry::Keep, get: true })
+        );
+        assert_eq!(
+            set(&["set", "lock", "me", "exat", "1700000000"]),
+            with_options(SetOptions { expiry: SetExpiry::AtUnixTimeMillis(1_700_000_000_000), ..SetOptions::default() })
+        );
+        assert_eq!(
+            set(&["set", "lock", "me", "pxat", "1700000000123", "nx", "get"]),
+            with_options(SetOptions { condition: SetCondition::IfNotExists, expiry: SetExpiry::AtUnixTimeMillis(1_700_000_000_123), get: true })
+        );
+        // repeating an option is allowed, the last one wins
+        assert_eq!(
+            set(&["set", "lock", "me", "ex", "1", "ex", "2"]),
+            with_options(SetOptions { expiry: SetExpiry::After(Duration::from_secs(2)), ..SetOptions::default() })
+        );
+    }
+
+    #[test]
+    fn cannot_create_set_command_with_clashing_options() {
+        let set = |args: &[&str]| Command::from(Array(args.iter().map(|arg| BulkString(arg.as_bytes().to_vec())).collect()));
+        let syntax_error = Err("syntax error".to_string());
+
+        assert_eq!(set(&["set", "k", "v", "nx", "xx"]), syntax_error);
+        assert_eq!(set(&["set", "k", "v", "ex", "10", "px", "10000"]), syntax_error);
+        assert_eq!(set(&["set", "k", "v", "keepttl", "exat", "10"]), syntax_error);
+        assert_eq!(set(&["set", "k", "v", "pxat", "10", "keepttl"]), syntax_error);
+        assert_eq!(set(&["set", "k", "v", "ex"]), syntax_error);
+        assert_eq!(set(&["set", "k", "v", "unknown"]), syntax_error);
+    }
+
+    #[test]
+    fn cannot_create_set_command_with_invalid_expire_time() {
+        let set = |args: &[&str]| Command::from(Array(args.iter().map(|arg| BulkString(arg.as_bytes().to_vec())).collect()));
+        let invalid_expire_time = Err("invalid expire time in 'set' command".to_string());
+
+        assert_eq!(set(&["set", "k", "v", "ex", "0"]), invalid_expire_time);
+        assert_eq!(set(&["set", "k", "v", "px", "-5"]), invalid_expire_time);
+        assert_eq!(set(&["set", "k", "v", "ex", "9223372036854775807"]), invalid_expire_time);
+        assert_eq!(set(&["set", "k", "v", "ex", "ten"]), Err("value is not an integer or out of range".to_string()));
+    }
+
+    #[test]
+    fn create_counter_commands() {
+        let create = |args: &[&str]| Command::from(Array(args.iter().map(|arg| BulkString(arg.as_bytes().to_vec())).collect()));
+
+        assert_eq!(create(&["incr", "hits"]), Ok(Command::parsed(RespCommand::Incrby { key: "hits".into(), increment: 1 })));
+        assert_eq!(create(&["DECR", "hits"]), Ok(Command::parsed(RespCommand::Incrby { key: "hits".into(), increment: -1 })));
+        assert_eq!(create(&["incrby", "hits", "10"]), Ok(Command::parsed(RespCommand::Incrby { key: "hits".into(), increment: 10 })));
+        assert_eq!(create(&["decrby", "hits", "10"]), Ok(Command::parsed(RespCommand::Incrby { key: "hits".into(), increment: -10 })));
+        assert_eq!(create(&["incrbyfloat", "hits", "-1.5e1"]), Ok(Command::parsed(RespCommand::Incrbyfloat { key: "hits".into(), increment: -15.0 })));
+    }
+
+    #[test]
+    fn cannot_create_counter_commands_with_invalid_amounts() {
+        let create = |args: &[&str]| Command::from(Array(args.iter().map(|arg| BulkString(arg.as_bytes().to_vec())).collect()));
+
+        assert_eq!(create(&["incrby", "hits", "ten"]), Err("value is not an integer or out of range".to_string()));
+        assert_eq!(create(&["decrby", "hits", "-9223372036854775808"]), Err("value is not an integer or out of range".to_string()));
+        assert_eq!(create(&["incrbyfloat", "hits", "nan"]), Err("value is not a valid float".to_string()));
+        assert_eq!(create(&["incr", "hits", "1"]), Err("Wrong number of arguments for 'incr' command".to_string()));
+    }
+
+    #[test]
+    fn create_string_commands() {
+        let create = |args: &[&str]| Command::from(Array(args.iter().map(|arg| BulkString(arg.as_bytes().to_vec())).collect()));
+        let set = |key: &str, value: &str, options: SetOptions| Ok(Command::parsed(RespCommand::Set(SetCommand::with_options((key.into(), value.into()), options))));
+
+        assert_eq!(create(&["getrange", "k", "0", "-1"]), Ok(Command::parsed(RespCommand::Getrange { key: "k".into(), start: 0, end: -1 })));
+        assert_eq!(create(&["setrange", "k", "6", "v"]), Ok(Command::parsed(RespCommand::Setrange { key: "k".into(), offset: 6, value: "v".into() })));
+        assert_eq!(create(&["getex", "k"]), Ok(Command::parsed(RespCommand::Getex { key: "k".into(), expiry: SetExpiry::Keep })));
+        assert_eq!(create(&["getex", "k", "PERSIST"]), Ok(Command::parsed(RespCommand::Getex { key: "k".into(), expiry: SetExpiry::Never })));
+        assert_eq!(create(&["getex", "k", "px", "100"]), Ok(Command::parsed(RespCommand::Getex { key: "k".into(), expiry: SetExpiry::After(Duration::from_millis(100)) })));
+        assert_eq!(create(&["getset", "k", "v"]), set("k", "v", SetOptions { get: true, ..SetOptions::default() }));
+        assert_eq!(create(&["setex", "k", "10", "v"]), set("k", "v", SetOptions { expiry: SetExpiry::After(Duration::from_secs(10)), ..SetOptions::default() }));
+        assert_eq!(create(&["psetex", "k", "10", "v"]), set("k", "v", SetOptions { expiry: SetExpiry::After(Duration::from_millis(10)), ..SetOptions::default() }));
+        assert_eq!(
+            create(&["msetnx", "a", "1", "b", "2"]),
+            Ok(Command::parsed(RespCommand::Msetnx { key_values: vec![("a".into(), "1".into()), ("b".into(), "2".into())] }))
+        );
+    }
+
+    #[test]
+    fn cannot_create_string_commands_with_invalid_arguments() {
+        let create = |args: &[&str]| Command::from(Array(args.iter().map(|arg| BulkString(arg.as_bytes().to_vec())).collect()));
+
+        assert_eq!(create(&["setrange", "k", "-1", "v"]), Err("offset is out of range".to_string()));
+        assert_eq!(create(&["getex", "k", "ex", "10", "persist"]), Err("syntax error".to_string()));
+        assert_eq!(create(&["getex", "k", "keepttl"]), Err("syntax error".to_string()));
+        assert_eq!(create(&["getex", "k", "ex", "0"]), Err("invalid expire time in 'getex' command".to_string()));
+        assert_eq!(create(&["setex", "k", "0", "v"]), Err("invalid expire time in 'setex' command".to_string()));
+        assert_eq!(create(&["msetnx", "a", "1", "b"]), Err("Wrong number of arguments for 'msetnx' command".to_string()));
+    }
+
+    #[test]
+    fn create_get_command() {
+        let cmd = Command::from(Array(vec![BulkString("get".into()), BulkString("Name".into())]));
+        assert_eq!(cmd, Ok(Command::parsed(RespCommand::Get(GetCommand::from("Name".into())))));
+    }
+
+    #[test]
+    fn create_ttl_command() {
+        let cmd = Command::from(Array(vec![BulkString("ttl".into()), BulkString("Name".into())]));
+        assert_eq!(cmd, Ok(Command::parsed(RespCommand::Ttl { key: "Name".into() })));
+    }
+
+    #[test]
+    fn create_expire_commands() {
+        let create = |args: &[&str]| Command::from(Array(args.iter().map(|arg| BulkString(arg.as_bytes().to_vec())).collect()));
+        let conditions = ExpireConditions::default();
+
+        assert_eq!(create(&["expire", "k", "10"]), Ok(Command::parsed(RespCommand::Expire { key: "k".into(), at: ExpireAt::AfterMillis(10_000), conditions })));
+        assert_eq!(create(&["PEXPIRE", "k", "-5"]), Ok(Command::parsed(RespCommand::Expire { key: "k".into(), at: ExpireAt::AfterMillis(-5), conditions })));
+        assert_eq!(create(&["expireat", "k", "1700000000"]), Ok(Command::parsed(RespCommand::Expire { key: "k".into(), at: ExpireAt::UnixTimeMillis(1_700_000_000_000), conditions })));
+        assert_eq!(create(&["pexpireat", "k", "1700000000123"]), Ok(Command::parsed(RespCommand::Expire { key: "k".into(), at: ExpireAt::UnixTimeMillis(1_700_000_000_123), conditions })));
+
+        let conditions = ExpireConditions { only_with_expiry: true, only_greater: true, ..ExpireConditions::default() };
+        assert_eq!(create(&["expire", "k", "10", "xx", "GT"]), Ok(Command::parsed(RespCommand::Expire { key: "k".into(), at: ExpireAt::AfterMillis(10_000), conditions })));
+
+        assert_eq!(create(&["pttl", "k"]), Ok(Command::parsed(RespCommand::Pttl { key: "k".into() })));
+        assert_eq!(create(&["expiretime", "k"]), Ok(Command::parsed(RespCommand::Expiretime { key: "k".into() })));
+        assert_eq!(create(&["pexpiretime", "k"]), Ok(Command::parsed(RespCommand::Pexpiretime { key: "k".into() })));
+        assert_eq!(create(&["persist", "k"]), Ok(Command::parsed(RespCommand::Persist { key: "k".into() })));
+    }
+
+    #[test]
+    fn create_scan_command() {
+        let create = |args: &[&str]| Command::from(Array(args.iter().map(|arg| BulkString(arg.as_bytes().to_vec())).collect()));
+
+        assert_eq!(create(&["scan", "0"]), Ok(Command::parsed(RespCommand::Scan { cursor: 0, pattern: None, count: 10, value_type: None })));
+        assert_eq!(create(&["SCAN", "42", "match", "user:*", "COUNT", "100", "type", "hash"]), Ok(Command::parsed(RespCommand::Scan {
+            cursor: 42, pattern: Some("user:*".into()), count: 100, value_type: Some("hash".into()),
+        })));
+        assert_eq!(create(&["scan", "-1"]), Err("invalid cursor".to_string()));
+        assert_eq!(create(&["scan", "0", "count", "0"]), Err("syntax error".to_string()));
+        assert_eq!(create(&["scan", "0", "count"]), Err("Wrong number of arguments for 'scan' command".to_string()));
+        assert_eq!(create(&["scan", "0", "limit", "5"]), Err("syntax error".to_string()));
+        assert_eq!(create(&["dbsize", "extra"]), Err("Wrong number of arguments for 'dbsize' command".to_string()));
+    }
+
+    #[test]
+    fn create_transaction_commands() {
+        let create = |args: &[&str]| Command::from(Array(args.iter().map(|arg| BulkString(arg.as_bytes().to_vec())).collect()));
+
+        assert_eq!(create(&["MULTI"]), Ok(Command::parsed(RespCommand::Multi)));
+        assert_eq!(create(&["exec"]), Ok(Command::parsed(RespCommand::Exec)));
+        assert_eq!(create(&["discard"]), Ok(Command::parsed(RespCommand::Discard)));
+        assert_eq!(create(&["watch", "a", "b"]), Ok(Command::parsed(RespCommand::Watch { keys: vec!["a".into(), "b".into()] })));
+        assert_eq!(create(&["unwatch"]), Ok(Command::parsed(RespCommand::Unwatch)));
+
+        assert_eq!(create(&["multi", "now"]), Err("Wrong number of arguments for 'multi' command".to_string()));
+        assert_eq!(create(&["watch"]), Err("Wrong number of arguments for 'watch' command".to_string()));
+    }
+
+    #[test]
+    fn create_connection_commands() {
+        let create = |args: &[&str]| Command::from(Array(args.iter().map(|arg| BulkString(arg.as_bytes().to_vec())).collect()));
+
+        assert_eq!(create(&["QUIT"]), Ok(Command::parsed(RespCommand::Quit)));
+        assert_eq!(create(&["quit", "now"]), Ok(Command::parsed(RespCommand::Quit)));
+        assert_eq!(create(&["reset"]), Ok(Command::parsed(RespCommand::Reset)));
+
+        assert_eq!(create(&["reset", "all"]), Err("Wrong number of arguments for 'reset' command".to_string()));
+    }
+
+    #[test]
+    fn create_pubsub_commands() {
+        let create = |args: &[&str]| Command::from(Array(args.iter().map(|arg| BulkString(arg.as_bytes().to_vec())).collect()));
+
+        assert_eq!(create(&["subscribe", "a", "b"]), Ok(Command::parsed(RespCommand::Subscribe { channels: vec!["a".into(), "b".into()] })));
+        assert_eq!(create(&["unsubscribe"]), Ok(Command::parsed(RespCommand::Unsubscribe { channels: vec![] })));
+        assert_eq!(create(&["psubscribe", "a*"]), Ok(Command::parsed(RespCommand::Psubscribe { patterns: vec!["a*".into()] })));
+        assert_eq!(create(&["publish", "a", "hi"]), Ok(Command::parsed(RespCommand::Publish { channel: "a".into(), message: "hi".into() })));
+        assert_eq!(create(&["pubsub", "CHANNELS"]), Ok(Command::parsed(RespCommand::PubsubChannels { pattern: None })));
+        assert_eq!(create(&["pubsub", "numsub", "a", "b"]), Ok(Command::parsed(RespCommand::PubsubNumsub { channels: vec!["a".into(), "b".into()] })));
+        assert_eq!(create(&["pubsub", "numpat"]), Ok(Command::parsed(RespCommand::PubsubNumpat)));
+
+        assert_eq!(create(&["subscribe"]), Err("Wrong number of arguments for 'subscribe' command".to_string()));
+        assert_eq!(create(&["publish", "a"]), Err("Wrong number of arguments for 'publish' command".to_string()));
+        assert_eq!(create(&["pubsub", "channels", "a", "b"]), Err("Wrong number of arguments for 'pubsub' command".to_string()));
+        assert_eq!(create(&["pubsub", "shardchannels"]), Err("unknown subcommand 'shardchannels' for 'pubsub' command".to_string()));
+    }
+
+    #[test]
+    fn create_database_commands() {
+        let create = |args: &[&str]| Command::from(Array(args.iter().map(|arg| BulkString(arg.as_bytes().to_vec())).collect()));
+
+        assert_eq!(create(&["select", "3"]), Ok(Command::parsed(RespCommand::Select { db: 3 })));
+        assert_eq!(create(&["swapdb", "0", "1"]), Ok(Command::parsed(RespCommand::Swapdb { first: 0, second: 1 })));
+        assert_eq!(create(&["move", "k", "2"]), Ok(Command::parsed(RespCommand::Move { key: "k".into(), db: 2 })));
+        assert_eq!(create(&["flushdb"]), Ok(Command::parsed(RespCommand::Flushdb { asynchronously: false })));
+        assert_eq!(create(&["flushall", "ASYNC"]), Ok(Command::parsed(RespCommand::Flushall { asynchronously: true })));
+        assert_eq!(create(&["copy", "a", "b", "DB", "4"]), Ok(Command::parsed(RespCommand::Copy { source: "a".into(), destination: "b".into(), destination_db: Some(4), replace: false })));
+
+        assert_eq!(create(&["select", "-1"]), Err("DB index is out of range".to_string()));
+        assert_eq!(create(&["select", "one"]), Err("value is not an integer or out of range".to_string()));
+        assert_eq!(create(&["swapdb", "0"]), Err("Wrong number of arguments for 'swapdb' command".to_string()));
+        assert_eq!(create(&["flushdb", "lazy"]), Err("syntax error".to_string()));
+        assert_eq!(create(&["flushall", "sync", "async"]), Err("Wrong number of arguments for 'flushall' command".to_string()));
+    }
+
+    #[test]
+    fn create_key_management_commands() {
+        let create = |args: &[&str]| Command::from(Array(args.iter().map(|arg| BulkString(arg.as_bytes().to_vec())).collect()));
+
+        assert_eq!(create(&["renamenx", "a", "b"]), Ok(Command::parsed(RespCommand::Rename { key: "a".into(), new_key: "b".into(), only_if_new: true })));
+        assert_eq!(create(&["copy", "a", "b", "REPLACE"]), Ok(Command::parsed(RespCommand::Copy { source: "a".into(), destination: "b".into(), destination_db: None, replace: true })));
+        assert_eq!(create(&["unlink", "a", "b"]), Ok(Command::parsed(RespCommand::Del(DelCommand::from_keys(vec!["a".into(), "b".into()])))));
+        assert_eq!(create(&["object", "FREQ", "a"]), Ok(Command::parsed(RespCommand::ObjectFreq { key: "a".into() })));
+
+        assert_eq!(create(&["copy", "a", "b", "force"]), Err("syntax error".to_string()));
+        assert_eq!(create(&["copy", "a", "b", "db"]), Err("Wrong number of arguments for 'copy' command".to_string()));
+        assert_eq!(create(&["touch"]), Err("Wrong number of arguments for 'touch' command".to_string()));
+        assert_eq!(create(&["object", "size", "a"]), Err("unknown subcommand 'size' for 'object' command".to_string()));
+        assert_eq!(create(&["rename", "a"]), Err("Wrong number of arguments for 'rename' command".to_string()));
+    }
+
+    #[test]
+    fn cannot_create_expire_commands_with_invalid_arguments() {
+        let create = |args: &[&str]| Command::from(Array(args.iter().map(|arg| BulkString(arg.as_bytes().to_vec())).collect()));
+
+        assert_eq!(create(&["expire", "k"]), Err("Wrong number of arguments for 'expire' command".to_string()));
+        assert_eq!(create(&["expire", "k", "soon"]), Err("value is not an integer or out of range".to_string()));
+        assert_eq!(create(&["expire", "k", &i64::MAX.to_string()]), Err("invalid expire time in 'expire' command".to_string()));
+        assert_eq!(create(&["expire", "k", "10", "later"]), Err("Unsupported option later".to_string()));
+        assert_eq!(create(&["expire", "k", "10", "nx", "xx"]), Err("NX and XX, GT or LT options at the same time are not compatible".to_string()));
+        assert_eq!(create(&["expire", "k", "10", "gt", "nx"]), Err("NX and XX, GT or LT options at the same time are not compatible".to_string()));
+        assert_eq!(create(&["expire", "k", "10", "gt", "lt"]), Err("GT and LT options at the same time are not compatible".to_string()));
+        assert_eq!(create(&["persist", "k", "v"]), Err("Wrong number of arguments for 'persist' command".to_string()));
+    }
+
+    #[test]
+    fn cannot_create_empty_mset_command() {
+        let cmd = Command::from(Array(vec![BulkString("mset".into())]));
+        assert_eq!(cmd, Err("Wrong number of arguments for 'mset' command".to_string()));
+    }
+
+    #[test]
+    fn create_mset_command() {
+        let cmd = Command::from(Array(vec![BulkString("mset".into()), BulkString("FirstName".into()), BulkString("Jane".into()), BulkString("LastName".into()), BulkString("Doe".into())]));
+        assert_eq!(cmd, Ok(Command::parsed(RespCommand::Mset(MsetCommand::from_key_values(vec![("FirstName".into(), "Jane".into()), ("LastName".into(), "Doe".into())])))));
+    }
+
+    #[test]
+    fn cannot_create_empty_mget_command() {
+        let cmd = Command::from(Array(vec![BulkString("mget".into())]));
+        assert_eq!(cmd, Err("Wrong number of arguments for 'mget' command".to_string()));
+    }
+
+    #[test]
+    fn create_mget_command() {
+        let cmd = Command::from(Array(vec![BulkString("mget".into()), BulkString("FirstName".into()), BulkString("LastName".into())]));
+        assert_eq!(cmd, Ok(Command::parsed(RespCommand::Mget(MgetCommand::from_keys(vec!["FirstName".into(), "LastName".into()])))));
+    }
+
+    #[test]
+    fn cannot_create_empty_del_command() {
+        let cmd = Command::from(Array(vec![BulkString("del".into())]));
+        assert_eq!(cmd, Err("Wrong number of arguments for 'del' command".to_string()));
+    }
+
+    #[test]
+    fn create_del_command() {
+        let cmd = Command::from(Array(vec![BulkString("del".into()), BulkString("FirstName".into()), BulkString("LastName".into())]));
+        assert_eq!(cmd, Ok(Command::parsed(RespCommand::Del(DelCommand::from_keys(vec!["FirstName".into(), "LastName".into()])))));
+    }
+
+    #[test]
+    fn create_exists_command() {
+        let cmd = Command::from(Array(vec![BulkString("exists".into()), BulkString("FirstName".into()), BulkString("LastName".into())]));
+        assert_eq!(cmd, Ok(Command::parsed(RespCommand::Exists(ExistsCommand::from_keys(vec!["FirstName".into(), "LastName".into()])))));
+    }
+
+    #[test]
+    fn create_lpush_command() {
+        let cmd = Command::from(Array(vec![BulkString("lpush".into()), BulkString("list".into()), BulkString("a".into()), BulkString("b".into())]));
+        assert_eq!(cmd, Ok(Command::parsed(RespCommand::Lpush { key: "list".into(), values: vec!["a".into(), "b".into()] })));
+    }
+
+    #[test]
+    fn cannot_create_rpush_command_without_values() {
+        let cmd = Command::from(Array(vec![BulkString("rpush".into()), BulkString("list".into())]));
+        assert_eq!(cmd, Err("Wrong number of arguments for 'rpush' command".to_string()));
+    }
+
+    #[test]
+    fn create_lpop_command_with_count() {
+        let cmd = Command::from(Array(vec![BulkString("lpop".into()), BulkString("list".into()), BulkString("2".into())]));
+        assert_eq!(cmd, Ok(Command::parsed(RespCommand::Lpop { key: "list".into(), count: Some(2) })));
+    }
+
+    #[test]
+    fn cannot_create_rpop_command_with_negative_count() {
+        let cmd = Command::from(Array(vec![BulkString("rpop".into()), BulkString("list".into()), BulkString("-2".into())]));
+        assert_eq!(cmd, Err("value is out of range, must be positive".to_string()));
+    }
+
+    #[test]
+    fn create_lrange_command_with_negative_indexes() {
+        let cmd = Command::from(Array(vec![BulkString("lrange".into()), BulkString("list".into()), BulkString("-3".into()), BulkString("-1".into())]));
+        assert_eq!(cmd, Ok(Command::parsed(RespCommand::Lrange { key: "list".into(), start: -3, stop: -1 })));
+    }
+
+    #[test]
+    fn create_linsert_command() {
+        let cmd = Command::from(Array(vec![BulkString("linsert".into()), BulkString("list".into()), BulkString("BEFORE".into()), BulkString("pivot".into()), BulkString("value".into())]));
+        assert_eq!(cmd, Ok(Command::parsed(RespCommand::Linsert { key: "list".into(), position: InsertPosition::Before, pivot: "pivot".into(), value: "value".into() })));
+    }
+
+    #[test]
+    fn cannot_create_linsert_command_with_unknown_position() {
+        let cmd = Command::from(Array(vec![BulkString("linsert".into()), BulkString("list".into()), BulkString("around".into()), BulkString("pivot".into()), BulkString("value".into())]));
+        assert_eq!(cmd, Err("syntax error".to_string()));
+    }
+
+    #[test]
+    fn create_blocking_commands() {
+        let create = |args: &[&str]| Command::from(Array(args.iter().map(|arg| BulkString(arg.as_bytes().to_vec())).collect()));
+        let keys = vec![b"a".to_vec(), b"b".to_vec()];
+
+        assert_eq!(create(&["blpop", "a", "b", "0"]), Ok(Command::parsed(RespCommand::Blpop { keys: keys.clone(), timeout: None })));
+        assert_eq!(create(&["BRPOP", "a", "b", "0.5"]), Ok(Command::parsed(RespCommand::Brpop { keys: keys.clone(), timeout: Some(Duration::from_millis(500)) })));
+        assert_eq!(create(&["bzpopmax", "a", "b", "2"]), Ok(Command::parsed(RespCommand::Bzpopmax { keys: keys.clone(), timeout: Some(Duration::from_secs(2)) })));
+        assert_eq!(
+            create(&["blmove", "a", "b", "LEFT", "right", "0.01"]),
+            Ok(Command::parsed(RespCommand::Blmove { source: "a".into(), destination: "b".into(), from: ListEnd::Left, to: ListEnd::Right, timeout: Some(Duration::from_millis(10)) }))
+        );
+        assert_eq!(create(&["blmpop", "0", "2", "a", "b", "right"]), Ok(Command::parsed(RespCommand::Blmpop { keys: keys.clone(), end: ListEnd::Right, count: 1, timeout: None })));
+        assert_eq!(create(&["blmpop", "1", "2", "a", "b", "left", "COUNT", "3"]), Ok(Command::parsed(RespCommand::Blmpop { keys, end: ListEnd::Left, count: 3, timeout: Some(Duration::from_secs(1)) })));
+    }
+
+    #[test]
+    fn cannot_create_blocking_commands_with_invalid_arguments() {
+        let create = |args: &[&str]| Command::from(Array(args.iter().map(|arg| BulkString(arg.as_bytes().to_vec())).collect()));
+
+        assert_eq!(create(&["blpop", "0"]), Err("Wrong number of arguments for 'blpop' command".to_string()));
+        assert_eq!(create(&["blpop", "a", "soon"]), Err("timeout is not a float or out of range".to_string()));
+        assert_eq!(create(&["bzpopmin", "a", "inf"]), Err("timeout is not a float or out of range".to_string()));
+        assert_eq!(create(&["brpop", "a", "-1"]), Err("timeout is negative".to_string()));
+        assert_eq!(create(&["blmove", "a", "b", "up", "left", "0"]), Err("syntax error".to_string()));
+        assert_eq!(create(&["blmpop", "0", "0", "left"]), Err("numkeys should be greater than 0".to_string()));
+        assert_eq!(create(&["blmpop", "0", "1", "a", "up"]), Err("syntax error".to_string()));
+        assert_eq!(create(&["blmpop", "0", "1", "a", "left", "count", "0"]), Err("count should be greater than 0".to_string()));
+    }
+
+    #[test]
+    fn create_hset_command() {
+        let cmd = Command::from(Array(vec![BulkString("hset".into()), BulkString("user".into()), BulkString("name".into()), BulkString("Jane".into()), BulkString("age".into()), BulkString("42".into())]));
+        assert_eq!(cmd, Ok(Command::parsed(RespCommand::Hset { key: "user".into(), field_values: vec![("name".into(), "Jane".into()), ("age".into(), "42".into())] })));
+    }
+
+    #[test]
+    fn cannot_create_hset_command_with_field_without_value() {
+        let cmd = Command::from(Array(vec![BulkString("hset".into()), BulkString("user".into()), BulkString("name".into())]));
+        assert_eq!(cmd, Err("Wrong number of arguments for 'hset' command".to_string()));
+    }
+
+    #[test]
+    fn create_hincrby_command() {
+        let cmd = Command::from(Array(vec![BulkString("hincrby".into()), BulkString("user".into()), BulkString("age".into()), BulkString("-1".into())]));
+        assert_eq!(cmd, Ok(Command::parsed(RespCommand::Hincrby { key: "user".into(), field: "age".into(), increment: -1 })));
+    }
+
+    #[test]
+    fn create_sadd_command() {
+        let cmd = Command::from(Array(vec![BulkString("sadd".into()), BulkString("tags".into()), BulkString("a".into()), BulkString("b".into())]));
+        assert_eq!(cmd, Ok(Command::parsed(RespCommand::Sadd { key: "tags".into(), members: vec!["a".into(), "b".into()] })));
+    }
+
+    #[test]
+    fn create_set_algebra_commands() {
+        let cmd = Command::from(Array(vec![BulkString("SINTER".into()), BulkString("a".into()), BulkString("b".into())]));
+        assert_eq!(cmd, Ok(Command::parsed(RespCommand::SetAlgebra { operation: SetOperation::Intersection, keys: vec!["a".into(), "b".into()] })));
+
+        let cmd = Command::from(Array(vec![BulkString("sdiffstore".into()), BulkString("dest".into()), BulkString("a".into())]));
+        assert_eq!(cmd, Ok(Command::parsed(RespCommand::SetAlgebraStore { operation: SetOperation::Difference, destination: "dest".into(), keys: vec!["a".into()] })));
+    }
+
+    #[test]
+    fn cannot_create_srandmember_command_with_out_of_range_count() {
+        let srandmember = |count: &str| Command::from(Array(vec![BulkString("srandmember".into()), BulkString("tags".into()), BulkString(count.into())]));
+
+        assert_eq!(srandmember("-9223372036854775808"), Err("value is out of range".to_string()));
+        assert_eq!(srandmember("4611686018427387904"), Err("value is out of range".to_string()));
+        assert_eq!(srandmember("-4611686018427387903"), Err("value is out of range".to_string()));
+        assert_eq!(srandmember("-2147483648"), Err("value is out of range".to_string()));
+        assert_eq!(srandmember("-2147483647"), Ok(Command::parsed(RespCommand::Srandmember { key: "tags".into(), count: Some(-2147483647) })));
+    }
+
+    #[test]
+    fn cannot_create_sunionstore_command_without_keys() {
+        let cmd = Command::from(Array(vec![BulkString("sunionstore".into()), BulkString("dest".into())]));
+        assert_eq!(cmd, Err("Wrong number of arguments for 'sunionstore' command".to_string()));
+    }
+
+    #[test]
+    fn create_zadd_command_with_options() {
+        let cmd = Command::from(Array(vec![BulkString("zadd".into()), BulkString("board".into()), BulkString("XX".into()), BulkString("gt".into()), BulkString("CH".into()), BulkString("10".into()), BulkString("jane".into()), BulkString("-inf".into()), BulkString("john".into())]));
+        let conditions = SortedSetUpdateConditions { only_existing: true, only_greater: true, ..Default::default() };
+        assert_eq!(cmd, Ok(Command::parsed(RespCommand::Zadd { key: "board".into(), entries: vec![(10.0, "jane".into()), (f64::NEG_INFINITY, "john".into())], conditions, count_changed: true, increment: false })));
+    }
+
+    #[test]
+    fn cannot_create_zadd_command_with_incompatible_options() {
+        let zadd = |options: &[&str]| {
+            let mut args = vec![BulkString("zadd".into()), BulkString("board".into())];
+            args.extend(options.iter().map(|option| BulkString(option.as_bytes().to_vec())));
+            Command::from(Array(args))
+        };
+
+        assert_eq!(zadd(&["nx", "xx", "1", "a"]), Err("XX and NX options at the same time are not compatible".to_string()));
+        assert_eq!(zadd(&["nx", "gt", "1", "a"]), Err("GT, LT, and/or NX options at the same time are not compatible".to_string()));
+        assert_eq!(zadd(&["incr", "1", "a", "2", "b"]), Err("INCR option supports a single increment-element pair".to_string()));
+        assert_eq!(zadd(&["1", "a", "2"]), Err("Wrong number of arguments for 'zadd' command".to_string()));
+        assert_eq!(zadd(&["nan", "a"]), Err("syntax error".to_string()));
+        assert_eq!(zadd(&["1", "a", "nan", "b"]), Err("value is not a valid float".to_string()));
+    }
+
+    #[test]
+    fn create_zrange_command_with_reversed_score_range_and_limit() {
+        let cmd = Command::from(Array(vec![BulkString("zrange".into()), BulkString("board".into()), BulkString("(10".into()), BulkString("-inf".into()), BulkString("BYSCORE".into()), BulkString("REV".into()), BulkString("LIMIT".into()), BulkString("1".into()), BulkString("5".into()), BulkString("WITHSCORES".into())]));
+        let range = SortedSetRange::ByScore(ScoreRange { min: f64::NEG_INFINITY, max: 10.0, min_exclusive: false, max_exclusive: true });
+        assert_eq!(cmd, Ok(Command::parsed(RespCommand::Zrange { key: "board".into(), range, reverse: true, limit: Some((1, 5)), with_scores: true })));
+    }
+
+    #[test]
+    fn cannot_create_zrange_command_with_invalid_option_combinations() {
+        let zrange = |args: &[&str]| {
+            let mut request = vec![BulkString("zrange".into()), BulkString("board".into())];
+            request.extend(args.iter().map(|arg| BulkString(arg.as_bytes().to_vec())));
+            Command::from(Array(request))
+        };
+
+        assert_eq!(zrange(&["0", "1", "limit", "0", "1"]), Err("syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX".to_string()));
+        assert_eq!(zrange(&["[a", "[b", "bylex", "withscores"]), Err("syntax error, WITHSCORES not supported in combination with BYLEX".to_string()));
+        assert_eq!(zrange(&["a", "b", "bylex"]), Err("min or max not valid string range item".to_string()));
+        assert_eq!(zrange(&["a", "1", "byscore"]), Err("min or max is not a float".to_string()));
+    }
+
+    #[test]
+    fn create_xadd_command_with_trimming_and_partial_id() {
+        let cmd = Command::from(Array(vec![BulkString("xadd".into()), BulkString("events".into()), BulkString("NOMKSTREAM".into()), BulkString("MAXLEN".into()), BulkString("~".into()), BulkString("10".into()), BulkString("LIMIT".into()), BulkString("5".into()), BulkString("12-*".into()), BulkString("field".into()), BulkString("value".into())]));
+        let trim = Some(StreamTrim { strategy: TrimStrategy::MaxLen(10), limit: Some(5) });
+        assert_eq!(cmd, Ok(Command::parsed(RespCommand::Xadd { key: "events".into(), id: NewStreamId::AutoSequence(12), fields: vec![("field".into(), "value".into())], trim, no_create: true })));
+    }
+
+    #[test]
+    fn cannot_create_xadd_command_with_invalid_arguments() {
+        let xadd = |args: &[&str]| Command::from(Array(args.iter().map(|arg| BulkString(arg.as_bytes().to_vec())).collect()));
+
+        assert_eq!(xadd(&["xadd", "events", "*", "field"]), Err("Wrong number of arguments for 'xadd' command".to_owned()));
+        assert_eq!(xadd(&["xadd", "events", "1-x", "field", "value"]), Err("Invalid stream ID specified as stream command argument".to_owned()));
+        assert_eq!(xadd(&["xadd", "events", "maxlen", "10", "limit", "5", "*", "field", "value"]), Err("syntax error, LIMIT cannot be used without the special ~ option".to_owned()));
+    }
+
+    #[test]
+    fn create_xrevrange_command_with_exclusive_and_incomplete_ids() {
+        let cmd = Command::from(Array(vec![BulkString("xrevrange".into()), BulkString("events".into()), BulkString("(5-0".into()), BulkString("2".into()), BulkString("COUNT".into()), BulkString("3".into())]));
+        assert_eq!(cmd, Ok(Command::parsed(RespCommand::Xrange { key: "events".into(), start: StreamId::new(2, 0), end: StreamId::new(4, u64::MAX), reverse: true, count: Some(3) })));
+    }
+
+    #[test]
+    fn create_xreadgroup_command() {
+        let cmd = Command::from(Array(vec![BulkString("xreadgroup".into()), BulkString("GROUP".into()), BulkString("group".into()), BulkString("alice".into()), BulkString("COUNT".into()), BulkString("2".into()), BulkString("STREAMS".into()), BulkString("a".into()), BulkString("b".into()), BulkString(">".into()), BulkString("0".into())]));
+        let streams = vec![("a".into(), None), ("b".into(), Some(StreamId::MIN))];
+        assert_eq!(cmd, Ok(Command::parsed(RespCommand::Xreadgroup { group: "group".into(), consumer: "alice".into(), streams, count: Some(2), no_ack: false })));
+    }
+
+    #[test]
+    fn create_xgroup_createconsumer_command_with_seen_time() {
+        let create = |args: &[&str]| Command::from(Array(args.iter().map(|arg| BulkString(arg.as_bytes().to_vec())).collect()));
+
+        assert_eq!(create(&["xgroup", "createconsumer", "events", "group", "bob", "SEENTIME", "1000"]),
+                   Ok(Command::parsed(RespCommand::XgroupCreateConsumer { key: "events".into(), group: "group".into(), consumer: "bob".into(), seen_time: Some(1000) })));
+        assert_eq!(create(&["xgroup", "createconsumer", "events", "group", "bob", "idle", "1000"]), Err("syntax error".to_owned()));
+        assert_eq!(create(&["xgroup", "createconsumer", "events", "group", "bob", "seentime", "-1"]), Err("value is out of range, must be positive".to_owned()));
+    }
+
+    #[test]
+    fn cannot_create_xreadgroup_command_with_unbalanced_streams() {
+        let cmd = Command::from(Array(vec![BulkString("xreadgroup".into()), BulkString("GROUP".into()), BulkString("group".into()), BulkString("alice".into()), BulkString("STREAMS".into()), BulkString("a".into()), BulkString("b".into()), BulkString(">".into())]));
+        assert!(cmd.is_err());
+    }
+
+    #[test]
+    fn create_xclaim_command_with_options() {
+        let cmd = Command::from(Array(vec![BulkString("xclaim".into()), BulkString("events".into()), BulkString("group".into()), BulkString("bob".into()), BulkString("1000".into()), BulkString("1-0".into()), BulkString("2-0".into()), BulkString("RETRYCOUNT".into()), BulkString("3".into()), BulkString("JUSTID".into())]));
+        let options = ClaimOptions { retry_count: Some(3), just_id: true, ..ClaimOptions::default() };
+        assert_eq!(cmd, Ok(Command::parsed(RespCommand::Xclaim { key: "events".into(), group: "group".into(), consumer: "bob".into(), min_idle: 1000, ids: vec![StreamId::new(1, 0), StreamId::new(2, 0)], options })));
+    }
+
+    #[test]
+    fn cannot_create_non_existing_command() {
+        let cmd = Command::from(Array(vec![BulkString("whubalubadubdub".into())]));
+        assert_eq!(cmd, Err("unknown command 'whubalubadubdub'".to_owned()));
+    }
+
+    #[test]
+    fn cannot_create_command_from_empty_array() {
+        let cmd = Command::from(Array(vec![]));
+        assert!(cmd.is_err());
+    }
+
+    #[test]
+    fn cannot_create_command_from_null_array() {
+        let cmd = Command::from(NullArray);
+        assert!(cmd.is_err());
+    }
+
+    #[test]
+    fn cannot_create_command_from_array_that_doesnt_contain_only_bulk_strings() {
+        let cmd = Command::from(Array(vec![Integer(4)]));
+        assert_eq!(cmd, Err("Array should only contain BulkStrings".to_owned()));
+    }
+
+    #[test]
+    fn cannot_create_command_from_error() {
+        let cmd = Command::from(Error(String::from("Error message")));
+        assert!(cmd.is_err());
+    }
+
+    #[test]
+    fn cannot_create_command_from_integer() {
+        let cmd = Command::from(Integer(0));
+        assert!(cmd.is_err());
+    }
+
+    #[test]
+    fn cannot_create_command_from_simple_string() {
+        let cmd = Command::from(SimpleString("foobar".to_owned()));
+        assert!(cmd.is_err());
+    }
+
+    #[test]
+    fn cannot_create_command_from_bulk_string() {
+        // 'PING' is a valid command, but commands are expected to come in an Array
+        let cmd = Command::from(BulkString("PING".into()));
+        assert!(cmd.is_err());
+    }
+
+    #[test]
+    fn cannot_create_command_from_null_bulk_string() {
+        let cmd = Command::from(NullBulkString);
+        assert!(cmd.is_err());
+    }
+}
+
+#[cfg(test)]
+mod command_execution_tests {
+    use crate::command::{Command, DelCommand, ExistsCommand, GetCommand, MgetCommand, MsetCommand, RespCommand, SetCommand};
+    use crate::engine::{ExpireConfig, InsertPosition, StorageEngine};
+    use crate::protocol::{ProtocolVersion, RespObject};
+    use crate::protocol::RespObject::{Array, BulkString, Error, Integer, Map, Null, NullArray, NullBulkString, SimpleString};
+    use crate::session::Session;
+    use mock_instant::thread_local::MockClock;
+    use std::time::Duration;
+
+    // replies as a client that has not switched protocol receives them
+    fn execute(engine: &mut StorageEngine, args: &[&str]) -> RespObject {
+        execute_in(&mut Session::new(), engine, args)
+    }
+
+    fn execute_in(session: &mut Session, engine: &mut StorageEngine, args: &[&str]) -> RespObject {
+        let request = Array(args.iter().map(|arg| BulkString(arg.as_bytes().to_vec())).collect());
+        Command::from(request).unwrap().execute(session, engine).into_protocol(session.protocol())
+    }
+
+    fn bulk_strings(values: &[&str]) -> RespObject {
+        Array(values.iter().map(|value| BulkString(value.as_bytes().to_vec())).collect())
+    }
+
+    #[test]
+    fn execute_ping_should_return_pong() {
+        let mut engine = StorageEngine::new();
+        let cmd = Command::parsed(RespCommand::Ping);
+
+        let result = cmd.execute_on(&mut engine);
+        assert_eq!(result, SimpleString("PONG".to_owned()));
+    }
+
+    #[test]
+    fn execute_hello_without_arguments_keeps_protocol() {
+        let mut engine = StorageEngine::new();
+        let mut session = Session::new();
+
+        let reply = execute_in(&mut session, &mut engine, &["hello"]);
+
+        assert_eq!(session.protocol(), ProtocolVersion::Resp2);
+        match reply {
+            Array(entries) => {
+                assert_eq!(entries[0..2], [BulkString("server".into()), BulkString("redis".into())]);
+                assert_eq!(entries[4..6], [BulkString("proto".into()), Integer(2)]);
+                assert_eq!(entries[6..8], [BulkString("id".into()), Integer(session.id() as i64)]);
+            }
+            other => panic!("Array expected, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn execute_hello_switches_to_resp3_and_back() {
+        let mut engine = StorageEngine::new();
+        let mut session = Session::new();
+
+        // the reply to HELLO 3 is already written in RESP3
+        let reply = execute_in(&mut session, &mut engine, &["hello", "3"]);
+        assert_eq!(session.protocol(), ProtocolVersion::Resp3);
+        assert!(matches!(&reply, Map(pairs) if pairs[2] == (BulkString("proto".into()), Integer(3))));
+
+        execute_in(&mut session, &mut engine, &["hello", "2"]);
+        assert_eq!(session.protocol(), ProtocolVersion::Resp2);
+    }
+
+    #[test]
+    fn execute_hello_with_auth_and_setname() {
+        let mut engine = StorageEngine::new();
+        let mut session = Session::new();
+
+        execute_in(&mut session, &mut engine, &["hello", "3", "AUTH", "default", "secret", "SETNAME", "worker-1"]);
+
+        assert_eq!(session.protocol(), ProtocolVersion::Resp3);
+        assert_eq!(session.client_name(), Some(b"worker-1".as_slice()));
+    }
+
+    #[test]
+    fn execute_hello_rejects_unknown_user_and_bad_names() {
+        let mut engine = StorageEngine::new();
+        let mut session = Session::new();
+
+        assert_eq!(
+            execute_in(&mut session, &mut engine, &["hello", "3", "auth", "jane", "secret"]),
+            Error("WRONGPASS invalid username-password pair or user is disabled.".into())
+        );
+        assert_eq!(
+            execute_in(&mut session, &mut engine, &["hello", "3", "setname", "with space"]),
+            Error("Client names cannot contain spaces, newlines or special characters.".into())
+        );
+        // nothing changes when HELLO fails
+        assert_eq!(session.protocol(), ProtocolVersion::Resp2);
+        assert_eq!(session.client_name(), None);
+    }
+
+    #[test]
+    fn execute_hgetall_replies_with_map_under_resp3() {
+        let mut engine = StorageEngine::new();
+        let mut session = Session::new();
+        execute_in(&mut session, &mut engine, &["hset", "user", "name", "Jane"]);
+        execute_in(&mut session, &mut engine, &["hello", "3"]);
+
+        assert_eq!(
+            execute_in(&mut session, &mut engine, &["hgetall", "user"]),
+            Map(vec![(BulkString("name".into()), BulkString("Jane".into()))])
+        );
+        assert_eq!(execute_in(&mut session, &mut engine, &["hgetall", "missing"]), Map(vec![]));
+    }
+
+    #[test]
+    fn execute_resp3_replies_use_sets_and_null() {
+        let mut engine = StorageEngine::new();
+        let mut session = Session::new();
+        execute_in(&mut session, &mut engine, &["sadd", "tags", "a"]);
+        execute_in(&mut session, &mut engine, &["hello", "3"]);
+
+        assert_eq!(execute_in(&mut session, &mut engine, &["smembers", "tags"]), RespObject::Set(vec![BulkString("a".into())]));
+        assert_eq!(execute_in(&mut session, &mut engine, &["sunion", "tags", "missing"]), RespObject::Set(vec![BulkString("a".into())]));
+        assert_eq!(execute_in(&mut session, &mut engine, &["get", "missing"]), Null);
+    }
+
+    #[test]
+    fn execute_echo_should_return_first_parameter() {
+        let mut engine = StorageEngine::new();
+        let cmd = Command::parsed(RespCommand::Echo { message: "\"Hello, world\"".into() });
+
+        let result = cmd.execute_on(&mut engine);
+        assert_eq!(result, BulkString("\"Hello, world\"".into()));
+    }
+
+    #[test]
+    fn execute_set_with_nx_and_xx() {
+        let mut engine = StorageEngine::new();
+
+        assert_eq!(execute(&mut engine, &["set", "lock", "a", "xx"]), NullBulkString);
+        assert_eq!(execute(&mut engine, &["get", "lock"]), NullBulkString);
+
+        assert_eq!(execute(&mut engine, &["set", "lock", "a", "nx"]), SimpleString("OK".into()));
+        assert_eq!(execute(&mut engine, &["set", "lock", "b", "nx"]), NullBulkString);
+        assert_eq!(execute(&mut engine, &["get", "lock"]), BulkString("a".into()));
+
+        assert_eq!(execute(&mut engine, &["set", "lock", "c", "xx"]), SimpleString("OK".into()));
+        assert_eq!(execute(&mut engine, &["get", "lock"]), BulkString("c".into()));
+    }
+
+    #[test]
+    fn execute_set_with_get_returns_previous_value() {
+        let mut engine = StorageEngine::new();
+
+        assert_eq!(execute(&mut engine, &["set", "k", "a", "get"]), NullBulkString);
+        assert_eq!(execute(&mut engine, &["set", "k", "b", "get"]), BulkString("a".into()));
+        // the previous value is returned even when NX prevents the write
+        assert_eq!(execute(&mut engine, &["set", "k", "c", "nx", "get"]), BulkString("b".into()));
+        assert_eq!(execute(&mut engine, &["get", "k"]), BulkString("b".into()));
+    }
+
+    #[test]
+    fn execute_set_with_get_on_wrong_type_does_not_write() {
+        let mut engine = StorageEngine::new();
+        execute(&mut engine, &["rpush", "list", "a"]);
+
+        assert_eq!(
+            execute(&mut engine, &["set", "list", "v", "get"]),
+            Error("WRONGTYPE Operation against a key holding the wrong kind of value".into())
+        );
+        assert_eq!(execute(&mut engine, &["llen", "list"]), Integer(1));
+        // without GET, SET overwrites any type
+        assert_eq!(execute(&mut engine, &["set", "list", "v"]), SimpleString("OK".into()));
+        assert_eq!(execute(&mut engine, &["get", "list"]), BulkString("v".into()));
+    }
+
+    #[test]
+    fn execute_set_with_px_expires_the_key() {
+        let mut engine = StorageEngine::new();
+
+        execute(&mut engine, &["set", "lock", "me", "nx", "px", "1500"]);
+
+        MockClock::advance(Duration::from_millis(1000));
+        assert_eq!(execute(&mut engine, &["get", "lock"]), BulkString("me".into()));
+        MockClock::advance(Duration::from_millis(1000));
+        assert_eq!(execute(&mut engine, &["get", "lock"]), NullBulkString);
+        // once expired, the lock can be taken again
+        assert_eq!(execute(&mut engine, &["set", "lock", "you", "nx", "px", "1500"]), SimpleString("OK".into()));
+    }
+
+    #[test]
+    fn execute_set_with_keepttl_keeps_the_expiry() {
+        let mut engine = StorageEngine::new();
+
+        execute(&mut engine, &["set", "k", "a", "ex", "10"]);
+        execute(&mut engine, &["set", "k", "b", "keepttl"]);
+
+        MockClock::advance(Duration::from_secs(9));
+        assert_eq!(execute(&mut engine, &["get", "k"]), BulkString("b".into()));
+        MockClock::advance(Duration::from_secs(2));
+        assert_eq!(execute(&mut engine, &["get", "k"]), NullBulkString);
+    }
+
+    #[test]
+    fn execute_set_without_expiry_discards_the_previous_one() {
+        let mut engine = StorageEngine::new();
+
+        execute(&mut engine, &["set", "k", "a", "ex", "10"]);
+        execute(&mut engine, &["set", "k", "b"]);
+
+        MockClock::advance(Duration::from_secs(11));
+        assert_eq!(execute(&mut engine, &["get", "k"]), BulkString("b".into()));
+    }
+
+    #[test]
+    fn execute_set_with_exat_and_pxat() {
+        let mut engine = StorageEngine::new();
+        MockClock::set_system_time(Duration::from_secs(1_700_000_000));
+
+        execute(&mut engine, &["set", "future", "v", "exat", "1700000010"]);
+        execute(&mut engine, &["set", "past", "v", "pxat", "1699999999000"]);
+
+        assert_eq!(execute(&mut engine, &["get", "future"]), BulkString("v".into()));
+        assert_eq!(execute(&mut engine, &["get", "past"]), NullBulkString);
+        MockClock::advance(Duration::from_secs(11));
+        assert_eq!(execute(&mut engine, &["get", "future"]), NullBulkString);
+    }
+
+    #[test]
+    fn execute_ttl_and_pttl_count_down() {
+        let mut engine = StorageEngine::new();
+        execute(&mut engine, &["set", "k", "v", "ex", "10"]);
+
+        assert_eq!(execute(&mut engine, &["ttl", "k"]), Integer(10));
+        assert_eq!(execute(&mut engine, &["pttl", "k"]), Integer(10_000));
+
+        MockClock::advance(Duration::from_millis(2_400));
+        assert_eq!(execute(&mut engine, &["ttl", "k"]), Integer(8));
+        assert_eq!(execute(&mut engine, &["pttl", "k"]), Integer(7_600));
+
+        // rounded to the closest second
+        MockClock::advance(Duration::from_millis(200));
+        assert_eq!(execute(&mut engine, &["ttl", "k"]), Integer(7));
+
+        MockClock::advance(Duration::from_millis(7_399));
+        assert_eq!(execute(&mut engine, &["pttl", "k"]), Integer(1));
+        MockClock::advance(Duration::from_millis(2));
+        assert_eq!(execute(&mut engine, &["pttl", "k"]), Integer(-2));
+        assert_eq!(execute(&mut engine, &["ttl", "k"]), Integer(-2));
+    }
+
+    #[test]
+    fn execute_expiry_ignores_wall_clock_jumps() {
+        let mut engine = StorageEngine::new();
+        MockClock::set_system_time(Duration::from_secs(1_700_000_000));
+        execute(&mut engine, &["set", "k", "v", "px", "1500"]);
+
+        // the wall clock jumping forward neither expires the key nor shortens its TTL
+        MockClock::advance_system_time(Duration::from_secs(3_600));
+        assert_eq!(execute(&mut engine, &["get", "k"]), BulkString("v".into()));
+        assert_eq!(execute(&mut engine, &["pttl", "k"]), Integer(1_500));
+
+        // nor does jumping backwards resurrect it once it expired
+        MockClock::advance(Duration::from_millis(1_501));
+        MockClock::set_system_time(Duration::from_secs(1_600_000_000));
+        assert_eq!(execute(&mut engine, &["get", "k"]), NullBulkString);
+    }
+
+    #[test]
+    fn execute_append_and_strlen() {
+        let mut engine = StorageEngine::new();
+
+        assert_eq!(execute(&mut engine, &["strlen", "greeting"]), Integer(0));
+        assert_eq!(execute(&mut engine, &["append", "greeting", "Hello"]), Integer(5));
+        assert_eq!(execute(&mut engine, &["append", "greeting", " World"]), Integer(11));
+        assert_eq!(execute(&mut engine, &["strlen", "greeting"]), Integer(11));
+        assert_eq!(execute(&mut engine, &["get", "greeting"]), BulkString("Hello World".into()));
+    }
+
+    #[test]
+    fn execute_getrange() {
+        let mut engine = StorageEngine::new();
+        execute(&mut engine, &["set", "k", "This is a string"]);
+
+        assert_eq!(execute(&mut engine, &["getrange", "k", "0", "3"]), BulkString("This".into()));
+        assert_eq!(execute(&mut engine, &["getrange", "k", "-3", "-1"]), BulkString("ing".into()));
+        assert_eq!(execute(&mut engine, &["getrange", "k", "0", "-1"]), BulkString("This is a string".into()));
+        assert_eq!(execute(&mut engine, &["getrange", "k", "10", "100"]), BulkString("string".into()));
+        assert_eq!(execute(&mut engine, &["getrange", "k", "0", "-100"]), BulkString("T".into()));
+        assert_eq!(execute(&mut engine, &["getrange", "k", "-1", "-5"]), BulkString("".into()));
+        assert_eq!(execute(&mut engine, &["getrange", "k", "5", "3"]), BulkString("".into()));
+        assert_eq!(execute(&mut engine, &["getrange", "missing", "0", "-1"]), BulkString("".into()));
+    }
+
+    #[test]
+    fn execute_setrange() {
+        let mut engine = StorageEngine::new();
+        execute(&mut engine, &["set", "k", "Hello World"]);
+
+        assert_eq!(execute(&mut engine, &["setrange", "k", "6", "Redis"]), Integer(11));
+        assert_eq!(execute(&mut engine, &["get", "k"]), BulkString("Hello Redis".into()));
+
+        // missing keys are padded with zero bytes
+        assert_eq!(execute(&mut engine, &["setrange", "padded", "3", "ab"]), Integer(5));
+        assert_eq!(execute(&mut engine, &["get", "padded"]), BulkString(b"\0\0\0ab".to_vec()));
+
+        // an empty value does not create the key
+        assert_eq!(execute(&mut engine, &["setrange", "empty", "10", ""]), Integer(0));
+        assert_eq!(execute(&mut engine, &["exists", "empty"]), Integer(0));
+
+        assert_eq!(
+            execute(&mut engine, &["setrange", "k", "536870911", "ab"]),
+            Error("string exceeds maximum allowed size (proto-max-bulk-len)".into())
+        );
+    }
+
+    #[test]
+    fn execute_getdel() {
+        let mut engine = StorageEngine::new();
+        execute(&mut engine, &["set", "k", "v"]);
+        execute(&mut engine, &["rpush", "list", "a"]);
+
+        assert_eq!(execute(&mut engine, &["getdel", "k"]), BulkString("v".into()));
+        assert_eq!(execute(&mut engine, &["getdel", "k"]), NullBulkString);
+        assert_eq!(
+            execute(&mut engine, &["getdel", "list"]),
+            Error("WRONGTYPE Operation against a key holding the wrong kind of value".into())
+        );
+        assert_eq!(execute(&mut engine, &["llen", "list"]), Integer(1));
+    }
+
+    #[test]
+    fn execute_getex_changes_the_expiry() {
+        let mut engine = StorageEngine::new();
+        execute(&mut engine, &["set", "expiring", "v"]);
+        execute(&mut engine, &["set", "persisted", "v", "ex", "5"]);
+        execute(&mut engine, &["set", "kept", "v", "ex", "5"]);
+
+        assert_eq!(execute(&mut engine, &["getex", "expiring", "ex", "5"]), BulkString("v".into()));
+        assert_eq!(execute(&mut engine, &["getex", "persisted", "persist"]), BulkString("v".into()));
+        assert_eq!(execute(&mut engine, &["getex", "kept"]), BulkString("v".into()));
+        assert_eq!(execute(&mut engine, &["getex", "missing", "ex", "5"]), NullBulkString);
+
+        MockClock::advance(Duration::from_secs(6));
+        assert_eq!(execute(&mut engine, &["get", "expiring"]), NullBulkString);
+        assert_eq!(execute(&mut engine, &["get", "persisted"]), BulkString("v".into()));
+        assert_eq!(execute(&mut engine, &["get", "kept"]), NullBulkString);
+    }
+
+    #[test]
+    fn execute_getset_returns_old_value_and_discards_ttl() {
+        let mut engine = StorageEngine::new();
+
+        assert_eq!(execute(&mut engine, &["getset", "k", "a"]), NullBulkString);
+        execute(&mut engine, &["set", "k", "a", "ex", "5"]);
+        assert_eq!(execute(&mut engine, &["getset", "k", "b"]), BulkString("a".into()));
+
+        MockClock::advance(Duration::from_secs(6));
+        assert_eq!(execute(&mut engine, &["get", "k"]), BulkString("b".into()));
+    }
+
+    #[test]
+    fn execute_setnx_setex_and_psetex() {
+        let mut engine = StorageEngine::new();
+
+        assert_eq!(execute(&mut engine, &["setnx", "k", "a"]), Integer(1));
+        assert_eq!(execute(&mut engine, &["setnx", "k", "b"]), Integer(0));
+        assert_eq!(execute(&mut engine, &["get", "k"]), BulkString("a".into()));
+
+        assert_eq!(execute(&mut engine, &["setex", "seconds", "2", "v"]), SimpleString("OK".into()));
+        assert_eq!(execute(&mut engine, &["psetex", "millis", "1500", "v"]), SimpleString("OK".into()));
+
+        MockClock::advance(Duration::from_millis(1600));
+        assert_eq!(execute(&mut engine, &["get", "seconds"]), BulkString("v".into()));
+        assert_eq!(execute(&mut engine, &["get", "millis"]), NullBulkString);
+    }
+
+    #[test]
+    fn execute_msetnx_is_all_or_nothing() {
+        let mut engine = StorageEngine::new();
+
+        assert_eq!(execute(&mut engine, &["msetnx", "a", "1", "b", "2"]), Integer(1));
+        assert_eq!(execute(&mut engine, &["msetnx", "b", "3", "c", "4"]), Integer(0));
+
+        assert_eq!(execute(&mut engine, &["mget", "a", "b", "c"]), Array(vec![BulkString("1".into()), BulkString("2".into()), NullBulkString]));
+    }
+
+    #[test]
+    fn execute_counters() {
+        let mut engine = StorageEngine::new();
+
+        assert_eq!(execute(&mut engine, &["incr", "hits"]), Integer(1));
+        assert_eq!(execute(&mut engine, &["incrby", "hits", "41"]), Integer(42));
+        assert_eq!(execute(&mut engine, &["decr", "hits"]), Integer(41));
+        assert_eq!(execute(&mut engine, &["decrby", "hits", "50"]), Integer(-9));
+        assert_eq!(execute(&mut engine, &["incrbyfloat", "hits", "0.5"]), BulkString("-8.5".into()));
+        assert_eq!(execute(&mut engine, &["get", "hits"]), BulkString("-8.5".into()));
+        assert_eq!(execute(&mut engine, &["incr", "hits"]), Error("value is not an integer or out of range".into()));
+    }
+
+    #[test]
+    fn execute_counters_keep_ttl() {
+        let mut engine = StorageEngine::new();
+
+        execute(&mut engine, &["set", "rate", "0", "ex", "10"]);
+        execute(&mut engine, &["incr", "rate"]);
+        execute(&mut engine, &["incrbyfloat", "rate", "1"]);
+
+        MockClock::advance(Duration::from_secs(11));
+        assert_eq!(execute(&mut engine, &["get", "rate"]), NullBulkString);
+    }
+
+    #[test]
+    fn execute_expire_and_persist() {
+        let mut engine = StorageEngine::new();
+        MockClock::set_system_time(Duration::from_secs(1_000));
+        execute(&mut engine, &["set", "session", "data"]);
+
+        assert_eq!(execute(&mut engine, &["pttl", "session"]), Integer(-1));
+        assert_eq!(execute(&mut engine, &["expiretime", "session"]), Integer(-1));
+        assert_eq!(execute(&mut engine, &["expire", "session", "10", "xx"]), Integer(0));
+        assert_eq!(execute(&mut engine, &["expire", "session", "10", "nx"]), Integer(1));
+        assert_eq!(execute(&mut engine, &["expiretime", "session"]), Integer(1_010));
+        assert_eq!(execute(&mut engine, &["pexpiretime", "session"]), Integer(1_010_000));
+
+        // extending the session without rewriting the value
+        assert_eq!(execute(&mut engine, &["pexpire", "session", "5000", "gt"]), Integer(0));
+        assert_eq!(execute(&mut engine, &["expireat", "session", "1030", "gt"]), Integer(1));
+        assert_eq!(execute(&mut engine, &["pexpiretime", "session"]), Integer(1_030_000));
+
+        assert_eq!(execute(&mut engine, &["persist", "session"]), Integer(1));
+        assert_eq!(execute(&mut engine, &["persist", "session"]), Integer(0));
+        assert_eq!(execute(&mut engine, &["expiretime", "session"]), Integer(-1));
+
+        assert_eq!(execute(&mut engine, &["pexpire", "session", "1500"]), Integer(1));
+        MockClock::advance(Duration::from_secs(2));
+        assert_eq!(execute(&mut engine, &["get", "session"]), NullBulkString);
+        assert_eq!(execute(&mut engine, &["pttl", "session"]), Integer(-2));
+        assert_eq!(execute(&mut engine, &["expiretime", "session"]), Integer(-2));
+        assert_eq!(execute(&mut engine, &["expire", "session", "10"]), Integer(0));
+        assert_eq!(execute(&mut engine, &["persist", "session"]), Integer(0));
+    }
+
+    #[test]
+    fn execute_config_get_and_set() {
+        let mut engine = StorageEngine::new();
+
+        assert_eq!(execute(&mut engine, &["config", "get", "HZ", "active-expire-effort", "unknown"]), Array(vec![
+            BulkString("hz".into()), BulkString("10".into()),
+            BulkString("active-expire-effort".into()), BulkString("1".into()),
+        ]));
+        assert_eq!(execute(&mut engine, &["config", "set", "hz", "1000", "active-expire-effort", "5"]), SimpleString("OK".into()));
+        assert_eq!(engine.expire_config(), ExpireConfig { hz: 500, effort: 5 });
+
+        // nothing is applied when any parameter is refused
+        assert_eq!(execute(&mut engine, &["config", "set", "hz", "20", "active-expire-effort", "11"]),
+                   Error("CONFIG SET failed (possibly related to argument 'active-expire-effort') - argument must be between 1 and 10 inclusive".into()));
+        assert_eq!(execute(&mut engine, &["config", "set", "hz", "fast"]),
+                   Error("CONFIG SET failed (possibly related to argument 'hz') - argument couldn't be parsed into an integer".into()));
+        assert_eq!(execute(&mut engine, &["config", "set", "maxclients", "10"]),
+                   Error("Unknown option or number of arguments for CONFIG SET - 'maxclients'".into()));
+        assert_eq!(engine.expire_config(), ExpireConfig { hz: 500, effort: 5 });
+
+        assert_eq!(execute(&mut engine, &["config", "get", "databases"]), Array(vec![BulkString("databases".into()), BulkString("16".into())]));
+        assert_eq!(execute(&mut engine, &["config", "set", "databases", "32"]),
+                   Error("CONFIG SET failed (possibly related to argument 'databases') - can't set immutable config".into()));
+
+        assert_eq!(execute(&mut engine, &["config", "set", "notify-keyspace-events", "KEA"]), SimpleString("OK".into()));
+        assert_eq!(execute(&mut engine, &["config", "get", "notify-keyspace-events"]),
+                   Array(vec![BulkString("notify-keyspace-events".into()), BulkString("AKE".into())]));
+        assert_eq!(execute(&mut engine, &["config", "set", "notify-keyspace-events", "Kq"]),
+                   Error("CONFIG SET failed (possibly related to argument 'notify-keyspace-events') - Invalid event class character. Use 'Ag$lshzxeKEtmdn'.".into()));
+
+        assert_eq!(execute(&mut engine, &["config", "get", "append*"]), Array(vec![
+            BulkString("appendonly".into()), BulkString("no".into()),
+            BulkString("appendfsync".into()), BulkString("everysec".into()),
+        ]));
+        assert_eq!(execute(&mut engine, &["config", "set", "appendonly", "yes"]),
+                   Error("CONFIG SET failed (possibly related to argument 'appendonly') - can't set immutable config".into()));
+        assert_eq!(execute(&mut engine, &["config", "set", "appendfsync", "sometimes"]),
+                   Error("CONFIG SET failed (possibly related to argument 'appendfsync') - argument(s) must be one of the following: always, everysec, no".into()));
+        assert_eq!(execute(&mut engine, &["bgrewriteaof"]),
+                   Error("The append only file is not enabled, start the server with '--appendonly yes'".into()));
+
+        assert_eq!(execute(&mut engine, &["config", "set", "save", "900 1 60 1000"]), SimpleString("OK".into()));
+        assert_eq!(execute(&mut engine, &["config", "get", "save"]), Array(vec![BulkString("save".into()), BulkString("900 1 60 1000".into())]));
+        assert_eq!(execute(&mut engine, &["config", "set", "save", "900"]),
+                   Error("CONFIG SET failed (possibly related to argument 'save') - Invalid save parameters".into()));
+        assert_eq!(execute(&mut engine, &["config", "set", "dbfilename", "/tmp/dump.rdb"]),
+                   Error("CONFIG SET failed (possibly related to argument 'dbfilename') - dbfilename can't be a path, just a filename".into()));
+        assert_eq!(execute(&mut engine, &["config", "get", "dbfilename"]), Array(vec![BulkString("dbfilename".into()), BulkString("dump.rdb".into())]));
+    }
+
+    #[test]
+    fn execute_info_reports_expired_keys() {
+        let mut engine = StorageEngine::new();
+        execute(&mut engine, &["set", "a", "1", "px", "100"]);
+        execute(&mut engine, &["set", "b", "1", "px", "100"]);
+
+        MockClock::advance(Duration::from_millis(101));
+        assert_eq!(execute(&mut engine, &["exists", "a"]), Integer(0));
+        engine.active_expire_cycle();
+
+        let BulkString(report) = execute(&mut engine, &["info", "stats"]) else {
+            panic!("INFO replies with a bulk string");
+        };
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.starts_with("# Stats\r\n"));
+        assert!(report.contains("expired_keys:2\r\n"));
+        assert_eq!(execute(&mut engine, &["info", "keyspace"]), BulkString(vec![]));
+    }
+
+    #[test]
+    fn execute_keys_randomkey_and_dbsize() {
+        let mut engine = StorageEngine::new();
+        assert_eq!(execute(&mut engine, &["randomkey"]), NullBulkString);
+        assert_eq!(execute(&mut engine, &["dbsize"]), Integer(0));
+
+        execute(&mut engine, &["set", "user:1", "a"]);
+        execute(&mut engine, &["set", "user:2", "b"]);
+        execute(&mut engine, &["set", "order:1", "c"]);
+        execute(&mut engine, &["set", "user:3", "d", "px", "10"]);
+        MockClock::advance(Duration::from_millis(11));
+
+        let Array(mut keys) = execute(&mut engine, &["keys", "user:*"]) else {
+            panic!("KEYS replies with an array");
+        };
+        keys.sort_by_key(|key| format!("{key:?}"));
+        assert_eq!(keys, vec![BulkString("user:1".into()), BulkString("user:2".into())]);
+        assert_eq!(execute(&mut engine, &["keys", "nothing*"]), Array(vec![]));
+
+        assert_eq!(execute(&mut engine, &["dbsize"]), Integer(3));
+        for _ in 0..10 {
+            let key = execute(&mut engine, &["randomkey"]);
+            assert!([BulkString("user:1".into()), BulkString("user:2".into()), BulkString("order:1".into())].contains(&key));
+        }
+    }
+
+    #[test]
+    fn execute_scan_until_the_cursor_returns_to_zero() {
+        let mut engine = StorageEngine::new();
+        for i in 0..30 {
+            execute(&mut engine, &["set", &format!("string:{i}"), "v"]);
+            execute(&mut engine, &["rpush", &format!("list:{i}"), "v"]);
+        }
+
+        let mut strings = vec![];
+        let mut cursor = "0".to_string();
+        loop {
+            let Array(reply) = execute(&mut engine, &["scan", &cursor, "match", "*:1*", "count", "7", "type", "string"]) else {
+                panic!("SCAN replies with an array");
+            };
+            let [BulkString(next), Array(keys)] = reply.as_slice() else {
+                panic!("SCAN replies with a cursor and the keys");
+            };
+            strings.extend(keys.iter().cloned());
+            cursor = String::from_utf8(next.clone()).unwrap();
+            if cursor == "0" {
+                break;
+            }
+        }
+
+        let mut expected: Vec<RespObject> = std::iter::once(1).chain(10..20)
+            .map(|i| BulkString(format!("string:{i}").into_bytes()))
+            .collect();
+        expected.sort_by_key(|key| format!("{key:?}"));
+        strings.sort_by_key(|key| format!("{key:?}"));
+        assert_eq!(strings, expected);
+    }
+
+    #[test]
+    fn execute_rename_keeps_value_and_expiry() {
+        let mut engine = StorageEngine::new();
+        MockClock::set_system_time(Duration::from_secs(1_000));
+        execute(&mut engine, &["set", "staged", "new", "ex", "100"]);
+        execute(&mut engine, &["set", "live", "old"]);
+
+        assert_eq!(execute(&mut engine, &["rename", "staged", "live"]), SimpleString("OK".into()));
+        assert_eq!(execute(&mut engine, &["get", "live"]), BulkString("new".into()));
+        assert_eq!(execute(&mut engine, &["exists", "staged"]), Integer(0));
+        assert_eq!(execute(&mut engine, &["expiretime", "live"]), Integer(1_100));
+
+        assert_eq!(execute(&mut engine, &["rename", "staged", "live"]), Error("no such key".into()));
+        assert_eq!(execute(&mut engine, &["rename", "live", "live"]), SimpleString("OK".into()));
+
+        execute(&mut engine, &["set", "other", "v"]);
+        assert_eq!(execute(&mut engine, &["renamenx", "other", "live"]), Integer(0));
+        assert_eq!(execute(&mut engine, &["renamenx", "other", "fresh"]), Integer(1));
+        assert_eq!(execute(&mut engine, &["get", "fresh"]), BulkString("v".into()));
+
+        // the expiry moved along with the value
+        MockClock::advance(Duration::from_secs(101));
+        assert_eq!(execute(&mut engine, &["get", "live"]), NullBulkString);
+    }
+
+    #[test]
+    fn execute_copy_duplicates_the_value() {
+        let mut engine = StorageEngine::new();
+        execute(&mut engine, &["rpush", "list", "a", "b"]);
+        execute(&mut engine, &["pexpire", "list", "500"]);
+        execute(&mut engine, &["set", "taken", "v"]);
+
+        assert_eq!(execute(&mut engine, &["copy", "list", "copy"]), Integer(1));
+        assert_eq!(execute(&mut engine, &["copy", "list", "taken"]), Integer(0));
+        assert_eq!(execute(&mut engine, &["copy", "list", "taken", "replace"]), Integer(1));
+        assert_eq!(execute(&mut engine, &["copy", "missing", "other"]), Integer(0));
+        assert_eq!(execute(&mut engine, &["copy", "list", "list"]), Error("source and destination objects are the same".into()));
+
+        // the copy is independent from the original
+        execute(&mut engine, &["rpush", "copy", "c"]);
+        assert_eq!(execute(&mut engine, &["llen", "list"]), Integer(2));
+        assert_eq!(execute(&mut engine, &["lrange", "taken", "0", "-1"]), Array(vec![BulkString("a".into()), BulkString("b".into())]));
+        assert_eq!(execute(&mut engine, &["pttl", "copy"]), Integer(500));
+    }
+
+    #[test]
+    fn execute_multi_queues_commands_until_exec() {
+        let mut engine = StorageEngine::new();
+        let mut session = Session::new();
+
+        assert_eq!(execute_in(&mut session, &mut engine, &["multi"]), SimpleString("OK".into()));
+        assert_eq!(execute_in(&mut session, &mut engine, &["set", "a", "1"]), SimpleString("QUEUED".into()));
+        assert_eq!(execute_in(&mut session, &mut engine, &["incr", "a"]), SimpleString("QUEUED".into()));
+        assert_eq!(execute_in(&mut session, &mut engine, &["select", "1"]), SimpleString("QUEUED".into()));
+        assert_eq!(execute_in(&mut session, &mut engine, &["lpush", "a", "x"]), SimpleString("QUEUED".into()));
+        assert_eq!(execute(&mut engine, &["get", "a"]), NullBulkString);
+
+        // errors while running do not stop the other commands
+        assert_eq!(execute_in(&mut session, &mut engine, &["exec"]), Array(vec![
+            SimpleString("OK".into()), Integer(2), SimpleString("OK".into()), Integer(1),
+        ]));
+        assert_eq!(execute(&mut engine, &["get", "a"]), BulkString("2".into()));
+        assert_eq!(execute_in(&mut session, &mut engine, &["llen", "a"]), Integer(1));
+
+        assert_eq!(execute_in(&mut session, &mut engine, &["exec"]), Error("EXEC without MULTI".into()));
+        assert_eq!(execute_in(&mut session, &mut engine, &["discard"]), Error("DISCARD without MULTI".into()));
+        execute_in(&mut session, &mut engine, &["multi"]);
+        execute_in(&mut session, &mut engine, &["del", "a"]);
+        assert_eq!(execute_in(&mut session, &mut engine, &["discard"]), SimpleString("OK".into()));
+        assert_eq!(execute_in(&mut session, &mut engine, &["llen", "a"]), Integer(1));
+    }
+
+    #[test]
+    fn execute_exec_aborts_after_queueing_errors() {
+        let mut engine = StorageEngine::new();
+        let mut session = Session::new();
+        let request = |args: &[&str]| Array(args.iter().map(|arg| BulkString(arg.as_bytes().to_vec())).collect());
+
+        execute_in(&mut session, &mut engine, &["multi"]);
+        execute_in(&mut session, &mut engine, &["set", "a", "1"]);
+        assert_eq!(Command::from_request(request(&["get"]), &mut session), Err("Not enough arguments for 'get'".to_string()));
+        assert_eq!(execute_in(&mut session, &mut engine, &["exec"]), Error("EXECABORT Transaction discarded because of previous errors.".into()));
+        assert_eq!(execute(&mut engine, &["exists", "a"]), Integer(0));
+
+        execute_in(&mut session, &mut engine, &["multi"]);
+        assert_eq!(execute_in(&mut session, &mut engine, &["multi"]), Error("Command not allowed inside a transaction".into()));
+        assert_eq!(execute_in(&mut session, &mut engine, &["watch", "a"]), Error("Command not allowed inside a transaction".into()));
+        assert_eq!(execute_in(&mut session, &mut engine, &["exec"]), Error("EXECABORT Transaction discarded because of previous errors.".into()));
+
+        // outside of a transaction, invalid requests are just errors
+        assert!(Command::from_request(request(&["get"]), &mut session).is_err());
+        assert!(!session.in_transaction());
+    }
+
+    #[test]
+    fn execute_exec_aborts_when_a_watched_key_changes() {
+        let mut engine = StorageEngine::new();
+        let mut session = Session::new();
+        let mut other = Session::new();
+        execute(&mut engine, &["set", "balance", "10"]);
+
+        assert_eq!(execute_in(&mut session, &mut engine, &["watch", "balance", "missing"]), SimpleString("OK".into()));
+        execute_in(&mut other, &mut engine, &["incrby", "balance", "5"]);
+        execute_in(&mut session, &mut engine, &["multi"]);
+        execute_in(&mut session, &mut engine, &["set", "balance", "0"]);
+        assert_eq!(execute_in(&mut session, &mut engine, &["exec"]), NullArray);
+        assert_eq!(execute(&mut engine, &["get", "balance"]), BulkString("15".into()));
+
+        // EXEC unwatched everything, so the next transaction goes through
+        execute_in(&mut other, &mut engine, &["set", "missing", "now"]);
+        execute_in(&mut session, &mut engine, &["multi"]);
+        execute_in(&mut session, &mut engine, &["set", "balance", "0"]);
+        assert_eq!(execute_in(&mut session, &mut engine, &["exec"]), Array(vec![SimpleString("OK".into())]));
+
+        // creating a watched key counts too, while reading it does not
+        execute_in(&mut session, &mut engine, &["watch", "created"]);
+        execute_in(&mut other, &mut engine, &["get", "created"]);
+        execute_in(&mut session, &mut engine, &["multi"]);
+        assert_eq!(execute_in(&mut session, &mut engine, &["exec"]), Array(vec![]));
+        execute_in(&mut session, &mut engine, &["watch", "created"]);
+        execute_in(&mut other, &mut engine, &["sadd", "created", "x"]);
+        execute_in(&mut session, &mut engine, &["multi"]);
+        assert_eq!(execute_in(&mut session, &mut engine, &["exec"]), NullArray);
+
+        // as does flushing the database
+        execute_in(&mut session, &mut engine, &["watch", "balance"]);
+        execute_in(&mut other, &mut engine, &["flushall"]);
+        execute_in(&mut session, &mut engine, &["multi"]);
+        assert_eq!(execute_in(&mut session, &mut engine, &["exec"]), NullArray);
+    }
+
+    #[test]
+    fn execute_exec_goes_through_when_watched_keys_see_only_failed_or_no_op_writes() {
+        let mut engine = StorageEngine::new();
+        let mut session = Session::new();
+        let mut other = Session::new();
+        execute(&mut engine, &["set", "string", "v"]);
+        execute(&mut engine, &["rpush", "list", "a"]);
+        execute(&mut engine, &["hset", "hash", "f", "v"]);
+        execute(&mut engine, &["set", "ttl", "v", "ex", "100"]);
+        let watched = ["string", "list", "hash", "ttl"];
+
+        let writes: [&[&str]; 10] = [
+            &["lpush", "string", "x"],
+            &["lset", "list", "5", "x"],
+            &["hdel", "hash", "missing"],
+            &["expire", "ttl", "10", "nx"],
+            &["expire", "ttl", "10", "gt"],
+            &["persist", "string"],
+            &["getex", "string"],
+            &["incrbyfloat", "string", "1"],
+            &["srem", "hash", "f"],
+            &["zadd", "list", "xx", "1", "a"],
+        ];
+        for write in writes {
+            let mut watch = vec!["watch"];
+            watch.extend(watched);
+            execute_in(&mut session, &mut engine, &watch);
+            execute_in(&mut other, &mut engine, write);
+            execute_in(&mut session, &mut engine, &["multi"]);
+            assert_eq!(execute_in(&mut session, &mut engine, &["exec"]), Array(vec![]), "aborted by {write:?}");
+        }
+        // none of which counts as a change to save either
+        assert_eq!(engine.rdb().changes_since_save(engine.dirty()), 4);
+    }
+
+    #[test]
+    fn execute_exec_aborts_when_a_watched_key_expires() {
+        let mut engine = StorageEngine::new();
+        let mut session = Session::new();
+        execute(&mut engine, &["set", "lock", "me", "px", "100"]);
+
+        execute_in(&mut session, &mut engine, &["watch", "lock"]);
+        MockClock::advance(Duration::from_millis(101));
+        execute_in(&mut session, &mut engine, &["multi"]);
+        assert_eq!(execute_in(&mut session, &mut engine, &["exec"]), NullArray);
+
+        // but unwatched keys do not matter
+        execute(&mut engine, &["set", "lock", "me", "px", "100"]);
+        execute_in(&mut session, &mut engine, &["watch", "lock"]);
+        assert_eq!(execute_in(&mut session, &mut engine, &["unwatch"]), SimpleString("OK".into()));
+        execute(&mut engine, &["del", "lock"]);
+        execute_in(&mut session, &mut engine, &["multi"]);
+        assert_eq!(execute_in(&mut session, &mut engine, &["exec"]), Array(vec![]));
+    }
+
+    #[test]
+    fn execute_publish_delivers_to_subscribers() {
+        let mut engine = StorageEngine::new();
+        let mut subscriber = Session::new();
+        let messages = subscriber.take_output().unwrap();
+        let mut publisher = Session::new();
+
+        // every channel is confirmed: the last one as the reply, the others ahead of it
+        assert_eq!(execute_in(&mut subscriber, &mut engine, &["subscribe", "news", "sports"]),
+                   Array(vec![BulkString("subscribe".into()), BulkString("sports".into()), Integer(2)]));
+        assert_eq!(messages.try_iter().collect::<Vec<_>>(),
+                   vec![Array(vec![BulkString("subscribe".into()), BulkString("news".into()), Integer(1)]).to_bytes()]);
+        assert_eq!(execute_in(&mut subscriber, &mut engine, &["psubscribe", "new*"]),
+                   Array(vec![BulkString("psubscribe".into()), BulkString("new*".into()), Integer(3)]));
+
+        assert_eq!(execute_in(&mut publisher, &mut engine, &["publish", "news", "hello"]), Integer(2));
+        assert_eq!(execute_in(&mut publisher, &mut engine, &["publish", "weather", "rain"]), Integer(0));
+        assert_eq!(messages.try_iter().collect::<Vec<_>>(), vec![
+            Array(vec![BulkString("message".into()), BulkString("news".into()), BulkString("hello".into())]).to_bytes(),
+            Array(vec![BulkString("pmessage".into()), BulkString("new*".into()), BulkString("news".into()), BulkString("hello".into())]).to_bytes(),
+        ]);
+
+        assert_eq!(execute_in(&mut subscriber, &mut engine, &["unsubscribe", "news", "sports"]),
+                   Array(vec![BulkString("unsubscribe".into()), BulkString("sports".into()), Integer(1)]));
+        assert_eq!(messages.try_iter().count(), 1);
+        assert_eq!(execute_in(&mut subscriber, &mut engine, &["punsubscribe"]),
+                   Array(vec![BulkString("punsubscribe".into()), BulkString("new*".into()), Integer(0)]));
+        assert_eq!(execute_in(&mut subscriber, &mut engine, &["unsubscribe"]),
+                   Array(vec![BulkString("unsubscribe".into()), NullBulkString, Integer(0)]));
+        assert_eq!(execute_in(&mut publisher, &mut engine, &["publish", "news", "hello"]), Integer(0));
+    }
+
+    #[test]
+    fn execute_restricts_subscribed_resp2_clients() {
+        let mut engine = StorageEngine::new();
+        let mut session = Session::new();
+        let request = |args: &[&str]| Array(args.iter().map(|arg| BulkString(arg.as_bytes().to_vec())).collect());
+
+        execute_in(&mut session, &mut engine, &["subscribe", "news"]);
+        assert_eq!(Command::from_request(request(&["GET", "k"]), &mut session),
+                   Err("Can't execute 'get': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context".to_string()));
+        let ping = Command::from_request(request(&["ping"]), &mut session).unwrap();
+        assert_eq!(ping.execute(&mut session, &mut engine), Array(vec![BulkString("pong".into()), BulkString("".into())]));
+
+        // RESP3 tells messages apart from replies, so anything goes
+        execute_in(&mut session, &mut engine, &["hello", "3"]);
+        assert!(Command::from_request(request(&["get", "k"]), &mut session).is_ok());
+        assert_eq!(execute_in(&mut session, &mut engine, &["ping"]), SimpleString("PONG".into()));
+
+        execute_in(&mut session, &mut engine, &["hello", "2"]);
+        execute_in(&mut session, &mut engine, &["unsubscribe", "news"]);
+        assert!(Command::from_request(request(&["get", "k"]), &mut session).is_ok());
+    }
+
+    #[test]
+    fn execute_quit_and_reset_are_allowed_to_subscribed_resp2_clients() {
+        let mut engine = StorageEngine::new();
+        let mut session = Session::new();
+        let request = |args: &[&str]| Array(args.iter().map(|arg| BulkString(arg.as_bytes().to_vec())).collect());
+        execute_in(&mut session, &mut engine, &["select", "1"]);
+        execute_in(&mut session, &mut engine, &["watch", "k"]);
+        execute_in(&mut session, &mut engine, &["subscribe", "news"]);
+        execute_in(&mut session, &mut engine, &["psubscribe", "new*"]);
+
+        // leaves the client as it was when it connected
+        let reset = Command::from_request(request(&["reset"]), &mut session).unwrap();
+        assert_eq!(reset.execute(&mut session, &mut engine), SimpleString("RESET".into()));
+        assert_eq!(session.subscription_count(), 0);
+        assert!(session.watched_keys().is_empty());
+        assert_eq!(session.db(), 0);
+        assert_eq!(execute(&mut engine, &["publish", "news", "hello"]), Integer(0));
+
+        // run right away rather than queued, back on RESP2 and without a name
+        execute_in(&mut session, &mut engine, &["hello", "3", "setname", "worker"]);
+        execute_in(&mut session, &mut engine, &["multi"]);
+        assert_eq!(execute_in(&mut session, &mut engine, &["reset"]), SimpleString("RESET".into()));
+        assert!(!session.in_transaction());
+        assert_eq!(session.protocol(), ProtocolVersion::Resp2);
+        assert_eq!(session.client_name(), None);
+
+        execute_in(&mut session, &mut engine, &["subscribe", "news"]);
+        let quit = Command::from_request(request(&["quit"]), &mut session).unwrap();
+        assert_eq!(quit.execute(&mut session, &mut engine), SimpleString("OK".into()));
+        assert!(session.is_closing());
+    }
+
+    #[test]
+    fn execute_pubsub_introspection() {
+        let mut engine = StorageEngine::new();
+        let mut first = Session::new();
+        let mut second = Session::new();
+        execute_in(&mut first, &mut engine, &["subscribe", "news", "sports"]);
+        execute_in(&mut second, &mut engine, &["subscribe", "news"]);
+        execute_in(&mut second, &mut engine, &["psubscribe", "a*", "b*"]);
+        execute_in(&mut first, &mut engine, &["psubscribe", "a*"]);
+
+        assert_eq!(execute(&mut engine, &["pubsub", "channels", "n*"]), bulk_strings(&["news"]));
+        assert_eq!(execute(&mut engine, &["pubsub", "numsub", "news", "sports", "none"]), Array(vec![
+            BulkString("news".into()), Integer(2), BulkString("sports".into()), Integer(1), BulkString("none".into()), Integer(0),
+        ]));
+        assert_eq!(execute(&mut engine, &["pubsub", "numpat"]), Integer(2));
+
+        // a client that goes away takes its subscriptions along
+        second.disconnect(&mut engine);
+        assert_eq!(execute(&mut engine, &["pubsub", "numsub", "news"]), Array(vec![BulkString("news".into()), Integer(1)]));
+        assert_eq!(execute(&mut engine, &["pubsub", "numpat"]), Integer(1));
+    }
+
+    #[test]
+    fn execute_publishes_keyspace_notifications() {
+        let mut engine = StorageEngine::new();
+        let mut subscriber = Session::new();
+        let messages = subscriber.take_output().unwrap();
+        let event = |channel: &str, message: &str| Array(vec![
+            BulkString("pmessage".into()), BulkString("__key*@0__:*".into()), BulkString(channel.into()), BulkString(message.into()),
+        ]).to_bytes();
+        execute_in(&mut subscriber, &mut engine, &["psubscribe", "__key*@0__:*"]);
+
+        // nothing is published until notifications are enabled
+        execute(&mut engine, &["set", "a", "1"]);
+        assert_eq!(messages.try_iter().count(), 0);
+
+        execute(&mut engine, &["config", "set", "notify-keyspace-events", "KEg$"]);
+        execute(&mut engine, &["set", "a", "2"]);
+        execute(&mut engine, &["del", "a", "missing"]);
+        assert_eq!(messages.try_iter().collect::<Vec<_>>(), vec![
+            event("__keyspace@0__:a", "set"), event("__keyevent@0__:set", "a"),
+            event("__keyspace@0__:a", "del"), event("__keyevent@0__:del", "a"),
+        ]);
+
+        // classes that are not enabled are left out
+        execute(&mut engine, &["config", "set", "notify-keyspace-events", "Eg"]);
+        execute(&mut engine, &["set", "a", "1"]);
+        execute(&mut engine, &["rpush", "list", "1"]);
+        execute(&mut engine, &["expire", "a", "10"]);
+        assert_eq!(messages.try_iter().collect::<Vec<_>>(), vec![event("__keyevent@0__:expire", "a")]);
+
+        // a refused increment changes nothing, so there is nothing to tell
+        execute(&mut engine, &["zadd", "board", "+inf", "jane"]);
+        execute(&mut engine, &["config", "set", "notify-keyspace-events", "Enz$"]);
+        execute(&mut engine, &["incrbyfloat", "float", "inf"]);
+        assert_eq!(execute(&mut engine, &["zincrby", "board", "-inf", "jane"]), Error("resulting score is not a number (NaN)".to_owned()));
+        assert_eq!(execute(&mut engine, &["zadd", "board", "incr", "-inf", "jane"]), Error("resulting score is not a number (NaN)".to_owned()));
+        assert_eq!(execute(&mut engine, &["zincrby", "other", "-inf", "jane"]), BulkString("-inf".into()));
+        assert_eq!(messages.try_iter().collect::<Vec<_>>(), vec![
+            event("__keyevent@0__:new", "other"), event("__keyevent@0__:zincr", "other"),
+        ]);
+    }
+
+    #[test]
+    fn execute_publishes_expired_keys() {
+        let mut engine = StorageEngine::new();
+        let mut subscriber = Session::new();
+        let messages = subscriber.take_output().unwrap();
+        let expired = |key: &str| Array(vec![
+            BulkString("message".into()), BulkString("__keyevent@0__:expired".into()), BulkString(key.into()),
+        ]).to_bytes();
+        execute_in(&mut subscriber, &mut engine, &["subscribe", "__keyevent@0__:expired"]);
+        execute(&mut engine, &["config", "set", "notify-keyspace-events", "Ex"]);
+
+        execute(&mut engine, &["set", "lazy", "1", "px", "100"]);
+        execute(&mut engine, &["set", "active", "1", "px", "100"]);
+        MockClock::advance(Duration::from_millis(101));
+
+        // once when the expired key is accessed, once when the active cycle finds it
+        assert_eq!(execute(&mut engine, &["get", "lazy"]), NullBulkString);
+        assert_eq!(messages.try_iter().collect::<Vec<_>>(), vec![expired("lazy")]);
+        engine.active_expire_cycle();
+        assert_eq!(messages.try_iter().collect::<Vec<_>>(), vec![expired("active")]);
+    }
+
+    #[test]
+    fn execute_blocking_pops_reply_right_away_when_there_is_something() {
+        let mut engine = StorageEngine::new();
+        execute(&mut engine, &["rpush", "b", "1", "2", "3"]);
+        execute(&mut engine, &["zadd", "board", "1", "low", "9", "high"]);
+        execute(&mut engine, &["set", "string", "x"]);
+
+        assert_eq!(execute(&mut engine, &["blpop", "a", "b", "0"]), bulk_strings(&["b", "1"]));
+        assert_eq!(execute(&mut engine, &["brpop", "b", "0"]), bulk_strings(&["b", "3"]));
+        assert_eq!(execute(&mut engine, &["blmove", "b", "c", "left", "right", "0"]), BulkString("2".into()));
+        assert_eq!(execute(&mut engine, &["blmpop", "0", "2", "b", "c", "left", "count", "5"]),
+                   Array(vec![BulkString("c".into()), bulk_strings(&["2"])]));
+        assert_eq!(execute(&mut engine, &["bzpopmax", "board", "0"]), bulk_strings(&["board", "high", "9"]));
+        assert_eq!(execute(&mut engine, &["bzpopmin", "board", "0"]), bulk_strings(&["board", "low", "1"]));
+        assert_eq!(execute(&mut engine, &["exists", "b", "c", "board"]), Integer(0));
+        assert_eq!(execute(&mut engine, &["blpop", "string", "0"]),
+                   Error("WRONGTYPE Operation against a key holding the wrong kind of value".into()));
+    }
+
+    #[test]
+    fn execute_blocked_clients_are_served_in_the_order_they_blocked() {
+        let mut engine = StorageEngine::new();
+        let mut first = Session::new();
+        let mut second = Session::new();
+        let mut third = Session::new();
+
+        assert_eq!(execute_in(&mut first, &mut engine, &["blpop", "jobs", "0"]), NullArray);
+        assert_eq!(execute_in(&mut second, &mut engine, &["brpop", "other", "jobs", "0"]), NullArray);
+        assert_eq!(execute_in(&mut third, &mut engine, &["blmove", "jobs", "done", "left", "left", "0"]), NullBulkString);
+        assert!(first.is_blocked() && second.is_blocked() && third.is_blocked());
+
+        assert_eq!(execute(&mut engine, &["rpush", "jobs", "a", "b"]), Integer(2));
+        assert_eq!(first.wait_until_served(Duration::ZERO), Some(bulk_strings(&["jobs", "a"])));
+        assert_eq!(second.wait_until_served(Duration::ZERO), Some(bulk_strings(&["jobs", "b"])));
+        assert!(third.is_blocked());
+
+        // what BLMOVE pushes serves the clients waiting on the destination in turn
+        execute_in(&mut first, &mut engine, &["bzpopmin", "board", "done", "0"]);
+        execute(&mut engine, &["lpush", "jobs", "c"]);
+        assert_eq!(third.wait_until_served(Duration::ZERO), Some(BulkString("c".into())));
+        assert_eq!(first.wait_until_served(Duration::ZERO),
+                   Some(Error("WRONGTYPE Operation against a key holding the wrong kind of value".into())));
+        assert_eq!(execute(&mut engine, &["exists", "jobs"]), Integer(0));
+    }
+
+    #[test]
+    fn execute_blocked_clients_are_woken_by_transactions_only_on_exec() {
+        let mut engine = StorageEngine::new();
+        let mut blocked = Session::new();
+        let mut session = Session::new();
+        execute_in(&mut blocked, &mut engine, &["blpop", "jobs", "0"]);
+
+        // the element pushed is gone again by the time the transaction is done, so the client keeps waiting
+        execute_in(&mut session, &mut engine, &["multi"]);
+        execute_in(&mut session, &mut engine, &["rpush", "jobs", "a"]);
+        execute_in(&mut session, &mut engine, &["lpop", "jobs"]);
+        assert_eq!(execute_in(&mut session, &mut engine, &["exec"]), Array(vec![Integer(1), BulkString("a".into())]));
+        assert!(blocked.is_blocked());
+        assert_eq!(blocked.wait_until_served(Duration::ZERO), None);
+
+        execute_in(&mut session, &mut engine, &["multi"]);
+        execute_in(&mut session, &mut engine, &["rpush", "jobs", "b"]);
+        assert_eq!(blocked.wait_until_served(Duration::ZERO), None);
+        execute_in(&mut session, &mut engine, &["exec"]);
+        assert_eq!(blocked.wait_until_served(Duration::ZERO), Some(bulk_strings(&["jobs", "b"])));
+
+        // and inside a transaction, blocking commands do not wait at all
+        execute_in(&mut session, &mut engine, &["multi"]);
+        execute_in(&mut session, &mut engine, &["blpop", "jobs", "0"]);
+        assert_eq!(execute_in(&mut session, &mut engine, &["exec"]), Array(vec![NullArray]));
+        assert!(!session.is_blocked());
+        assert_eq!(engine.blocked_clients().waiting_on(0, b"jobs"), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn execute_blocked_clients_time_out() {
+        let mut engine = StorageEngine::new();
+        let mut session = Session::new();
+
+        assert_eq!(execute_in(&mut session, &mut engine, &["blpop", "jobs", "0.01"]), NullArray);
+        assert_eq!(session.wait_until_served(Duration::from_secs(5)), None);
+        assert!(session.timed_out());
+        assert_eq!(session.unblock(&mut engine), None);
+
+        // once given up, the client is not served anymore
+        execute(&mut engine, &["rpush", "jobs", "a"]);
+        assert_eq!(execute(&mut engine, &["llen", "jobs"]), Integer(1));
+    }
+
+    #[test]
+    fn execute_blocked_clients_wait_forever_for_timeouts_out_of_reach() {
+        let mut engine = StorageEngine::new();
+        let mut session = Session::new();
+
+        // longer than the clock can count, which must not bring down the server
+        execute_in(&mut session, &mut engine, &["blpop", "jobs", "1e19"]);
+        assert!(session.is_blocked());
+        assert!(!session.timed_out());
+
+        execute(&mut engine, &["rpush", "jobs", "a"]);
+        assert_eq!(session.wait_until_served(Duration::from_secs(5)), Some(bulk_strings(&["jobs", "a"])));
+    }
+
+    #[test]
+    fn execute_select_keeps_clients_apart() {
+        let mut engine = StorageEngine::new();
+        let mut first = Session::new();
+        let mut second = Session::new();
+
+        assert_eq!(execute_in(&mut first, &mut engine, &["select", "1"]), SimpleString("OK".into()));
+        execute_in(&mut first, &mut engine, &["set", "k", "in 1"]);
+        execute_in(&mut second, &mut engine, &["set", "k", "in 0"]);
+
+        assert_eq!(execute_in(&mut first, &mut engine, &["get", "k"]), BulkString("in 1".into()));
+        assert_eq!(execute_in(&mut second, &mut engine, &["get", "k"]), BulkString("in 0".into()));
+        assert_eq!(execute_in(&mut first, &mut engine, &["select", "16"]), Error("DB index is out of range".into()));
+        assert_eq!(first.db(), 1);
+    }
+
+    #[test]
+    fn execute_swapdb_move_and_copy_across_databases() {
+        let mut engine = StorageEngine::new();
+        let mut session = Session::new();
+        execute_in(&mut session, &mut engine, &["set", "a", "1"]);
+        execute_in(&mut session, &mut engine, &["set", "b", "2"]);
+
+        assert_eq!(execute_in(&mut session, &mut engine, &["move", "a", "1"]), Integer(1));
+        assert_eq!(execute_in(&mut session, &mut engine, &["move", "missing", "1"]), Integer(0));
+        assert_eq!(execute_in(&mut session, &mut engine, &["move", "b", "0"]), Error("source and destination objects are the same".into()));
+        assert_eq!(execute_in(&mut session, &mut engine, &["copy", "b", "b", "db", "1"]), Integer(1));
+        assert_eq!(execute_in(&mut session, &mut engine, &["copy", "b", "a", "db", "1"]), Integer(0));
+        assert_eq!(execute_in(&mut session, &mut engine, &["copy", "b", "c", "db", "99"]), Error("DB index is out of range".into()));
+        assert_eq!(execute_in(&mut session, &mut engine, &["dbsize"]), Integer(1));
+
+        // the other database is what this client sees now
+        assert_eq!(execute_in(&mut session, &mut engine, &["swapdb", "0", "1"]), SimpleString("OK".into()));
+        assert_eq!(execute_in(&mut session, &mut engine, &["get", "a"]), BulkString("1".into()));
+        assert_eq!(execute_in(&mut session, &mut engine, &["dbsize"]), Integer(2));
+        assert_eq!(execute_in(&mut session, &mut engine, &["swapdb", "0", "16"]), Error("DB index is out of range".into()));
+    }
+
+    #[test]
+    fn execute_flushdb_and_flushall() {
+        let mut engine = StorageEngine::new();
+        let mut session = Session::new();
+        execute_in(&mut session, &mut engine, &["set", "a", "1"]);
+        execute_in(&mut session, &mut engine, &["select", "2"]);
+        execute_in(&mut session, &mut engine, &["set", "b", "2"]);
+        execute_in(&mut session, &mut engine, &["set", "c", "3"]);
+
+        assert_eq!(execute_in(&mut session, &mut engine, &["flushdb", "async"]), SimpleString("OK".into()));
+        assert_eq!(execute_in(&mut session, &mut engine, &["dbsize"]), Integer(0));
+        assert_eq!(execute(&mut engine, &["dbsize"]), Integer(1));
+
+        assert_eq!(execute_in(&mut session, &mut engine, &["flushall", "sync"]), SimpleString("OK".into()));
+        assert_eq!(execute(&mut engine, &["dbsize"]), Integer(0));
+    }
+
+    #[test]
+    fn execute_type_touch_and_unlink() {
+        let mut engine = StorageEngine::new();
+        execute(&mut engine, &["set", "string", "v"]);
+        execute(&mut engine, &["hset", "hash", "f", "v"]);
+        execute(&mut engine, &["zadd", "zset", "1", "m"]);
+        execute(&mut engine, &["xadd", "stream", "*", "f", "v"]);
+
+        assert_eq!(execute(&mut engine, &["type", "string"]), SimpleString("string".into()));
+        assert_eq!(execute(&mut engine, &["type", "hash"]), SimpleString("hash".into()));
+        assert_eq!(execute(&mut engine, &["type", "zset"]), SimpleString("zset".into()));
+        assert_eq!(execute(&mut engine, &["type", "stream"]), SimpleString("stream".into()));
+        assert_eq!(execute(&mut engine, &["type", "missing"]), SimpleString("none".into()));
+
+        assert_eq!(execute(&mut engine, &["touch", "string", "hash", "missing"]), Integer(2));
+        assert_eq!(execute(&mut engine, &["unlink", "string", "hash", "missing"]), Integer(2));
+        assert_eq!(execute(&mut engine, &["type", "string"]), SimpleString("none".into()));
+    }
+
+    #[test]
+    fn execute_object_encoding() {
+        let mut engine = StorageEngine::new();
+        let long = "x".repeat(65);
+        execute(&mut engine, &["set", "int", "12345"]);
+        execute(&mut engine, &["set", "padded", "012"]);
+        execute(&mut engine, &["set", "raw", &long]);
+        execute(&mut engine, &["sadd", "numbers", "1", "2", "3"]);
+        execute(&mut engine, &["sadd", "words", "a", "b"]);
+        execute(&mut engine, &["sadd", "big", "a", &long]);
+        execute(&mut engine, &["hset", "hash", "f", "v"]);
+        execute(&mut engine, &["zadd", "zset", "1", &long]);
+        execute(&mut engine, &["rpush", "list", "a"]);
+
+        let encoding = |engine: &mut StorageEngine, key: &str| execute(engine, &["object", "encoding", key]);
+        assert_eq!(encoding(&mut engine, "int"), BulkString("int".into()));
+        assert_eq!(encoding(&mut engine, "padded"), BulkString("embstr".into()));
+        assert_eq!(encoding(&mut engine, "raw"), BulkString("raw".into()));
+        assert_eq!(encoding(&mut engine, "numbers"), BulkString("intset".into()));
+        assert_eq!(encoding(&mut engine, "words"), BulkString("listpack".into()));
+        assert_eq!(encoding(&mut engine, "big"), BulkString("hashtable".into()));
+        assert_eq!(encoding(&mut engine, "hash"), BulkString("listpack".into()));
+        assert_eq!(encoding(&mut engine, "zset"), BulkString("skiplist".into()));
+        assert_eq!(encoding(&mut engine, "list"), BulkString("listpack".into()));
+        assert_eq!(encoding(&mut engine, "missing"), NullBulkString);
+        assert_eq!(execute(&mut engine, &["object", "refcount", "int"]), Integer(1));
+    }
+
+    #[test]
+    fn execute_object_idletime_and_freq_track_accesses() {
+        let mut engine = StorageEngine::new();
+        execute(&mut engine, &["set", "k", "v"]);
+        assert_eq!(execute(&mut engine, &["object", "freq", "k"]), Integer(5));
+
+        MockClock::advance(Duration::from_secs(30));
+        // inspecting the key does not count as an access
+        assert_eq!(execute(&mut engine, &["object", "idletime", "k"]), Integer(30));
+        assert_eq!(execute(&mut engine, &["type", "k"]), SimpleString("string".into()));
+        assert_eq!(execute(&mut engine, &["object", "idletime", "k"]), Integer(30));
+
+        execute(&mut engine, &["get", "k"]);
+        assert_eq!(execute(&mut engine, &["object", "idletime", "k"]), Integer(0));
+
+        // an access to a key that was barely used always counts, later ones ever less likely
+        execute(&mut engine, &["get", "k"]);
+        let Integer(frequency) = execute(&mut engine, &["object", "freq", "k"]) else {
+            panic!("OBJECT FREQ replies with an integer");
+        };
+        assert!(frequency >= 6);
+
+        // and it decays by one for every minute without access
+        MockClock::advance(Duration::from_secs(120));
+        assert_eq!(execute(&mut engine, &["object", "freq", "k"]), Integer(frequency - 2));
+        assert_eq!(execute(&mut engine, &["object", "freq", "missing"]), NullBulkString);
+    }
+
+    #[test]
+    fn execute_expire_in_the_past_deletes_the_key() {
+        let mut engine = StorageEngine::new();
+        execute(&mut engine, &["set", "k", "v"]);
+
+        assert_eq!(execute(&mut engine, &["expire", "k", "-1"]), Integer(1));
+        assert_eq!(execute(&mut engine, &["get", "k"]), NullBulkString);
+    }
+
+    #[test]
+    fn execute_counters_on_wrong_type() {
+        let mut engine = StorageEngine::new();
+        execute(&mut engine, &["rpush", "list", "1"]);
+
+        let wrong_type = Error("WRONGTYPE Operation against a key holding the wrong kind of value".into());
+        assert_eq!(execute(&mut engine, &["incr", "list"]), wrong_type);
+        assert_eq!(execute(&mut engine, &["incrbyfloat", "list", "1"]), wrong_type);
+    }
+
+    #[test]
+    fn execute_set_and_get_are_binary_safe() {
+        let mut engine = StorageEngine::new();
+        let key = vec![0xff, 0x00, b'k'];
+        let value = vec![0x1f, 0x8b, 0x08, 0x00, b'\r', b'\n', 0xc3];
+
+        let set = Command::from(Array(vec![BulkString("set".into()), BulkString(key.clone()), BulkString(value.clone())])).unwrap();
+        assert_eq!(set.execute_on(&mut engine), SimpleString("OK".to_owned()));
+
+        let get = Command::from(Array(vec![BulkString("get".into()), BulkString(key)])).unwrap();
+        assert_eq!(get.execute_on(&mut engine), BulkString(value));
+    }
+
+    #[test]
+    fn execute_get_should_return_nil_when_unset() {
+        let mut engine = StorageEngine::new();
+        let cmd = Command::parsed(RespCommand::Get(GetCommand::from("foo".into())));
+
+        let result = cmd.execute_on(&mut engine);
+        assert_eq!(result, NullBulkString);
+    }
+
+    #[test]
+    fn execute_get_should_return_the_previously_set_value() {
+        let mut engine = StorageEngine::new();
+        let set_cmd = Command::parsed(RespCommand::Set( SetCommand::from_key_value(("foo".into(), "bar".into()))));
+        let get_cmd = Command::parsed(RespCommand::Get(GetCommand::from("foo".into())));
+
+        let result = set_cmd.execute_on(&mut engine);
+        // SET responds with a simple string of 'OK'
+        assert_eq!(result, SimpleString("OK".to_owned()));
+
+        let result = get_cmd.execute_on(&mut engine);
+        assert_eq!(result, BulkString("bar".into()));
+    }
+
+    #[test]
+    fn execute_get_should_return_the_previously_mset_values() {
+        let mut engine = StorageEngine::new();
+        let mset_cmd = Command::parsed(RespCommand::Mset(MsetCommand::from_key_values(vec![("key1".into(), "1".into()), ("key2".into(), "2".into())])));
+        let get_cmd1 = Command::parsed(RespCommand::Get(GetCommand::from("key1".into())));
+        let get_cmd2 = Command::parsed(RespCommand::Get(GetCommand::from("key2".into())));
+
+        let result = mset_cmd.execute_on(&mut engine);
+        // MSET responds with a simple string of 'OK'
+        assert_eq!(result, SimpleString("OK".to_owned()));
+
+        let result = get_cmd1.execute_on(&mut engine);
+        assert_eq!(result, BulkString("1".into()));
+
+        let result = get_cmd2.execute_on(&mut engine);
+        assert_eq!(result, BulkString("2".into()));
+    }
+
+    #[test]
+    fn execute_mset_with_repeated_key_applies_the_last_value() {
+        let mut engine = StorageEngine::new();
+        let mset_cmd = Command::parsed(RespCommand::Mset(MsetCommand::from_key_values(vec![("foo".into(), "bar".into()), ("foo".into(), "baz".into())])));
+        let get_cmd = Command::parsed(RespCommand::Get(GetCommand::from("foo".into())));
+
+        let result = mset_cmd.execute_on(&mut engine);
+        // MSET responds with a simple string of 'OK'
+        assert_eq!(result, SimpleString("OK".to_owned()));
+
+        // will return the second value, because it overwrites the first entry in mset
+        let result = get_cmd.execute_on(&mut engine);
+        assert_eq!(result, BulkString("baz".into()));
     }
 
     #[test]
-    fn create_get_command() {
-        let cmd = Command::from(Array(vec![BulkString("get".to_owned()), BulkString("Name".to_owned())]));
-        assert_eq!(cmd, Ok(Command(RespCommand::Get(GetCommand::from(String::from("Name"))))));
+    fn execute_mget_should_return_all_previously_set_or_mset_values() {
+        let mut engine = StorageEngine::new();
+        let set_cmd = Command::parsed(RespCommand::Set(SetCommand::from_key_value(("fromSet".into(), "set".into()))));
+        let mset_cmd = Command::parsed(RespCommand::Mset(MsetCommand::from_key_values(vec![("fromMset".into(), "mset".into())])));
+        let mget_cmd = Command::parsed(RespCommand::Mget(MgetCommand::from_keys(vec!["fromSet".into(), "fromMset".into(), "fromNonExistent".into()])));
+
+        let result = set_cmd.execute_on(&mut engine);
+        assert_eq!(result, SimpleString("OK".to_owned()));
+
+        let result = mset_cmd.execute_on(&mut engine);
+        assert_eq!(result, SimpleString("OK".to_owned()));
+
+        let result = mget_cmd.execute_on(&mut engine);
+        // the set values are returning in the order requested, non-existing values are Null(BulkString)
+        assert_eq!(result, Array(vec![BulkString("set".into()), BulkString("mset".into()), NullBulkString]));
     }
 
     #[test]
-    fn create_ttl_command() {
-        let cmd = Command::from(Array(vec![BulkString("ttl".to_owned()), BulkString("Name".to_owned())]));
-        assert_eq!(cmd, Ok(Command(RespCommand::Ttl { key: String::from("Name") })));
+    fn execute_del_removes_previously_set_values() {
+        let mut engine = StorageEngine::new();
+        let mset_cmd = Command::parsed(RespCommand::Mset(MsetCommand::from_key_values(vec![("key1".into(), "value1".into()), ("key2".into(), "value2".into())])));
+        let del_cmd = Command::parsed(RespCommand::Del(DelCommand::from_keys(vec!["key1".into(), "key2".into(), "key3".into()])));
+        let mget_cmd = Command::parsed(RespCommand::Mget(MgetCommand::from_keys(vec!["key1".into(), "key2".into()])));
+
+        let result = mset_cmd.execute_on(&mut engine);
+        assert_eq!(result, SimpleString("OK".to_owned()));
+
+        let result = del_cmd.execute_on(&mut engine);
+        // 'del' response tells us how many items were removed
+        assert_eq!(result, Integer(2));
+
+        let result = mget_cmd.execute_on(&mut engine);
+        // getting the deleted keys shows that they were deleted
+        assert_eq!(result, Array(vec![NullBulkString, NullBulkString]));
     }
 
     #[test]
-    fn cannot_create_empty_mset_command() {
-        let cmd = Command::from(Array(vec![BulkString("mset".to_owned())]));
-        assert_eq!(cmd, Err("Wrong number of arguments for 'mset' command".to_string()));
+    fn execute_exists_returns_the_count_of_existing_keys() {
+        let mut engine = StorageEngine::new();
+        let mset_cmd = Command::parsed(RespCommand::Mset(MsetCommand::from_key_values(vec![("key1".into(), "value1".into()), ("key2".into(), "value2".into())])));
+        let exists_cmd = Command::parsed(RespCommand::Exists(ExistsCommand::from_keys(vec!["key1".into(), "key2".into(), "key3".into()])));
+        let mget_cmd = Command::parsed(RespCommand::Mget(MgetCommand::from_keys(vec!["key1".into(), "key2".into()])));
+
+        let result = mset_cmd.execute_on(&mut engine);
+        assert_eq!(result, SimpleString("OK".to_owned()));
+
+        let result = exists_cmd.execute_on(&mut engine);
+        // 'exists' response tells us how many items exist
+        assert_eq!(result, Integer(2));
+
+        // checking for existence doesn't affect the values
+        let result = mget_cmd.execute_on(&mut engine);
+        assert_eq!(result, Array(vec![BulkString("value1".into()), BulkString("value2".into())]));
     }
 
     #[test]
-    fn create_mset_command() {
-        let cmd = Command::from(Array(vec![BulkString("mset".to_owned()), BulkString("FirstName".to_owned()), BulkString("Jane".to_owned()), BulkString("LastName".to_owned()), BulkString("Doe".to_owned())]));
-        assert_eq!(cmd, Ok(Command(RespCommand::Mset(MsetCommand::from_key_values(vec![("FirstName".to_string(), "Jane".to_string()), ("LastName".to_string(), "Doe".to_string())])))));
+    fn execute_push_returns_the_list_length_and_lrange_returns_the_elements_in_order() {
+        let mut engine = StorageEngine::new();
+
+        assert_eq!(execute(&mut engine, &["rpush", "list", "b", "c"]), Integer(2));
+        assert_eq!(execute(&mut engine, &["lpush", "list", "a", "z"]), Integer(4));
+
+        // every value of LPUSH is pushed to the head, so the last one ends up first
+        let result = execute(&mut engine, &["lrange", "list", "0", "-1"]);
+        assert_eq!(result, bulk_strings(&["z", "a", "b", "c"]));
     }
 
     #[test]
-    fn cannot_create_empty_mget_command() {
-        let cmd = Command::from(Array(vec![BulkString("mget".to_owned())]));
-        assert_eq!(cmd, Err("Wrong number of arguments for 'mget' command".to_string()));
+    fn execute_lrange_supports_negative_and_out_of_range_indexes() {
+        let mut engine = StorageEngine::new();
+        execute(&mut engine, &["rpush", "list", "a", "b", "c", "d"]);
+
+        assert_eq!(execute(&mut engine, &["lrange", "list", "-2", "-1"]), bulk_strings(&["c", "d"]));
+        assert_eq!(execute(&mut engine, &["lrange", "list", "-100", "1"]), bulk_strings(&["a", "b"]));
+        assert_eq!(execute(&mut engine, &["lrange", "list", "2", "100"]), bulk_strings(&["c", "d"]));
+        assert_eq!(execute(&mut engine, &["lrange", "list", "3", "1"]), bulk_strings(&[]));
+        assert_eq!(execute(&mut engine, &["lrange", "missing", "0", "-1"]), bulk_strings(&[]));
     }
 
     #[test]
-    fn create_mget_command() {
-        let cmd = Command::from(Array(vec![BulkString("mget".to_owned()), BulkString("FirstName".to_owned()), BulkString("LastName".to_owned())]));
-        assert_eq!(cmd, Ok(Command(RespCommand::Mget(MgetCommand::from_keys(vec!["FirstName".to_string(), "LastName".to_string()])))));
+    fn execute_pop_without_count_returns_a_single_element_and_removes_empty_lists() {
+        let mut engine = StorageEngine::new();
+        execute(&mut engine, &["rpush", "list", "a", "b"]);
+
+        assert_eq!(execute(&mut engine, &["rpop", "list"]), BulkString("b".into()));
+        assert_eq!(execute(&mut engine, &["lpop", "list"]), BulkString("a".into()));
+        assert_eq!(execute(&mut engine, &["lpop", "list"]), NullBulkString);
+        assert_eq!(execute(&mut engine, &["exists", "list"]), Integer(0));
     }
 
     #[test]
-    fn cannot_create_empty_del_command() {
-        let cmd = Command::from(Array(vec![BulkString("del".to_owned())]));
-        assert_eq!(cmd, Err("Wrong number of arguments for 'del' command".to_string()));
+    fn execute_pop_with_count_returns_an_array() {
+        let mut engine = StorageEngine::new();
+        execute(&mut engine, &["rpush", "list", "a", "b", "c", "d"]);
+
+        assert_eq!(execute(&mut engine, &["lpop", "list", "2"]), bulk_strings(&["a", "b"]));
+        assert_eq!(execute(&mut engine, &["rpop", "list", "5"]), bulk_strings(&["d", "c"]));
+        assert_eq!(execute(&mut engine, &["rpop", "list", "5"]), NullArray);
     }
 
     #[test]
-    fn create_del_command() {
-        let cmd = Command::from(Array(vec![BulkString("del".to_owned()), BulkString("FirstName".to_owned()), BulkString("LastName".to_owned())]));
-        assert_eq!(cmd, Ok(Command(RespCommand::Del(DelCommand::from_keys(vec!["FirstName".to_string(), "LastName".to_string()])))));
+    fn execute_lindex_and_lset_support_negative_indexes() {
+        let mut engine = StorageEngine::new();
+        execute(&mut engine, &["rpush", "list", "a", "b", "c"]);
+
+        assert_eq!(execute(&mut engine, &["lindex", "list", "-1"]), BulkString("c".into()));
+        assert_eq!(execute(&mut engine, &["lindex", "list", "3"]), NullBulkString);
+
+        assert_eq!(execute(&mut engine, &["lset", "list", "-3", "x"]), SimpleString("OK".to_owned()));
+        assert_eq!(execute(&mut engine, &["lindex", "list", "0"]), BulkString("x".into()));
+
+        assert_eq!(execute(&mut engine, &["lset", "list", "3", "x"]), Error("index out of range".to_owned()));
+        assert_eq!(execute(&mut engine, &["lset", "missing", "0", "x"]), Error("no such key".to_owned()));
     }
 
     #[test]
-    fn create_exists_command() {
-        let cmd = Command::from(Array(vec![BulkString("exists".to_owned()), BulkString("FirstName".to_owned()), BulkString("LastName".to_owned())]));
-        assert_eq!(cmd, Ok(Command(RespCommand::Exists(ExistsCommand::from_keys(vec!["FirstName".to_string(), "LastName".to_string()])))));
+    fn execute_lrem_removes_from_head_tail_or_everywhere_depending_on_count() {
+        let mut engine = StorageEngine::new();
+        execute(&mut engine, &["rpush", "list", "x", "a", "x", "b", "x", "c", "x"]);
+
+        assert_eq!(execute(&mut engine, &["lrem", "list", "1", "x"]), Integer(1));
+        assert_eq!(execute(&mut engine, &["lrange", "list", "0", "-1"]), bulk_strings(&["a", "x", "b", "x", "c", "x"]));
+
+        assert_eq!(execute(&mut engine, &["lrem", "list", "-2", "x"]), Integer(2));
+        assert_eq!(execute(&mut engine, &["lrange", "list", "0", "-1"]), bulk_strings(&["a", "x", "b", "c"]));
+
+        assert_eq!(execute(&mut engine, &["lrem", "list", "0", "x"]), Integer(1));
+        assert_eq!(execute(&mut engine, &["lrange", "list", "0", "-1"]), bulk_strings(&["a", "b", "c"]));
     }
 
     #[test]
-    fn cannot_create_non_existing_command() {
-        let cmd = Command::from(Array(vec![BulkString("whubalubadubdub".to_owned())]));
-        assert_eq!(cmd, Err("unknown command 'whubalubadubdub'".to_owned()));
+    fn execute_ltrim_keeps_only_the_range_and_removes_the_key_when_empty() {
+        let mut engine = StorageEngine::new();
+        execute(&mut engine, &["rpush", "list", "a", "b", "c", "d"]);
+
+        assert_eq!(execute(&mut engine, &["ltrim", "list", "1", "-2"]), SimpleString("OK".to_owned()));
+        assert_eq!(execute(&mut engine, &["lrange", "list", "0", "-1"]), bulk_strings(&["b", "c"]));
+
+        assert_eq!(execute(&mut engine, &["ltrim", "list", "5", "10"]), SimpleString("OK".to_owned()));
+        assert_eq!(execute(&mut engine, &["exists", "list"]), Integer(0));
     }
 
     #[test]
-    fn cannot_create_command_from_empty_array() {
-        let cmd = Command::from(Array(vec![]));
-        assert!(cmd.is_err());
+    fn execute_linsert_places_the_value_around_the_pivot() {
+        let mut engine = StorageEngine::new();
+        execute(&mut engine, &["rpush", "list", "a", "c"]);
+
+        assert_eq!(execute(&mut engine, &["linsert", "list", "before", "c", "b"]), Integer(3));
+        assert_eq!(execute(&mut engine, &["linsert", "list", "after", "c", "d"]), Integer(4));
+        assert_eq!(execute(&mut engine, &["lrange", "list", "0", "-1"]), bulk_strings(&["a", "b", "c", "d"]));
+
+        assert_eq!(execute(&mut engine, &["linsert", "list", "after", "z", "d"]), Integer(-1));
+        assert_eq!(execute(&mut engine, &["linsert", "missing", "after", "z", "d"]), Integer(0));
+
+        let cmd = Command::parsed(RespCommand::Linsert { key: "list".into(), position: InsertPosition::Before, pivot: "a".into(), value: "_".into() });
+        assert_eq!(cmd.execute_on(&mut engine), Integer(5));
     }
 
     #[test]
-    fn cannot_create_command_from_null_array() {
-        let cmd = Command::from(NullArray);
-        assert!(cmd.is_err());
+    fn execute_list_and_string_commands_fail_on_keys_of_the_other_type() {
+        let mut engine = StorageEngine::new();
+        execute(&mut engine, &["set", "string", "value"]);
+        execute(&mut engine, &["rpush", "list", "a"]);
+
+        let wrong_type = Error("WRONGTYPE Operation against a key holding the wrong kind of value".to_owned());
+        assert_eq!(execute(&mut engine, &["lpush", "string", "a"]), wrong_type);
+        assert_eq!(execute(&mut engine, &["lrange", "string", "0", "-1"]), wrong_type);
+        assert_eq!(execute(&mut engine, &["llen", "string"]), wrong_type);
+        assert_eq!(execute(&mut engine, &["get", "list"]), wrong_type);
+
+        // MGET replies with nil for keys of another type
+        assert_eq!(execute(&mut engine, &["mget", "list"]), Array(vec![NullBulkString]));
+
+        // SET overwrites values of any type
+        assert_eq!(execute(&mut engine, &["set", "list", "value"]), SimpleString("OK".to_owned()));
+        assert_eq!(execute(&mut engine, &["get", "list"]), BulkString("value".into()));
     }
 
     #[test]
-    fn cannot_create_command_from_array_that_doesnt_contain_only_bulk_strings() {
-        let cmd = Command::from(Array(vec![Integer(4)]));
-        assert_eq!(cmd, Err("Array should only contain BulkStrings".to_owned()));
+    fn execute_hset_and_hget_store_and_fetch_fields() {
+        let mut engine = StorageEngine::new();
+
+        // HSET replies with how many fields were added
+        assert_eq!(execute(&mut engine, &["hset", "user", "name", "Jane", "age", "42"]), Integer(2));
+        assert_eq!(execute(&mut engine, &["hset", "user", "name", "John", "city", "Lisbon"]), Integer(1));
+
+        assert_eq!(execute(&mut engine, &["hget", "user", "name"]), BulkString("John".into()));
+        assert_eq!(execute(&mut engine, &["hget", "user", "unknown"]), NullBulkString);
+        assert_eq!(execute(&mut engine, &["hget", "missing", "name"]), NullBulkString);
+        assert_eq!(execute(&mut engine, &["hlen", "user"]), Integer(3));
+
+        let result = execute(&mut engine, &["hmget", "user", "age", "unknown", "city"]);
+        assert_eq!(result, Array(vec![BulkString("42".into()), NullBulkString, BulkString("Lisbon".into())]));
     }
 
     #[test]
-    fn cannot_create_command_from_error() {
-        let cmd = Command::from(Error(String::from("Error message")));
-        assert!(cmd.is_err());
+    fn execute_hgetall_hkeys_and_hvals_return_the_whole_hash() {
+        let mut engine = StorageEngine::new();
+        execute(&mut engine, &["hset", "user", "name", "Jane", "age", "42"]);
+
+        // hashes are not ordered, so the replies are sorted before comparing
+        let sorted = |reply: RespObject| match reply {
+            Array(entries) => {
+                let mut entries: Vec<String> = entries.into_iter()
+                    .map(|entry| if let BulkString(value) = entry { String::from_utf8(value).unwrap() } else { panic!("BulkString expected") })
+                    .collect();
+                entries.sort();
+                entries
+            },
+            other => panic!("Array expected, got {other:?}"),
+        };
+
+        assert_eq!(sorted(execute(&mut engine, &["hkeys", "user"])), vec!["age", "name"]);
+        assert_eq!(sorted(execute(&mut engine, &["hvals", "user"])), vec!["42", "Jane"]);
+        assert_eq!(sorted(execute(&mut engine, &["hgetall", "user"])), vec!["42", "Jane", "age", "name"]);
+        assert_eq!(execute(&mut engine, &["hgetall", "missing"]), bulk_strings(&[]));
     }
 
     #[test]
-    fn cannot_create_command_from_integer() {
-        let cmd = Command::from(Integer(0));
-        assert!(cmd.is_err());
+    fn execute_hdel_removes_fields_and_the_key_when_empty() {
+        let mut engine = StorageEngine::new();
+        execute(&mut engine, &["hset", "user", "name", "Jane", "age", "42"]);
+
+        assert_eq!(execute(&mut engine, &["hdel", "user", "name", "unknown"]), Integer(1));
+        assert_eq!(execute(&mut engine, &["hexists", "user", "name"]), Integer(0));
+        assert_eq!(execute(&mut engine, &["hexists", "user", "age"]), Integer(1));
+
+        assert_eq!(execute(&mut engine, &["hdel", "user", "age"]), Integer(1));
+        assert_eq!(execute(&mut engine, &["exists", "user"]), Integer(0));
     }
 
     #[test]
-    fn cannot_create_command_from_simple_string() {
-        let cmd = Command::from(SimpleString("foobar".to_owned()));
-        assert!(cmd.is_err());
+    fn execute_hsetnx_only_sets_missing_fields() {
+        let mut engine = StorageEngine::new();
+
+        assert_eq!(execute(&mut engine, &["hsetnx", "user", "name", "Jane"]), Integer(1));
+        assert_eq!(execute(&mut engine, &["hsetnx", "user", "name", "John"]), Integer(0));
+        assert_eq!(execute(&mut engine, &["hget", "user", "name"]), BulkString("Jane".into()));
     }
 
     #[test]
-    fn cannot_create_command_from_bulk_string() {
-        // 'PING' is a valid command, but commands are expected to come in an Array
-        let cmd = Command::from(BulkString("PING".to_owned()));
-        assert!(cmd.is_err());
+    fn execute_hincrby_increments_integer_fields() {
+        let mut engine = StorageEngine::new();
+
+        assert_eq!(execute(&mut engine, &["hincrby", "user", "visits", "5"]), Integer(5));
+        assert_eq!(execute(&mut engine, &["hincrby", "user", "visits", "-2"]), Integer(3));
+
+        execute(&mut engine, &["hset", "user", "name", "Jane", "big", &i64::MAX.to_string()]);
+        assert_eq!(execute(&mut engine, &["hincrby", "user", "name", "1"]), Error("hash value is not an integer".to_owned()));
+        assert_eq!(execute(&mut engine, &["hincrby", "user", "big", "1"]), Error("increment or decrement would overflow".to_owned()));
     }
 
     #[test]
-    fn cannot_create_command_from_null_bulk_string() {
-        let cmd = Command::from(NullBulkString);
-        assert!(cmd.is_err());
+    fn execute_hash_commands_fail_on_keys_of_another_type() {
+        let mut engine = StorageEngine::new();
+        execute(&mut engine, &["set", "string", "value"]);
+        execute(&mut engine, &["hset", "hash", "field", "value"]);
+
+        let wrong_type = Error("WRONGTYPE Operation against a key holding the wrong kind of value".to_owned());
+        assert_eq!(execute(&mut engine, &["hset", "string", "field", "value"]), wrong_type);
+        assert_eq!(execute(&mut engine, &["hget", "string", "field"]), wrong_type);
+        assert_eq!(execute(&mut engine, &["hgetall", "string"]), wrong_type);
+        assert_eq!(execute(&mut engine, &["get", "hash"]), wrong_type);
+        assert_eq!(execute(&mut engine, &["lpush", "hash", "a"]), wrong_type);
     }
-}
 
-#[cfg(test)]
-mod command_execution_tests {
-    use crate::command::{Command, DelCommand, ExistsCommand, GetCommand, MgetCommand, MsetCommand, RespCommand, SetCommand};
-    use crate::engine::StorageEngine;
-    use crate::protocol::RespObject::{Array, BulkString, Integer, NullBulkString, SimpleString};
+    // sets are not ordered, so replies holding members are sorted before comparing
+    fn sorted_members(reply: RespObject) -> Vec<String> {
+        match reply {
+            Array(entries) => {
+                let mut members: Vec<String> = entries.into_iter()
+                    .map(|entry| if let BulkString(value) = entry { String::from_utf8(value).unwrap() } else { panic!("BulkString expected") })
+                    .collect();
+                members.sort();
+                members
+            },
+            other => panic!("Array expected, got {other:?}"),
+        }
+    }
 
     #[test]
-    fn execute_ping_should_return_pong() {
+    fn execute_sadd_srem_and_membership_commands() {
         let mut engine = StorageEngine::new();
-        let cmd = Command(RespCommand::Ping);
 
-        let result = cmd.execute_on(&mut engine);
-        assert_eq!(result, SimpleString("PONG".to_owned()));
+        assert_eq!(execute(&mut engine, &["sadd", "tags", "a", "b", "a"]), Integer(2));
+        assert_eq!(execute(&mut engine, &["sadd", "tags", "b", "c"]), Integer(1));
+        assert_eq!(execute(&mut engine, &["scard", "tags"]), Integer(3));
+        assert_eq!(sorted_members(execute(&mut engine, &["smembers", "tags"])), vec!["a", "b", "c"]);
+
+        assert_eq!(execute(&mut engine, &["sismember", "tags", "a"]), Integer(1));
+        assert_eq!(execute(&mut engine, &["sismember", "tags", "z"]), Integer(0));
+        assert_eq!(execute(&mut engine, &["smismember", "tags", "a", "z"]), Array(vec![Integer(1), Integer(0)]));
+        assert_eq!(execute(&mut engine, &["smismember", "missing", "a"]), Array(vec![Integer(0)]));
+
+        assert_eq!(execute(&mut engine, &["srem", "tags", "a", "b", "z"]), Integer(2));
+        assert_eq!(execute(&mut engine, &["srem", "tags", "c"]), Integer(1));
+        assert_eq!(execute(&mut engine, &["exists", "tags"]), Integer(0));
     }
 
     #[test]
-    fn execute_echo_should_return_first_parameter() {
+    fn execute_spop_removes_random_members() {
         let mut engine = StorageEngine::new();
-        let cmd = Command(RespCommand::Echo { message: String::from("\"Hello, world\"") });
+        execute(&mut engine, &["sadd", "tags", "a", "b", "c"]);
 
-        let result = cmd.execute_on(&mut engine);
-        assert_eq!(result, SimpleString(String::from("\"Hello, world\"")));
+        let popped = execute(&mut engine, &["spop", "tags"]);
+        assert!(matches!(&popped, BulkString(member) if ["a", "b", "c"].contains(&std::str::from_utf8(member).unwrap())));
+        assert_eq!(execute(&mut engine, &["scard", "tags"]), Integer(2));
+
+        let popped = sorted_members(execute(&mut engine, &["spop", "tags", "5"]));
+        assert_eq!(popped.len(), 2);
+        assert_eq!(execute(&mut engine, &["exists", "tags"]), Integer(0));
+
+        assert_eq!(execute(&mut engine, &["spop", "tags"]), NullBulkString);
+        assert_eq!(execute(&mut engine, &["spop", "tags", "2"]), Array(vec![]));
     }
 
     #[test]
-    fn execute_get_should_return_nil_when_unset() {
+    fn execute_srandmember_returns_distinct_members_for_positive_count_and_repeats_for_negative() {
         let mut engine = StorageEngine::new();
-        let cmd = Command(RespCommand::Get(GetCommand::from(String::from("foo"))));
+        execute(&mut engine, &["sadd", "tags", "a", "b", "c"]);
 
-        let result = cmd.execute_on(&mut engine);
-        assert_eq!(result, NullBulkString);
+        assert_eq!(sorted_members(execute(&mut engine, &["srandmember", "tags", "10"])), vec!["a", "b", "c"]);
+
+        let members = sorted_members(execute(&mut engine, &["srandmember", "tags", "2"]));
+        assert_eq!(members.len(), 2);
+        assert_ne!(members[0], members[1]);
+
+        let members = sorted_members(execute(&mut engine, &["srandmember", "tags", "-10"]));
+        assert_eq!(members.len(), 10);
+
+        // the set is left untouched
+        assert_eq!(execute(&mut engine, &["scard", "tags"]), Integer(3));
+        assert_eq!(execute(&mut engine, &["srandmember", "missing"]), NullBulkString);
     }
 
     #[test]
-    fn execute_get_should_return_the_previously_set_value() {
+    fn execute_set_algebra_commands() {
         let mut engine = StorageEngine::new();
-        let set_cmd = Command(RespCommand::Set( SetCommand::from_key_value((String::from("foo"), String::from("bar")))));
-        let get_cmd = Command(RespCommand::Get(GetCommand::from(String::from("foo"))));
+        execute(&mut engine, &["sadd", "a", "1", "2", "3", "4"]);
+        execute(&mut engine, &["sadd", "b", "2", "3", "5"]);
+        execute(&mut engine, &["sadd", "c", "3", "6"]);
 
-        let result = set_cmd.execute_on(&mut engine);
-        // SET responds with a simple string of 'OK'
-        assert_eq!(result, SimpleString("OK".to_owned()));
+        assert_eq!(sorted_members(execute(&mut engine, &["sinter", "a", "b", "c"])), vec!["3"]);
+        assert_eq!(sorted_members(execute(&mut engine, &["sinter", "a", "missing"])), Vec::<String>::new());
+        assert_eq!(sorted_members(execute(&mut engine, &["sunion", "a", "b", "missing"])), vec!["1", "2", "3", "4", "5"]);
+        assert_eq!(sorted_members(execute(&mut engine, &["sdiff", "a", "b", "c"])), vec!["1", "4"]);
+        assert_eq!(sorted_members(execute(&mut engine, &["sdiff", "missing", "a"])), Vec::<String>::new());
+    }
 
-        let result = get_cmd.execute_on(&mut engine);
-        assert_eq!(result, BulkString("bar".to_owned()));
+    #[test]
+    fn execute_set_algebra_store_commands_overwrite_the_destination() {
+        let mut engine = StorageEngine::new();
+        execute(&mut engine, &["sadd", "a", "1", "2", "3"]);
+        execute(&mut engine, &["sadd", "b", "2", "3", "4"]);
+        execute(&mut engine, &["set", "dest", "string"]);
+
+        assert_eq!(execute(&mut engine, &["sinterstore", "dest", "a", "b"]), Integer(2));
+        assert_eq!(sorted_members(execute(&mut engine, &["smembers", "dest"])), vec!["2", "3"]);
+
+        assert_eq!(execute(&mut engine, &["sunionstore", "dest", "a", "b"]), Integer(4));
+        assert_eq!(execute(&mut engine, &["scard", "dest"]), Integer(4));
+
+        // an empty result removes the destination
+        assert_eq!(execute(&mut engine, &["sdiffstore", "dest", "a", "a"]), Integer(0));
+        assert_eq!(execute(&mut engine, &["exists", "dest"]), Integer(0));
     }
 
     #[test]
-    fn execute_get_should_return_the_previously_mset_values() {
+    fn execute_set_commands_fail_on_keys_of_another_type() {
         let mut engine = StorageEngine::new();
-        let mset_cmd = Command(RespCommand::Mset(MsetCommand::from_key_values(vec![(String::from("key1"), String::from("1")), (String::from("key2"), String::from("2"))])));
-        let get_cmd1 = Command(RespCommand::Get(GetCommand::from(String::from("key1"))));
-        let get_cmd2 = Command(RespCommand::Get(GetCommand::from(String::from("key2"))));
+        execute(&mut engine, &["set", "string", "value"]);
+        execute(&mut engine, &["sadd", "set", "a"]);
 
-        let result = mset_cmd.execute_on(&mut engine);
-        // MSET responds with a simple string of 'OK'
-        assert_eq!(result, SimpleString("OK".to_owned()));
+        let wrong_type = Error("WRONGTYPE Operation against a key holding the wrong kind of value".to_owned());
+        assert_eq!(execute(&mut engine, &["sadd", "string", "a"]), wrong_type);
+        assert_eq!(execute(&mut engine, &["smembers", "string"]), wrong_type);
+        assert_eq!(execute(&mut engine, &["sunion", "set", "string"]), wrong_type);
+        assert_eq!(execute(&mut engine, &["sinterstore", "dest", "set", "string"]), wrong_type);
+        assert_eq!(execute(&mut engine, &["get", "set"]), wrong_type);
+    }
 
-        let result = get_cmd1.execute_on(&mut engine);
-        assert_eq!(result, BulkString("1".to_owned()));
+    #[test]
+    fn execute_zadd_and_zrange_order_members_by_score() {
+        let mut engine = StorageEngine::new();
 
-        let result = get_cmd2.execute_on(&mut engine);
-        assert_eq!(result, BulkString("2".to_owned()));
+        assert_eq!(execute(&mut engine, &["zadd", "board", "10", "jane", "5", "john", "7.5", "mary"]), Integer(3));
+        assert_eq!(execute(&mut engine, &["zadd", "board", "1", "jane", "3", "bob"]), Integer(1));
+        assert_eq!(execute(&mut engine, &["zcard", "board"]), Integer(4));
+
+        assert_eq!(execute(&mut engine, &["zrange", "board", "0", "-1"]), bulk_strings(&["jane", "bob", "john", "mary"]));
+        assert_eq!(execute(&mut engine, &["zrange", "board", "0", "1", "rev", "withscores"]), bulk_strings(&["mary", "7.5", "john", "5"]));
+        assert_eq!(execute(&mut engine, &["zrange", "missing", "0", "-1"]), bulk_strings(&[]));
     }
 
     #[test]
-    fn execute_mset_with_repeated_key_applies_the_last_value() {
+    fn execute_zadd_respects_update_conditions() {
         let mut engine = StorageEngine::new();
-        let mset_cmd = Command(RespCommand::Mset(MsetCommand::from_key_values(vec![(String::from("foo"), String::from("bar")), (String::from("foo"), String::from("baz"))])));
-        let get_cmd = Command(RespCommand::Get(GetCommand::from(String::from("foo"))));
+        execute(&mut engine, &["zadd", "board", "10", "jane"]);
 
-        let result = mset_cmd.execute_on(&mut engine);
-        // MSET responds with a simple string of 'OK'
-        assert_eq!(result, SimpleString("OK".to_owned()));
+        // NX never updates, XX never adds
+        assert_eq!(execute(&mut engine, &["zadd", "board", "nx", "20", "jane", "1", "john"]), Integer(1));
+        assert_eq!(execute(&mut engine, &["zadd", "board", "xx", "ch", "20", "jane", "1", "mary"]), Integer(1));
+        assert_eq!(execute(&mut engine, &["zscore", "board", "jane"]), BulkString("20".into()));
+        assert_eq!(execute(&mut engine, &["zscore", "board", "mary"]), NullBulkString);
 
-        // will return the second value, because it overwrites the first entry in mset
-        let result = get_cmd.execute_on(&mut engine);
-        assert_eq!(result, BulkString("baz".to_owned()));
+        // GT/LT only update when the score moves in the given direction, but still add new members
+        assert_eq!(execute(&mut engine, &["zadd", "board", "gt", "ch", "15", "jane", "2", "john", "3", "bob"]), Integer(2));
+        assert_eq!(execute(&mut engine, &["zadd", "board", "lt", "ch", "25", "jane", "0", "john"]), Integer(1));
+        assert_eq!(execute(&mut engine, &["zrange", "board", "0", "-1", "withscores"]), bulk_strings(&["john", "0", "bob", "3", "jane", "20"]));
+
+        // XX on a missing key doesn't create it
+        assert_eq!(execute(&mut engine, &["zadd", "missing", "xx", "1", "a"]), Integer(0));
+        assert_eq!(execute(&mut engine, &["exists", "missing"]), Integer(0));
     }
 
     #[test]
-    fn execute_mget_should_return_all_previously_set_or_mset_values() {
+    fn execute_zadd_incr_and_zincrby_return_the_new_score() {
         let mut engine = StorageEngine::new();
-        let set_cmd = Command(RespCommand::Set(SetCommand::from_key_value((String::from("fromSet"), String::from("set")))));
-        let mset_cmd = Command(RespCommand::Mset(MsetCommand::from_key_values(vec![(String::from("fromMset"), String::from("mset"))])));
-        let mget_cmd = Command(RespCommand::Mget(MgetCommand::from_keys(vec![String::from("fromSet"), String::from("fromMset"), String::from("fromNonExistent")])));
 
-        let result = set_cmd.execute_on(&mut engine);
-        assert_eq!(result, SimpleString("OK".to_owned()));
+        assert_eq!(execute(&mut engine, &["zadd", "board", "incr", "5", "jane"]), BulkString("5".into()));
+        assert_eq!(execute(&mut engine, &["zincrby", "board", "2.5", "jane"]), BulkString("7.5".into()));
+        assert_eq!(execute(&mut engine, &["zadd", "board", "incr", "nx", "1", "jane"]), NullBulkString);
+        assert_eq!(execute(&mut engine, &["zadd", "board", "incr", "+inf", "jane"]), BulkString("inf".into()));
+        assert_eq!(execute(&mut engine, &["zincrby", "board", "-inf", "jane"]), Error("resulting score is not a number (NaN)".to_owned()));
+    }
 
-        let result = mset_cmd.execute_on(&mut engine);
-        assert_eq!(result, SimpleString("OK".to_owned()));
+    #[test]
+    fn execute_scores_are_written_like_redis_does_for_any_magnitude() {
+        let mut engine = StorageEngine::new();
+        execute(&mut engine, &["zadd", "board", "1e20", "huge", "123456789012345678", "large", "1e16", "big", "0.0001", "small", "1e-7", "tiny"]);
 
-        let result = mget_cmd.execute_on(&mut engine);
-        // the set values are returning in the order requested, non-existing values are Null(BulkString)
-        assert_eq!(result, Array(vec![BulkString(String::from("set")), BulkString(String::from("mset")), NullBulkString]));
+        assert_eq!(execute(&mut engine, &["zscore", "board", "huge"]), BulkString("1e+20".into()));
+        assert_eq!(execute(&mut engine, &["zscore", "board", "large"]), BulkString("1.2345678901234568e+17".into()));
+        assert_eq!(execute(&mut engine, &["zscore", "board", "big"]), BulkString("10000000000000000".into()));
+        assert_eq!(execute(&mut engine, &["zscore", "board", "small"]), BulkString("0.0001".into()));
+        assert_eq!(execute(&mut engine, &["zrange", "board", "0", "0", "withscores"]), bulk_strings(&["tiny", "1e-07"]));
+        assert_eq!(execute(&mut engine, &["zincrby", "board", "1e20", "huge"]), BulkString("2e+20".into()));
+        assert_eq!(execute(&mut engine, &["zincrby", "board", "-2.5e-7", "tiny"]), BulkString("-1.5e-07".into()));
     }
 
     #[test]
-    fn execute_del_removes_previously_set_values() {
+    fn execute_zrank_zrem_and_zcount() {
         let mut engine = StorageEngine::new();
-        let mset_cmd = Command(RespCommand::Mset(MsetCommand::from_key_values(vec![(String::from("key1"), String::from("value1")), (String::from("key2"), String::from("value2"))])));
-        let del_cmd = Command(RespCommand::Del(DelCommand::from_keys(vec![String::from("key1"), String::from("key2"), String::from("key3")])));
-        let mget_cmd = Command(RespCommand::Mget(MgetCommand::from_keys(vec![String::from("key1"), String::from("key2")])));
+        execute(&mut engine, &["zadd", "board", "1", "a", "2", "b", "3", "c", "4", "d"]);
 
-        let result = mset_cmd.execute_on(&mut engine);
-        assert_eq!(result, SimpleString("OK".to_owned()));
+        assert_eq!(execute(&mut engine, &["zrank", "board", "c"]), Integer(2));
+        assert_eq!(execute(&mut engine, &["zrevrank", "board", "c"]), Integer(1));
+        assert_eq!(execute(&mut engine, &["zrank", "board", "c", "withscore"]), Array(vec![Integer(2), BulkString("3".into())]));
+        assert_eq!(execute(&mut engine, &["zrank", "board", "z"]), NullBulkString);
 
-        let result = del_cmd.execute_on(&mut engine);
-        // 'del' response tells us how many items were removed
-        assert_eq!(result, Integer(2));
+        assert_eq!(execute(&mut engine, &["zcount", "board", "(1", "3"]), Integer(2));
+        assert_eq!(execute(&mut engine, &["zcount", "board", "-inf", "+inf"]), Integer(4));
 
-        let result = mget_cmd.execute_on(&mut engine);
-        // getting the deleted keys shows that they were deleted
-        assert_eq!(result, Array(vec![NullBulkString, NullBulkString]));
+        assert_eq!(execute(&mut engine, &["zrem", "board", "a", "z"]), Integer(1));
+        assert_eq!(execute(&mut engine, &["zrank", "board", "c"]), Integer(1));
+        assert_eq!(execute(&mut engine, &["zrem", "board", "b", "c", "d"]), Integer(3));
+        assert_eq!(execute(&mut engine, &["exists", "board"]), Integer(0));
     }
 
     #[test]
-    fn execute_exists_returns_the_count_of_existing_keys() {
+    fn execute_zrange_by_score_and_by_lex() {
         let mut engine = StorageEngine::new();
-        let mset_cmd = Command(RespCommand::Mset(MsetCommand::from_key_values(vec![(String::from("key1"), String::from("value1")), (String::from("key2"), String::from("value2"))])));
-        let exists_cmd = Command(RespCommand::Exists(ExistsCommand::from_keys(vec![String::from("key1"), String::from("key2"), String::from("key3")])));
-        let mget_cmd = Command(RespCommand::Mget(MgetCommand::from_keys(vec![String::from("key1"), String::from("key2")])));
+        execute(&mut engine, &["zadd", "board", "1", "a", "2", "b", "3", "c", "4", "d", "5", "e"]);
 
-        let result = mset_cmd.execute_on(&mut engine);
-        assert_eq!(result, SimpleString("OK".to_owned()));
+        assert_eq!(execute(&mut engine, &["zrangebyscore", "board", "2", "(4"]), bulk_strings(&["b", "c"]));
+        assert_eq!(execute(&mut engine, &["zrangebyscore", "board", "-inf", "+inf", "limit", "1", "2"]), bulk_strings(&["b", "c"]));
+        assert_eq!(execute(&mut engine, &["zrange", "board", "+inf", "3", "byscore", "rev", "withscores"]), bulk_strings(&["e", "5", "d", "4", "c", "3"]));
+        assert_eq!(execute(&mut engine, &["zrange", "board", "0", "10", "byscore", "limit", "-1", "2"]), bulk_strings(&[]));
+        assert_eq!(execute(&mut engine, &["zrange", "board", "0", "10", "byscore", "limit", "3", "-1"]), bulk_strings(&["d", "e"]));
 
-        let result = exists_cmd.execute_on(&mut engine);
-        // 'exists' response tells us how many items exist
-        assert_eq!(result, Integer(2));
+        execute(&mut engine, &["zadd", "names", "0", "alice", "0", "bob", "0", "carol", "0", "dave"]);
+        assert_eq!(execute(&mut engine, &["zrange", "names", "[b", "(d", "bylex"]), bulk_strings(&["bob", "carol"]));
+        assert_eq!(execute(&mut engine, &["zrange", "names", "+", "-", "bylex", "rev", "limit", "0", "2"]), bulk_strings(&["dave", "carol"]));
+    }
+
+    #[test]
+    fn execute_sorted_set_commands_fail_on_keys_of_another_type() {
+        let mut engine = StorageEngine::new();
+        execute(&mut engine, &["set", "string", "value"]);
+        execute(&mut engine, &["zadd", "board", "1", "a"]);
+
+        let wrong_type = Error("WRONGTYPE Operation against a key holding the wrong kind of value".to_owned());
+        assert_eq!(execute(&mut engine, &["zadd", "string", "1", "a"]), wrong_type);
+        assert_eq!(execute(&mut engine, &["zrange", "string", "0", "-1"]), wrong_type);
+        assert_eq!(execute(&mut engine, &["zscore", "string", "a"]), wrong_type);
+        assert_eq!(execute(&mut engine, &["get", "board"]), wrong_type);
+        assert_eq!(execute(&mut engine, &["sadd", "board", "a"]), wrong_type);
+    }
+
+    fn stream_entry(id: &str, fields: &[&str]) -> RespObject {
+        Array(vec![BulkString(id.into()), bulk_strings(fields)])
+    }
+
+    #[test]
+    fn execute_xadd_generates_increasing_ids_and_xrange_returns_the_entries() {
+        let mut engine = StorageEngine::new();
+
+        assert_eq!(execute(&mut engine, &["xadd", "events", "1-1", "type", "login"]), BulkString("1-1".into()));
+        assert_eq!(execute(&mut engine, &["xadd", "events", "1-*", "type", "logout"]), BulkString("1-2".into()));
+        assert_eq!(execute(&mut engine, &["xadd", "events", "1", "type", "login"]), Error("The ID specified in XADD is equal or smaller than the target stream top item".to_owned()));
+        assert_eq!(execute(&mut engine, &["xadd", "events", "3-0", "type", "login", "user", "bob"]), BulkString("3-0".into()));
+        assert_eq!(execute(&mut engine, &["xadd", "missing", "nomkstream", "*", "type", "login"]), NullBulkString);
+        assert_eq!(execute(&mut engine, &["xadd", "other", "0-0", "type", "login"]), Error("The ID specified in XADD must be greater than 0-0".to_owned()));
+        assert_eq!(execute(&mut engine, &["exists", "missing", "other"]), Integer(0));
+        assert_eq!(execute(&mut engine, &["xlen", "events"]), Integer(3));
+
+        assert_eq!(execute(&mut engine, &["xrange", "events", "-", "+", "count", "2"]), Array(vec![
+            stream_entry("1-1", &["type", "login"]),
+            stream_entry("1-2", &["type", "logout"]),
+        ]));
+        assert_eq!(execute(&mut engine, &["xrange", "events", "(1-1", "1"]), Array(vec![stream_entry("1-2", &["type", "logout"])]));
+        assert_eq!(execute(&mut engine, &["xrevrange", "events", "+", "2"]), Array(vec![stream_entry("3-0", &["type", "login", "user", "bob"])]));
+    }
+
+    #[test]
+    fn execute_xadd_with_an_invalid_id_leaves_no_stream_behind() {
+        let mut engine = StorageEngine::new();
+        let mut subscriber = Session::new();
+        let messages = subscriber.take_output().unwrap();
+        execute_in(&mut subscriber, &mut engine, &["subscribe", "__keyevent@0__:new"]);
+        execute(&mut engine, &["config", "set", "notify-keyspace-events", "En"]);
+
+        // the stream is only created once an entry made it in, so the refused one is not announced as new
+        assert_eq!(execute(&mut engine, &["xadd", "events", "0-0", "type", "login"]), Error("The ID specified in XADD must be greater than 0-0".to_owned()));
+        assert_eq!(execute(&mut engine, &["exists", "events"]), Integer(0));
+        assert_eq!(execute(&mut engine, &["xadd", "events", "1-1", "type", "login"]), BulkString("1-1".into()));
+        let new = Array(vec![BulkString("message".into()), BulkString("__keyevent@0__:new".into()), BulkString("events".into())]).to_bytes();
+        assert_eq!(messages.try_iter().collect::<Vec<_>>(), vec![new]);
+    }
+
+    #[test]
+    fn execute_xtrim_xadd_with_trimming_and_xdel_remove_entries() {
+        let mut engine = StorageEngine::new();
+        for id in 1..=5 {
+            execute(&mut engine, &["xadd", "events", &id.to_string(), "n", &id.to_string()]);
+        }
+
+        assert_eq!(execute(&mut engine, &["xtrim", "events", "maxlen", "4"]), Integer(1));
+        assert_eq!(execute(&mut engine, &["xtrim", "events", "minid", "~", "5", "limit", "2"]), Integer(2));
+        assert_eq!(execute(&mut engine, &["xadd", "events", "minid", "5", "6", "n", "6"]), BulkString("6-0".into()));
+        assert_eq!(execute(&mut engine, &["xlen", "events"]), Integer(2));
+
+        assert_eq!(execute(&mut engine, &["xdel", "events", "5", "7"]), Integer(1));
+        assert_eq!(execute(&mut engine, &["xdel", "events", "6"]), Integer(1));
+        // unlike other collections, empty streams are kept
+        assert_eq!(execute(&mut engine, &["xlen", "events"]), Integer(0));
+        assert_eq!(execute(&mut engine, &["exists", "events"]), Integer(1));
+    }
+
+    #[test]
+    fn execute_xgroup_create_requires_the_stream_unless_mkstream() {
+        let mut engine = StorageEngine::new();
+
+        assert!(matches!(execute(&mut engine, &["xgroup", "create", "events", "group", "$"]), Error(_)));
+        assert_eq!(execute(&mut engine, &["xgroup", "create", "events", "group", "$", "mkstream"]), SimpleString("OK".to_owned()));
+        assert_eq!(execute(&mut engine, &["xgroup", "create", "events", "group", "0"]), Error("BUSYGROUP Consumer Group name already exists".to_owned()));
+        assert_eq!(execute(&mut engine, &["xgroup", "createconsumer", "events", "group", "alice"]), Integer(1));
+        assert_eq!(execute(&mut engine, &["xgroup", "createconsumer", "events", "group", "alice"]), Integer(0));
+        assert_eq!(execute(&mut engine, &["xgroup", "delconsumer", "events", "group", "alice"]), Integer(0));
+        assert_eq!(execute(&mut engine, &["xgroup", "destroy", "events", "group"]), Integer(1));
+        assert_eq!(execute(&mut engine, &["xgroup", "setid", "events", "group", "0"]), Error("NOGROUP No such key 'events' or consumer group 'group'".to_owned()));
+    }
+
+    #[test]
+    fn execute_xreadgroup_delivers_new_entries_once_and_tracks_them_until_xack() {
+        let mut engine = StorageEngine::new();
+        execute(&mut engine, &["xadd", "events", "1", "n", "1"]);
+        execute(&mut engine, &["xadd", "events", "2", "n", "2"]);
+        execute(&mut engine, &["xgroup", "create", "events", "group", "0"]);
+
+        assert_eq!(execute(&mut engine, &["xreadgroup", "group", "group", "alice", "count", "1", "streams", "events", ">"]), Array(vec![
+            Array(vec![BulkString("events".into()), Array(vec![stream_entry("1-0", &["n", "1"])])]),
+        ]));
+        assert_eq!(execute(&mut engine, &["xreadgroup", "group", "group", "bob", "streams", "events", ">"]), Array(vec![
+            Array(vec![BulkString("events".into()), Array(vec![stream_entry("2-0", &["n", "2"])])]),
+        ]));
+        assert_eq!(execute(&mut engine, &["xreadgroup", "group", "group", "bob", "streams", "events", ">"]), NullArray);
+        assert_eq!(execute(&mut engine, &["xreadgroup", "group", "missing", "bob", "streams", "events", ">"]), Error("NOGROUP No such key 'events' or consumer group 'missing'".to_owned()));
+
+        assert_eq!(execute(&mut engine, &["xpending", "events", "group"]), Array(vec![
+            Integer(2),
+            BulkString("1-0".into()),
+            BulkString("2-0".into()),
+            Array(vec![bulk_strings(&["alice", "1"]), bulk_strings(&["bob", "1"])]),
+        ]));
+
+        // the history of a consumer holds its pending entries, deleted ones without fields
+        execute(&mut engine, &["xdel", "events", "1"]);
+        assert_eq!(execute(&mut engine, &["xreadgroup", "group", "group", "alice", "streams", "events", "0"]), Array(vec![
+            Array(vec![BulkString("events".into()), Array(vec![Array(vec![BulkString("1-0".into()), NullArray])])]),
+        ]));
+
+        assert_eq!(execute(&mut engine, &["xack", "events", "group", "1", "2", "3"]), Integer(2));
+        assert_eq!(execute(&mut engine, &["xpending", "events", "group"]), Array(vec![Integer(0), NullBulkString, NullBulkString, NullArray]));
+    }
+
+    #[test]
+    fn execute_xclaim_and_xautoclaim_transfer_pending_entries() {
+        let mut engine = StorageEngine::new();
+        for id in 1..=3 {
+            execute(&mut engine, &["xadd", "events", &id.to_string(), "n", &id.to_string()]);
+        }
+        execute(&mut engine, &["xgroup", "create", "events", "group", "0"]);
+        execute(&mut engine, &["xreadgroup", "group", "group", "alice", "streams", "events", ">"]);
+
+        assert_eq!(execute(&mut engine, &["xclaim", "events", "group", "bob", "0", "1", "justid"]), bulk_strings(&["1-0"]));
+        assert_eq!(execute(&mut engine, &["xclaim", "events", "group", "bob", "0", "2", "retrycount", "5"]), Array(vec![stream_entry("2-0", &["n", "2"])]));
+
+        let pending = execute(&mut engine, &["xpending", "events", "group", "-", "+", "10", "bob"]);
+        let Array(pending) = pending else { panic!("expected an array, got {:?}", pending) };
+        let delivery_counts: Vec<RespObject> = pending.into_iter()
+            .map(|entry| match entry {
+                Array(mut entry) => entry.swap_remove(3),
+                _ => panic!("expected an array"),
+            })
+            .collect();
+        assert_eq!(delivery_counts, vec![Integer(1), Integer(5)]);
+
+        execute(&mut engine, &["xdel", "events", "2"]);
+        assert_eq!(execute(&mut engine, &["xautoclaim", "events", "group", "carol", "0", "0", "count", "2"]), Array(vec![
+            BulkString("3-0".into()),
+            Array(vec![stream_entry("1-0", &["n", "1"])]),
+            bulk_strings(&["2-0"]),
+        ]));
+        assert_eq!(execute(&mut engine, &["xautoclaim", "events", "group", "carol", "0", "3-0", "justid"]), Array(vec![
+            BulkString("0-0".into()),
+            bulk_strings(&["3-0"]),
+            bulk_strings(&[]),
+        ]));
+    }
+
+    #[test]
+    fn execute_stream_commands_fail_on_keys_of_another_type() {
+        let mut engine = StorageEngine::new();
+        execute(&mut engine, &["set", "string", "value"]);
+        execute(&mut engine, &["xadd", "events", "*", "n", "1"]);
+
+        let wrong_type = Error("WRONGTYPE Operation against a key holding the wrong kind of value".to_owned());
+        assert_eq!(execute(&mut engine, &["xadd", "string", "*", "n", "1"]), wrong_type);
+        assert_eq!(execute(&mut engine, &["xrange", "string", "-", "+"]), wrong_type);
+        assert_eq!(execute(&mut engine, &["xgroup", "create", "string", "group", "$"]), wrong_type);
+        assert_eq!(execute(&mut engine, &["get", "events"]), wrong_type);
+        assert_eq!(execute(&mut engine, &["lpush", "events", "a"]), wrong_type);
     }
 }
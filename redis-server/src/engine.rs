@@ -1,201 +1,2707 @@
-use std::collections::hash_map::Entry::{Occupied, Vacant};
-use std::collections::HashMap;
-use std::ops::Add;
+mod encoding;
+mod expiry;
+mod keyspace;
+mod notify;
+mod random;
+mod snapshot;
+mod sorted_set;
+mod stream;
+mod watch;
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::thread;
 use std::time::Duration;
 
 #[cfg(test)]
-use mock_instant::global::SystemTime;
+use mock_instant::thread_local::{Instant, SystemTime};
 
 #[cfg(not(test))]
-use std::time::SystemTime;
-use crate::engine::Value::StringValue;
+use std::time::{Instant, SystemTime};
+use crate::engine::expiry::ExpiryIndex;
+use crate::engine::keyspace::Keyspace;
+use crate::engine::notify::notify_keyspace_event;
+use crate::engine::random::Random;
+use crate::engine::snapshot::Entry;
+use crate::engine::sorted_set::SortedSet;
+use crate::engine::stream::Stream;
+use crate::engine::watch::WatchedKeys;
+use crate::aof::AppendOnlyFile;
+use crate::rdb::{RdbFile, DEFAULT_RDB_FILENAME};
+use crate::blocking::BlockedClients;
+use crate::glob::glob_match;
+use crate::protocol::MAX_ARRAY_LENGTH;
+use crate::pubsub::Broker;
+use crate::engine::Value::{HashValue, ListValue, SetValue, SortedSetValue, StreamValue, StringValue};
+
+pub use crate::engine::expiry::{ExpireConfig, ExpireStats};
+pub use crate::engine::notify::NotifyFlags;
+pub use crate::engine::snapshot::Snapshot;
+pub use crate::engine::sorted_set::{LexBound, LexRange, ScoreRange};
+pub use crate::engine::stream::{AutoClaimOptions, AutoClaimResult, ClaimOptions, GroupReadEntries, GroupStartId, NewStreamId, PendingEntryInfo, PendingFilter, PendingSummary, StreamFields, StreamId, StreamTrim, TrimStrategy};
+
+const WRONG_TYPE_ERROR: &str = "WRONGTYPE Operation against a key holding the wrong kind of value";
+// same as the default proto-max-bulk-len of Redis, which SETRANGE can't grow a string beyond
+const MAX_STRING_LENGTH: usize = 512 * 1024 * 1024;
+// how the active expire cycle works at the lowest effort, as in Redis; each extra effort step raises them a bit
+const ACTIVE_EXPIRE_KEYS_PER_LOOP: u32 = 20;
+const ACTIVE_EXPIRE_ACCEPTABLE_STALE_PERC: u32 = 10;
+const ACTIVE_EXPIRE_CPU_PERC: u32 = 25;
+// the logarithmic access counter behind OBJECT FREQ, tuned like the defaults of Redis (lfu-log-factor/lfu-decay-time)
+const LFU_INIT_VAL: u8 = 5;
+const LFU_LOG_FACTOR: f64 = 10.0;
+const LFU_DECAY_TIME: Duration = Duration::from_secs(60);
+// up to where Redis keeps values in their compact encodings, as reported by OBJECT ENCODING
+const LISTPACK_MAX_ENTRIES: usize = 128;
+const LISTPACK_MAX_VALUE_LEN: usize = 64;
+const LIST_LISTPACK_MAX_SIZE: usize = 8 * 1024;
+const INTSET_MAX_ENTRIES: usize = 512;
+const EMBSTR_MAX_LEN: usize = 44;
+pub const DEFAULT_DATABASES: usize = 16;
 
 pub struct StorageEngine {
+    databases: Vec<Database>,
+    // the database that key operations apply to, which is set to the one of the connection before each command
+    selected_db: usize,
+    // where the next active expire cycle starts
+    next_expire_db: usize,
+    // the keys that clients WATCH, by database; these stay with the database index when databases are swapped
+    watched: Vec<WatchedKeys>,
+    // pub/sub is shared by all clients the same way the data is
+    broker: Broker,
+    // the clients waiting in blocking commands (e.g. BLPOP), which writes to the keys they wait on wake up
+    blocked: BlockedClients,
+    notify_flags: NotifyFlags,
+    expire_config: ExpireConfig,
+    expire_stats: ExpireStats,
+    // how many changes were made to the data, which tells whether a command wrote anything
+    dirty: u64,
+    // set while the data is loaded on startup, when keys must not expire (later commands may still use them)
+    loading: bool,
+    // where writes are logged, when the append only file is enabled
+    aof: Option<AppendOnlyFile>,
+    // where snapshots are saved, by SAVE, BGSAVE and the save rules
+    rdb: RdbFile,
+}
+
+/// One of the logical databases, each an independent keyspace
+struct Database {
     // todo: this works fine to start with get/set, need to review for other types perhaps
-    map: HashMap<String, Item>,
+    map: Keyspace<Item>,
+    // the keys in 'map' that have an expiry, for the active expire cycle to sample
+    expiry_index: ExpiryIndex,
+    random: Random,
+}
+
+impl Database {
+    fn new() -> Database {
+        Database {
+            map: Keyspace::new(),
+            expiry_index: ExpiryIndex::new(),
+            random: Random::new(),
+        }
+    }
+}
+
+impl Default for StorageEngine {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 pub enum TimeToLive {
     KeyDoesNotExist,
     DoesNotExpire,
-    ExpiresInSeconds(u64),
+    ExpiresInMillis(u64),
+}
+
+/// When a key expires, as an absolute time, for EXPIRETIME/PEXPIRETIME
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum ExpireTime {
+    KeyDoesNotExist,
+    DoesNotExpire,
+    AtUnixTimeMillis(u64),
+}
+
+/// What OBJECT reports about a key
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct ObjectInfo {
+    /// the encoding Redis would use for the value
+    pub encoding: &'static str,
+    /// seconds since the key was last read or written
+    pub idle_seconds: u64,
+    /// logarithmic access counter, in the style of the LFU eviction policy of Redis
+    pub frequency: u8,
+    /// values are never shared between keys, so this is always 1
+    pub ref_count: usize,
 }
 
 struct Item {
     value: Value,
-    expires_at: Option<SystemTime>,
+    // on the monotonic clock, so that wall clock jumps neither expire nor resurrect keys
+    expires_at: Option<Instant>,
+    // when the key was last read or written, for OBJECT IDLETIME
+    last_access: Instant,
+    // for OBJECT FREQ, see 'Item::touch'
+    access_frequency: u8,
+}
+
+impl Item {
+    fn new(value: Value, expires_at: Option<Instant>) -> Item {
+        Item { value, expires_at, last_access: Instant::now(), access_frequency: LFU_INIT_VAL }
+    }
+
+    /// Registers an access to the key. Like the LFU counter of Redis, the access frequency grows ever more
+    /// slowly (it is incremented with a probability that falls as it grows), and decays by one for every
+    /// minute without any access, so a byte is enough to tell hot keys from cold ones.
+    fn touch(&mut self, random: &mut Random) {
+        let now = Instant::now();
+        let frequency = self.decayed_frequency(now);
+        let base = frequency.saturating_sub(LFU_INIT_VAL) as f64;
+        let increment_probability = 1.0 / (base * LFU_LOG_FACTOR + 1.0);
+
+        self.access_frequency = if frequency < u8::MAX && random.next_f64() < increment_probability {
+            frequency + 1
+        } else {
+            frequency
+        };
+        self.last_access = now;
+    }
+
+    fn decayed_frequency(&self, now: Instant) -> u8 {
+        let periods = now.saturating_duration_since(self.last_access).as_secs() / LFU_DECAY_TIME.as_secs();
+        self.access_frequency.saturating_sub(periods.min(u8::MAX as u64) as u8)
+    }
 }
 
-// todo: to try and support operations on other data types
+// field -> value
+type Hash = HashMap<Vec<u8>, Vec<u8>>;
+
+/// Borrowed field/value pairs of a hash, in no particular order.
+pub type HashEntries<'a> = Vec<(&'a [u8], &'a [u8])>;
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Clone)]
 enum Value {
-    StringValue(String),
+    StringValue(Vec<u8>),
+    ListValue(VecDeque<Vec<u8>>),
+    HashValue(Hash),
+    SetValue(HashSet<Vec<u8>>),
+    SortedSetValue(SortedSet),
+    StreamValue(Stream),
 }
 
 impl Value {
-    fn get_string(&self) -> Result<&String, String> {
+    fn get_string(&self) -> Result<&Vec<u8>, String> {
         match self {
             StringValue(value) => Ok(value),
-            _ => Err("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())
+            _ => Err(WRONG_TYPE_ERROR.to_string())
+        }
+    }
+
+    fn get_list(&self) -> Result<&VecDeque<Vec<u8>>, String> {
+        match self {
+            ListValue(list) => Ok(list),
+            _ => Err(WRONG_TYPE_ERROR.to_string())
+        }
+    }
+
+    fn get_list_mut(&mut self) -> Result<&mut VecDeque<Vec<u8>>, String> {
+        match self {
+            ListValue(list) => Ok(list),
+            _ => Err(WRONG_TYPE_ERROR.to_string())
+        }
+    }
+
+    fn get_hash(&self) -> Result<&Hash, String> {
+        match self {
+            HashValue(hash) => Ok(hash),
+            _ => Err(WRONG_TYPE_ERROR.to_string())
+        }
+    }
+
+    fn get_hash_mut(&mut self) -> Result<&mut Hash, String> {
+        match self {
+            HashValue(hash) => Ok(hash),
+            _ => Err(WRONG_TYPE_ERROR.to_string())
+        }
+    }
+
+    fn get_set(&self) -> Result<&HashSet<Vec<u8>>, String> {
+        match self {
+            SetValue(set) => Ok(set),
+            _ => Err(WRONG_TYPE_ERROR.to_string())
+        }
+    }
+
+    fn get_set_mut(&mut self) -> Result<&mut HashSet<Vec<u8>>, String> {
+        match self {
+            SetValue(set) => Ok(set),
+            _ => Err(WRONG_TYPE_ERROR.to_string())
+        }
+    }
+
+    fn get_sorted_set(&self) -> Result<&SortedSet, String> {
+        match self {
+            SortedSetValue(sorted_set) => Ok(sorted_set),
+            _ => Err(WRONG_TYPE_ERROR.to_string())
+        }
+    }
+
+    fn get_sorted_set_mut(&mut self) -> Result<&mut SortedSet, String> {
+        match self {
+            SortedSetValue(sorted_set) => Ok(sorted_set),
+            _ => Err(WRONG_TYPE_ERROR.to_string())
+        }
+    }
+
+    fn get_stream(&self) -> Result<&Stream, String> {
+        match self {
+            StreamValue(stream) => Ok(stream),
+            _ => Err(WRONG_TYPE_ERROR.to_string())
         }
     }
+
+    fn get_stream_mut(&mut self) -> Result<&mut Stream, String> {
+        match self {
+            StreamValue(stream) => Ok(stream),
+            _ => Err(WRONG_TYPE_ERROR.to_string())
+        }
+    }
+
+    /// The name TYPE (and SCAN's TYPE option) know this kind of value by.
+    fn type_name(&self) -> &'static str {
+        match self {
+            StringValue(_) => "string",
+            ListValue(_) => "list",
+            HashValue(_) => "hash",
+            SetValue(_) => "set",
+            SortedSetValue(_) => "zset",
+            StreamValue(_) => "stream",
+        }
+    }
+
+    /// The encoding Redis would use for this value, as reported by OBJECT ENCODING: Redis keeps small values
+    /// in compact encodings (e.g. 'listpack'), and converts them once they grow past some thresholds.
+    fn encoding(&self) -> &'static str {
+        match self {
+            // only integers in their canonical form are stored as such
+            StringValue(value) if parse_integer(value).is_some_and(|integer| integer.to_string().as_bytes() == value) => "int",
+            StringValue(value) if value.len() <= EMBSTR_MAX_LEN => "embstr",
+            StringValue(_) => "raw",
+            ListValue(list) if list.iter().map(Vec::len).sum::<usize>() <= LIST_LISTPACK_MAX_SIZE => "listpack",
+            ListValue(_) => "quicklist",
+            HashValue(hash) if fits_listpack(hash.len(), hash.iter().flat_map(|(field, value)| [field, value])) => "listpack",
+            HashValue(_) => "hashtable",
+            SetValue(set) if set.len() <= INTSET_MAX_ENTRIES && set.iter().all(|member| parse_integer(member).is_some()) => "intset",
+            SetValue(set) if fits_listpack(set.len(), set.iter()) => "listpack",
+            SetValue(_) => "hashtable",
+            SortedSetValue(sorted_set) if sorted_set.len() <= LISTPACK_MAX_ENTRIES
+                && fits_listpack(sorted_set.len(), sorted_set.range_by_rank(0, -1, false).into_iter().map(|(member, _)| member)) => "listpack",
+            SortedSetValue(_) => "skiplist",
+            StreamValue(_) => "stream",
+        }
+    }
+
+    fn is_empty_collection(&self) -> bool {
+        match self {
+            StringValue(_) => false,
+            ListValue(list) => list.is_empty(),
+            HashValue(hash) => hash.is_empty(),
+            SetValue(set) => set.is_empty(),
+            SortedSetValue(sorted_set) => sorted_set.is_empty(),
+            // unlike other collections, empty streams are kept (e.g. to hold their consumer groups)
+            StreamValue(_) => false,
+        }
+    }
+}
+
+/// Which end of a list an operation applies to (e.g. LPUSH vs RPUSH)
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum ListEnd {
+    Left,
+    Right,
+}
+
+/// The set algebra operations behind SINTER/SUNION/SDIFF (and their STORE variants)
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum SetOperation {
+    Intersection,
+    Union,
+    Difference,
+}
+
+/// Conditions for updating sorted set members, from the ZADD flags NX/XX/GT/LT
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub struct SortedSetUpdateConditions {
+    /// NX: only add new members, never update existing ones
+    pub only_new: bool,
+    /// XX: only update existing members, never add new ones
+    pub only_existing: bool,
+    /// GT: only update existing members when the new score is greater
+    pub only_greater: bool,
+    /// LT: only update existing members when the new score is lower
+    pub only_lower: bool,
+}
+
+impl SortedSetUpdateConditions {
+    fn allow(&self, current: Option<f64>, new_score: f64) -> bool {
+        match current {
+            None => !self.only_existing,
+            Some(current) => !self.only_new
+                && (!self.only_greater || new_score > current)
+                && (!self.only_lower || new_score < current),
+        }
+    }
+}
+
+/// The new expiry given by EXPIRE/PEXPIRE (relative) and EXPIREAT/PEXPIREAT (absolute), in milliseconds.
+/// Unlike SET, these accept times in the past, which delete the key.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum ExpireAt {
+    AfterMillis(i64),
+    UnixTimeMillis(i64),
+}
+
+/// Conditions for changing the expiry of a key, from the EXPIRE flags NX/XX/GT/LT.
+/// A key without an expiry counts as having an infinite TTL for GT/LT.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub struct ExpireConditions {
+    /// NX: only when the key has no expiry yet
+    pub only_without_expiry: bool,
+    /// XX: only when the key already has an expiry
+    pub only_with_expiry: bool,
+    /// GT: only when the new expiry is later than the current one
+    pub only_greater: bool,
+    /// LT: only when the new expiry is earlier than the current one
+    pub only_lower: bool,
+}
+
+impl ExpireConditions {
+    fn allow(&self, current: Option<Instant>, new_expires_at: Instant) -> bool {
+        match current {
+            None => !self.only_with_expiry && !self.only_greater,
+            Some(current) => !self.only_without_expiry
+                && (!self.only_greater || new_expires_at > current)
+                && (!self.only_lower || new_expires_at < current),
+        }
+    }
+}
+
+/// The ways ZRANGE can select members
+#[derive(Debug, PartialEq, Clone)]
+pub enum SortedSetRange {
+    ByRank { start: i64, stop: i64 },
+    ByScore(ScoreRange),
+    ByLex(LexRange),
+}
+
+/// Where LINSERT places the new element relative to the pivot
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum InsertPosition {
+    Before,
+    After,
+}
+
+/// When SET writes its value, from the flags NX/XX
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub enum SetCondition {
+    #[default]
+    Always,
+    /// NX: only when the key does not exist yet
+    IfNotExists,
+    /// XX: only when the key already exists
+    IfExists,
+}
+
+/// The expiry SET gives the key it writes, from the options EX/PX/EXAT/PXAT/KEEPTTL.
+/// Also used by GETEX, where PERSIST is 'Never' and no option at all is 'Keep'.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub enum SetExpiry {
+    /// the key does not expire, even if it previously did
+    #[default]
+    Never,
+    /// EX/PX: the key expires once the duration elapses
+    After(Duration),
+    /// EXAT/PXAT: the key expires at the given unix time, in milliseconds
+    AtUnixTimeMillis(u64),
+    /// KEEPTTL: the key keeps the expiry it already had
+    Keep,
+}
+
+/// All the options of SET, besides the key and value
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub struct SetOptions {
+    pub condition: SetCondition,
+    pub expiry: SetExpiry,
+    /// GET: the previous value is returned (and must be a string)
+    pub get: bool,
 }
 
 impl StorageEngine {
     pub fn new() -> StorageEngine {
+        StorageEngine::with_databases(DEFAULT_DATABASES)
+    }
+
+    /// An engine with the given number of databases (at least one).
+    pub fn with_databases(count: usize) -> StorageEngine {
         StorageEngine {
-            map: HashMap::new(),
+            databases: (0..count.max(1)).map(|_| Database::new()).collect(),
+            selected_db: 0,
+            next_expire_db: 0,
+            watched: (0..count.max(1)).map(|_| WatchedKeys::new()).collect(),
+            broker: Broker::new(),
+            blocked: BlockedClients::new(),
+            notify_flags: NotifyFlags::default(),
+            expire_config: ExpireConfig::default(),
+            expire_stats: ExpireStats::default(),
+            dirty: 0,
+            loading: false,
+            aof: None,
+            rdb: RdbFile::new(DEFAULT_RDB_FILENAME),
         }
     }
 
+    fn db(&self) -> &Database {
+        &self.databases[self.selected_db]
+    }
+
+    fn db_mut(&mut self) -> &mut Database {
+        &mut self.databases[self.selected_db]
+    }
+
     /// Generic (and private) 'get_item' that contains necessary retrieval logic and is used by multiple functions.
     ///
     /// This function handles:
     /// - item expiry
-    fn get_item(&mut self, key: &str) -> Option<&Item> {
-        let now = SystemTime::now();
-        match self.map.entry(String::from(key)) {
-            Occupied(entry) => {
-                if let Some(expires_at) = entry.get().expires_at {
-                    if expires_at < now {
-                        entry.remove();
-                        return None
-                    }
-                }
+    /// - access tracking (for OBJECT IDLETIME/FREQ)
+    fn get_item(&mut self, key: &[u8]) -> Option<&Item> {
+        if self.remove_if_expired(key) {
+            return None;
+        }
 
-                // 'entry.get()' returns a reference with lifetime of "entry"
-                // 'entry.into_mut()' is the only one that returns a reference with lifetime of the HashMap
-                // - so that's what's used - even though a mutable reference is not needed
-                Some(entry.into_mut())
-            }
-            Vacant(_) => None,
+        let db = self.db_mut();
+        let item = db.map.get_mut(key)?;
+        item.touch(&mut db.random);
+        Some(item)
+    }
+
+    /// Mutable counterpart of 'get_item', for operations that modify a value in place. Those call 'key_modified'
+    /// themselves once they did change something, as a write that fails or changes nothing is no modification.
+    fn get_item_mut(&mut self, key: &[u8]) -> Option<&mut Item> {
+        self.get_item(key)?;
+        self.db_mut().map.get_mut(key)
+    }
+
+    /// Like 'get_item', but without counting as an access to the key: for commands that inspect keys rather
+    /// than use them (e.g. TYPE, OBJECT, TTL, KEYS), like the LOOKUP_NOTOUCH flag of Redis.
+    fn peek_item(&mut self, key: &[u8]) -> Option<&Item> {
+        self.remove_if_expired(key);
+        self.db().map.get(key)
+    }
+
+    /// Removes the key if it expired, returning whether it did.
+    fn remove_if_expired(&mut self, key: &[u8]) -> bool {
+        let now = Instant::now();
+        let expired = !self.loading && self.db().map.get(key)
+            .and_then(|item| item.expires_at)
+            .is_some_and(|expires_at| expires_at < now);
+
+        if expired {
+            self.db_mut().map.remove(key);
+            self.db_mut().expiry_index.remove(key);
+            self.key_modified(key);
+            self.notify(NotifyFlags::EXPIRED, "expired", key);
+            self.expire_stats.expired_keys += 1;
+            // the key is gone for good, whenever the file is replayed
+            self.propagate(&[b"DEL".to_vec(), key.to_vec()]);
+        }
+        expired
+    }
+
+    // tells the clients watching the key that it changed, and those blocked on it that it may have what they wait for
+    fn key_modified(&mut self, key: &[u8]) {
+        self.watched[self.selected_db].modified(key);
+        self.blocked.key_modified(self.selected_db, key);
+        self.dirty += 1;
+    }
+
+    /// Removes the key if it holds a collection that became empty, as Redis never keeps empty collections around.
+    fn remove_if_empty(&mut self, key: &[u8]) {
+        if self.db().map.get(key).is_some_and(|item| item.value.is_empty_collection()) {
+            self.remove(key);
         }
     }
 
     // 'get' requires a mutable reference because of how the expiry mechanism is implemented
-    pub fn get(&mut self, key: &str) -> Result<Option<&String>, String> {
+    pub fn get(&mut self, key: &[u8]) -> Result<Option<&Vec<u8>>, String> {
         self.get_item(key)
             .map(|item|item.value.get_string())
             .transpose()
     }
 
-    pub fn set(&mut self, key: String, value: String, expiry_seconds: Option<u64>) -> Result<(), String> {
-        // calculate expiry, if any
-        let expires_at =
-            expiry_seconds.map(|exp| SystemTime::now().add(Duration::from_secs(exp)));
+    pub fn set(&mut self, key: Vec<u8>, value: Vec<u8>, expiry_seconds: Option<u64>) -> Result<(), String> {
+        let expiry = expiry_seconds.map_or(SetExpiry::Never, |exp| SetExpiry::After(Duration::from_secs(exp)));
 
-        self.map.insert(key, Item { value: StringValue(value), expires_at });
+        // always writes, because it overwrites existing values
+        self.set_with_options(key, value, SetOptions { expiry, ..SetOptions::default() })
+            .map(|_| ())
+    }
 
-        // always succeeds because it overwrites existing values
-        Ok(())
+    /// Writes a string value the way SET does with all its options.
+    /// Returns whether the value was written (the NX/XX condition held), along with the previous value when
+    /// 'options.get' asks for it. GET fails with WRONGTYPE when the key holds something other than a string,
+    /// in which case nothing is written.
+    pub fn set_with_options(&mut self, key: Vec<u8>, value: Vec<u8>, options: SetOptions) -> Result<(bool, Option<Vec<u8>>), String> {
+        let current = self.get_item(&key);
+        let exists = current.is_some();
+        let current_expires_at = current.and_then(|item| item.expires_at);
+        let previous = match current {
+            Some(item) if options.get => Some(item.value.get_string()?.clone()),
+            _ => None,
+        };
+
+        let allowed = match options.condition {
+            SetCondition::Always => true,
+            SetCondition::IfNotExists => !exists,
+            SetCondition::IfExists => exists,
+        };
+        if !allowed {
+            return Ok((false, previous));
+        }
+
+        let expires_at = expiry_time(options.expiry, current_expires_at, "set")?;
+
+        // an EXAT/PXAT in the past still counts as a write, one that deletes the key
+        if is_expired(expires_at) {
+            self.remove(&key);
+        } else {
+            if expires_at.is_some() {
+                self.db_mut().expiry_index.insert(&key);
+            }
+            self.key_modified(&key);
+            if !exists {
+                self.notify(NotifyFlags::NEW, "new", &key);
+            }
+            self.notify(NotifyFlags::STRING, "set", &key);
+            if expires_at.is_some() && options.expiry != SetExpiry::Keep {
+                self.notify(NotifyFlags::GENERIC, "expire", &key);
+            }
+            self.db_mut().map.insert(key, Item::new(StringValue(value), expires_at));
+        }
+        Ok((true, previous))
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> bool {
+        let removed = self.delete_item(key).is_some();
+        if removed {
+            self.notify(NotifyFlags::GENERIC, "del", key);
+        }
+        removed
+    }
+
+    // adds a key (replacing nothing) without any event, for operations that have events of their own
+    fn insert_item(&mut self, key: &[u8], item: Item) {
+        if item.expires_at.is_some() {
+            self.db_mut().expiry_index.insert(key);
+        }
+        self.key_modified(key);
+        self.db_mut().map.insert(key.to_vec(), item);
     }
 
-    pub fn remove(&mut self, key: &str) -> bool {
-        let removed = self.map.remove(key);
-        removed.is_some()
+    // removes a key without any event, for operations that have events of their own (e.g. RENAME)
+    fn delete_item(&mut self, key: &[u8]) -> Option<Item> {
+        let removed = self.db_mut().map.remove(key)?;
+        if removed.expires_at.is_some() {
+            self.db_mut().expiry_index.remove(key);
+        }
+        self.key_modified(key);
+        Some(removed)
     }
 
-    pub fn exists(&mut self, key: &str) -> bool {
-        self.map.contains_key(key)
+    pub fn exists(&mut self, key: &[u8]) -> bool {
+        self.peek_item(key).is_some()
     }
 
-    pub fn time_to_live(&mut self, key: &str) -> TimeToLive {
-        match self.get_item(key) {
+    pub fn time_to_live(&mut self, key: &[u8]) -> TimeToLive {
+        match self.peek_item(key) {
             None => TimeToLive::KeyDoesNotExist,
             Some(item) => {
                 match item.expires_at {
                     None => TimeToLive::DoesNotExpire,
                     Some(expires_at) => {
-                        SystemTime::now().duration_since(expires_at)
-                            .map(|duration| TimeToLive::ExpiresInSeconds(duration.as_secs()))
-                            // don't expect 'duration_since' to ever Err here, so falling back to does not expire if this ever happens
-                            .unwrap_or_else(|err| {
-                                eprintln!("Error calculating expiry duration for {}: {}. Falling back to 'DoesNotExpire'", key, err);
-                                TimeToLive::DoesNotExpire
-                            })
+                        let remaining = expires_at.saturating_duration_since(Instant::now());
+                        TimeToLive::ExpiresInMillis(remaining.as_millis() as u64)
                     }
                 }
             },
         }
     }
-}
 
-#[cfg(test)]
-mod engine_tests {
-    use super::*;
-    use mock_instant::global::MockClock;
+    /// Absolute time at which the key expires, as reported by EXPIRETIME/PEXPIRETIME.
+    pub fn expire_time(&mut self, key: &[u8]) -> ExpireTime {
+        match self.peek_item(key) {
+            None => ExpireTime::KeyDoesNotExist,
+            Some(Item { expires_at: None, .. }) => ExpireTime::DoesNotExpire,
+            Some(Item { expires_at: Some(expires_at), .. }) => ExpireTime::AtUnixTimeMillis(unix_time_of(*expires_at)),
+        }
+    }
 
-    #[test]
-    fn get_should_return_nil_when_unset() {
-        let mut engine = StorageEngine::new();
+    /// Sets the expiry of an existing key, as long as the NX/XX/GT/LT conditions allow it.
+    /// An expiry that is already in the past deletes the key right away.
+    /// Returns whether the expiry was changed (or the key deleted).
+    pub fn expire(&mut self, key: &[u8], at: ExpireAt, conditions: ExpireConditions) -> Result<bool, String> {
+        let millis = match at {
+            ExpireAt::AfterMillis(millis) => millis,
+            ExpireAt::UnixTimeMillis(millis) => millis_until_unix_time(millis),
+        };
+        let now = Instant::now();
+        let expires_at = instant_in_millis(now, millis)
+            .ok_or_else(|| "invalid expire time".to_string())?;
 
-        let result = engine.get("foo").unwrap();
-        assert_eq!(result, None)
+        let loading = self.loading;
+        let Some(item) = self.get_item_mut(key) else {
+            return Ok(false);
+        };
+        if !conditions.allow(item.expires_at, expires_at) {
+            return Ok(false);
+        }
+
+        // while loading, the key is kept (expired) for the commands that follow in the file
+        if expires_at <= now && !loading {
+            self.remove(key);
+        } else {
+            item.expires_at = Some(expires_at);
+            self.db_mut().expiry_index.insert(key);
+            self.key_modified(key);
+            self.notify(NotifyFlags::GENERIC, "expire", key);
+        }
+        Ok(true)
     }
 
-    #[test]
-    fn get_should_return_the_previously_set_value_when_no_ttl_is_defined() {
-        let mut engine = StorageEngine::new();
+    /// Removes the expiry of a key, returning whether it had one.
+    pub fn persist(&mut self, key: &[u8]) -> bool {
+        let persisted = self.get_item_mut(key)
+            .and_then(|item| item.expires_at.take())
+            .is_some();
+        if persisted {
+            self.db_mut().expiry_index.remove(key);
+            self.key_modified(key);
+            self.notify(NotifyFlags::GENERIC, "persist", key);
+        }
+        persisted
+    }
 
-        engine.set(String::from("foo"), String::from("bar"), None).unwrap();
+    pub fn expire_config(&self) -> ExpireConfig {
+        self.expire_config
+    }
 
-        let result = engine.get("foo").unwrap();
-        assert_eq!(result, Some(&"bar".to_owned()));
+    pub fn set_expire_config(&mut self, config: ExpireConfig) {
+        self.expire_config = config;
     }
 
-    #[test]
-    fn get_should_return_the_previously_set_value_or_not_based_on_whether_ttl_is_expired() {
-        let mut engine = StorageEngine::new();
+    pub fn expire_stats(&self) -> ExpireStats {
+        self.expire_stats
+    }
 
-        // set the value with ttl=10s
-        engine.set(String::from("foo"), String::from("bar"), Some(10)).unwrap();
+    pub fn notify_flags(&self) -> NotifyFlags {
+        self.notify_flags
+    }
 
-        // fetch the value after 1s
-        MockClock::advance_system_time(Duration::from_secs(1));
-        let result = engine.get("foo").unwrap();
-        assert_eq!(result, Some(&"bar".to_owned()));
+    pub fn set_notify_flags(&mut self, flags: NotifyFlags) {
+        self.notify_flags = flags;
+    }
 
-        // fetch the value after 7s more (8s total)
-        MockClock::advance_system_time(Duration::from_secs(7));
-        let result = engine.get("foo").unwrap();
-        assert_eq!(result, Some(&"bar".to_owned()));
+    // publishes a keyspace event about a key of the selected database
+    fn notify(&self, class: NotifyFlags, event: &str, key: &[u8]) {
+        notify_keyspace_event(&self.broker, self.notify_flags, class, event, self.selected_db, key);
+    }
 
-        // fetch the value after another 3s more (11s total) -> TTL expired
-        MockClock::advance_system_time(Duration::from_secs(7));
-        let result = engine.get("foo").unwrap();
-        assert_eq!(result, None);
+    /// One run of the active expire cycle, which reclaims expired keys that nobody accesses anymore, following the
+    /// adaptive algorithm of Redis: it samples random keys with an expiry and removes the expired ones, and keeps
+    /// going for as long as too many of the sampled keys turn out to be expired (and it has time left).
+    /// Meant to be called 'hz' times per second. Returns how many keys were removed.
+    pub fn active_expire_cycle(&mut self) -> usize {
+        let effort = self.expire_config.effort.clamp(1, 10) - 1;
+        let keys_per_loop = (ACTIVE_EXPIRE_KEYS_PER_LOOP + ACTIVE_EXPIRE_KEYS_PER_LOOP / 4 * effort) as usize;
+        let acceptable_stale = (ACTIVE_EXPIRE_ACCEPTABLE_STALE_PERC - effort) as usize;
+        // the share of each 1/hz second slot the cycle may spend
+        let time_limit = Duration::from_micros(
+            10_000 * u64::from(ACTIVE_EXPIRE_CPU_PERC + 2 * effort) / u64::from(self.expire_config.hz.max(1)));
+
+        let start = Instant::now();
+        let mut total_sampled = 0;
+        let mut total_expired = 0;
+
+        // a cycle that runs out of time resumes from the database it stopped at, so that none is starved
+        let db_count = self.databases.len();
+        let first_db = self.next_expire_db;
+        'databases: for db_index in (first_db..first_db + db_count).map(|index| index % db_count) {
+            let db = &mut self.databases[db_index];
+
+            for iteration in 1.. {
+                if db.expiry_index.is_empty() {
+                    break;
+                }
+
+                let mut sampled = 0;
+                let mut expired = 0;
+
+                for _ in 0..keys_per_loop.min(db.expiry_index.len()) {
+                    let Some(key) = db.expiry_index.random_key(&mut db.random).map(<[u8]>::to_vec) else {
+                        break;
+                    };
+
+                    match db.map.get(&key).and_then(|item| item.expires_at) {
+                        // a leftover of a key that lost its expiry, which does not count as a sample
+                        None => db.expiry_index.remove(&key),
+                        Some(expires_at) => {
+                            sampled += 1;
+                            if expires_at < Instant::now() {
+                                db.map.remove(&key);
+                                db.expiry_index.remove(&key);
+                                self.watched[db_index].modified(&key);
+                                notify_keyspace_event(&self.broker, self.notify_flags, NotifyFlags::EXPIRED, "expired", db_index, &key);
+                                append_to_aof(&mut self.aof, db_index, &[b"DEL".to_vec(), key.clone()]);
+                                self.dirty += 1;
+                                expired += 1;
+                            }
+                        }
+                    }
+                }
+
+                total_sampled += sampled;
+                total_expired += expired;
+
+                // checking the time is not free either, so it only happens every now and then
+                if iteration % 16 == 0 && start.elapsed() > time_limit {
+                    self.expire_stats.expired_time_cap_reached_count += 1;
+                    self.next_expire_db = db_index;
+                    break 'databases;
+                }
+                if sampled == 0 || expired * 100 <= sampled * acceptable_stale {
+                    break;
+                }
+            }
+        }
+
+        self.expire_stats.expired_keys += total_expired as u64;
+        // a running average, so a single unlucky sample does not swing it
+        let stale_perc = if total_sampled > 0 { total_expired as f64 * 100.0 / total_sampled as f64 } else { 0.0 };
+        self.expire_stats.expired_stale_perc = stale_perc * 0.05 + self.expire_stats.expired_stale_perc * 0.95;
+        total_expired
     }
 
-    #[test]
-    fn remove_should_remove_and_indicate_if_something_was_removed_or_not() {
-        let mut engine = StorageEngine::new();
+    /// Fetches the item under the given key, inserting a new one built by 'new_value' when absent (or expired).
+    /// Like 'get_item_mut', the caller calls 'key_modified' once the write went through.
+    fn get_or_insert_item(&mut self, key: &[u8], new_value: impl FnOnce() -> Value) -> &mut Item {
+        // makes sure that an expired item is removed, instead of being reused
+        self.remove_if_expired(key);
+        if self.db().map.get(key).is_none() {
+            self.notify(NotifyFlags::NEW, "new", key);
+        }
 
-        engine.set(String::from("foo"), String::from("bar"), None).unwrap();
+        let db = self.db_mut();
+        let item = db.map.get_or_insert_with(key, || Item::new(new_value(), None));
+        item.touch(&mut db.random);
+        item
+    }
 
-        let result = engine.remove("foo");
-        assert_eq!(result, true);
+    // ===== Keyspace =====
 
-        // since the value was removed, it can no longer be found
-        let result = engine.get("foo").unwrap();
-        assert_eq!(result, None);
+    /// All the keys matching the glob-style 'pattern', as KEYS.
+    pub fn keys(&mut self, pattern: &[u8]) -> Vec<Vec<u8>> {
+        let matching: Vec<Vec<u8>> = self.db().map.keys()
+            .filter(|key| glob_match(pattern, key, false))
+            .map(<[u8]>::to_vec)
+            .collect();
 
-        // a new 'remove' will now not remove anything
-        let result = engine.remove("foo");
-        assert_eq!(result, false);
+        // expired keys are left out (and removed along the way)
+        matching.into_iter()
+            .filter(|key| self.peek_item(key).is_some())
+            .collect()
     }
 
+    /// One step of a SCAN iteration: looks at about 'count' keys from 'cursor' on, and returns the ones matching
+    /// 'pattern' and 'value_type' (when given) along with the cursor of the next step, which is 0 once done.
+    /// Keys present for the whole iteration are returned at least once.
+    pub fn scan(&mut self, cursor: u64, count: usize, pattern: Option<&[u8]>, value_type: Option<&[u8]>) -> (u64, Vec<Vec<u8>>) {
+        let (next_cursor, keys) = self.db().map.scan(cursor, count);
+        let keys: Vec<Vec<u8>> = keys.into_iter()
+            .filter(|key| pattern.is_none_or(|pattern| glob_match(pattern, key, false)))
+            .map(<[u8]>::to_vec)
+            .collect();
 
-    #[test]
-    fn exists_should_tell_whether_an_entry_exists_for_key() {
-        let mut engine = StorageEngine::new();
+        let keys = keys.into_iter()
+            .filter(|key| match self.peek_item(key) {
+                None => false,
+                Some(item) => value_type.is_none_or(|value_type| value_type.eq_ignore_ascii_case(item.value.type_name().as_bytes())),
+            })
+            .collect();
+        (next_cursor, keys)
+    }
+
+    /// A random key, as RANDOMKEY, or 'None' if there are no keys at all.
+    pub fn random_key(&mut self) -> Option<Vec<u8>> {
+        // expired keys are removed when picked, until a live one comes up
+        loop {
+            let db = self.db_mut();
+            let key = db.map.random_key(&mut db.random)?.to_vec();
+            if self.peek_item(&key).is_some() {
+                return Some(key);
+            }
+        }
+    }
 
-        let key = String::from("foo");
+    /// The number of keys, as DBSIZE (keys that expired but were not removed yet included, like Redis).
+    pub fn db_size(&self) -> usize {
+        self.db().map.len()
+    }
 
-        // initially doesn't exist
-        assert_eq!(engine.exists(&key), false);
+    /// Moves the value (and expiry) of 'key' to 'new_key', replacing whatever 'new_key' held, unless
+    /// 'only_if_new' is set and 'new_key' exists (RENAMENX). Returns whether the key was renamed.
+    pub fn rename(&mut self, key: &[u8], new_key: &[u8], only_if_new: bool) -> Result<bool, String> {
+        if self.peek_item(key).is_none() {
+            return Err("no such key".to_string());
+        }
+        if key == new_key {
+            return Ok(!only_if_new);
+        }
+        if only_if_new && self.peek_item(new_key).is_some() {
+            return Ok(false);
+        }
 
-        // after setting, exists
-        engine.set(key.clone(), String::from("bar"), None).unwrap();
+        let Some(item) = self.delete_item(key) else {
+            return Ok(false);
+        };
+        self.delete_item(new_key);
+        self.insert_item(new_key, item);
+        self.notify(NotifyFlags::GENERIC, "rename_from", key);
+        self.notify(NotifyFlags::GENERIC, "rename_to", new_key);
+        Ok(true)
+    }
+
+    /// Copies the value (and expiry) of 'source' to 'destination', as COPY. An existing destination is only
+    /// overwritten with 'replace'. Returns whether the value was copied.
+    pub fn copy(&mut self, source: &[u8], destination: &[u8], destination_db: Option<usize>, replace: bool) -> Result<bool, String> {
+        let destination_db = destination_db.unwrap_or(self.selected_db);
+        self.check_db_index(destination_db)?;
+        if source == destination && destination_db == self.selected_db {
+            return Err("source and destination objects are the same".to_string());
+        }
+        let Some(item) = self.get_item(source) else {
+            return Ok(false);
+        };
+        let (value, expires_at) = (item.value.clone(), item.expires_at);
+
+        self.with_db(destination_db, |engine| {
+            if engine.peek_item(destination).is_some() {
+                if !replace {
+                    return Ok(false);
+                }
+                engine.delete_item(destination);
+            }
+            engine.insert_item(destination, Item::new(value, expires_at));
+            engine.notify(NotifyFlags::GENERIC, "copy_to", destination);
+            Ok(true)
+        })
+    }
+
+    /// Registers an access to each of the keys, as TOUCH. Returns how many of them exist.
+    pub fn touch(&mut self, keys: &[Vec<u8>]) -> usize {
+        keys.iter()
+            .filter(|key| self.get_item(key).is_some())
+            .count()
+    }
+
+    /// The type of the value under the key, as TYPE, or 'None' if there is no such key.
+    pub fn key_type(&mut self, key: &[u8]) -> Option<&'static str> {
+        self.peek_item(key).map(|item| item.value.type_name())
+    }
+
+    /// What OBJECT reports about the key, or 'None' if there is no such key.
+    pub fn object_info(&mut self, key: &[u8]) -> Option<ObjectInfo> {
+        let item = self.peek_item(key)?;
+        let now = Instant::now();
+
+        Some(ObjectInfo {
+            encoding: item.value.encoding(),
+            idle_seconds: now.saturating_duration_since(item.last_access).as_secs(),
+            frequency: item.decayed_frequency(now),
+            ref_count: 1,
+        })
+    }
+
+    // ===== Databases =====
+
+    pub fn db_count(&self) -> usize {
+        self.databases.len()
+    }
+
+    /// Points key operations at the database with the given index, which must be lower than 'db_count'.
+    pub fn select_db(&mut self, index: usize) {
+        self.selected_db = index;
+    }
+
+    /// Runs 'operation' against another database than the selected one.
+    fn with_db<T>(&mut self, index: usize, operation: impl FnOnce(&mut StorageEngine) -> T) -> T {
+        let selected_db = self.selected_db;
+        self.selected_db = index;
+        let result = operation(self);
+        self.selected_db = selected_db;
+        result
+    }
+
+    fn check_db_index(&self, index: usize) -> Result<(), String> {
+        if index < self.databases.len() {
+            Ok(())
+        } else {
+            Err("DB index is out of range".to_string())
+        }
+    }
+
+    /// Swaps the contents of two databases, as SWAPDB: clients see the other data right away.
+    pub fn swap_dbs(&mut self, first: usize, second: usize) -> Result<(), String> {
+        self.check_db_index(first)?;
+        self.check_db_index(second)?;
+
+        self.databases.swap(first, second);
+        self.watched[first].all_modified();
+        self.watched[second].all_modified();
+        self.blocked.all_modified(first);
+        self.blocked.all_modified(second);
+        self.dirty += 1;
+        Ok(())
+    }
+
+    /// Moves a key (with its expiry) from the selected database to another one, as MOVE. Nothing moves when the
+    /// key is missing or already exists in the other database. Returns whether the key was moved.
+    pub fn move_key(&mut self, key: &[u8], db: usize) -> Result<bool, String> {
+        self.check_db_index(db)?;
+        if db == self.selected_db {
+            return Err("source and destination objects are the same".to_string());
+        }
+        if self.peek_item(key).is_none() || self.with_db(db, |engine| engine.peek_item(key).is_some()) {
+            return Ok(false);
+        }
+
+        let Some(item) = self.delete_item(key) else {
+            return Ok(false);
+        };
+        self.notify(NotifyFlags::GENERIC, "move_from", key);
+        self.with_db(db, |engine| {
+            engine.insert_item(key, item);
+            engine.notify(NotifyFlags::GENERIC, "move_to", key);
+        });
+        Ok(true)
+    }
+
+    /// Removes all the keys of the selected database, as FLUSHDB. With 'asynchronously', the memory is freed
+    /// by a background thread, so that the command does not wait for it.
+    pub fn flush_db(&mut self, asynchronously: bool) {
+        let flushed = std::mem::replace(self.db_mut(), Database::new());
+        self.watched[self.selected_db].all_modified();
+        // a flush counts as a change even when there was nothing to flush, like in Redis
+        self.dirty += flushed.map.len() as u64 + 1;
+        free(flushed, asynchronously);
+    }
+
+    /// Removes all the keys of all the databases, as FLUSHALL.
+    pub fn flush_all(&mut self, asynchronously: bool) {
+        let flushed: Vec<Database> = self.databases.iter_mut()
+            .map(|db| std::mem::replace(db, Database::new()))
+            .collect();
+        self.watched.iter_mut().for_each(WatchedKeys::all_modified);
+        self.dirty += flushed.iter().map(|db| db.map.len() as u64).sum::<u64>() + 1;
+        free(flushed, asynchronously);
+    }
+
+    // ===== Persistence =====
+
+    /// How many changes were made to the data since the engine started, which only ever grows.
+    pub fn dirty(&self) -> u64 {
+        self.dirty
+    }
+
+    /// While loading, keys do not expire: what a file holds was written when they had not expired yet.
+    pub fn set_loading(&mut self, loading: bool) {
+        self.loading = loading;
+    }
+
+    pub fn aof(&self) -> Option<&AppendOnlyFile> {
+        self.aof.as_ref()
+    }
+
+    pub fn aof_mut(&mut self) -> Option<&mut AppendOnlyFile> {
+        self.aof.as_mut()
+    }
+
+    pub fn set_aof(&mut self, aof: Option<AppendOnlyFile>) {
+        self.aof = aof;
+    }
+
+    pub fn rdb(&self) -> &RdbFile {
+        &self.rdb
+    }
+
+    pub fn rdb_mut(&mut self) -> &mut RdbFile {
+        &mut self.rdb
+    }
+
+    /// Logs a command that wrote to the selected database to the append only file, if enabled.
+    pub fn propagate(&mut self, arguments: &[Vec<u8>]) {
+        append_to_aof(&mut self.aof, self.selected_db, arguments);
+    }
+
+    /// A copy of all the data that has not expired, to write out in the background.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::new(&self.databases)
+    }
+
+    /// Adds the keys of a snapshot (as read from a file on startup), leaving out those that expired since it was
+    /// taken. Returns how many keys were loaded.
+    pub fn load_snapshot(&mut self, snapshot: Snapshot) -> Result<usize, String> {
+        if snapshot.databases.len() > self.databases.len() {
+            return Err(format!("The data has {} databases, but the server is configured with {}",
+                               snapshot.databases.len(), self.databases.len()));
+        }
+
+        let now = unix_time_millis();
+        let mut loaded = 0;
+        for (db, entries) in self.databases.iter_mut().zip(snapshot.databases) {
+            for Entry { key, value, expires_at } in entries {
+                if expires_at.is_some_and(|expires_at| expires_at <= now) {
+                    continue;
+                }
+                // an expiry too far in the future for the clock is as good as none
+                let expires_at = expires_at.and_then(|expires_at| instant_in_millis(Instant::now(), i64::try_from(expires_at - now).unwrap_or(i64::MAX)));
+                if expires_at.is_some() {
+                    db.expiry_index.insert(&key);
+                }
+                db.map.insert(key, Item::new(value, expires_at));
+                loaded += 1;
+            }
+        }
+        Ok(loaded)
+    }
+
+    // ===== Pub/Sub =====
+
+    pub fn broker(&self) -> &Broker {
+        &self.broker
+    }
+
+    pub fn broker_mut(&mut self) -> &mut Broker {
+        &mut self.broker
+    }
+
+    // ===== Blocking commands =====
+
+    pub fn blocked_clients(&self) -> &BlockedClients {
+        &self.blocked
+    }
+
+    pub fn blocked_clients_mut(&mut self) -> &mut BlockedClients {
+        &mut self.blocked
+    }
+
+    // ===== Transactions =====
+
+    /// Starts watching a key of the selected database, as WATCH, returning the version to later compare with.
+    pub fn watch(&mut self, key: &[u8]) -> u64 {
+        // a key that already expired goes away now, so that this does not count as a modification later on
+        self.remove_if_expired(key);
+        self.watched[self.selected_db].watch(key)
+    }
+
+    pub fn unwatch(&mut self, db: usize, key: &[u8]) {
+        self.watched[db].unwatch(key);
+    }
+
+    /// Whether a key was modified since it was watched with the given version, which includes expiring.
+    pub fn watched_key_modified(&mut self, db: usize, key: &[u8], version: u64) -> bool {
+        self.with_db(db, |engine| {
+            engine.remove_if_expired(key);
+            engine.watched[db].version(key) != Some(version)
+        })
+    }
+
+    // ===== Strings =====
+
+    /// Appends to the string under 'key', creating it when missing, and returns the new length.
+    pub fn append(&mut self, key: &[u8], value: &[u8]) -> Result<usize, String> {
+        let item = self.get_or_insert_item(key, || StringValue(Vec::new()));
+
+        let len = match &mut item.value {
+            StringValue(current) => {
+                current.extend_from_slice(value);
+                current.len()
+            }
+            _ => return Err(WRONG_TYPE_ERROR.to_string()),
+        };
+        self.key_modified(key);
+        self.notify(NotifyFlags::STRING, "append", key);
+        Ok(len)
+    }
+
+    pub fn string_len(&mut self, key: &[u8]) -> Result<usize, String> {
+        Ok(self.get(key)?.map_or(0, |value| value.len()))
+    }
+
+    /// The bytes of the string from 'start' to 'end' (both inclusive, negative offsets count from the end), as GETRANGE.
+    pub fn get_range(&mut self, key: &[u8], start: i64, end: i64) -> Result<Vec<u8>, String> {
+        let Some(value) = self.get(key)? else {
+            return Ok(Vec::new());
+        };
+
+        let len = value.len() as i64;
+        if (start < 0 && end < 0 && start > end) || len == 0 {
+            return Ok(Vec::new());
+        }
+        let start = if start < 0 { (len + start).max(0) } else { start };
+        let end = if end < 0 { (len + end).max(0) } else { end.min(len - 1) };
+
+        if start > end {
+            Ok(Vec::new())
+        } else {
+            Ok(value[start as usize..=end as usize].to_vec())
+        }
+    }
+
+    /// Overwrites the string from 'offset' on, padding it with zero bytes when it is shorter, and returns the new length.
+    /// A missing key is only created when there is something to write.
+    pub fn set_range(&mut self, key: &[u8], offset: usize, value: &[u8]) -> Result<usize, String> {
+        if value.is_empty() {
+            return self.string_len(key);
+        }
+        if offset.checked_add(value.len()).is_none_or(|end| end > MAX_STRING_LENGTH) {
+            return Err("string exceeds maximum allowed size (proto-max-bulk-len)".to_string());
+        }
+
+        let item = self.get_or_insert_item(key, || StringValue(Vec::new()));
+        let len = match &mut item.value {
+            StringValue(current) => {
+                let end = offset + value.len();
+                if current.len() < end {
+                    current.resize(end, 0);
+                }
+                current[offset..end].copy_from_slice(value);
+                current.len()
+            }
+            _ => return Err(WRONG_TYPE_ERROR.to_string()),
+        };
+        self.key_modified(key);
+        self.notify(NotifyFlags::STRING, "setrange", key);
+        Ok(len)
+    }
+
+    /// Removes the string under 'key', returning its value. Keys holding other types are left untouched.
+    pub fn get_del(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        if self.get(key)?.is_none() {
+            return Ok(None);
+        }
+
+        let removed = self.delete_item(key);
+        self.notify(NotifyFlags::GENERIC, "del", key);
+        Ok(removed.and_then(|item| match item.value {
+            StringValue(value) => Some(value),
+            _ => None,
+        }))
+    }
+
+    /// Returns the string under 'key' while changing its expiry, as GETEX.
+    pub fn get_ex(&mut self, key: &[u8], expiry: SetExpiry) -> Result<Option<Vec<u8>>, String> {
+        let Some(item) = self.get_item_mut(key) else {
+            return Ok(None);
+        };
+
+        let value = item.value.get_string()?.clone();
+        let expires_at = expiry_time(expiry, item.expires_at, "getex")?;
+        if is_expired(expires_at) {
+            self.remove(key);
+        } else {
+            let had_expiry = item.expires_at.is_some();
+            item.expires_at = expires_at;
+            if expires_at.is_some() {
+                self.db_mut().expiry_index.insert(key);
+            }
+            let event = match expiry {
+                SetExpiry::After(_) | SetExpiry::AtUnixTimeMillis(_) => Some("expire"),
+                SetExpiry::Never if had_expiry => Some("persist"),
+                _ => None,
+            };
+            if let Some(event) = event {
+                self.key_modified(key);
+                self.notify(NotifyFlags::GENERIC, event, key);
+            }
+        }
+        Ok(Some(value))
+    }
+
+    /// Sets all the given keys, but only if none of them exists yet (MSETNX). Returns whether they were set.
+    pub fn set_all_if_none_exist(&mut self, key_values: &[(Vec<u8>, Vec<u8>)]) -> bool {
+        if key_values.iter().any(|(key, _)| self.get_item(key).is_some()) {
+            return false;
+        }
+
+        for (key, value) in key_values {
+            self.insert_item(key, Item::new(StringValue(value.clone()), None));
+            self.notify(NotifyFlags::NEW, "new", key);
+            self.notify(NotifyFlags::STRING, "set", key);
+        }
+        true
+    }
+
+    // ===== Counters =====
+
+    /// Adds 'increment' to the integer held by the string under 'key' (INCR/DECR/INCRBY/DECRBY), with a missing key
+    /// counting as 0. The key keeps its expiry, if it has one.
+    pub fn increment_by(&mut self, key: &[u8], increment: i64) -> Result<i64, String> {
+        let item = self.get_or_insert_item(key, || StringValue(b"0".to_vec()));
+
+        let new_value = parse_integer(item.value.get_string()?)
+            .and_then(|current| current.checked_add(increment))
+            .ok_or_else(|| "value is not an integer or out of range".to_string())?;
+
+        item.value = StringValue(new_value.to_string().into_bytes());
+        self.key_modified(key);
+        self.notify(NotifyFlags::STRING, "incrby", key);
+        Ok(new_value)
+    }
+
+    /// Floating point counterpart of 'increment_by' (INCRBYFLOAT), returning the new value as it is stored.
+    pub fn increment_by_float(&mut self, key: &[u8], increment: f64) -> Result<Vec<u8>, String> {
+        // the new value is checked before the key is created, as a refused increment must not leave a "0" behind
+        let current = match self.get(key)? {
+            Some(value) => std::str::from_utf8(value).ok()
+                .and_then(|value| value.parse::<f64>().ok())
+                .ok_or_else(|| "value is not a valid float".to_string())?,
+            None => 0.0,
+        };
+
+        let new_value = current + increment;
+        if !new_value.is_finite() {
+            return Err("increment would produce NaN or Infinity".to_string());
+        }
+
+        let new_value = new_value.to_string().into_bytes();
+        self.get_or_insert_item(key, || StringValue(vec![])).value = StringValue(new_value.clone());
+        self.key_modified(key);
+        self.notify(NotifyFlags::STRING, "incrbyfloat", key);
+        Ok(new_value)
+    }
+
+    // ===== Lists =====
+
+    fn get_list(&mut self, key: &[u8]) -> Result<Option<&VecDeque<Vec<u8>>>, String> {
+        self.get_item(key)
+            .map(|item| item.value.get_list())
+            .transpose()
+    }
+
+    fn get_list_mut(&mut self, key: &[u8]) -> Result<Option<&mut VecDeque<Vec<u8>>>, String> {
+        self.get_item_mut(key)
+            .map(|item| item.value.get_list_mut())
+            .transpose()
+    }
+
+    /// Pushes the values, one after the other, into the given end of the list, creating the list if needed.
+    ///
+    /// Returns the length of the list after the operation.
+    pub fn push(&mut self, key: &[u8], values: &[Vec<u8>], end: ListEnd) -> Result<usize, String> {
+        let list = self.get_or_insert_item(key, || ListValue(VecDeque::new()))
+            .value
+            .get_list_mut()?;
+
+        for value in values {
+            match end {
+                ListEnd::Left => list.push_front(value.clone()),
+                ListEnd::Right => list.push_back(value.clone()),
+            }
+        }
+
+        let len = list.len();
+        self.key_modified(key);
+        self.notify(NotifyFlags::LIST, if end == ListEnd::Left { "lpush" } else { "rpush" }, key);
+        Ok(len)
+    }
+
+    /// Pops up to 'count' elements from the given end of the list, in the order they were popped.
+    ///
+    /// Returns 'None' when the key does not exist.
+    pub fn pop(&mut self, key: &[u8], end: ListEnd, count: usize) -> Result<Option<Vec<Vec<u8>>>, String> {
+        let popped: Vec<Vec<u8>> = match self.get_list_mut(key)? {
+            None => return Ok(None),
+            Some(list) => {
+                let count = count.min(list.len());
+                match end {
+                    ListEnd::Left => list.drain(..count).collect(),
+                    ListEnd::Right => list.drain(list.len() - count..).rev().collect(),
+                }
+            }
+        };
+
+        if !popped.is_empty() {
+            self.key_modified(key);
+            self.notify(NotifyFlags::LIST, if end == ListEnd::Left { "lpop" } else { "rpop" }, key);
+        }
+        self.remove_if_empty(key);
+        Ok(Some(popped))
+    }
+
+    /// Pops an element from one end of the source list and pushes it into one end of the destination list, as LMOVE,
+    /// creating the destination if needed. Returns 'None' when the source does not exist.
+    pub fn list_move(&mut self, source: &[u8], destination: &[u8], from: ListEnd, to: ListEnd) -> Result<Option<Vec<u8>>, String> {
+        // both types are checked before popping anything, so that nothing is popped that can't be pushed
+        if self.get_list(source)?.is_none() {
+            return Ok(None);
+        }
+        self.get_list(destination)?;
+
+        let Some(value) = self.pop(source, from, 1)?.and_then(|mut popped| popped.pop()) else {
+            return Ok(None);
+        };
+        self.push(destination, std::slice::from_ref(&value), to)?;
+        Ok(Some(value))
+    }
+
+    pub fn list_len(&mut self, key: &[u8]) -> Result<usize, String> {
+        Ok(self.get_list(key)?.map_or(0, |list| list.len()))
+    }
+
+    /// Returns the elements between 'start' and 'stop' (both inclusive, negative values count from the end).
+    pub fn list_range(&mut self, key: &[u8], start: i64, stop: i64) -> Result<Vec<&Vec<u8>>, String> {
+        let range = match self.get_list(key)? {
+            None => vec![],
+            Some(list) => match normalize_range(start, stop, list.len()) {
+                Some((start, stop)) => list.range(start..=stop).collect(),
+                None => vec![],
+            }
+        };
+        Ok(range)
+    }
+
+    pub fn list_index(&mut self, key: &[u8], index: i64) -> Result<Option<&Vec<u8>>, String> {
+        let element = self.get_list(key)?
+            .and_then(|list| normalize_index(index, list.len()).and_then(|index| list.get(index)));
+        Ok(element)
+    }
+
+    pub fn list_set(&mut self, key: &[u8], index: i64, value: &[u8]) -> Result<(), String> {
+        let list = self.get_list_mut(key)?
+            .ok_or_else(|| "no such key".to_string())?;
+
+        let index = normalize_index(index, list.len())
+            .ok_or_else(|| "index out of range".to_string())?;
+
+        list[index] = value.to_vec();
+        self.key_modified(key);
+        self.notify(NotifyFlags::LIST, "lset", key);
+        Ok(())
+    }
+
+    /// Removes occurrences of 'value' from the list:
+    /// - count > 0: the first 'count' occurrences, starting from the head
+    /// - count < 0: the last 'count' occurrences, starting from the tail
+    /// - count = 0: all occurrences
+    ///
+    /// Returns how many elements were removed.
+    pub fn list_remove(&mut self, key: &[u8], count: i64, value: &[u8]) -> Result<usize, String> {
+        let removed = match self.get_list_mut(key)? {
+            None => return Ok(0),
+            Some(list) => {
+                let occurrences = list.iter().filter(|element| *element == value).count();
+                let to_remove = match count {
+                    0 => occurrences,
+                    _ => occurrences.min(count.unsigned_abs() as usize),
+                };
+                // when removing from the tail, the first occurrences are the ones that are kept
+                let to_skip = if count < 0 { occurrences - to_remove } else { 0 };
+
+                let mut seen = 0;
+                list.retain(|element| {
+                    if element != value {
+                        return true;
+                    }
+                    seen += 1;
+                    seen <= to_skip || seen > to_skip + to_remove
+                });
+                to_remove
+            }
+        };
+
+        if removed > 0 {
+            self.key_modified(key);
+            self.notify(NotifyFlags::LIST, "lrem", key);
+        }
+        self.remove_if_empty(key);
+        Ok(removed)
+    }
+
+    /// Trims the list so that it only contains the elements between 'start' and 'stop' (both inclusive).
+    pub fn list_trim(&mut self, key: &[u8], start: i64, stop: i64) -> Result<(), String> {
+        if let Some(list) = self.get_list_mut(key)? {
+            match normalize_range(start, stop, list.len()) {
+                Some((start, stop)) => {
+                    list.truncate(stop + 1);
+                    list.drain(..start);
+                }
+                None => list.clear(),
+            }
+            self.key_modified(key);
+            self.notify(NotifyFlags::LIST, "ltrim", key);
+        }
+
+        self.remove_if_empty(key);
+        Ok(())
+    }
+
+    /// Inserts 'value' before or after the first occurrence of 'pivot'.
+    ///
+    /// Returns the length of the list after the operation, 0 when the key doesn't exist or -1 when the pivot isn't found.
+    pub fn list_insert(&mut self, key: &[u8], position: InsertPosition, pivot: &[u8], value: &[u8]) -> Result<i64, String> {
+        let list = match self.get_list_mut(key)? {
+            None => return Ok(0),
+            Some(list) => list,
+        };
+
+        match list.iter().position(|element| element == pivot) {
+            None => Ok(-1),
+            Some(index) => {
+                let index = match position {
+                    InsertPosition::Before => index,
+                    InsertPosition::After => index + 1,
+                };
+                list.insert(index, value.to_vec());
+                let len = list.len() as i64;
+                self.key_modified(key);
+                self.notify(NotifyFlags::LIST, "linsert", key);
+                Ok(len)
+            }
+        }
+    }
+
+    // ===== Hashes =====
+
+    fn get_hash(&mut self, key: &[u8]) -> Result<Option<&Hash>, String> {
+        self.get_item(key)
+            .map(|item| item.value.get_hash())
+            .transpose()
+    }
+
+    fn get_hash_mut(&mut self, key: &[u8]) -> Result<Option<&mut Hash>, String> {
+        self.get_item_mut(key)
+            .map(|item| item.value.get_hash_mut())
+            .transpose()
+    }
+
+    fn get_or_insert_hash(&mut self, key: &[u8]) -> Result<&mut Hash, String> {
+        self.get_or_insert_item(key, || HashValue(HashMap::new()))
+            .value
+            .get_hash_mut()
+    }
+
+    pub fn hash_get(&mut self, key: &[u8], field: &[u8]) -> Result<Option<&Vec<u8>>, String> {
+        Ok(self.get_hash(key)?.and_then(|hash| hash.get(field)))
+    }
+
+    /// Fetches multiple fields at once, in the order requested; missing fields (or key) are 'None'.
+    pub fn hash_get_multiple(&mut self, key: &[u8], fields: &[Vec<u8>]) -> Result<Vec<Option<&Vec<u8>>>, String> {
+        let values = match self.get_hash(key)? {
+            None => vec![None; fields.len()],
+            Some(hash) => fields.iter().map(|field| hash.get(field)).collect(),
+        };
+        Ok(values)
+    }
+
+    pub fn hash_get_all(&mut self, key: &[u8]) -> Result<HashEntries<'_>, String> {
+        Ok(self.get_hash(key)?.map_or_else(Vec::new, |hash| {
+            hash.iter().map(|(field, value)| (field.as_slice(), value.as_slice())).collect()
+        }))
+    }
+
+    pub fn hash_keys(&mut self, key: &[u8]) -> Result<Vec<&Vec<u8>>, String> {
+        Ok(self.get_hash(key)?.map_or_else(Vec::new, |hash| hash.keys().collect()))
+    }
+
+    pub fn hash_values(&mut self, key: &[u8]) -> Result<Vec<&Vec<u8>>, String> {
+        Ok(self.get_hash(key)?.map_or_else(Vec::new, |hash| hash.values().collect()))
+    }
+
+    pub fn hash_len(&mut self, key: &[u8]) -> Result<usize, String> {
+        Ok(self.get_hash(key)?.map_or(0, |hash| hash.len()))
+    }
+
+    pub fn hash_exists(&mut self, key: &[u8], field: &[u8]) -> Result<bool, String> {
+        Ok(self.get_hash(key)?.is_some_and(|hash| hash.contains_key(field)))
+    }
+
+    /// Sets the given fields, creating the hash if needed.
+    ///
+    /// Returns how many of the fields were newly added (as opposed to updated).
+    pub fn hash_set(&mut self, key: &[u8], field_values: &[(Vec<u8>, Vec<u8>)]) -> Result<usize, String> {
+        let hash = self.get_or_insert_hash(key)?;
+
+        let added = field_values.iter()
+            .filter(|(field, value)| hash.insert(field.clone(), value.clone()).is_none())
+            .count();
+
+        self.key_modified(key);
+        self.notify(NotifyFlags::HASH, "hset", key);
+        Ok(added)
+    }
+
+    /// Sets the field only if it does not exist yet, returning whether it was set.
+    pub fn hash_set_if_absent(&mut self, key: &[u8], field: &[u8], value: &[u8]) -> Result<bool, String> {
+        let hash = self.get_or_insert_hash(key)?;
+
+        if hash.contains_key(field) {
+            return Ok(false);
+        }
+        hash.insert(field.to_vec(), value.to_vec());
+        self.key_modified(key);
+        self.notify(NotifyFlags::HASH, "hset", key);
+        Ok(true)
+    }
+
+    /// Removes the given fields, returning how many of them existed.
+    pub fn hash_delete(&mut self, key: &[u8], fields: &[Vec<u8>]) -> Result<usize, String> {
+        let removed = match self.get_hash_mut(key)? {
+            None => return Ok(0),
+            Some(hash) => fields.iter()
+                .filter(|field| hash.remove(*field).is_some())
+                .count(),
+        };
+
+        if removed > 0 {
+            self.key_modified(key);
+            self.notify(NotifyFlags::HASH, "hdel", key);
+        }
+        self.remove_if_empty(key);
+        Ok(removed)
+    }
+
+    /// Increments the integer stored in the field (a missing field counts as 0), returning the new value.
+    pub fn hash_increment_by(&mut self, key: &[u8], field: &[u8], increment: i64) -> Result<i64, String> {
+        let hash = self.get_or_insert_hash(key)?;
+
+        let current = match hash.get(field) {
+            None => 0,
+            Some(value) => parse_integer(value)
+                .ok_or_else(|| "hash value is not an integer".to_string())?,
+        };
+
+        let new_value = current.checked_add(increment)
+            .ok_or_else(|| "increment or decrement would overflow".to_string())?;
+
+        hash.insert(field.to_vec(), new_value.to_string().into_bytes());
+        self.key_modified(key);
+        self.notify(NotifyFlags::HASH, "hincrby", key);
+        Ok(new_value)
+    }
+
+    // ===== Sets =====
+
+    fn get_set(&mut self, key: &[u8]) -> Result<Option<&HashSet<Vec<u8>>>, String> {
+        self.get_item(key)
+            .map(|item| item.value.get_set())
+            .transpose()
+    }
+
+    fn get_set_mut(&mut self, key: &[u8]) -> Result<Option<&mut HashSet<Vec<u8>>>, String> {
+        self.get_item_mut(key)
+            .map(|item| item.value.get_set_mut())
+            .transpose()
+    }
+
+    /// Fetches several sets at once (missing keys are 'None'), failing if any of the keys holds another type.
+    fn get_sets(&mut self, keys: &[Vec<u8>]) -> Result<Vec<Option<&HashSet<Vec<u8>>>>, String> {
+        // expiry requires mutable access, so that's handled for all keys before borrowing any of the sets
+        for key in keys {
+            self.get_set(key)?;
+        }
+
+        keys.iter()
+            .map(|key| self.db().map.get(key).map(|item| item.value.get_set()).transpose())
+            .collect()
+    }
+
+    /// Adds the members to the set, creating it if needed. Returns how many members were not yet in the set.
+    pub fn set_add(&mut self, key: &[u8], members: &[Vec<u8>]) -> Result<usize, String> {
+        let set = self.get_or_insert_item(key, || SetValue(HashSet::new()))
+            .value
+            .get_set_mut()?;
+
+        let added = members.iter()
+            .filter(|member| set.insert(member.to_vec()))
+            .count();
+
+        if added > 0 {
+            self.key_modified(key);
+            self.notify(NotifyFlags::SET, "sadd", key);
+        }
+        Ok(added)
+    }
+
+    /// Removes the members from the set, returning how many of them were in the set.
+    pub fn set_remove(&mut self, key: &[u8], members: &[Vec<u8>]) -> Result<usize, String> {
+        let removed = match self.get_set_mut(key)? {
+            None => return Ok(0),
+            Some(set) => members.iter()
+                .filter(|member| set.remove(*member))
+                .count(),
+        };
+
+        if removed > 0 {
+            self.key_modified(key);
+            self.notify(NotifyFlags::SET, "srem", key);
+        }
+        self.remove_if_empty(key);
+        Ok(removed)
+    }
+
+    pub fn set_members(&mut self, key: &[u8]) -> Result<Vec<&Vec<u8>>, String> {
+        Ok(self.get_set(key)?.map_or_else(Vec::new, |set| set.iter().collect()))
+    }
+
+    pub fn set_len(&mut self, key: &[u8]) -> Result<usize, String> {
+        Ok(self.get_set(key)?.map_or(0, |set| set.len()))
+    }
+
+    /// Tells, for each of the given members, whether it belongs to the set.
+    pub fn set_contains(&mut self, key: &[u8], members: &[Vec<u8>]) -> Result<Vec<bool>, String> {
+        let set = self.get_set(key)?;
+        Ok(members.iter()
+            .map(|member| set.is_some_and(|set| set.contains(member)))
+            .collect())
+    }
+
+    /// Removes and returns up to 'count' random members of the set, or 'None' if the key does not exist.
+    pub fn set_pop(&mut self, key: &[u8], count: usize) -> Result<Option<Vec<Vec<u8>>>, String> {
+        if self.get_set(key)?.is_none() {
+            return Ok(None);
+        }
+        // borrowing from the map directly (instead of through 'get_set_mut') keeps 'random' available
+        let db = self.db_mut();
+        let Some(Item { value: SetValue(set), .. }) = db.map.get_mut(key) else {
+            return Ok(None);
+        };
+
+        let popped: Vec<Vec<u8>> = if count >= set.len() {
+            set.drain().collect()
+        } else {
+            let mut members: Vec<&Vec<u8>> = set.iter().collect();
+            let mut popped = Vec::with_capacity(count);
+            for _ in 0..count {
+                let index = db.random.next_below(members.len());
+                popped.push(members.swap_remove(index).clone());
+            }
+            for member in &popped {
+                set.remove(member);
+            }
+            popped
+        };
+
+        if !popped.is_empty() {
+            self.key_modified(key);
+            self.notify(NotifyFlags::SET, "spop", key);
+        }
+        self.remove_if_empty(key);
+        Ok(Some(popped))
+    }
+
+    /// Returns random members of the set, following SRANDMEMBER semantics:
+    /// - count >= 0: up to 'count' distinct members
+    /// - count < 0: exactly '-count' members, which may repeat
+    pub fn set_random_members(&mut self, key: &[u8], count: i64) -> Result<Vec<&Vec<u8>>, String> {
+        // a negative count repeats members, so only the longest reply the protocol allows bounds the work
+        if count.unsigned_abs() > MAX_ARRAY_LENGTH as u64 {
+            return Err("value is out of range".to_string());
+        }
+        if self.get_set(key)?.is_none() {
+            return Ok(vec![]);
+        }
+        let db = self.db_mut();
+        let Some(Item { value: SetValue(set), .. }) = db.map.get(key) else {
+            return Ok(vec![]);
+        };
+        let random = &mut db.random;
+        let mut members: Vec<&Vec<u8>> = set.iter().collect();
+
+        if count < 0 {
+            // grown as it is filled, rather than allocated up front for whatever count the client asked for
+            let mut picked = vec![];
+            for _ in 0..count.unsigned_abs() {
+                picked.push(members[random.next_below(members.len())]);
+            }
+            return Ok(picked);
+        }
+
+        let count = (count as usize).min(members.len());
+        // partial Fisher-Yates shuffle: only the first 'count' positions need to be shuffled
+        for position in 0..count {
+            let index = position + random.next_below(members.len() - position);
+            members.swap(position, index);
+        }
+        members.truncate(count);
+        Ok(members)
+    }
+
+    /// Combines the sets stored in the given keys (missing keys count as empty sets).
+    pub fn set_combine(&mut self, operation: SetOperation, keys: &[Vec<u8>]) -> Result<Vec<&Vec<u8>>, String> {
+        let sets = self.get_sets(keys)?;
+
+        let result = match operation {
+            SetOperation::Intersection => {
+                if sets.iter().any(Option::is_none) {
+                    return Ok(vec![]);
+                }
+                let sets: Vec<&HashSet<Vec<u8>>> = sets.into_iter().flatten().collect();
+                // iterating over the smallest set keeps the number of lookups to a minimum
+                match sets.iter().min_by_key(|set| set.len()) {
+                    None => vec![],
+                    Some(smallest) => smallest.iter()
+                        .filter(|member| sets.iter().all(|set| set.contains(*member)))
+                        .collect(),
+                }
+            }
+            SetOperation::Union => {
+                let mut union: HashSet<&Vec<u8>> = HashSet::new();
+                sets.into_iter().flatten().for_each(|set| union.extend(set.iter()));
+                union.into_iter().collect()
+            }
+            SetOperation::Difference => {
+                let mut sets = sets.into_iter();
+                match sets.next().flatten() {
+                    None => vec![],
+                    Some(first) => {
+                        let others: Vec<&HashSet<Vec<u8>>> = sets.flatten().collect();
+                        first.iter()
+                            .filter(|member| !others.iter().any(|set| set.contains(*member)))
+                            .collect()
+                    }
+                }
+            }
+        };
+        Ok(result)
+    }
+
+    /// Combines the sets stored in the given keys and stores the result in 'destination', overwriting it.
+    ///
+    /// Returns the size of the resulting set.
+    pub fn set_combine_and_store(&mut self, operation: SetOperation, destination: &[u8], keys: &[Vec<u8>]) -> Result<usize, String> {
+        let result: HashSet<Vec<u8>> = self.set_combine(operation, keys)?
+            .into_iter()
+            .cloned()
+            .collect();
+        let size = result.len();
+
+        let existed = self.delete_item(destination).is_some();
+        if !result.is_empty() {
+            self.insert_item(destination, Item::new(SetValue(result), None));
+            let event = match operation {
+                SetOperation::Intersection => "sinterstore",
+                SetOperation::Union => "sunionstore",
+                SetOperation::Difference => "sdiffstore",
+            };
+            self.notify(NotifyFlags::SET, event, destination);
+        } else if existed {
+            self.notify(NotifyFlags::GENERIC, "del", destination);
+        }
+        Ok(size)
+    }
+
+    // ===== Sorted sets =====
+
+    fn get_sorted_set(&mut self, key: &[u8]) -> Result<Option<&SortedSet>, String> {
+        self.get_item(key)
+            .map(|item| item.value.get_sorted_set())
+            .transpose()
+    }
+
+    fn get_sorted_set_mut(&mut self, key: &[u8]) -> Result<Option<&mut SortedSet>, String> {
+        self.get_item_mut(key)
+            .map(|item| item.value.get_sorted_set_mut())
+            .transpose()
+    }
+
+    fn get_or_insert_sorted_set(&mut self, key: &[u8]) -> Result<&mut SortedSet, String> {
+        self.get_or_insert_item(key, || SortedSetValue(SortedSet::new()))
+            .value
+            .get_sorted_set_mut()
+    }
+
+    /// Adds or updates the (score, member) pairs that satisfy the conditions, creating the sorted set if needed.
+    ///
+    /// Returns how many members were added, and how many had their score changed.
+    pub fn sorted_set_add(&mut self, key: &[u8], entries: &[(f64, Vec<u8>)], conditions: SortedSetUpdateConditions) -> Result<(usize, usize), String> {
+        // with XX there is nothing to do on a missing key, which is not even created
+        if conditions.only_existing && self.get_sorted_set(key)?.is_none() {
+            return Ok((0, 0));
+        }
+        let sorted_set = self.get_or_insert_sorted_set(key)?;
+
+        let mut added = 0;
+        let mut updated = 0;
+        for (score, member) in entries {
+            let current = sorted_set.score(member);
+            if !conditions.allow(current, *score) {
+                continue;
+            }
+
+            match current {
+                None => added += 1,
+                Some(current) if current != *score => updated += 1,
+                Some(_) => {}
+            }
+            sorted_set.insert(member, *score);
+        }
+
+        if added > 0 || updated > 0 {
+            self.key_modified(key);
+            self.notify(NotifyFlags::ZSET, "zadd", key);
+        }
+        // with XX nothing may have been added, and empty sorted sets are not kept
+        self.remove_if_empty(key);
+        Ok((added, updated))
+    }
+
+    /// Increments the score of the member (a missing member starts at 0) if the conditions allow it.
+    ///
+    /// Returns the new score, or 'None' if the conditions prevented the update.
+    pub fn sorted_set_increment(&mut self, key: &[u8], member: &[u8], increment: f64, conditions: SortedSetUpdateConditions) -> Result<Option<f64>, String> {
+        // the new score is checked before the key is created, as a refused increment must not leave a set behind
+        let current = match self.get_sorted_set(key)? {
+            Some(sorted_set) => sorted_set.score(member),
+            None if conditions.only_existing => return Ok(None),
+            None => None,
+        };
+        let new_score = current.unwrap_or(0.0) + increment;
+        if new_score.is_nan() {
+            return Err("resulting score is not a number (NaN)".to_string());
+        }
+        if !conditions.allow(current, new_score) {
+            return Ok(None);
+        }
+
+        self.get_or_insert_sorted_set(key)?.insert(member, new_score);
+        self.key_modified(key);
+        self.notify(NotifyFlags::ZSET, "zincr", key);
+        Ok(Some(new_score))
+    }
+
+    /// Removes the members, returning how many of them were in the sorted set.
+    pub fn sorted_set_remove(&mut self, key: &[u8], members: &[Vec<u8>]) -> Result<usize, String> {
+        let removed = match self.get_sorted_set_mut(key)? {
+            None => return Ok(0),
+            Some(sorted_set) => members.iter()
+                .filter(|member| sorted_set.remove(member))
+                .count(),
+        };
+
+        if removed > 0 {
+            self.key_modified(key);
+            self.notify(NotifyFlags::ZSET, "zrem", key);
+        }
+        self.remove_if_empty(key);
+        Ok(removed)
+    }
+
+    /// Pops up to 'count' members with the lowest scores (the highest, with 'max'), in the order they were popped.
+    pub fn sorted_set_pop(&mut self, key: &[u8], max: bool, count: usize) -> Result<Vec<(Vec<u8>, f64)>, String> {
+        let Some(stop) = count.checked_sub(1) else {
+            return Ok(vec![]);
+        };
+        let popped: Vec<(Vec<u8>, f64)> = match self.get_sorted_set_mut(key)? {
+            None => return Ok(vec![]),
+            Some(sorted_set) => {
+                let popped: Vec<(Vec<u8>, f64)> = sorted_set.range_by_rank(0, stop as i64, max)
+                    .into_iter()
+                    .map(|(member, score)| (member.clone(), score))
+                    .collect();
+                for (member, _) in &popped {
+                    sorted_set.remove(member);
+                }
+                popped
+            }
+        };
+
+        if !popped.is_empty() {
+            self.key_modified(key);
+            self.notify(NotifyFlags::ZSET, if max { "zpopmax" } else { "zpopmin" }, key);
+        }
+        self.remove_if_empty(key);
+        Ok(popped)
+    }
+
+    pub fn sorted_set_len(&mut self, key: &[u8]) -> Result<usize, String> {
+        Ok(self.get_sorted_set(key)?.map_or(0, |sorted_set| sorted_set.len()))
+    }
+
+    pub fn sorted_set_score(&mut self, key: &[u8], member: &[u8]) -> Result<Option<f64>, String> {
+        Ok(self.get_sorted_set(key)?.and_then(|sorted_set| sorted_set.score(member)))
+    }
+
+    /// 0-based rank of the member (from the highest score when 'reverse') together with its score.
+    pub fn sorted_set_rank(&mut self, key: &[u8], member: &[u8], reverse: bool) -> Result<Option<(usize, f64)>, String> {
+        Ok(self.get_sorted_set(key)?.and_then(|sorted_set| {
+            sorted_set.rank(member, reverse)
+                .zip(sorted_set.score(member))
+        }))
+    }
+
+    pub fn sorted_set_count(&mut self, key: &[u8], range: &ScoreRange) -> Result<usize, String> {
+        Ok(self.get_sorted_set(key)?.map_or(0, |sorted_set| sorted_set.count_by_score(range)))
+    }
+
+    /// Members (with their scores) selected by the range, in ascending order or descending when 'reverse'.
+    ///
+    /// For score and lex ranges, 'limit' is the (offset, count) pair of the LIMIT option.
+    pub fn sorted_set_range(&mut self, key: &[u8], range: &SortedSetRange, reverse: bool, limit: Option<(usize, Option<usize>)>) -> Result<Vec<(&Vec<u8>, f64)>, String> {
+        let sorted_set = match self.get_sorted_set(key)? {
+            None => return Ok(vec![]),
+            Some(sorted_set) => sorted_set,
+        };
+
+        let (offset, count) = limit.unwrap_or((0, None));
+        let result = match range {
+            SortedSetRange::ByRank { start, stop } => sorted_set.range_by_rank(*start, *stop, reverse),
+            SortedSetRange::ByScore(range) => sorted_set.range_by_score(range, reverse, offset, count),
+            SortedSetRange::ByLex(range) => sorted_set.range_by_lex(range, reverse, offset, count),
+        };
+        Ok(result)
+    }
+
+    // ===== Streams =====
+
+    fn get_stream(&mut self, key: &[u8]) -> Result<Option<&Stream>, String> {
+        self.get_item(key)
+            .map(|item| item.value.get_stream())
+            .transpose()
+    }
+
+    fn get_stream_mut(&mut self, key: &[u8]) -> Result<Option<&mut Stream>, String> {
+        self.get_item_mut(key)
+            .map(|item| item.value.get_stream_mut())
+            .transpose()
+    }
+
+    /// Fetches a stream that must exist and hold the given consumer group, as required by most consumer group commands.
+    fn get_stream_with_group(&mut self, key: &[u8], group: &[u8]) -> Result<&mut Stream, String> {
+        match self.get_stream_mut(key)? {
+            Some(stream) if stream.has_group(group) => Ok(stream),
+            _ => Err(format!("NOGROUP No such key '{}' or consumer group '{}'", String::from_utf8_lossy(key), String::from_utf8_lossy(group))),
+        }
+    }
+
+    /// Appends an entry to the stream, creating the stream unless 'no_create' (NOMKSTREAM), then trims it if requested.
+    ///
+    /// Returns the ID of the new entry, or 'None' if the stream did not exist and could not be created.
+    pub fn stream_add(&mut self, key: &[u8], id: NewStreamId, fields: StreamFields, trim: Option<StreamTrim>, no_create: bool) -> Result<Option<StreamId>, String> {
+        let exists = self.get_stream(key)?.is_some();
+        if !exists && no_create {
+            return Ok(None);
+        }
+
+        let now = unix_time_millis();
+        let add = |stream: &mut Stream| -> Result<(StreamId, bool), String> {
+            let id = stream.add(id, fields, now)?;
+            Ok((id, trim.is_some_and(|trim| stream.trim(&trim) > 0)))
+        };
+        let (id, trimmed) = if let Some(stream) = self.get_stream_mut(key)? {
+            add(stream)?
+        } else {
+            // a new stream is only created once the entry made it in, as an invalid ID must not leave one behind
+            let mut stream = Stream::new();
+            let added = add(&mut stream)?;
+            self.get_or_insert_item(key, || StreamValue(stream));
+            added
+        };
+
+        self.key_modified(key);
+        self.notify(NotifyFlags::STREAM, "xadd", key);
+        if trimmed {
+            self.notify(NotifyFlags::STREAM, "xtrim", key);
+        }
+        Ok(Some(id))
+    }
+
+    /// Entries with IDs between 'start' and 'end' (both inclusive), newest first when 'reverse'.
+    pub fn stream_range(&mut self, key: &[u8], start: StreamId, end: StreamId, reverse: bool, count: Option<usize>) -> Result<Vec<(StreamId, &StreamFields)>, String> {
+        Ok(self.get_stream(key)?.map_or(vec![], |stream| stream.range(start, end, reverse, count)))
+    }
+
+    pub fn stream_len(&mut self, key: &[u8]) -> Result<usize, String> {
+        Ok(self.get_stream(key)?.map_or(0, |stream| stream.len()))
+    }
+
+    /// Trims the stream, returning how many entries were evicted.
+    pub fn stream_trim(&mut self, key: &[u8], trim: &StreamTrim) -> Result<usize, String> {
+        let trimmed = self.get_stream_mut(key)?.map_or(0, |stream| stream.trim(trim));
+        if trimmed > 0 {
+            self.key_modified(key);
+            self.notify(NotifyFlags::STREAM, "xtrim", key);
+        }
+        Ok(trimmed)
+    }
+
+    /// Deletes the entries, returning how many of them existed.
+    pub fn stream_delete(&mut self, key: &[u8], ids: &[StreamId]) -> Result<usize, String> {
+        let deleted = self.get_stream_mut(key)?.map_or(0, |stream| stream.delete(ids));
+        if deleted > 0 {
+            self.key_modified(key);
+            self.notify(NotifyFlags::STREAM, "xdel", key);
+        }
+        Ok(deleted)
+    }
+
+    /// Creates a consumer group on the stream, creating an empty stream first if 'make_stream' (MKSTREAM).
+    pub fn stream_group_create(&mut self, key: &[u8], group: &[u8], start: GroupStartId, make_stream: bool) -> Result<(), String> {
+        if self.get_stream(key)?.is_none() && !make_stream {
+            return Err("The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.".to_string());
+        }
+
+        self.get_or_insert_item(key, || StreamValue(Stream::new()))
+            .value
+            .get_stream_mut()?
+            .create_group(group, start)?;
+        self.key_modified(key);
+        self.notify(NotifyFlags::STREAM, "xgroup-create", key);
+        Ok(())
+    }
+
+    /// Destroys the consumer group, returning whether it existed.
+    pub fn stream_group_destroy(&mut self, key: &[u8], group: &[u8]) -> Result<bool, String> {
+        let destroyed = match self.get_stream_mut(key)? {
+            None => return Err("The XGROUP subcommand requires the key to exist".to_string()),
+            Some(stream) => stream.destroy_group(group),
+        };
+        if destroyed {
+            self.key_modified(key);
+            self.notify(NotifyFlags::STREAM, "xgroup-destroy", key);
+        }
+        Ok(destroyed)
+    }
+
+    pub fn stream_group_set_id(&mut self, key: &[u8], group: &[u8], start: GroupStartId) -> Result<(), String> {
+        self.get_stream_with_group(key, group)?.set_group_id(group, start);
+        self.key_modified(key);
+        self.notify(NotifyFlags::STREAM, "xgroup-setid", key);
+        Ok(())
+    }
+
+    /// Creates a consumer in the group, returning whether it didn't exist yet. With 'seen_time' (a unix time in
+    /// milliseconds), the consumer is taken as last seen then, whether it was just created or not.
+    pub fn stream_group_create_consumer(&mut self, key: &[u8], group: &[u8], consumer: &[u8], seen_time: Option<u64>) -> Result<bool, String> {
+        let now = unix_time_millis();
+        let stream = self.get_stream_with_group(key, group)?;
+        let created = stream.create_consumer(group, consumer, now);
+        if let Some(seen_time) = seen_time {
+            stream.set_consumer_seen_time(group, consumer, seen_time);
+        }
+
+        if created || seen_time.is_some() {
+            self.key_modified(key);
+        }
+        if created {
+            self.notify(NotifyFlags::STREAM, "xgroup-createconsumer", key);
+        }
+        Ok(created)
+    }
+
+    /// Deletes a consumer from the group, returning how many entries it still had pending.
+    pub fn stream_group_delete_consumer(&mut self, key: &[u8], group: &[u8], consumer: &[u8]) -> Result<usize, String> {
+        let pending = self.get_stream_with_group(key, group)?.delete_consumer(group, consumer);
+        self.key_modified(key);
+        self.notify(NotifyFlags::STREAM, "xgroup-delconsumer", key);
+        Ok(pending)
+    }
+
+    /// Reads from each (key, ID) pair on behalf of a consumer of the group, as XREADGROUP does.
+    ///
+    /// An ID of 'None' ('>') reads entries never delivered to the group, and only streams with new entries are part of
+    /// the result; any other ID reads the consumer's pending entries, and the stream is always part of the result.
+    pub fn stream_read_group(&mut self, group: &[u8], consumer: &[u8], streams: &[(Vec<u8>, Option<StreamId>)], count: Option<usize>, no_ack: bool) -> Result<Vec<(Vec<u8>, GroupReadEntries)>, String> {
+        // all the streams are validated before any of them is read
+        for (key, _) in streams {
+            self.get_stream_with_group(key, group)?;
+        }
+
+        let now = unix_time_millis();
+        let mut result = vec![];
+        for (key, id) in streams {
+            let entries = self.get_stream_with_group(key, group)?
+                .read_group(group, consumer, *id, count, no_ack, now);
+            // the consumer is seen (and maybe created) either way
+            self.key_modified(key);
+            if id.is_some() || !entries.is_empty() {
+                result.push((key.clone(), entries));
+            }
+        }
+        Ok(result)
+    }
+
+    /// Acknowledges the entries for the group, returning how many of them were pending.
+    pub fn stream_ack(&mut self, key: &[u8], group: &[u8], ids: &[StreamId]) -> Result<usize, String> {
+        let acknowledged = self.get_stream_mut(key)?.map_or(0, |stream| stream.ack(group, ids));
+        if acknowledged > 0 {
+            self.key_modified(key);
+        }
+        Ok(acknowledged)
+    }
+
+    pub fn stream_pending_summary(&mut self, key: &[u8], group: &[u8]) -> Result<PendingSummary, String> {
+        Ok(self.get_stream_with_group(key, group)?.pending_summary(group))
+    }
+
+    pub fn stream_pending(&mut self, key: &[u8], group: &[u8], filter: &PendingFilter) -> Result<Vec<PendingEntryInfo>, String> {
+        let now = unix_time_millis();
+        Ok(self.get_stream_with_group(key, group)?.pending(group, filter, now))
+    }
+
+    /// Claims the pending entries idle for at least 'min_idle' milliseconds for 'consumer', returning the claimed entries.
+    pub fn stream_claim(&mut self, key: &[u8], group: &[u8], consumer: &[u8], min_idle: u64, ids: &[StreamId], options: &ClaimOptions) -> Result<Vec<(StreamId, &StreamFields)>, String> {
+        let now = unix_time_millis();
+        // the consumer is seen (and maybe created) whether anything is claimed or not
+        self.get_stream_with_group(key, group)?;
+        self.key_modified(key);
+        Ok(self.get_stream_with_group(key, group)?.claim(group, consumer, min_idle, ids, options, now))
+    }
+
+    /// Scans the pending entries from 'start', claiming the ones idle for at least 'min_idle' milliseconds for 'consumer'.
+    pub fn stream_auto_claim(&mut self, key: &[u8], group: &[u8], consumer: &[u8], min_idle: u64, start: StreamId, options: &AutoClaimOptions) -> Result<AutoClaimResult<'_>, String> {
+        let now = unix_time_millis();
+        self.get_stream_with_group(key, group)?;
+        self.key_modified(key);
+        Ok(self.get_stream_with_group(key, group)?.auto_claim(group, consumer, min_idle, start, options, now))
+    }
+}
+
+/// When a key given 'expiry' expires, where 'current' is when it would have expired otherwise.
+fn expiry_time(expiry: SetExpiry, current: Option<Instant>, cmd_name: &str) -> Result<Option<Instant>, String> {
+    let expires_at = match expiry {
+        SetExpiry::Never => return Ok(None),
+        SetExpiry::Keep => return Ok(current),
+        SetExpiry::After(duration) => Instant::now().checked_add(duration),
+        SetExpiry::AtUnixTimeMillis(millis) => {
+            let millis = i64::try_from(millis).unwrap_or(i64::MAX);
+            instant_in_millis(Instant::now(), millis_until_unix_time(millis))
+        }
+    };
+
+    expires_at
+        .map(Some)
+        .ok_or_else(|| format!("invalid expire time in '{cmd_name}' command"))
+}
+
+/// Whether an expiry is already due, in which case the key should not be kept at all.
+fn is_expired(expires_at: Option<Instant>) -> bool {
+    expires_at.is_some_and(|expires_at| expires_at <= Instant::now())
+}
+
+/// The moment 'millis' milliseconds after 'now', or 'now' itself for a moment in the past (it is due either way).
+/// 'None' when the moment is too far in the future for the clock.
+fn instant_in_millis(now: Instant, millis: i64) -> Option<Instant> {
+    if millis <= 0 {
+        Some(now)
+    } else {
+        now.checked_add(Duration::from_millis(millis as u64))
+    }
+}
+
+/// How many milliseconds from now the wall clock reaches the given unix time, negative when it is in the past.
+fn millis_until_unix_time(millis: i64) -> i64 {
+    millis.saturating_sub(unix_time_millis() as i64)
+}
+
+// drops what was flushed, possibly on another thread, as freeing millions of keys takes a while
+fn free<T: Send + 'static>(flushed: T, asynchronously: bool) {
+    if asynchronously {
+        thread::spawn(move || drop(flushed));
+    } else {
+        drop(flushed);
+    }
+}
+
+// whether a collection is small enough for Redis to keep it as a listpack
+fn fits_listpack<'a>(len: usize, mut elements: impl Iterator<Item = &'a Vec<u8>>) -> bool {
+    len <= LISTPACK_MAX_ENTRIES && elements.all(|element| element.len() <= LISTPACK_MAX_VALUE_LEN)
+}
+
+/// Parses a value stored as bytes into an integer, if it is the textual representation of one.
+fn parse_integer(value: &[u8]) -> Option<i64> {
+    std::str::from_utf8(value).ok()?.parse::<i64>().ok()
+}
+
+/// Current unix time in milliseconds, as used for stream IDs and the idle time of pending entries.
+fn unix_time_millis() -> u64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis() as u64)
+}
+
+/// The unix time in milliseconds of an expiry, which is kept on the monotonic clock: it is relative to the current
+/// unix time, and a moment already past is now.
+fn unix_time_of(expires_at: Instant) -> u64 {
+    let remaining = expires_at.saturating_duration_since(Instant::now());
+    unix_time_millis() + remaining.as_millis() as u64
+}
+
+// a failure to log a write does not undo it, so it is only reported
+fn append_to_aof(aof: &mut Option<AppendOnlyFile>, db: usize, arguments: &[Vec<u8>]) {
+    if let Some(aof) = aof {
+        if let Err(e) = aof.log(db, arguments) {
+            eprintln!("Error writing to the append only file: {e}");
+        }
+    }
+}
+
+/// Converts a Redis index (negative values count from the end) into a position in a collection of size 'len'.
+fn normalize_index(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 { len as i64 + index } else { index };
+
+    if index < 0 || index >= len as i64 {
+        None
+    } else {
+        Some(index as usize)
+    }
+}
+
+/// Converts a Redis range (inclusive on both ends, negative values count from the end) into a range of
+/// valid positions in a collection of size 'len', or 'None' if the range is empty.
+fn normalize_range(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 { (len + start).max(0) } else { start };
+    let stop = if stop < 0 { len + stop } else { stop.min(len - 1) };
+
+    if start > stop || start >= len {
+        None
+    } else {
+        Some((start as usize, stop as usize))
+    }
+}
+
+#[cfg(test)]
+mod engine_tests {
+    use super::*;
+    use mock_instant::thread_local::MockClock;
+
+    #[test]
+    fn get_should_return_nil_when_unset() {
+        let mut engine = StorageEngine::new();
+
+        let result = engine.get(b"foo").unwrap();
+        assert_eq!(result, None)
+    }
+
+    #[test]
+    fn get_should_return_the_previously_set_value_when_no_ttl_is_defined() {
+        let mut engine = StorageEngine::new();
+
+        engine.set(b"foo".to_vec(), b"bar".to_vec(), None).unwrap();
+
+        let result = engine.get(b"foo").unwrap();
+        assert_eq!(result, Some(&b"bar".to_vec()));
+    }
+
+    #[test]
+    fn get_should_return_the_previously_set_value_or_not_based_on_whether_ttl_is_expired() {
+        let mut engine = StorageEngine::new();
+
+        // set the value with ttl=10s
+        engine.set(b"foo".to_vec(), b"bar".to_vec(), Some(10)).unwrap();
+
+        // fetch the value after 1s
+        MockClock::advance(Duration::from_secs(1));
+        let result = engine.get(b"foo").unwrap();
+        assert_eq!(result, Some(&b"bar".to_vec()));
+
+        // fetch the value after 7s more (8s total)
+        MockClock::advance(Duration::from_secs(7));
+        let result = engine.get(b"foo").unwrap();
+        assert_eq!(result, Some(&b"bar".to_vec()));
+
+        // fetch the value after another 3s more (11s total) -> TTL expired
+        MockClock::advance(Duration::from_secs(7));
+        let result = engine.get(b"foo").unwrap();
+        assert_eq!(result, None);
+    }
+
+    #[test]
+    fn time_to_live_counts_down_in_milliseconds() {
+        let mut engine = StorageEngine::new();
+        engine.set(b"foo".to_vec(), b"bar".to_vec(), Some(10)).unwrap();
+        engine.set(b"persistent".to_vec(), b"bar".to_vec(), None).unwrap();
+
+        assert!(matches!(engine.time_to_live(b"foo"), TimeToLive::ExpiresInMillis(10_000)));
+        MockClock::advance(Duration::from_millis(1_234));
+        assert!(matches!(engine.time_to_live(b"foo"), TimeToLive::ExpiresInMillis(8_766)));
+        assert!(matches!(engine.time_to_live(b"persistent"), TimeToLive::DoesNotExpire));
+        assert!(matches!(engine.time_to_live(b"missing"), TimeToLive::KeyDoesNotExist));
+
+        MockClock::advance(Duration::from_millis(8_767));
+        assert!(matches!(engine.time_to_live(b"foo"), TimeToLive::KeyDoesNotExist));
+    }
+
+    #[test]
+    fn exists_should_ignore_expired_keys() {
+        let mut engine = StorageEngine::new();
+        engine.set(b"foo".to_vec(), b"bar".to_vec(), Some(10)).unwrap();
+
+        assert!(engine.exists(b"foo"));
+        MockClock::advance(Duration::from_secs(11));
+        assert!(!engine.exists(b"foo"));
+        assert_eq!(engine.expire_stats().expired_keys, 1);
+    }
+
+    #[test]
+    fn active_expire_cycle_reclaims_keys_that_are_never_accessed() {
+        let mut engine = StorageEngine::new();
+        for i in 0..1_000 {
+            engine.set(format!("session:{i}").into_bytes(), b"token".to_vec(), Some(1)).unwrap();
+        }
+        for i in 0..10 {
+            engine.set(format!("long:{i}").into_bytes(), b"token".to_vec(), Some(60)).unwrap();
+            engine.set(format!("persistent:{i}").into_bytes(), b"value".to_vec(), None).unwrap();
+        }
+
+        assert_eq!(engine.active_expire_cycle(), 0);
+        MockClock::advance(Duration::from_secs(2));
+
+        // with nearly every sample expired, a single cycle keeps going until expired keys become rare
+        let mut expired = engine.active_expire_cycle();
+        assert!(expired >= 900, "only {expired} keys expired in the first cycle");
+        for _ in 0..1_000 {
+            expired += engine.active_expire_cycle();
+        }
+        assert_eq!(expired, 1_000);
+        assert_eq!(engine.db().map.len(), 20);
+        assert_eq!(engine.db().expiry_index.len(), 10);
+        assert_eq!(engine.expire_stats().expired_keys, 1_000);
+        assert!(engine.expire_stats().expired_stale_perc > 0.0);
+    }
+
+    #[test]
+    fn active_expire_cycle_drops_keys_that_lost_their_expiry_from_the_index() {
+        let mut engine = StorageEngine::new();
+        engine.set(b"foo".to_vec(), b"bar".to_vec(), Some(10)).unwrap();
+        engine.set(b"foo".to_vec(), b"baz".to_vec(), None).unwrap();
+        assert_eq!(engine.db().expiry_index.len(), 1);
+
+        assert_eq!(engine.active_expire_cycle(), 0);
+        assert!(engine.db().expiry_index.is_empty());
+        assert_eq!(engine.get(b"foo").unwrap(), Some(&b"baz".to_vec()));
+    }
+
+    #[test]
+    fn set_with_options_only_writes_when_condition_holds() {
+        let mut engine = StorageEngine::new();
+        let only_new = SetOptions { condition: SetCondition::IfNotExists, get: true, ..SetOptions::default() };
+
+        assert_eq!(engine.set_with_options(b"foo".to_vec(), b"a".to_vec(), only_new), Ok((true, None)));
+        assert_eq!(engine.set_with_options(b"foo".to_vec(), b"b".to_vec(), only_new), Ok((false, Some(b"a".to_vec()))));
+        assert_eq!(engine.get(b"foo").unwrap(), Some(&b"a".to_vec()));
+    }
+
+    #[test]
+    fn set_with_options_keeps_expiry_of_existing_key_only() {
+        let mut engine = StorageEngine::new();
+        let keep_ttl = SetOptions { expiry: SetExpiry::Keep, ..SetOptions::default() };
+
+        engine.set(b"foo".to_vec(), b"a".to_vec(), Some(10)).unwrap();
+        engine.set_with_options(b"foo".to_vec(), b"b".to_vec(), keep_ttl).unwrap();
+        engine.set_with_options(b"bar".to_vec(), b"c".to_vec(), keep_ttl).unwrap();
+
+        MockClock::advance(Duration::from_secs(11));
+        assert_eq!(engine.get(b"foo").unwrap(), None);
+        assert_eq!(engine.get(b"bar").unwrap(), Some(&b"c".to_vec()));
+    }
+
+    #[test]
+    fn expire_applies_conditions_against_the_current_expiry() {
+        let mut engine = StorageEngine::new();
+        MockClock::set_system_time(Duration::from_secs(1_000));
+        engine.set(b"foo".to_vec(), b"bar".to_vec(), None).unwrap();
+        let only_greater = ExpireConditions { only_greater: true, ..ExpireConditions::default() };
+        let only_lower = ExpireConditions { only_lower: true, ..ExpireConditions::default() };
+        let only_without_expiry = ExpireConditions { only_without_expiry: true, ..ExpireConditions::default() };
+
+        // a key without expiry has an infinite TTL, so GT never applies and LT always does
+        assert_eq!(engine.expire(b"foo", ExpireAt::AfterMillis(10_000), only_greater), Ok(false));
+        assert_eq!(engine.expire(b"foo", ExpireAt::AfterMillis(10_000), only_lower), Ok(true));
+        assert_eq!(engine.expire_time(b"foo"), ExpireTime::AtUnixTimeMillis(1_010_000));
+
+        assert_eq!(engine.expire(b"foo", ExpireAt::AfterMillis(20_000), only_without_expiry), Ok(false));
+        assert_eq!(engine.expire(b"foo", ExpireAt::AfterMillis(5_000), only_greater), Ok(false));
+        assert_eq!(engine.expire(b"foo", ExpireAt::UnixTimeMillis(1_020_000), only_greater), Ok(true));
+        assert_eq!(engine.expire_time(b"foo"), ExpireTime::AtUnixTimeMillis(1_020_000));
+
+        assert_eq!(engine.expire(b"missing", ExpireAt::AfterMillis(10_000), ExpireConditions::default()), Ok(false));
+        assert_eq!(engine.expire_time(b"missing"), ExpireTime::KeyDoesNotExist);
+    }
+
+    #[test]
+    fn expire_in_the_past_deletes_the_key() {
+        let mut engine = StorageEngine::new();
+        MockClock::set_system_time(Duration::from_secs(1_000));
+        engine.set(b"foo".to_vec(), b"bar".to_vec(), None).unwrap();
+        engine.set(b"baz".to_vec(), b"qux".to_vec(), None).unwrap();
+
+        assert_eq!(engine.expire(b"foo", ExpireAt::AfterMillis(-1), ExpireConditions::default()), Ok(true));
+        assert_eq!(engine.expire(b"baz", ExpireAt::UnixTimeMillis(999_000), ExpireConditions::default()), Ok(true));
+        assert_eq!(engine.get(b"foo").unwrap(), None);
+        assert_eq!(engine.get(b"baz").unwrap(), None);
+    }
+
+    #[test]
+    fn persist_removes_the_expiry() {
+        let mut engine = StorageEngine::new();
+        engine.set(b"foo".to_vec(), b"bar".to_vec(), Some(10)).unwrap();
+
+        assert!(engine.persist(b"foo"));
+        assert!(!engine.persist(b"foo"));
+        assert!(!engine.persist(b"missing"));
+        assert_eq!(engine.expire_time(b"foo"), ExpireTime::DoesNotExpire);
+
+        MockClock::advance(Duration::from_secs(11));
+        assert_eq!(engine.get(b"foo").unwrap(), Some(&b"bar".to_vec()));
+    }
+
+    #[test]
+    fn increment_by_creates_missing_key_and_keeps_expiry() {
+        let mut engine = StorageEngine::new();
+
+        assert_eq!(engine.increment_by(b"counter", 5), Ok(5));
+        assert_eq!(engine.get(b"counter").unwrap(), Some(&b"5".to_vec()));
+
+        engine.set(b"limited".to_vec(), b"10".to_vec(), Some(10)).unwrap();
+        assert_eq!(engine.increment_by(b"limited", -3), Ok(7));
+        MockClock::advance(Duration::from_secs(11));
+        assert_eq!(engine.get(b"limited").unwrap(), None);
+    }
+
+    #[test]
+    fn increment_by_fails_on_non_integer_values_and_overflow() {
+        let mut engine = StorageEngine::new();
+        let error = Err("value is not an integer or out of range".to_string());
+
+        engine.set(b"text".to_vec(), b"abc".to_vec(), None).unwrap();
+        engine.set(b"float".to_vec(), b"1.5".to_vec(), None).unwrap();
+        engine.set(b"max".to_vec(), i64::MAX.to_string().into_bytes(), None).unwrap();
+
+        assert_eq!(engine.increment_by(b"text", 1), error);
+        assert_eq!(engine.increment_by(b"float", 1), error);
+        assert_eq!(engine.increment_by(b"max", 1), error);
+        // nothing changes on failure
+        assert_eq!(engine.get(b"max").unwrap(), Some(&i64::MAX.to_string().into_bytes()));
+    }
+
+    #[test]
+    fn increment_by_float_stores_shortest_representation() {
+        let mut engine = StorageEngine::new();
+
+        assert_eq!(engine.increment_by_float(b"f", 10.5), Ok(b"10.5".to_vec()));
+        assert_eq!(engine.increment_by_float(b"f", 0.1), Ok(b"10.6".to_vec()));
+        assert_eq!(engine.increment_by_float(b"f", -0.6), Ok(b"10".to_vec()));
+        assert_eq!(engine.increment_by(b"f", 1), Ok(11));
+    }
+
+    #[test]
+    fn increment_by_float_fails_on_non_numbers_and_infinity() {
+        let mut engine = StorageEngine::new();
+
+        engine.set(b"text".to_vec(), b"abc".to_vec(), None).unwrap();
+        engine.set(b"big".to_vec(), b"1e308".to_vec(), None).unwrap();
+
+        assert_eq!(engine.increment_by_float(b"text", 1.0), Err("value is not a valid float".to_string()));
+        assert_eq!(engine.increment_by_float(b"big", 1e308), Err("increment would produce NaN or Infinity".to_string()));
+        assert_eq!(engine.increment_by_float(b"missing", f64::INFINITY), Err("increment would produce NaN or Infinity".to_string()));
+        assert_eq!(engine.get(b"missing"), Ok(None));
+    }
+
+    #[test]
+    fn remove_should_remove_and_indicate_if_something_was_removed_or_not() {
+        let mut engine = StorageEngine::new();
+
+        engine.set(b"foo".to_vec(), b"bar".to_vec(), None).unwrap();
+
+        let result = engine.remove(b"foo");
+        assert!(result);
+
+        // since the value was removed, it can no longer be found
+        let result = engine.get(b"foo").unwrap();
+        assert_eq!(result, None);
+
+        // a new 'remove' will now not remove anything
+        let result = engine.remove(b"foo");
+        assert!(!result);
+    }
+
+
+    #[test]
+    fn exists_should_tell_whether_an_entry_exists_for_key() {
+        let mut engine = StorageEngine::new();
+
+        let key = b"foo".to_vec();
+
+        // initially doesn't exist
+        assert!(!engine.exists(&key));
+
+        // after setting, exists
+        engine.set(key.clone(), b"bar".to_vec(), None).unwrap();
+
+        assert!(engine.exists(&key));
+    }
+
+    #[test]
+    fn push_and_pop_should_work_on_both_ends_of_the_list() {
+        let mut engine = StorageEngine::new();
+
+        let length = engine.push(b"list", &[b"b".to_vec(), b"c".to_vec()], ListEnd::Right).unwrap();
+        assert_eq!(length, 2);
+        let length = engine.push(b"list", &[b"a".to_vec()], ListEnd::Left).unwrap();
+        assert_eq!(length, 3);
+
+        let popped = engine.pop(b"list", ListEnd::Left, 1).unwrap();
+        assert_eq!(popped, Some(vec![b"a".to_vec()]));
+        let popped = engine.pop(b"list", ListEnd::Right, 5).unwrap();
+        assert_eq!(popped, Some(vec![b"c".to_vec(), b"b".to_vec()]));
+
+        // the list is removed once it's empty
+        assert!(!engine.exists(b"list"));
+        assert_eq!(engine.pop(b"list", ListEnd::Left, 1).unwrap(), None);
+    }
+
+    #[test]
+    fn list_operations_should_fail_on_a_string_value() {
+        let mut engine = StorageEngine::new();
+        engine.set(b"foo".to_vec(), b"bar".to_vec(), None).unwrap();
+
+        assert!(engine.push(b"foo", &[b"a".to_vec()], ListEnd::Left).is_err());
+        assert!(engine.list_len(b"foo").is_err());
+
+        // the original value is left untouched
+        assert_eq!(engine.get(b"foo").unwrap(), Some(&b"bar".to_vec()));
+    }
+
+    #[test]
+    fn list_move_should_not_pop_what_it_cannot_push() {
+        let mut engine = StorageEngine::new();
+        engine.push(b"source", &[b"a".to_vec(), b"b".to_vec()], ListEnd::Right).unwrap();
+        engine.set(b"string".to_vec(), b"x".to_vec(), None).unwrap();
+
+        assert!(engine.list_move(b"source", b"string", ListEnd::Left, ListEnd::Left).is_err());
+        assert_eq!(engine.list_len(b"source").unwrap(), 2);
+
+        assert_eq!(engine.list_move(b"source", b"source", ListEnd::Left, ListEnd::Right).unwrap(), Some(b"a".to_vec()));
+        assert_eq!(engine.pop(b"source", ListEnd::Left, 2).unwrap(), Some(vec![b"b".to_vec(), b"a".to_vec()]));
+        assert_eq!(engine.list_move(b"source", b"other", ListEnd::Left, ListEnd::Left).unwrap(), None);
+        assert!(!engine.exists(b"other"));
+    }
+
+    #[test]
+    fn normalize_range_should_follow_redis_semantics() {
+        assert_eq!(normalize_range(0, -1, 5), Some((0, 4)));
+        assert_eq!(normalize_range(-2, -1, 5), Some((3, 4)));
+        assert_eq!(normalize_range(-10, 1, 5), Some((0, 1)));
+        assert_eq!(normalize_range(3, 10, 5), Some((3, 4)));
+        assert_eq!(normalize_range(5, 10, 5), None);
+        assert_eq!(normalize_range(2, 1, 5), None);
+        assert_eq!(normalize_range(0, -1, 0), None);
+        assert_eq!(normalize_range(0, -6, 5), None);
+    }
+
+    #[test]
+    fn hash_set_should_report_only_new_fields_and_hash_get_should_return_them() {
+        let mut engine = StorageEngine::new();
+
+        let added = engine.hash_set(b"hash", &[(b"a".to_vec(), b"1".to_vec()), (b"b".to_vec(), b"2".to_vec())]).unwrap();
+        assert_eq!(added, 2);
+        let added = engine.hash_set(b"hash", &[(b"a".to_vec(), b"3".to_vec())]).unwrap();
+        assert_eq!(added, 0);
+
+        assert_eq!(engine.hash_get(b"hash", b"a").unwrap(), Some(&b"3".to_vec()));
+        assert_eq!(engine.hash_get(b"hash", b"c").unwrap(), None);
+        assert_eq!(engine.hash_len(b"hash").unwrap(), 2);
+    }
+
+    #[test]
+    fn hash_operations_should_fail_on_a_string_value() {
+        let mut engine = StorageEngine::new();
+        engine.set(b"foo".to_vec(), b"bar".to_vec(), None).unwrap();
+
+        assert!(engine.hash_set(b"foo", &[(b"a".to_vec(), b"1".to_vec())]).is_err());
+        assert!(engine.hash_get(b"foo", b"a").is_err());
+        assert!(engine.hash_increment_by(b"foo", b"a", 1).is_err());
+    }
+
+    #[test]
+    fn set_combine_should_not_require_all_keys_to_exist() {
+        let mut engine = StorageEngine::new();
+        engine.set_add(b"a", &[b"1".to_vec(), b"2".to_vec()]).unwrap();
+        engine.set_add(b"b", &[b"2".to_vec(), b"3".to_vec()]).unwrap();
+
+        let mut union = engine.set_combine(SetOperation::Union, &[b"a".to_vec(), b"missing".to_vec(), b"b".to_vec()]).unwrap();
+        union.sort();
+        assert_eq!(union, vec![b"1", b"2", b"3"]);
+
+        let intersection = engine.set_combine(SetOperation::Intersection, &[b"a".to_vec(), b"b".to_vec()]).unwrap();
+        assert_eq!(intersection, vec![b"2"]);
+
+        let difference = engine.set_combine(SetOperation::Difference, &[b"a".to_vec(), b"missing".to_vec()]).unwrap();
+        assert_eq!(difference.len(), 2);
+    }
+
+    #[test]
+    fn set_pop_should_remove_the_popped_members() {
+        let mut engine = StorageEngine::new();
+        engine.set_add(b"set", &[b"1".to_vec(), b"2".to_vec(), b"3".to_vec()]).unwrap();
+
+        let popped = engine.set_pop(b"set", 2).unwrap().unwrap();
+        assert_eq!(popped.len(), 2);
+        assert_eq!(engine.set_len(b"set").unwrap(), 1);
+        assert_eq!(engine.set_contains(b"set", &popped).unwrap(), vec![false, false]);
+    }
+
+    #[test]
+    fn set_random_members_should_refuse_negative_counts_longer_than_a_reply() {
+        let mut engine = StorageEngine::new();
+        engine.set_add(b"set", &[b"1".to_vec()]).unwrap();
+
+        // refused before a single member is picked, rather than filling a reply nobody could receive
+        assert_eq!(engine.set_random_members(b"set", i64::MIN / 2), Err("value is out of range".to_string()));
+        assert_eq!(engine.set_random_members(b"set", -MAX_ARRAY_LENGTH - 1), Err("value is out of range".to_string()));
+        assert_eq!(engine.set_random_members(b"set", -3).unwrap(), vec![b"1", b"1", b"1"]);
+    }
+
+    #[test]
+    fn moving_a_key_keeps_its_expiry_in_the_other_database() {
+        let mut engine = StorageEngine::with_databases(2);
+        engine.set(b"volatile".to_vec(), b"v".to_vec(), Some(100)).unwrap();
+
+        assert_eq!(engine.move_key(b"volatile", 1), Ok(true));
+        assert_eq!(engine.db_size(), 0);
+        assert!(engine.db().expiry_index.is_empty());
+
+        engine.select_db(1);
+        assert!(matches!(engine.time_to_live(b"volatile"), TimeToLive::ExpiresInMillis(100_000)));
+        assert_eq!(engine.databases[1].expiry_index.len(), 1);
+        assert_eq!(engine.move_key(b"volatile", 2), Err("DB index is out of range".to_string()));
+    }
+
+    #[test]
+    fn flushing_asynchronously_empties_the_database_right_away() {
+        let mut engine = StorageEngine::with_databases(2);
+        for i in 0..1000 {
+            engine.set(format!("key:{i}").into_bytes(), b"v".to_vec(), None).unwrap();
+        }
+        engine.select_db(1);
+        engine.set(b"other".to_vec(), b"v".to_vec(), None).unwrap();
+
+        engine.select_db(0);
+        engine.flush_db(true);
+        assert_eq!(engine.db_size(), 0);
+        assert_eq!(engine.databases[1].map.len(), 1);
 
-        assert_eq!(engine.exists(&key), true);
+        engine.flush_all(true);
+        assert!(engine.databases.iter().all(|db| db.map.len() == 0));
     }
 }
@@ -0,0 +1,310 @@
+//! The binary form of snapshots, as saved to the RDB file:
+//!
+//! - the magic string and the version of the format
+//! - for each database with keys: SELECTDB, its index and how many keys follow
+//! - for each key: EXPIRETIME_MS and the unix time in milliseconds (only when it expires), then the type of the
+//!   value, the key and the value
+//! - EOF, followed by the CRC-64 of everything before it (the same checksum Redis uses)
+//!
+//! Lengths are unsigned LEB128 (7 bits per byte, so small ones take a single byte), strings are their length followed
+//! by their bytes, and fixed-size numbers are little endian; scores are kept as their exact bits.
+
+use crate::engine::snapshot::{Entry, Snapshot};
+use crate::engine::sorted_set::SortedSet;
+use crate::engine::stream::Stream;
+use crate::engine::Value;
+use crate::engine::Value::{HashValue, ListValue, SetValue, SortedSetValue, StreamValue, StringValue};
+
+const MAGIC: &[u8] = b"REDIS-LITE";
+const VERSION: u8 = 1;
+
+const OPCODE_SELECTDB: u8 = 0xFE;
+const OPCODE_EXPIRETIME_MS: u8 = 0xFC;
+const OPCODE_EOF: u8 = 0xFF;
+
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_SET: u8 = 2;
+const TYPE_SORTED_SET: u8 = 3;
+const TYPE_HASH: u8 = 4;
+const TYPE_STREAM: u8 = 5;
+
+// the reflected Jones polynomial of the CRC-64 variant of Redis
+const CRC64_POLYNOMIAL: u64 = 0x95AC_9329_AC4B_C9B5;
+const CRC64_TABLE: [u64; 256] = crc64_table();
+
+impl Snapshot {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+
+        for (db, entries) in self.databases.iter().enumerate().filter(|(_, entries)| !entries.is_empty()) {
+            out.push(OPCODE_SELECTDB);
+            write_length(&mut out, db);
+            write_length(&mut out, entries.len());
+
+            for Entry { key, value, expires_at } in entries {
+                if let Some(expires_at) = expires_at {
+                    out.push(OPCODE_EXPIRETIME_MS);
+                    write_u64(&mut out, *expires_at);
+                }
+                out.push(value_type(value));
+                write_bytes(&mut out, key);
+                write_value(&mut out, value);
+            }
+        }
+
+        out.push(OPCODE_EOF);
+        let checksum = crc64(&out);
+        write_u64(&mut out, checksum);
+        out
+    }
+
+    /// Reads a snapshot back from its binary form, which must be complete and match its checksum.
+    pub fn decode(bytes: &[u8]) -> Result<Snapshot, String> {
+        let (content, checksum) = bytes.split_at_checked(bytes.len().saturating_sub(8))
+            .filter(|(_, checksum)| checksum.len() == 8)
+            .ok_or_else(|| "the file is too short".to_string())?;
+        if crc64(content) != u64::from_le_bytes(checksum.try_into().unwrap_or_default()) {
+            return Err("the checksum does not match, the file is corrupt".to_string());
+        }
+
+        let mut input = Decoder::new(content);
+        if input.read_slice(MAGIC.len())? != MAGIC {
+            return Err("the file is not an RDB file".to_string());
+        }
+        let version = input.read_u8()?;
+        if version != VERSION {
+            return Err(format!("can't handle RDB format version {version}"));
+        }
+
+        let mut databases: Vec<Vec<Entry>> = vec![];
+        loop {
+            match input.read_u8()? {
+                OPCODE_EOF => break,
+                OPCODE_SELECTDB => {
+                    let db = input.read_length()?;
+                    let key_count = input.read_count()?;
+                    if databases.len() <= db {
+                        databases.resize_with(db + 1, Vec::new);
+                    }
+                    for _ in 0..key_count {
+                        databases[db].push(read_entry(&mut input)?);
+                    }
+                }
+                opcode => return Err(format!("unknown opcode {opcode}")),
+            }
+        }
+        if !input.is_at_end() {
+            return Err("unexpected data after the end of the file".to_string());
+        }
+        Ok(Snapshot { databases })
+    }
+}
+
+fn read_entry(input: &mut Decoder) -> Result<Entry, String> {
+    let mut value_type = input.read_u8()?;
+    let mut expires_at = None;
+    if value_type == OPCODE_EXPIRETIME_MS {
+        expires_at = Some(input.read_u64()?);
+        value_type = input.read_u8()?;
+    }
+    let key = input.read_bytes()?;
+    let value = read_value(input, value_type)?;
+    Ok(Entry { key, value, expires_at })
+}
+
+fn value_type(value: &Value) -> u8 {
+    match value {
+        StringValue(_) => TYPE_STRING,
+        ListValue(_) => TYPE_LIST,
+        SetValue(_) => TYPE_SET,
+        SortedSetValue(_) => TYPE_SORTED_SET,
+        HashValue(_) => TYPE_HASH,
+        StreamValue(_) => TYPE_STREAM,
+    }
+}
+
+fn write_value(out: &mut Vec<u8>, value: &Value) {
+    match value {
+        StringValue(value) => write_bytes(out, value),
+        ListValue(list) => {
+            write_length(out, list.len());
+            list.iter().for_each(|element| write_bytes(out, element));
+        }
+        SetValue(set) => {
+            write_length(out, set.len());
+            set.iter().for_each(|member| write_bytes(out, member));
+        }
+        SortedSetValue(sorted_set) => {
+            write_length(out, sorted_set.len());
+            for (member, score) in sorted_set.range_by_rank(0, -1, false) {
+                write_bytes(out, member);
+                write_u64(out, score.to_bits());
+            }
+        }
+        HashValue(hash) => {
+            write_length(out, hash.len());
+            for (field, value) in hash {
+                write_bytes(out, field);
+                write_bytes(out, value);
+            }
+        }
+        StreamValue(stream) => stream.encode(out),
+    }
+}
+
+fn read_value(input: &mut Decoder, value_type: u8) -> Result<Value, String> {
+    Ok(match value_type {
+        TYPE_STRING => StringValue(input.read_bytes()?),
+        TYPE_LIST => ListValue((0..input.read_count()?).map(|_| input.read_bytes()).collect::<Result<_, _>>()?),
+        TYPE_SET => SetValue((0..input.read_count()?).map(|_| input.read_bytes()).collect::<Result<_, _>>()?),
+        TYPE_SORTED_SET => {
+            let mut sorted_set = SortedSet::new();
+            for _ in 0..input.read_count()? {
+                let member = input.read_bytes()?;
+                sorted_set.insert(&member, f64::from_bits(input.read_u64()?));
+            }
+            SortedSetValue(sorted_set)
+        }
+        TYPE_HASH => HashValue((0..input.read_count()?)
+            .map(|_| Ok((input.read_bytes()?, input.read_bytes()?)))
+            .collect::<Result<_, String>>()?),
+        TYPE_STREAM => StreamValue(Stream::decode(input)?),
+        value_type => return Err(format!("unknown value type {value_type}")),
+    })
+}
+
+pub(super) fn write_length(out: &mut Vec<u8>, length: usize) {
+    let mut length = length as u64;
+    while length >= 0x80 {
+        out.push((length as u8 & 0x7F) | 0x80);
+        length >>= 7;
+    }
+    out.push(length as u8);
+}
+
+pub(super) fn write_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+pub(super) fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_length(out, bytes.len());
+    out.extend_from_slice(bytes);
+}
+
+/// Reads what the 'write_*' functions wrote, in the same order, failing when the input ends before it should.
+pub(super) struct Decoder<'a> {
+    input: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    pub(super) fn new(input: &'a [u8]) -> Decoder<'a> {
+        Decoder { input }
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.input.is_empty()
+    }
+
+    fn read_slice(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let (slice, rest) = self.input.split_at_checked(len)
+            .ok_or_else(|| "unexpected end of the file".to_string())?;
+        self.input = rest;
+        Ok(slice)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, String> {
+        Ok(self.read_slice(1)?[0])
+    }
+
+    pub(super) fn read_u64(&mut self) -> Result<u64, String> {
+        let bytes = self.read_slice(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap_or_default()))
+    }
+
+    pub(super) fn read_length(&mut self) -> Result<usize, String> {
+        let mut length = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.read_u8()?;
+            length |= u64::from(byte & 0x7F) << shift;
+            if byte & 0x80 == 0 {
+                return usize::try_from(length).map_err(|_| format!("invalid length {length}"));
+            }
+        }
+        Err("invalid length".to_string())
+    }
+
+    /// Reads how many elements a collection has. Each takes a byte at least, so there can't be more than there are
+    /// bytes left, which keeps a bogus count from allocating a huge collection.
+    pub(super) fn read_count(&mut self) -> Result<usize, String> {
+        let count = self.read_length()?;
+        if count > self.input.len() {
+            return Err(format!("invalid count {count}"));
+        }
+        Ok(count)
+    }
+
+    pub(super) fn read_bytes(&mut self) -> Result<Vec<u8>, String> {
+        let len = self.read_length()?;
+        Ok(self.read_slice(len)?.to_vec())
+    }
+}
+
+const fn crc64_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut crc = index as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ CRC64_POLYNOMIAL } else { crc >> 1 };
+            bit += 1;
+        }
+        table[index] = crc;
+        index += 1;
+    }
+    table
+}
+
+fn crc64(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |crc, byte| CRC64_TABLE[((crc ^ u64::from(*byte)) & 0xFF) as usize] ^ (crc >> 8))
+}
+
+#[cfg(test)]
+mod encoding_tests {
+    use super::*;
+
+    #[test]
+    fn computes_the_checksum_of_redis() {
+        assert_eq!(crc64(b"123456789"), 0xe9c6d914c4b8d9ca);
+    }
+
+    #[test]
+    fn reads_back_what_it_writes() {
+        let lengths = [0, 127, 128, 300, usize::MAX];
+        let mut out = vec![];
+        for length in lengths {
+            write_length(&mut out, length);
+        }
+        write_bytes(&mut out, b"bytes");
+        write_u64(&mut out, u64::MAX);
+
+        let mut input = Decoder::new(&out);
+        for length in lengths {
+            assert_eq!(input.read_length(), Ok(length));
+        }
+        assert_eq!(input.read_bytes(), Ok(b"bytes".to_vec()));
+        assert_eq!(input.read_u64(), Ok(u64::MAX));
+        assert_eq!(input.read_u8(), Err("unexpected end of the file".to_string()));
+    }
+
+    #[test]
+    fn refuses_counts_larger_than_what_is_left() {
+        let mut out = vec![];
+        write_length(&mut out, 3);
+        out.extend_from_slice(b"ab");
+
+        assert_eq!(Decoder::new(&out).read_count(), Err("invalid count 3".to_string()));
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use crate::engine::random::Random;
+
+/// Keys that have an expiry, kept apart from the keyspace so that the active expire cycle can sample them at
+/// random in O(1), the way Redis samples its 'expires' dictionary.
+///
+/// The index may still hold keys that lost their expiry (or the key altogether) in some way that did not bother
+/// updating it, e.g. a SET without options overwriting a volatile key; the cycle drops those when it samples them.
+/// What it must never do is miss a key that has an expiry.
+pub struct ExpiryIndex {
+    keys: Vec<Vec<u8>>,
+    // key -> its position in 'keys'
+    positions: HashMap<Vec<u8>, usize>,
+}
+
+/// Tuning of the active expire cycle, from the 'hz' and 'active-expire-effort' configs
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct ExpireConfig {
+    /// how many times per second the cycle runs, between 1 and 500
+    pub hz: u32,
+    /// how hard each cycle works to reclaim expired keys, between 1 and 10
+    pub effort: u32,
+}
+
+impl Default for ExpireConfig {
+    fn default() -> Self {
+        ExpireConfig { hz: 10, effort: 1 }
+    }
+}
+
+/// Counters about key expiry, as reported by INFO
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct ExpireStats {
+    /// keys removed because they expired, both lazily (on access) and by the active cycle
+    pub expired_keys: u64,
+    /// estimate of the percentage of keys with an expiry that are expired but not reclaimed yet
+    pub expired_stale_perc: f64,
+    /// how many active cycles stopped early because they ran out of time
+    pub expired_time_cap_reached_count: u64,
+}
+
+impl ExpiryIndex {
+    pub fn new() -> ExpiryIndex {
+        ExpiryIndex { keys: vec![], positions: HashMap::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn insert(&mut self, key: &[u8]) {
+        if !self.positions.contains_key(key) {
+            self.positions.insert(key.to_vec(), self.keys.len());
+            self.keys.push(key.to_vec());
+        }
+    }
+
+    pub fn remove(&mut self, key: &[u8]) {
+        if let Some(position) = self.positions.remove(key) {
+            // the last key takes the place of the removed one, which keeps removals O(1)
+            self.keys.swap_remove(position);
+            if let Some(moved) = self.keys.get(position) {
+                self.positions.insert(moved.clone(), position);
+            }
+        }
+    }
+
+    pub fn random_key(&self, random: &mut Random) -> Option<&[u8]> {
+        if self.keys.is_empty() {
+            return None;
+        }
+
+        Some(&self.keys[random.next_below(self.keys.len())])
+    }
+}
+
+#[cfg(test)]
+mod expiry_index_tests {
+    use super::*;
+
+    #[test]
+    fn removing_keeps_the_other_keys_reachable() {
+        let mut index = ExpiryIndex::new();
+        index.insert(b"a");
+        index.insert(b"b");
+        index.insert(b"c");
+        index.insert(b"a");
+        assert_eq!(index.len(), 3);
+
+        index.remove(b"a");
+        index.remove(b"missing");
+        assert_eq!(index.len(), 2);
+
+        let mut random = Random::with_seed(7);
+        let mut sampled: Vec<Vec<u8>> = (0..50).map(|_| index.random_key(&mut random).unwrap().to_vec()).collect();
+        sampled.sort();
+        sampled.dedup();
+        assert_eq!(sampled, vec![b"b".to_vec(), b"c".to_vec()]);
+
+        index.remove(b"c");
+        index.remove(b"b");
+        assert!(index.is_empty());
+        assert_eq!(index.random_key(&mut random), None);
+    }
+}
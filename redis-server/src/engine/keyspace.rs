@@ -0,0 +1,177 @@
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeSet, HashMap};
+use std::hash::BuildHasher;
+use std::sync::Arc;
+
+use crate::engine::random::Random;
+
+/// All the keys of a database and what they hold, plus the order SCAN walks them in.
+///
+/// Redis walks the buckets of its hash table with a reverse-binary cursor, so that keys are visited even when the
+/// table grows or shrinks between calls. The buckets of the standard 'HashMap' are not exposed, so instead the keys
+/// are also kept ordered by a hash of their own (under a per-process seed): a cursor is simply the hash to resume
+/// from, which stays meaningful whatever is added or removed in between. Like Redis, every key present during a full
+/// iteration is returned at least once; keys added or removed along the way may or may not be.
+///
+/// The map and the scan order share each key, so its bytes are only stored once.
+pub struct Keyspace<V> {
+    items: HashMap<Arc<[u8]>, V>,
+    scan_order: BTreeSet<(u64, Arc<[u8]>)>,
+    hasher: RandomState,
+}
+
+impl<V> Keyspace<V> {
+    pub fn new() -> Keyspace<V> {
+        Keyspace {
+            items: HashMap::new(),
+            scan_order: BTreeSet::new(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&V> {
+        self.items.get(key)
+    }
+
+    pub fn get_mut(&mut self, key: &[u8]) -> Option<&mut V> {
+        self.items.get_mut(key)
+    }
+
+    pub fn insert(&mut self, key: Vec<u8>, value: V) -> Option<V> {
+        if let Some(current) = self.items.get_mut(key.as_slice()) {
+            return Some(std::mem::replace(current, value));
+        }
+        let key: Arc<[u8]> = key.into();
+        self.scan_order.insert((self.hash(&key), key.clone()));
+        self.items.insert(key, value)
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> Option<V> {
+        let (key, removed) = self.items.remove_entry(key)?;
+        self.scan_order.remove(&(self.hash(&key), key));
+        Some(removed)
+    }
+
+    fn hash(&self, key: &[u8]) -> u64 {
+        self.hasher.hash_one(key)
+    }
+
+    pub fn get_or_insert_with(&mut self, key: &[u8], new_value: impl FnOnce() -> V) -> &mut V {
+        if !self.items.contains_key(key) {
+            self.insert(key.to_vec(), new_value());
+        }
+        self.items.get_mut(key).expect("the key was just inserted")
+    }
+
+    /// All keys, in scan order.
+    pub fn keys(&self) -> impl Iterator<Item = &[u8]> {
+        self.scan_order.iter().map(|(_, key)| &**key)
+    }
+
+    /// Returns about 'count' keys starting at 'cursor', along with the cursor to continue from (0 once done).
+    /// Keys with the same hash are always returned together, so that resuming from a hash never skips any.
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<&[u8]>) {
+        let mut keys = vec![];
+        let mut last_hash = None;
+
+        for (hash, key) in self.scan_order.range(from_hash(cursor)) {
+            // this key starts the next call, unless it shares its hash with the last one returned
+            if keys.len() >= count && last_hash != Some(*hash) {
+                return (*hash, keys);
+            }
+            keys.push(&**key);
+            last_hash = Some(*hash);
+        }
+        (0, keys)
+    }
+
+    /// A key picked at random, or 'None' when there are no keys.
+    pub fn random_key(&self, random: &mut Random) -> Option<&[u8]> {
+        let start = random.next_u64();
+        self.scan_order.range(from_hash(start)).next()
+            .or_else(|| self.scan_order.first())
+            .map(|(_, key)| &**key)
+    }
+}
+
+// the keys from the given hash on, the empty key being the smallest of any hash
+fn from_hash(hash: u64) -> std::ops::RangeFrom<(u64, Arc<[u8]>)> {
+    (hash, Arc::from([].as_slice()))..
+}
+
+#[cfg(test)]
+mod keyspace_tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn scan_returns_every_key_once_when_nothing_changes() {
+        let mut keyspace = Keyspace::new();
+        for i in 0..100 {
+            keyspace.insert(format!("key:{i}").into_bytes(), i);
+        }
+
+        let mut seen = HashSet::new();
+        let mut cursor = 0;
+        loop {
+            let (next, keys) = keyspace.scan(cursor, 7);
+            for key in keys {
+                assert!(seen.insert(key.to_vec()), "returned twice");
+            }
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        assert_eq!(seen.len(), 100);
+    }
+
+    #[test]
+    fn scan_returns_keys_present_for_the_whole_iteration_despite_changes() {
+        let mut keyspace = Keyspace::new();
+        for i in 0..50 {
+            keyspace.insert(format!("stable:{i}").into_bytes(), i);
+        }
+
+        let mut seen = HashSet::new();
+        let mut cursor = 0;
+        let mut round = 0;
+        loop {
+            let (next, keys) = keyspace.scan(cursor, 5);
+            seen.extend(keys.into_iter().map(<[u8]>::to_vec));
+
+            // keys come and go in between calls
+            for i in 0..3 {
+                keyspace.insert(format!("new:{round}:{i}").into_bytes(), i);
+            }
+            keyspace.remove(format!("new:{round}:0").as_bytes());
+            round += 1;
+
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        assert!((0..50).all(|i| seen.contains(format!("stable:{i}").as_bytes())));
+    }
+
+    #[test]
+    fn keeps_scan_order_in_sync() {
+        let mut keyspace = Keyspace::new();
+        keyspace.insert(b"a".to_vec(), 1);
+        keyspace.insert(b"a".to_vec(), 2);
+        *keyspace.get_or_insert_with(b"b", || 0) += 1;
+        *keyspace.get_or_insert_with(b"b", || 0) += 1;
+        assert_eq!(keyspace.get(b"b"), Some(&2));
+        assert_eq!(keyspace.keys().count(), 2);
+
+        assert_eq!(keyspace.remove(b"a"), Some(2));
+        assert_eq!(keyspace.remove(b"a"), None);
+        assert_eq!(keyspace.keys().collect::<Vec<_>>(), vec![b"b".as_slice()]);
+        assert_eq!(keyspace.random_key(&mut Random::with_seed(3)), Some(b"b".as_slice()));
+    }
+}
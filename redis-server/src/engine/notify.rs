@@ -0,0 +1,116 @@
+use crate::pubsub::Broker;
+
+/// Which keyspace events get published, as set with the 'notify-keyspace-events' config.
+///
+/// Events come in classes (generic, string, list, ...), and are published to '__keyspace@<db>__:<key>' (with K) and/or
+/// '__keyevent@<db>__:<event>' (with E). Nothing is published unless at least one of K and E is set, along with the
+/// class of the event.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub struct NotifyFlags(u16);
+
+impl NotifyFlags {
+    pub const KEYSPACE: NotifyFlags = NotifyFlags(1 << 0);
+    pub const KEYEVENT: NotifyFlags = NotifyFlags(1 << 1);
+    pub const GENERIC: NotifyFlags = NotifyFlags(1 << 2);
+    pub const STRING: NotifyFlags = NotifyFlags(1 << 3);
+    pub const LIST: NotifyFlags = NotifyFlags(1 << 4);
+    pub const SET: NotifyFlags = NotifyFlags(1 << 5);
+    pub const HASH: NotifyFlags = NotifyFlags(1 << 6);
+    pub const ZSET: NotifyFlags = NotifyFlags(1 << 7);
+    pub const EXPIRED: NotifyFlags = NotifyFlags(1 << 8);
+    pub const EVICTED: NotifyFlags = NotifyFlags(1 << 9);
+    pub const STREAM: NotifyFlags = NotifyFlags(1 << 10);
+    pub const MODULE: NotifyFlags = NotifyFlags(1 << 11);
+    pub const KEY_MISS: NotifyFlags = NotifyFlags(1 << 12);
+    pub const NEW: NotifyFlags = NotifyFlags(1 << 13);
+
+    // what 'A' stands for: every class, but key misses and new keys
+    const ALL: NotifyFlags = NotifyFlags(
+        Self::GENERIC.0 | Self::STRING.0 | Self::LIST.0 | Self::SET.0 | Self::HASH.0 | Self::ZSET.0
+            | Self::EXPIRED.0 | Self::EVICTED.0 | Self::STREAM.0 | Self::MODULE.0
+    );
+
+    // the character of each flag, in the order Redis lists them
+    const CHARACTERS: [(char, NotifyFlags); 13] = [
+        ('g', Self::GENERIC), ('$', Self::STRING), ('l', Self::LIST), ('s', Self::SET), ('h', Self::HASH),
+        ('z', Self::ZSET), ('x', Self::EXPIRED), ('e', Self::EVICTED), ('t', Self::STREAM), ('d', Self::MODULE),
+        ('K', Self::KEYSPACE), ('E', Self::KEYEVENT), ('m', Self::KEY_MISS),
+    ];
+
+    /// Parses flags like "KEA" or "Ex", failing on unknown characters.
+    pub fn parse(flags: &str) -> Option<NotifyFlags> {
+        flags.chars().try_fold(NotifyFlags::default(), |parsed, character| {
+            let flag = match character {
+                'A' => Self::ALL,
+                'n' => Self::NEW,
+                _ => Self::CHARACTERS.iter().find(|(known, _)| *known == character)?.1,
+            };
+            Some(NotifyFlags(parsed.0 | flag.0))
+        })
+    }
+
+    fn contains(self, flags: NotifyFlags) -> bool {
+        self.0 & flags.0 == flags.0
+    }
+
+    fn publishes(self, class: NotifyFlags) -> bool {
+        self.0 & class.0 != 0 && self.0 & (Self::KEYSPACE.0 | Self::KEYEVENT.0) != 0
+    }
+}
+
+impl std::fmt::Display for NotifyFlags {
+    // the canonical form of the flags, e.g. "AKE" whatever order they were given in
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let all = self.contains(Self::ALL);
+        if all {
+            write!(f, "A")?;
+        }
+        for (character, flag) in Self::CHARACTERS {
+            if !(all && Self::ALL.contains(flag)) && self.contains(flag) {
+                write!(f, "{character}")?;
+            }
+        }
+        if self.contains(Self::NEW) {
+            write!(f, "n")?;
+        }
+        Ok(())
+    }
+}
+
+/// Publishes that the event happened to the key of the given database, if the flags say so.
+pub fn notify_keyspace_event(broker: &Broker, flags: NotifyFlags, class: NotifyFlags, event: &str, db: usize, key: &[u8]) {
+    if !flags.publishes(class) {
+        return;
+    }
+
+    if flags.contains(NotifyFlags::KEYSPACE) {
+        let mut channel = format!("__keyspace@{db}__:").into_bytes();
+        channel.extend_from_slice(key);
+        broker.publish(&channel, event.as_bytes());
+    }
+    if flags.contains(NotifyFlags::KEYEVENT) {
+        broker.publish(format!("__keyevent@{db}__:{event}").as_bytes(), key);
+    }
+}
+
+#[cfg(test)]
+mod notify_flags_tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_prints_flags() {
+        assert_eq!(NotifyFlags::parse(""), Some(NotifyFlags::default()));
+        assert_eq!(NotifyFlags::parse("KEA").unwrap().to_string(), "AKE");
+        assert_eq!(NotifyFlags::parse("xE").unwrap().to_string(), "xE");
+        assert_eq!(NotifyFlags::parse("g$lshzxetdKEmn").unwrap().to_string(), "AKEmn");
+        assert_eq!(NotifyFlags::parse("Kq"), None);
+    }
+
+    #[test]
+    fn publishes_only_enabled_classes_with_k_or_e() {
+        let flags = NotifyFlags::parse("Ex").unwrap();
+        assert!(flags.publishes(NotifyFlags::EXPIRED));
+        assert!(!flags.publishes(NotifyFlags::GENERIC));
+        assert!(!NotifyFlags::parse("A").unwrap().publishes(NotifyFlags::EXPIRED));
+    }
+}
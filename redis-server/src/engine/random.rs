@@ -0,0 +1,40 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+/// Small and fast pseudo-random number generator (xorshift64*), used wherever Redis picks something at random
+/// (e.g. SPOP/SRANDMEMBER). It is not meant to be cryptographically secure.
+#[derive(Clone)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new() -> Random {
+        // 'RandomState' is randomly seeded by the standard library, which makes it a cheap source for a seed
+        let seed = RandomState::new().build_hasher().finish();
+        Random::with_seed(seed)
+    }
+
+    pub fn with_seed(seed: u64) -> Random {
+        // the state of xorshift can never be 0, otherwise it only ever generates 0s
+        Random { state: seed.max(1) }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Returns a number in the range [0, 1).
+    pub fn next_f64(&mut self) -> f64 {
+        // the top 53 bits are all the precision an f64 mantissa has room for
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Returns a number in the range [0, bound). 'bound' must be greater than 0.
+    pub fn next_below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+}
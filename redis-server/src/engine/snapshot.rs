@@ -0,0 +1,84 @@
+use crate::engine::{is_expired, unix_time_of, Database, Value};
+use crate::engine::Value::{HashValue, ListValue, SetValue, SortedSetValue, StreamValue, StringValue};
+use crate::protocol::RespObject::{Array, BulkString};
+
+use std::io::{self, Write};
+
+// how many elements of a collection go into each command that rebuilds it, like AOF_REWRITE_ITEMS_PER_CMD of Redis
+const ITEMS_PER_COMMAND: usize = 64;
+
+/// A copy of the data of every database as of some moment, which can be written out while the engine goes on
+/// serving clients. Taking it copies every value while holding the engine, so it costs as much as the data set.
+pub struct Snapshot {
+    // by database, the keys that had not expired
+    pub(super) databases: Vec<Vec<Entry>>,
+}
+
+// A key along with its value and its expiry, as a unix time in milliseconds
+pub(super) struct Entry {
+    pub(super) key: Vec<u8>,
+    pub(super) value: Value,
+    pub(super) expires_at: Option<u64>,
+}
+
+impl Snapshot {
+    pub(super) fn new(databases: &[Database]) -> Snapshot {
+        let databases = databases.iter()
+            .map(|db| db.map.keys()
+                .filter_map(|key| {
+                    let item = db.map.get(key)?;
+                    (!is_expired(item.expires_at))
+                        .then(|| Entry { key: key.to_vec(), value: item.value.clone(), expires_at: item.expires_at.map(unix_time_of) })
+                })
+                .collect())
+            .collect();
+        Snapshot { databases }
+    }
+
+    /// Writes the commands that rebuild the data from scratch, as a rewritten append only file holds them: a SELECT
+    /// for each database with keys, then the commands that create each key, followed by a PEXPIREAT for those that
+    /// expire.
+    pub fn write_commands(&self, out: &mut impl Write) -> io::Result<()> {
+        for (db, keys) in self.databases.iter().enumerate().filter(|(_, keys)| !keys.is_empty()) {
+            write_command(out, vec![b"SELECT".to_vec(), db.to_string().into_bytes()])?;
+
+            for Entry { key, value, expires_at } in keys {
+                for command in value_commands(key, value) {
+                    write_command(out, command)?;
+                }
+                if let Some(expires_at) = expires_at {
+                    write_command(out, vec![b"PEXPIREAT".to_vec(), key.clone(), expires_at.to_string().into_bytes()])?;
+                }
+            }
+        }
+        out.flush()
+    }
+}
+
+fn write_command(out: &mut impl Write, arguments: Vec<Vec<u8>>) -> io::Result<()> {
+    out.write_all(&Array(arguments.into_iter().map(BulkString).collect()).to_bytes())
+}
+
+// the commands that create the key with the value, collections taking as many commands as their size requires
+fn value_commands(key: &[u8], value: &Value) -> Vec<Vec<Vec<u8>>> {
+    let batched = |name: &[u8], elements: Vec<Vec<Vec<u8>>>| -> Vec<Vec<Vec<u8>>> {
+        elements.chunks(ITEMS_PER_COMMAND)
+            .map(|chunk| {
+                let mut command = vec![name.to_vec(), key.to_vec()];
+                command.extend(chunk.iter().flatten().cloned());
+                command
+            })
+            .collect()
+    };
+
+    match value {
+        StringValue(value) => vec![vec![b"SET".to_vec(), key.to_vec(), value.clone()]],
+        ListValue(list) => batched(b"RPUSH", list.iter().map(|element| vec![element.clone()]).collect()),
+        HashValue(hash) => batched(b"HSET", hash.iter().map(|(field, value)| vec![field.clone(), value.clone()]).collect()),
+        SetValue(set) => batched(b"SADD", set.iter().map(|member| vec![member.clone()]).collect()),
+        SortedSetValue(sorted_set) => batched(b"ZADD", sorted_set.range_by_rank(0, -1, false).into_iter()
+            .map(|(member, score)| vec![score.to_string().into_bytes(), member.clone()])
+            .collect()),
+        StreamValue(stream) => stream.rewrite_commands(key),
+    }
+}
@@ -0,0 +1,640 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use crate::engine::normalize_range;
+use crate::engine::random::Random;
+
+/// Sorted set, as in Redis: a member -> score map for O(1) score lookups, plus a skiplist ordered by
+/// (score, member) that answers rank and range queries in O(log n).
+#[derive(Clone)]
+pub struct SortedSet {
+    scores: HashMap<Vec<u8>, f64>,
+    index: SkipList,
+}
+
+/// Score interval used by ZRANGEBYSCORE/ZCOUNT & co, where each end may be inclusive or exclusive
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ScoreRange {
+    pub min: f64,
+    pub max: f64,
+    pub min_exclusive: bool,
+    pub max_exclusive: bool,
+}
+
+impl ScoreRange {
+    fn is_above_min(&self, score: f64) -> bool {
+        if self.min_exclusive { score > self.min } else { score >= self.min }
+    }
+
+    fn is_below_max(&self, score: f64) -> bool {
+        if self.max_exclusive { score < self.max } else { score <= self.max }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.min > self.max || (self.min == self.max && (self.min_exclusive || self.max_exclusive))
+    }
+}
+
+/// One end of a lexicographical interval, as used by ZRANGE ... BYLEX ('-' and '+' are the infinite ends)
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum LexBound {
+    Inclusive(Vec<u8>),
+    Exclusive(Vec<u8>),
+    NegativeInfinity,
+    PositiveInfinity,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct LexRange {
+    pub min: LexBound,
+    pub max: LexBound,
+}
+
+impl LexRange {
+    fn is_above_min(&self, member: &[u8]) -> bool {
+        match &self.min {
+            LexBound::Inclusive(min) => member >= min.as_slice(),
+            LexBound::Exclusive(min) => member > min.as_slice(),
+            LexBound::NegativeInfinity => true,
+            LexBound::PositiveInfinity => false,
+        }
+    }
+
+    fn is_below_max(&self, member: &[u8]) -> bool {
+        match &self.max {
+            LexBound::Inclusive(max) => member <= max.as_slice(),
+            LexBound::Exclusive(max) => member < max.as_slice(),
+            LexBound::NegativeInfinity => false,
+            LexBound::PositiveInfinity => true,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        match (&self.min, &self.max) {
+            (LexBound::PositiveInfinity, _) | (_, LexBound::NegativeInfinity) => true,
+            (LexBound::NegativeInfinity, _) | (_, LexBound::PositiveInfinity) => false,
+            (LexBound::Inclusive(min), LexBound::Inclusive(max)) => min > max,
+            (LexBound::Inclusive(min) | LexBound::Exclusive(min), LexBound::Inclusive(max) | LexBound::Exclusive(max)) => min >= max,
+        }
+    }
+}
+
+/// Score- or lex-based ranges share the same skiplist traversal, this abstracts over them.
+trait Range {
+    fn is_above_min(&self, score: f64, member: &[u8]) -> bool;
+    fn is_below_max(&self, score: f64, member: &[u8]) -> bool;
+    fn is_empty(&self) -> bool;
+}
+
+impl Range for ScoreRange {
+    fn is_above_min(&self, score: f64, _: &[u8]) -> bool {
+        ScoreRange::is_above_min(self, score)
+    }
+
+    fn is_below_max(&self, score: f64, _: &[u8]) -> bool {
+        ScoreRange::is_below_max(self, score)
+    }
+
+    fn is_empty(&self) -> bool {
+        ScoreRange::is_empty(self)
+    }
+}
+
+impl Range for LexRange {
+    fn is_above_min(&self, _: f64, member: &[u8]) -> bool {
+        LexRange::is_above_min(self, member)
+    }
+
+    fn is_below_max(&self, _: f64, member: &[u8]) -> bool {
+        LexRange::is_below_max(self, member)
+    }
+
+    fn is_empty(&self) -> bool {
+        LexRange::is_empty(self)
+    }
+}
+
+impl SortedSet {
+    pub fn new() -> SortedSet {
+        SortedSet {
+            scores: HashMap::new(),
+            index: SkipList::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Adds the member, or updates its score if it's already there. Returns whether the member was added.
+    pub fn insert(&mut self, member: &[u8], score: f64) -> bool {
+        match self.scores.get_mut(member) {
+            Some(current) => {
+                if *current != score {
+                    self.index.delete(*current, member);
+                    self.index.insert(score, member.to_vec());
+                    *current = score;
+                }
+                false
+            }
+            None => {
+                self.scores.insert(member.to_vec(), score);
+                self.index.insert(score, member.to_vec());
+                true
+            }
+        }
+    }
+
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match self.scores.remove(member) {
+            Some(score) => {
+                self.index.delete(score, member);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// 0-based position of the member, counting from the lowest score (or the highest, if 'reverse').
+    pub fn rank(&self, member: &[u8], reverse: bool) -> Option<usize> {
+        let score = self.score(member)?;
+        let rank = self.index.rank(score, member)?;
+
+        if reverse {
+            Some(self.len() - rank)
+        } else {
+            Some(rank - 1)
+        }
+    }
+
+    /// Members between the positions 'start' and 'stop' (both inclusive, negative values count from the end).
+    pub fn range_by_rank(&self, start: i64, stop: i64, reverse: bool) -> Vec<(&Vec<u8>, f64)> {
+        let (start, stop) = match normalize_range(start, stop, self.len()) {
+            Some(range) => range,
+            None => return vec![],
+        };
+
+        // the skiplist's ranks are 1-based
+        let first_rank = if reverse { self.len() - start } else { start + 1 };
+        let first = self.index.node_by_rank(first_rank);
+        self.index.walk(first, reverse)
+            .take(stop - start + 1)
+            .collect()
+    }
+
+    pub fn range_by_score(&self, range: &ScoreRange, reverse: bool, offset: usize, count: Option<usize>) -> Vec<(&Vec<u8>, f64)> {
+        self.range(range, reverse, offset, count)
+    }
+
+    pub fn range_by_lex(&self, range: &LexRange, reverse: bool, offset: usize, count: Option<usize>) -> Vec<(&Vec<u8>, f64)> {
+        self.range(range, reverse, offset, count)
+    }
+
+    pub fn count_by_score(&self, range: &ScoreRange) -> usize {
+        let first = self.index.first_in_range(range);
+        let last = self.index.last_in_range(range);
+
+        match (first, last) {
+            (Some(first), Some(last)) => {
+                let first = &self.index.nodes[first];
+                let last = &self.index.nodes[last];
+                let first_rank = self.index.rank(first.score, &first.member).unwrap_or(0);
+                let last_rank = self.index.rank(last.score, &last.member).unwrap_or(0);
+                last_rank + 1 - first_rank
+            }
+            _ => 0,
+        }
+    }
+
+    fn range(&self, range: &impl Range, reverse: bool, offset: usize, count: Option<usize>) -> Vec<(&Vec<u8>, f64)> {
+        let first = if reverse {
+            self.index.last_in_range(range)
+        } else {
+            self.index.first_in_range(range)
+        };
+
+        self.index.walk(first, reverse)
+            .skip(offset)
+            .take_while(|(member, score)| if reverse {
+                range.is_above_min(*score, member)
+            } else {
+                range.is_below_max(*score, member)
+            })
+            .take(count.unwrap_or(usize::MAX))
+            .collect()
+    }
+}
+
+// ===== Skiplist =====
+
+const MAX_LEVEL: usize = 32;
+const HEADER: usize = 0;
+
+/// Skiplist in the style of Redis' 'zskiplist': every forward link also records its span (how many nodes it skips),
+/// which is what makes rank computations logarithmic.
+///
+/// Nodes live in an arena (a Vec indexed by usize) instead of being individually allocated and linked by pointers.
+#[derive(Clone)]
+struct SkipList {
+    nodes: Vec<Node>,
+    free: Vec<usize>,
+    tail: Option<usize>,
+    level: usize,
+    length: usize,
+    random: Random,
+}
+
+#[derive(Clone)]
+struct Node {
+    member: Vec<u8>,
+    score: f64,
+    backward: Option<usize>,
+    levels: Vec<Level>,
+}
+
+#[derive(Clone, Copy)]
+struct Level {
+    forward: Option<usize>,
+    span: usize,
+}
+
+impl SkipList {
+    fn new() -> SkipList {
+        let header = Node {
+            member: Vec::new(),
+            score: 0.0,
+            backward: None,
+            levels: vec![Level { forward: None, span: 0 }; MAX_LEVEL],
+        };
+
+        SkipList {
+            nodes: vec![header],
+            free: vec![],
+            tail: None,
+            level: 1,
+            length: 0,
+            random: Random::new(),
+        }
+    }
+
+    // each additional level is given with a probability of 1/4
+    fn random_level(&mut self) -> usize {
+        let mut level = 1;
+        while level < MAX_LEVEL && self.random.next_u64().is_multiple_of(4) {
+            level += 1;
+        }
+        level
+    }
+
+    fn forward(&self, node: usize, level: usize) -> Option<usize> {
+        self.nodes[node].levels[level].forward
+    }
+
+    // whether the node sorts before the given (score, member) pair
+    fn is_before(&self, node: usize, score: f64, member: &[u8]) -> bool {
+        let node = &self.nodes[node];
+        match node.score.partial_cmp(&score) {
+            Some(Ordering::Less) => true,
+            Some(Ordering::Equal) => node.member.as_slice() < member,
+            _ => false,
+        }
+    }
+
+    fn insert(&mut self, score: f64, member: Vec<u8>) {
+        let mut update = [HEADER; MAX_LEVEL];
+        let mut rank = [0; MAX_LEVEL];
+
+        let mut x = HEADER;
+        for i in (0..self.level).rev() {
+            rank[i] = if i == self.level - 1 { 0 } else { rank[i + 1] };
+            while let Some(next) = self.forward(x, i).filter(|next| self.is_before(*next, score, &member)) {
+                rank[i] += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            update[i] = x;
+        }
+
+        let level = self.random_level();
+        if level > self.level {
+            for i in self.level..level {
+                rank[i] = 0;
+                update[i] = HEADER;
+                self.nodes[HEADER].levels[i].span = self.length;
+            }
+            self.level = level;
+        }
+
+        let node = Node { member, score, backward: None, levels: vec![Level { forward: None, span: 0 }; level] };
+        let x = match self.free.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+
+        for i in 0..level {
+            let previous = self.nodes[update[i]].levels[i];
+            self.nodes[x].levels[i] = Level {
+                forward: previous.forward,
+                span: previous.span - (rank[0] - rank[i]),
+            };
+            self.nodes[update[i]].levels[i] = Level {
+                forward: Some(x),
+                span: (rank[0] - rank[i]) + 1,
+            };
+        }
+
+        // the levels above the new node's height now skip one more node
+        for (i, node) in update.iter().enumerate().take(self.level).skip(level) {
+            self.nodes[*node].levels[i].span += 1;
+        }
+
+        self.nodes[x].backward = if update[0] == HEADER { None } else { Some(update[0]) };
+        match self.forward(x, 0) {
+            Some(next) => self.nodes[next].backward = Some(x),
+            None => self.tail = Some(x),
+        }
+        self.length += 1;
+    }
+
+    fn delete(&mut self, score: f64, member: &[u8]) -> bool {
+        let mut update = [HEADER; MAX_LEVEL];
+
+        let mut x = HEADER;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i).filter(|next| self.is_before(*next, score, member)) {
+                x = next;
+            }
+            update[i] = x;
+        }
+
+        let x = match self.forward(x, 0) {
+            Some(x) if self.nodes[x].score == score && self.nodes[x].member == member => x,
+            _ => return false,
+        };
+
+        for (i, node) in update.iter().enumerate().take(self.level) {
+            if self.forward(*node, i) == Some(x) {
+                let removed = self.nodes[x].levels[i];
+                let level = &mut self.nodes[*node].levels[i];
+                level.span = level.span + removed.span - 1;
+                level.forward = removed.forward;
+            } else {
+                self.nodes[*node].levels[i].span -= 1;
+            }
+        }
+
+        match self.forward(x, 0) {
+            Some(next) => self.nodes[next].backward = self.nodes[x].backward,
+            None => self.tail = self.nodes[x].backward,
+        }
+
+        while self.level > 1 && self.forward(HEADER, self.level - 1).is_none() {
+            self.level -= 1;
+        }
+        self.length -= 1;
+
+        // releases the member's memory, the slot itself is kept for reuse
+        self.nodes[x].member = Vec::new();
+        self.nodes[x].levels = vec![];
+        self.free.push(x);
+        true
+    }
+
+    /// 1-based rank of the (score, member) pair, if present.
+    fn rank(&self, score: f64, member: &[u8]) -> Option<usize> {
+        let mut rank = 0;
+        let mut x = HEADER;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i)
+                .filter(|next| self.is_before(*next, score, member) || self.nodes[*next].member == member) {
+                rank += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            if x != HEADER && self.nodes[x].member == member {
+                return Some(rank);
+            }
+        }
+        None
+    }
+
+    /// Node at the given 1-based rank.
+    fn node_by_rank(&self, rank: usize) -> Option<usize> {
+        let mut traversed = 0;
+        let mut x = HEADER;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                if traversed + self.nodes[x].levels[i].span > rank {
+                    break;
+                }
+                traversed += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            if traversed == rank {
+                return if x == HEADER { None } else { Some(x) };
+            }
+        }
+        None
+    }
+
+    // quick check on whether any node can fall within the range
+    fn intersects(&self, range: &impl Range) -> bool {
+        if range.is_empty() {
+            return false;
+        }
+
+        match (self.tail, self.forward(HEADER, 0)) {
+            (Some(last), Some(first)) => {
+                let last = &self.nodes[last];
+                let first = &self.nodes[first];
+                range.is_above_min(last.score, &last.member) && range.is_below_max(first.score, &first.member)
+            }
+            _ => false,
+        }
+    }
+
+    fn first_in_range(&self, range: &impl Range) -> Option<usize> {
+        if !self.intersects(range) {
+            return None;
+        }
+
+        let mut x = HEADER;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i)
+                .filter(|next| !range.is_above_min(self.nodes[*next].score, &self.nodes[*next].member)) {
+                x = next;
+            }
+        }
+
+        // the range intersects the list, so there is a next node
+        let x = self.forward(x, 0)?;
+        if range.is_below_max(self.nodes[x].score, &self.nodes[x].member) { Some(x) } else { None }
+    }
+
+    fn last_in_range(&self, range: &impl Range) -> Option<usize> {
+        if !self.intersects(range) {
+            return None;
+        }
+
+        let mut x = HEADER;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i)
+                .filter(|next| range.is_below_max(self.nodes[*next].score, &self.nodes[*next].member)) {
+                x = next;
+            }
+        }
+
+        if x != HEADER && range.is_above_min(self.nodes[x].score, &self.nodes[x].member) { Some(x) } else { None }
+    }
+
+    /// Iterates over (member, score) pairs starting at the given node, going forward or backward.
+    fn walk(&self, start: Option<usize>, reverse: bool) -> impl Iterator<Item = (&Vec<u8>, f64)> {
+        std::iter::successors(start, move |node| if reverse {
+            self.nodes[*node].backward
+        } else {
+            self.forward(*node, 0)
+        })
+            .map(|node| (&self.nodes[node].member, self.nodes[node].score))
+    }
+}
+
+#[cfg(test)]
+mod sorted_set_tests {
+    use super::*;
+
+    fn sorted_set(entries: &[(&str, f64)]) -> SortedSet {
+        let mut set = SortedSet::new();
+        for (member, score) in entries {
+            set.insert(member.as_bytes(), *score);
+        }
+        set
+    }
+
+    fn members<'a>(entries: &[(&'a Vec<u8>, f64)]) -> Vec<&'a str> {
+        entries.iter().map(|(member, _)| std::str::from_utf8(member).unwrap()).collect()
+    }
+
+    fn score_range(min: f64, max: f64) -> ScoreRange {
+        ScoreRange { min, max, min_exclusive: false, max_exclusive: false }
+    }
+
+    #[test]
+    fn members_are_ordered_by_score_then_by_member() {
+        let set = sorted_set(&[("c", 2.0), ("b", 1.0), ("a", 2.0), ("d", -1.0)]);
+
+        assert_eq!(members(&set.range_by_rank(0, -1, false)), vec!["d", "b", "a", "c"]);
+        assert_eq!(members(&set.range_by_rank(0, -1, true)), vec!["c", "a", "b", "d"]);
+        assert_eq!(members(&set.range_by_rank(1, 2, false)), vec!["b", "a"]);
+        assert_eq!(members(&set.range_by_rank(-2, -1, true)), vec!["b", "d"]);
+    }
+
+    #[test]
+    fn updating_a_score_moves_the_member() {
+        let mut set = sorted_set(&[("a", 1.0), ("b", 2.0), ("c", 3.0)]);
+
+        assert!(!set.insert(b"a", 5.0));
+        assert_eq!(set.score(b"a"), Some(5.0));
+        assert_eq!(members(&set.range_by_rank(0, -1, false)), vec!["b", "c", "a"]);
+        assert_eq!(set.rank(b"a", false), Some(2));
+        assert_eq!(set.rank(b"a", true), Some(0));
+    }
+
+    #[test]
+    fn removing_members_keeps_ranks_consistent() {
+        let mut set = SortedSet::new();
+        for i in 0..1000 {
+            set.insert(format!("member{i:04}").as_bytes(), i as f64);
+        }
+        for i in (0..1000).step_by(2) {
+            assert!(set.remove(format!("member{i:04}").as_bytes()));
+        }
+        assert!(!set.remove(b"member0000"));
+
+        assert_eq!(set.len(), 500);
+        for i in 0..500 {
+            let member = format!("member{:04}", i * 2 + 1).into_bytes();
+            assert_eq!(set.rank(&member, false), Some(i));
+            assert_eq!(set.range_by_rank(i as i64, i as i64, false)[0].0, &member);
+        }
+    }
+
+    #[test]
+    fn range_by_score_respects_exclusive_bounds_offset_and_count() {
+        let set = sorted_set(&[("a", 1.0), ("b", 2.0), ("c", 3.0), ("d", 4.0), ("e", 5.0)]);
+
+        assert_eq!(members(&set.range_by_score(&score_range(2.0, 4.0), false, 0, None)), vec!["b", "c", "d"]);
+        let exclusive = ScoreRange { min: 2.0, max: 4.0, min_exclusive: true, max_exclusive: true };
+        assert_eq!(members(&set.range_by_score(&exclusive, false, 0, None)), vec!["c"]);
+        assert_eq!(members(&set.range_by_score(&score_range(f64::NEG_INFINITY, f64::INFINITY), true, 1, Some(2))), vec!["d", "c"]);
+        assert_eq!(members(&set.range_by_score(&score_range(6.0, 10.0), false, 0, None)), Vec::<&str>::new());
+        assert_eq!(members(&set.range_by_score(&score_range(4.0, 2.0), false, 0, None)), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn count_by_score_uses_the_ranks_of_both_ends() {
+        let set = sorted_set(&[("a", 1.0), ("b", 2.0), ("c", 3.0), ("d", 4.0), ("e", 5.0)]);
+
+        assert_eq!(set.count_by_score(&score_range(2.0, 4.0)), 3);
+        assert_eq!(set.count_by_score(&score_range(f64::NEG_INFINITY, f64::INFINITY)), 5);
+        assert_eq!(set.count_by_score(&score_range(2.5, 2.7)), 0);
+    }
+
+    #[test]
+    fn range_by_lex_compares_members() {
+        let set = sorted_set(&[("a", 0.0), ("b", 0.0), ("c", 0.0), ("d", 0.0)]);
+
+        let range = LexRange { min: LexBound::Exclusive(b"a".to_vec()), max: LexBound::Inclusive(b"c".to_vec()) };
+        assert_eq!(members(&set.range_by_lex(&range, false, 0, None)), vec!["b", "c"]);
+
+        let range = LexRange { min: LexBound::NegativeInfinity, max: LexBound::PositiveInfinity };
+        assert_eq!(members(&set.range_by_lex(&range, true, 0, Some(3))), vec!["d", "c", "b"]);
+    }
+
+    #[test]
+    fn ranks_and_ranges_match_a_naively_sorted_list_under_random_updates() {
+        let mut random = Random::with_seed(42);
+        let mut set = SortedSet::new();
+        let mut expected: Vec<(f64, Vec<u8>)> = vec![];
+
+        for _ in 0..2000 {
+            let member = format!("m{}", random.next_below(300)).into_bytes();
+            let score = random.next_below(50) as f64;
+
+            if random.next_below(3) == 0 {
+                set.remove(&member);
+                expected.retain(|(_, m)| *m != member);
+            } else {
+                set.insert(&member, score);
+                expected.retain(|(_, m)| *m != member);
+                expected.push((score, member));
+            }
+        }
+        expected.sort_by(|a, b| a.0.total_cmp(&b.0).then_with(|| a.1.cmp(&b.1)));
+
+        let actual: Vec<(f64, Vec<u8>)> = set.range_by_rank(0, -1, false).into_iter()
+            .map(|(member, score)| (score, member.clone()))
+            .collect();
+        assert_eq!(actual, expected);
+
+        for (rank, (score, member)) in expected.iter().enumerate() {
+            assert_eq!(set.rank(member, false), Some(rank));
+            assert_eq!(set.score(member), Some(*score));
+        }
+
+        let in_range = expected.iter().filter(|(score, _)| (10.0..=20.0).contains(score)).count();
+        assert_eq!(set.count_by_score(&score_range(10.0, 20.0)), in_range);
+        assert_eq!(set.range_by_score(&score_range(10.0, 20.0), false, 0, None).len(), in_range);
+    }
+}
@@ -0,0 +1,772 @@
+use crate::engine::encoding::{write_bytes, write_length, write_u64, Decoder};
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::ops::Bound::{Excluded, Unbounded};
+
+/// ID of a stream entry: the milliseconds part plus a sequence number for entries added in the same millisecond.
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Copy, Hash, Default)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId { ms: u64::MAX, seq: u64::MAX };
+
+    pub fn new(ms: u64, seq: u64) -> StreamId {
+        StreamId { ms, seq }
+    }
+
+    /// The ID right after this one, if any.
+    pub fn next(&self) -> Option<StreamId> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(StreamId::new(self.ms, seq)),
+            None => self.ms.checked_add(1).map(|ms| StreamId::new(ms, 0)),
+        }
+    }
+
+    /// The ID right before this one, if any.
+    pub fn previous(&self) -> Option<StreamId> {
+        match self.seq.checked_sub(1) {
+            Some(seq) => Some(StreamId::new(self.ms, seq)),
+            None => self.ms.checked_sub(1).map(|ms| StreamId::new(ms, u64::MAX)),
+        }
+    }
+}
+
+impl Display for StreamId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// The ID requested for a new entry in XADD
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum NewStreamId {
+    /// '*': fully generated from the current time
+    Auto,
+    /// '<ms>-*': the sequence number is generated
+    AutoSequence(u64),
+    Explicit(StreamId),
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum TrimStrategy {
+    MaxLen(usize),
+    MinId(StreamId),
+}
+
+/// Trimming requested by XADD/XTRIM: either by length or by minimum ID, optionally capping how many entries are evicted
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct StreamTrim {
+    pub strategy: TrimStrategy,
+    pub limit: Option<usize>,
+}
+
+/// Where a new consumer group starts reading from
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum GroupStartId {
+    /// '$': only entries added after the group was created
+    LastEntry,
+    Id(StreamId),
+}
+
+pub type StreamFields = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// Entries read by a consumer of a group; entries deleted since they were delivered have no fields
+pub type GroupReadEntries = Vec<(StreamId, Option<StreamFields>)>;
+
+/// Summary form of XPENDING
+#[derive(Debug, Eq, PartialEq)]
+pub struct PendingSummary {
+    pub count: usize,
+    pub ids: Option<(StreamId, StreamId)>,
+    pub consumers: Vec<(Vec<u8>, usize)>,
+}
+
+/// One entry of the extended form of XPENDING
+#[derive(Debug, Eq, PartialEq)]
+pub struct PendingEntryInfo {
+    pub id: StreamId,
+    pub consumer: Vec<u8>,
+    pub idle: u64,
+    pub delivery_count: u64,
+}
+
+/// Filters of the extended form of XPENDING
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct PendingFilter {
+    pub min_idle: Option<u64>,
+    pub start: StreamId,
+    pub end: StreamId,
+    pub count: usize,
+    pub consumer: Option<Vec<u8>>,
+}
+
+/// Options of XCLAIM
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub struct ClaimOptions {
+    pub idle: Option<u64>,
+    pub time: Option<u64>,
+    pub retry_count: Option<u64>,
+    pub force: bool,
+    pub just_id: bool,
+    pub last_id: Option<StreamId>,
+}
+
+/// Options of XAUTOCLAIM
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct AutoClaimOptions {
+    pub count: usize,
+    pub just_id: bool,
+}
+
+/// Result of XAUTOCLAIM: the cursor for the next call, the claimed entries and the IDs that no longer exist
+pub type AutoClaimResult<'a> = (StreamId, Vec<(StreamId, &'a StreamFields)>, Vec<StreamId>);
+
+#[derive(Clone)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, StreamFields>,
+    last_id: StreamId,
+    groups: BTreeMap<Vec<u8>, ConsumerGroup>,
+}
+
+#[derive(Clone)]
+struct ConsumerGroup {
+    last_delivered_id: StreamId,
+    // the "pending entries list": entries delivered to a consumer but not yet acknowledged
+    pending: BTreeMap<StreamId, PendingEntry>,
+    consumers: BTreeMap<Vec<u8>, Consumer>,
+}
+
+#[derive(Clone)]
+struct PendingEntry {
+    consumer: Vec<u8>,
+    delivery_time: u64,
+    delivery_count: u64,
+}
+
+#[derive(Clone)]
+struct Consumer {
+    seen_time: u64,
+}
+
+impl ConsumerGroup {
+    fn touch_consumer(&mut self, consumer: &[u8], now: u64) {
+        self.consumers.entry(consumer.to_vec())
+            .or_insert(Consumer { seen_time: now })
+            .seen_time = now;
+    }
+}
+
+impl Stream {
+    pub fn new() -> Stream {
+        Stream {
+            entries: BTreeMap::new(),
+            last_id: StreamId::MIN,
+            groups: BTreeMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn has_group(&self, group: &[u8]) -> bool {
+        self.groups.contains_key(group)
+    }
+
+    /// Appends a new entry, returning its ID. 'now' is the current unix time in milliseconds.
+    pub fn add(&mut self, id: NewStreamId, fields: StreamFields, now: u64) -> Result<StreamId, String> {
+        let last = self.last_id;
+        let id = match id {
+            NewStreamId::Auto => {
+                if now > last.ms {
+                    StreamId::new(now, 0)
+                } else {
+                    // the clock went backwards (or many entries in the same millisecond): keep IDs increasing
+                    last.next().ok_or_else(|| "The stream has exhausted the last possible ID, unable to add more items".to_string())?
+                }
+            }
+            NewStreamId::AutoSequence(ms) => {
+                if ms == last.ms {
+                    last.next()
+                        .filter(|id| id.ms == ms)
+                        .ok_or_else(|| "The ID specified in XADD is equal or smaller than the target stream top item".to_string())?
+                } else if ms > last.ms {
+                    StreamId::new(ms, 0)
+                } else {
+                    return Err("The ID specified in XADD is equal or smaller than the target stream top item".to_string());
+                }
+            }
+            NewStreamId::Explicit(id) => id,
+        };
+
+        if id == StreamId::MIN {
+            return Err("The ID specified in XADD must be greater than 0-0".to_string());
+        }
+        if id <= last {
+            return Err("The ID specified in XADD is equal or smaller than the target stream top item".to_string());
+        }
+
+        self.entries.insert(id, fields);
+        self.last_id = id;
+        Ok(id)
+    }
+
+    /// Evicts the oldest entries according to the trim strategy, returning how many were evicted.
+    pub fn trim(&mut self, trim: &StreamTrim) -> usize {
+        let limit = trim.limit.unwrap_or(usize::MAX);
+
+        let to_evict = match trim.strategy {
+            TrimStrategy::MaxLen(max_len) => self.entries.len().saturating_sub(max_len),
+            TrimStrategy::MinId(min_id) => self.entries.range(..min_id).count(),
+        }.min(limit);
+
+        for _ in 0..to_evict {
+            self.entries.pop_first();
+        }
+        to_evict
+    }
+
+    /// Deletes the entries with the given IDs, returning how many existed.
+    pub fn delete(&mut self, ids: &[StreamId]) -> usize {
+        ids.iter()
+            .filter(|id| self.entries.remove(id).is_some())
+            .count()
+    }
+
+    /// Entries between 'start' and 'end' (both inclusive), oldest first or newest first when 'reverse'.
+    pub fn range(&self, start: StreamId, end: StreamId, reverse: bool, count: Option<usize>) -> Vec<(StreamId, &StreamFields)> {
+        if start > end {
+            return vec![];
+        }
+
+        let range = self.entries.range(start..=end)
+            .map(|(id, fields)| (*id, fields));
+        let count = count.unwrap_or(usize::MAX);
+
+        if reverse {
+            range.rev().take(count).collect()
+        } else {
+            range.take(count).collect()
+        }
+    }
+
+    pub fn create_group(&mut self, group: &[u8], start: GroupStartId) -> Result<(), String> {
+        if self.groups.contains_key(group) {
+            return Err("BUSYGROUP Consumer Group name already exists".to_string());
+        }
+
+        let last_delivered_id = match start {
+            GroupStartId::LastEntry => self.last_id,
+            GroupStartId::Id(id) => id,
+        };
+        self.groups.insert(group.to_vec(), ConsumerGroup {
+            last_delivered_id,
+            pending: BTreeMap::new(),
+            consumers: BTreeMap::new(),
+        });
+        Ok(())
+    }
+
+    pub fn destroy_group(&mut self, group: &[u8]) -> bool {
+        self.groups.remove(group).is_some()
+    }
+
+    pub fn set_group_id(&mut self, group: &[u8], start: GroupStartId) {
+        let last_id = self.last_id;
+        if let Some(group) = self.groups.get_mut(group) {
+            group.last_delivered_id = match start {
+                GroupStartId::LastEntry => last_id,
+                GroupStartId::Id(id) => id,
+            };
+        }
+    }
+
+    /// Creates the consumer in the group, returning whether it didn't exist yet.
+    pub fn create_consumer(&mut self, group: &[u8], consumer: &[u8], now: u64) -> bool {
+        match self.groups.get_mut(group) {
+            Some(group) if !group.consumers.contains_key(consumer) => {
+                group.touch_consumer(consumer, now);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Sets when the consumer was last seen, as a unix time in milliseconds.
+    pub fn set_consumer_seen_time(&mut self, group: &[u8], consumer: &[u8], seen_time: u64) {
+        if let Some(consumer) = self.groups.get_mut(group).and_then(|group| group.consumers.get_mut(consumer)) {
+            consumer.seen_time = seen_time;
+        }
+    }
+
+    /// Deletes the consumer (and its pending entries) from the group, returning how many entries were pending.
+    pub fn delete_consumer(&mut self, group: &[u8], consumer: &[u8]) -> usize {
+        let Some(group) = self.groups.get_mut(group) else {
+            return 0;
+        };
+
+        if group.consumers.remove(consumer).is_none() {
+            return 0;
+        }
+        let pending_before = group.pending.len();
+        group.pending.retain(|_, entry| entry.consumer != consumer);
+        pending_before - group.pending.len()
+    }
+
+    /// Reads entries on behalf of a consumer of the group:
+    /// - with 'id' as None ('>'), reads entries never delivered to the group, adding them to the pending entries
+    ///   (unless 'no_ack')
+    /// - otherwise, re-reads the consumer's own pending entries with an ID greater than 'id'; entries that were
+    ///   deleted in the meantime have no fields
+    pub fn read_group(&mut self, group: &[u8], consumer: &[u8], id: Option<StreamId>, count: Option<usize>, no_ack: bool, now: u64) -> GroupReadEntries {
+        let Some(group) = self.groups.get_mut(group) else {
+            return vec![];
+        };
+        group.touch_consumer(consumer, now);
+        let count = count.unwrap_or(usize::MAX);
+
+        match id {
+            None => {
+                let entries: GroupReadEntries = self.entries
+                    .range((Excluded(group.last_delivered_id), Unbounded))
+                    .take(count)
+                    .map(|(id, fields)| (*id, Some(fields.clone())))
+                    .collect();
+
+                for (id, _) in &entries {
+                    group.last_delivered_id = *id;
+                    if !no_ack {
+                        group.pending.insert(*id, PendingEntry {
+                            consumer: consumer.to_vec(),
+                            delivery_time: now,
+                            delivery_count: 1,
+                        });
+                    }
+                }
+                entries
+            }
+            Some(id) => {
+                group.pending.range_mut((Excluded(id), Unbounded))
+                    .filter(|(_, entry)| entry.consumer == consumer)
+                    .take(count)
+                    .map(|(id, entry)| {
+                        entry.delivery_time = now;
+                        entry.delivery_count += 1;
+                        (*id, self.entries.get(id).cloned())
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    /// Acknowledges the entries, removing them from the group's pending entries. Returns how many were pending.
+    pub fn ack(&mut self, group: &[u8], ids: &[StreamId]) -> usize {
+        match self.groups.get_mut(group) {
+            None => 0,
+            Some(group) => ids.iter()
+                .filter(|id| group.pending.remove(id).is_some())
+                .count(),
+        }
+    }
+
+    pub fn pending_summary(&self, group: &[u8]) -> PendingSummary {
+        let Some(group) = self.groups.get(group) else {
+            return PendingSummary { count: 0, ids: None, consumers: vec![] };
+        };
+
+        let mut consumers: BTreeMap<&Vec<u8>, usize> = BTreeMap::new();
+        for entry in group.pending.values() {
+            *consumers.entry(&entry.consumer).or_default() += 1;
+        }
+
+        PendingSummary {
+            count: group.pending.len(),
+            ids: group.pending.keys().next().copied().zip(group.pending.keys().next_back().copied()),
+            consumers: consumers.into_iter().map(|(consumer, count)| (consumer.clone(), count)).collect(),
+        }
+    }
+
+    pub fn pending(&self, group: &[u8], filter: &PendingFilter, now: u64) -> Vec<PendingEntryInfo> {
+        let Some(group) = self.groups.get(group) else {
+            return vec![];
+        };
+        if filter.start > filter.end {
+            return vec![];
+        }
+
+        group.pending.range(filter.start..=filter.end)
+            .map(|(id, entry)| PendingEntryInfo {
+                id: *id,
+                consumer: entry.consumer.clone(),
+                idle: now.saturating_sub(entry.delivery_time),
+                delivery_count: entry.delivery_count,
+            })
+            .filter(|info| filter.min_idle.is_none_or(|min_idle| info.idle >= min_idle))
+            .filter(|info| filter.consumer.as_ref().is_none_or(|consumer| info.consumer == *consumer))
+            .take(filter.count)
+            .collect()
+    }
+
+    /// Transfers the ownership of pending entries idle for at least 'min_idle' milliseconds to 'consumer'.
+    ///
+    /// Entries that no longer exist in the stream are removed from the pending entries and not returned.
+    pub fn claim(&mut self, group: &[u8], consumer: &[u8], min_idle: u64, ids: &[StreamId], options: &ClaimOptions, now: u64) -> Vec<(StreamId, &StreamFields)> {
+        let Some(group) = self.groups.get_mut(group) else {
+            return vec![];
+        };
+        group.touch_consumer(consumer, now);
+
+        if let Some(last_id) = options.last_id {
+            if last_id > group.last_delivered_id {
+                group.last_delivered_id = last_id;
+            }
+        }
+
+        let delivery_time = match (options.idle, options.time) {
+            (Some(idle), _) => now.saturating_sub(idle),
+            (None, Some(time)) => time,
+            (None, None) => now,
+        };
+
+        let mut claimed = vec![];
+        for id in ids {
+            if !self.entries.contains_key(id) {
+                group.pending.remove(id);
+                continue;
+            }
+
+            let entry = match group.pending.get_mut(id) {
+                Some(entry) => entry,
+                None if options.force => group.pending.entry(*id).or_insert(PendingEntry {
+                    consumer: consumer.to_vec(),
+                    delivery_time: now,
+                    delivery_count: 0,
+                }),
+                None => continue,
+            };
+
+            if now.saturating_sub(entry.delivery_time) < min_idle {
+                continue;
+            }
+
+            entry.consumer = consumer.to_vec();
+            entry.delivery_time = delivery_time;
+            match options.retry_count {
+                Some(retry_count) => entry.delivery_count = retry_count,
+                None if !options.just_id => entry.delivery_count += 1,
+                None => {}
+            }
+            claimed.push(*id);
+        }
+
+        claimed.into_iter()
+            .filter_map(|id| self.entries.get(&id).map(|fields| (id, fields)))
+            .collect()
+    }
+
+    /// Scans up to 'options.count' pending entries starting at 'start', claiming the ones idle for at least 'min_idle'.
+    pub fn auto_claim(&mut self, group: &[u8], consumer: &[u8], min_idle: u64, start: StreamId, options: &AutoClaimOptions, now: u64) -> AutoClaimResult<'_> {
+        let Some(group) = self.groups.get_mut(group) else {
+            return (StreamId::MIN, vec![], vec![]);
+        };
+        group.touch_consumer(consumer, now);
+
+        let scanned: Vec<StreamId> = group.pending.range(start..)
+            .take(options.count)
+            .map(|(id, _)| *id)
+            .collect();
+        let cursor = scanned.last()
+            .and_then(|last| group.pending.range((Excluded(*last), Unbounded)).next())
+            .map_or(StreamId::MIN, |(id, _)| *id);
+
+        let mut claimed = vec![];
+        let mut deleted = vec![];
+        for id in scanned {
+            if !self.entries.contains_key(&id) {
+                group.pending.remove(&id);
+                deleted.push(id);
+                continue;
+            }
+
+            if let Some(entry) = group.pending.get_mut(&id) {
+                if now.saturating_sub(entry.delivery_time) >= min_idle {
+                    entry.consumer = consumer.to_vec();
+                    entry.delivery_time = now;
+                    if !options.just_id {
+                        entry.delivery_count += 1;
+                    }
+                    claimed.push(id);
+                }
+            }
+        }
+
+        let claimed = claimed.into_iter()
+            .filter_map(|id| self.entries.get(&id).map(|fields| (id, fields)))
+            .collect();
+        (cursor, claimed, deleted)
+    }
+
+    /// The commands that rebuild the stream under 'key', for rewriting the append only file: the entries, the last
+    /// ID (which is past the last entry when the newest entries were deleted), and the consumer groups with their
+    /// pending entries and consumers. The consumers come last, as claiming an entry counts as seeing its consumer.
+    ///
+    /// Pending entries that were deleted from the stream cannot be claimed again, so they are left out. An empty
+    /// stream that never had an entry nor a group (only left by XGROUP DESTROY) cannot be created by any command.
+    pub fn rewrite_commands(&self, key: &[u8]) -> Vec<Vec<Vec<u8>>> {
+        let command = |parts: &[&[u8]]| parts.iter().map(|part| part.to_vec()).collect::<Vec<_>>();
+        let mut commands = vec![];
+
+        for (id, fields) in &self.entries {
+            let mut xadd = command(&[b"XADD", key, id.to_string().as_bytes()]);
+            xadd.extend(fields.iter().flat_map(|(field, value)| [field.clone(), value.clone()]));
+            commands.push(xadd);
+        }
+        if self.last_id != StreamId::MIN && self.entries.last_key_value().is_none_or(|(id, _)| *id < self.last_id) {
+            let last_id = self.last_id.to_string();
+            commands.push(command(&[b"XADD", key, last_id.as_bytes(), b"x", b"y"]));
+            commands.push(command(&[b"XDEL", key, last_id.as_bytes()]));
+        }
+
+        for (name, group) in &self.groups {
+            commands.push(command(&[b"XGROUP", b"CREATE", key, name, group.last_delivered_id.to_string().as_bytes(), b"MKSTREAM"]));
+            for (id, pending) in group.pending.iter().filter(|(id, _)| self.entries.contains_key(id)) {
+                commands.push(command(&[
+                    b"XCLAIM", key, name, &pending.consumer, b"0", id.to_string().as_bytes(),
+                    b"TIME", pending.delivery_time.to_string().as_bytes(),
+                    b"RETRYCOUNT", pending.delivery_count.to_string().as_bytes(), b"FORCE", b"JUSTID",
+                ]));
+            }
+            for (consumer, Consumer { seen_time }) in &group.consumers {
+                commands.push(command(&[b"XGROUP", b"CREATECONSUMER", key, name, consumer, b"SEENTIME", seen_time.to_string().as_bytes()]));
+            }
+        }
+        commands
+    }
+
+    /// Writes the stream in the binary form of snapshots, everything included.
+    pub(super) fn encode(&self, out: &mut Vec<u8>) {
+        write_length(out, self.entries.len());
+        for (id, fields) in &self.entries {
+            write_stream_id(out, *id);
+            write_length(out, fields.len());
+            for (field, value) in fields {
+                write_bytes(out, field);
+                write_bytes(out, value);
+            }
+        }
+        write_stream_id(out, self.last_id);
+
+        write_length(out, self.groups.len());
+        for (name, group) in &self.groups {
+            write_bytes(out, name);
+            write_stream_id(out, group.last_delivered_id);
+            write_length(out, group.pending.len());
+            for (id, pending) in &group.pending {
+                write_stream_id(out, *id);
+                write_bytes(out, &pending.consumer);
+                write_u64(out, pending.delivery_time);
+                write_u64(out, pending.delivery_count);
+            }
+            write_length(out, group.consumers.len());
+            for (name, consumer) in &group.consumers {
+                write_bytes(out, name);
+                write_u64(out, consumer.seen_time);
+            }
+        }
+    }
+
+    pub(super) fn decode(input: &mut Decoder) -> Result<Stream, String> {
+        let mut entries = BTreeMap::new();
+        for _ in 0..input.read_count()? {
+            let id = read_stream_id(input)?;
+            let fields = (0..input.read_count()?)
+                .map(|_| Ok((input.read_bytes()?, input.read_bytes()?)))
+                .collect::<Result<_, String>>()?;
+            entries.insert(id, fields);
+        }
+        let last_id = read_stream_id(input)?;
+
+        let mut groups = BTreeMap::new();
+        for _ in 0..input.read_count()? {
+            let name = input.read_bytes()?;
+            let last_delivered_id = read_stream_id(input)?;
+            let mut pending = BTreeMap::new();
+            for _ in 0..input.read_count()? {
+                let id = read_stream_id(input)?;
+                let consumer = input.read_bytes()?;
+                pending.insert(id, PendingEntry { consumer, delivery_time: input.read_u64()?, delivery_count: input.read_u64()? });
+            }
+            let mut consumers = BTreeMap::new();
+            for _ in 0..input.read_count()? {
+                consumers.insert(input.read_bytes()?, Consumer { seen_time: input.read_u64()? });
+            }
+            groups.insert(name, ConsumerGroup { last_delivered_id, pending, consumers });
+        }
+        Ok(Stream { entries, last_id, groups })
+    }
+}
+
+fn write_stream_id(out: &mut Vec<u8>, id: StreamId) {
+    write_u64(out, id.ms);
+    write_u64(out, id.seq);
+}
+
+fn read_stream_id(input: &mut Decoder) -> Result<StreamId, String> {
+    Ok(StreamId::new(input.read_u64()?, input.read_u64()?))
+}
+
+#[cfg(test)]
+mod stream_tests {
+    use super::*;
+    use crate::command::Command;
+    use crate::engine::StorageEngine;
+    use crate::protocol::RespObject::{Array, BulkString};
+    use mock_instant::thread_local::MockClock;
+    use std::time::Duration;
+
+    fn fields(values: &[(&str, &str)]) -> StreamFields {
+        values.iter().map(|(field, value)| (field.as_bytes().to_vec(), value.as_bytes().to_vec())).collect()
+    }
+
+    fn ids(entries: &[(StreamId, &StreamFields)]) -> Vec<StreamId> {
+        entries.iter().map(|(id, _)| *id).collect()
+    }
+
+    #[test]
+    fn auto_generated_ids_always_increase() {
+        let mut stream = Stream::new();
+
+        assert_eq!(stream.add(NewStreamId::Auto, fields(&[("a", "1")]), 100), Ok(StreamId::new(100, 0)));
+        assert_eq!(stream.add(NewStreamId::Auto, fields(&[("a", "2")]), 100), Ok(StreamId::new(100, 1)));
+        // even if the clock goes backwards
+        assert_eq!(stream.add(NewStreamId::Auto, fields(&[("a", "3")]), 50), Ok(StreamId::new(100, 2)));
+        assert_eq!(stream.add(NewStreamId::AutoSequence(100), fields(&[("a", "4")]), 0), Ok(StreamId::new(100, 3)));
+        assert_eq!(stream.add(NewStreamId::AutoSequence(200), fields(&[("a", "5")]), 0), Ok(StreamId::new(200, 0)));
+        assert_eq!(stream.len(), 5);
+    }
+
+    #[test]
+    fn explicit_ids_must_be_greater_than_the_last_one() {
+        let mut stream = Stream::new();
+
+        assert!(stream.add(NewStreamId::Explicit(StreamId::MIN), fields(&[("a", "1")]), 0).is_err());
+        assert_eq!(stream.add(NewStreamId::Explicit(StreamId::new(5, 5)), fields(&[("a", "1")]), 0), Ok(StreamId::new(5, 5)));
+        assert!(stream.add(NewStreamId::Explicit(StreamId::new(5, 5)), fields(&[("a", "1")]), 0).is_err());
+        assert!(stream.add(NewStreamId::AutoSequence(4), fields(&[("a", "1")]), 0).is_err());
+    }
+
+    #[test]
+    fn trim_by_length_or_min_id_evicts_the_oldest_entries() {
+        let mut stream = Stream::new();
+        for ms in 1..=10 {
+            stream.add(NewStreamId::Explicit(StreamId::new(ms, 0)), fields(&[("a", "1")]), 0).unwrap();
+        }
+
+        assert_eq!(stream.trim(&StreamTrim { strategy: TrimStrategy::MaxLen(8), limit: None }), 2);
+        assert_eq!(stream.trim(&StreamTrim { strategy: TrimStrategy::MinId(StreamId::new(6, 0)), limit: Some(2) }), 2);
+        assert_eq!(ids(&stream.range(StreamId::MIN, StreamId::MAX, false, Some(1))), vec![StreamId::new(5, 0)]);
+        assert_eq!(stream.len(), 6);
+    }
+
+    #[test]
+    fn consumer_groups_track_pending_entries_until_acknowledged() {
+        let mut stream = Stream::new();
+        for ms in 1..=3 {
+            stream.add(NewStreamId::Explicit(StreamId::new(ms, 0)), fields(&[("a", "1")]), 0).unwrap();
+        }
+        stream.create_group(b"group", GroupStartId::Id(StreamId::MIN)).unwrap();
+        assert!(stream.create_group(b"group", GroupStartId::LastEntry).is_err());
+
+        let read = stream.read_group(b"group", b"alice", None, Some(2), false, 1000);
+        assert_eq!(read.len(), 2);
+        let read = stream.read_group(b"group", b"bob", None, None, false, 1000);
+        assert_eq!(read.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![StreamId::new(3, 0)]);
+
+        let summary = stream.pending_summary(b"group");
+        assert_eq!(summary.count, 3);
+        assert_eq!(summary.ids, Some((StreamId::new(1, 0), StreamId::new(3, 0))));
+        assert_eq!(summary.consumers, vec![(b"alice".to_vec(), 2), (b"bob".to_vec(), 1)]);
+
+        assert_eq!(stream.ack(b"group", &[StreamId::new(1, 0), StreamId::new(9, 0)]), 1);
+
+        // reading the history only returns the consumer's own pending entries
+        let history = stream.read_group(b"group", b"alice", Some(StreamId::MIN), None, false, 2000);
+        assert_eq!(history.iter().map(|(id, _)| *id).collect::<Vec<_>>(), vec![StreamId::new(2, 0)]);
+    }
+
+    #[test]
+    fn claim_transfers_idle_entries_and_drops_deleted_ones() {
+        let mut stream = Stream::new();
+        for ms in 1..=3 {
+            stream.add(NewStreamId::Explicit(StreamId::new(ms, 0)), fields(&[("a", "1")]), 0).unwrap();
+        }
+        stream.create_group(b"group", GroupStartId::Id(StreamId::MIN)).unwrap();
+        stream.read_group(b"group", b"alice", None, None, false, 1000);
+        stream.delete(&[StreamId::new(3, 0)]);
+
+        let all = [StreamId::new(1, 0), StreamId::new(2, 0), StreamId::new(3, 0)];
+
+        // not idle for long enough yet
+        assert!(stream.claim(b"group", b"bob", 500, &all, &ClaimOptions::default(), 1200).is_empty());
+
+        let claimed = stream.claim(b"group", b"bob", 500, &all, &ClaimOptions::default(), 2000);
+        assert_eq!(ids(&claimed), vec![StreamId::new(1, 0), StreamId::new(2, 0)]);
+
+        let filter = PendingFilter { min_idle: None, start: StreamId::MIN, end: StreamId::MAX, count: 10, consumer: None };
+        let pending = stream.pending(b"group", &filter, 2500);
+        assert_eq!(pending.len(), 2);
+        assert!(pending.iter().all(|info| info.consumer == b"bob" && info.idle == 500 && info.delivery_count == 2));
+    }
+
+    #[test]
+    fn consumers_keep_when_they_were_last_seen_across_snapshots_and_rewrites() {
+        let mut stream = Stream::new();
+        stream.add(NewStreamId::Explicit(StreamId::new(1, 0)), fields(&[("a", "1")]), 0).unwrap();
+        stream.create_group(b"group", GroupStartId::Id(StreamId::MIN)).unwrap();
+        stream.read_group(b"group", b"alice", None, None, false, 1000);
+        stream.create_consumer(b"group", b"bob", 2000);
+        let seen_times = |stream: &Stream| stream.groups[b"group".as_slice()].consumers.iter()
+            .map(|(name, consumer)| (name.clone(), consumer.seen_time))
+            .collect::<Vec<_>>();
+        let expected = vec![(b"alice".to_vec(), 1000), (b"bob".to_vec(), 2000)];
+
+        let mut out = vec![];
+        stream.encode(&mut out);
+        assert_eq!(seen_times(&Stream::decode(&mut Decoder::new(&out)).unwrap()), expected);
+
+        // replayed later on, when claiming alice's pending entry counts as seeing her
+        MockClock::set_system_time(Duration::from_millis(5000));
+        let mut engine = StorageEngine::new();
+        for command in stream.rewrite_commands(b"events") {
+            let request = Array(command.into_iter().map(BulkString).collect());
+            Command::from(request).unwrap().execute_on(&mut engine);
+        }
+        assert_eq!(seen_times(engine.get_stream(b"events").unwrap().unwrap()), expected);
+    }
+
+    #[test]
+    fn auto_claim_returns_a_cursor_and_the_deleted_ids() {
+        let mut stream = Stream::new();
+        for ms in 1..=4 {
+            stream.add(NewStreamId::Explicit(StreamId::new(ms, 0)), fields(&[("a", "1")]), 0).unwrap();
+        }
+        stream.create_group(b"group", GroupStartId::Id(StreamId::MIN)).unwrap();
+        stream.read_group(b"group", b"alice", None, None, false, 0);
+        stream.delete(&[StreamId::new(2, 0)]);
+        let options = AutoClaimOptions { count: 2, just_id: false };
+
+        let (cursor, claimed, deleted) = stream.auto_claim(b"group", b"bob", 0, StreamId::MIN, &options, 10);
+        assert_eq!(cursor, StreamId::new(3, 0));
+        assert_eq!(ids(&claimed), vec![StreamId::new(1, 0)]);
+        assert_eq!(deleted, vec![StreamId::new(2, 0)]);
+
+        let (cursor, claimed, _) = stream.auto_claim(b"group", b"bob", 0, cursor, &options, 10);
+        assert_eq!(cursor, StreamId::MIN);
+        assert_eq!(ids(&claimed), vec![StreamId::new(3, 0), StreamId::new(4, 0)]);
+    }
+}
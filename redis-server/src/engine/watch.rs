@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+
+/// The keys of a database that clients WATCH, each with a version that changes whenever the key is modified.
+///
+/// Only watched keys are tracked, so that writes to all the other keys cost a single lookup. A key stays here for as
+/// long as some client watches it, even while it does not exist, so that creating it also counts as a modification.
+pub struct WatchedKeys {
+    keys: HashMap<Vec<u8>, WatchedKey>,
+}
+
+struct WatchedKey {
+    version: u64,
+    // how many clients watch the key
+    watchers: usize,
+}
+
+impl WatchedKeys {
+    pub fn new() -> WatchedKeys {
+        WatchedKeys { keys: HashMap::new() }
+    }
+
+    /// Starts watching the key, returning its current version.
+    pub fn watch(&mut self, key: &[u8]) -> u64 {
+        let watched = self.keys.entry(key.to_vec()).or_insert(WatchedKey { version: 0, watchers: 0 });
+        watched.watchers += 1;
+        watched.version
+    }
+
+    /// Stops watching the key, on behalf of one of the clients that watch it.
+    pub fn unwatch(&mut self, key: &[u8]) {
+        if let Some(watched) = self.keys.get_mut(key) {
+            watched.watchers -= 1;
+            if watched.watchers == 0 {
+                self.keys.remove(key);
+            }
+        }
+    }
+
+    /// The current version of the key, or 'None' when nobody watches it.
+    pub fn version(&self, key: &[u8]) -> Option<u64> {
+        self.keys.get(key).map(|watched| watched.version)
+    }
+
+    pub fn modified(&mut self, key: &[u8]) {
+        if let Some(watched) = self.keys.get_mut(key) {
+            watched.version += 1;
+        }
+    }
+
+    /// Counts as a modification of every watched key, for operations that replace a whole database.
+    pub fn all_modified(&mut self) {
+        for watched in self.keys.values_mut() {
+            watched.version += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod watched_keys_tests {
+    use super::*;
+
+    #[test]
+    fn tracks_versions_while_watched() {
+        let mut watched = WatchedKeys::new();
+        watched.modified(b"a");
+        assert_eq!(watched.version(b"a"), None);
+
+        let version = watched.watch(b"a");
+        assert_eq!(watched.watch(b"a"), version);
+        watched.modified(b"a");
+        assert_ne!(watched.version(b"a"), Some(version));
+
+        // the key is tracked until its last watcher is gone
+        watched.unwatch(b"a");
+        assert!(watched.version(b"a").is_some());
+        watched.unwatch(b"a");
+        assert_eq!(watched.version(b"a"), None);
+    }
+}
@@ -0,0 +1,146 @@
+/// Whether 'string' matches the glob-style 'pattern', following the same rules as Redis (used by KEYS, SCAN,
+/// CONFIG GET and pattern subscriptions):
+/// - '*' matches any sequence of bytes, including an empty one
+/// - '?' matches any single byte
+/// - '[abc]' matches one of the bytes in the brackets, '[^abc]' any byte but those, and '[a-z]' a range
+/// - '\' escapes the byte that follows it
+pub fn glob_match(pattern: &[u8], string: &[u8], nocase: bool) -> bool {
+    let (mut p, mut s) = (0, 0);
+    // where to resume after the last '*' when what follows it stops matching: the pattern right after the '*',
+    // and the position in the string up to which the '*' swallows bytes
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while s < string.len() {
+        let next = match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p + 1, s));
+                p += 1;
+                continue;
+            }
+            Some(b'?') => Some(p + 1),
+            Some(b'[') => {
+                let (matched, end) = match_class(pattern, p + 1, string[s], nocase);
+                matched.then_some(end)
+            }
+            Some(b'\\') if p + 1 < pattern.len() => bytes_equal(pattern[p + 1], string[s], nocase).then_some(p + 2),
+            Some(&byte) => bytes_equal(byte, string[s], nocase).then_some(p + 1),
+            None => None,
+        };
+
+        match (next, backtrack) {
+            (Some(next), _) => {
+                p = next;
+                s += 1;
+            }
+            // the last '*' swallows one more byte, and matching resumes after it
+            (None, Some((after_star, swallowed))) => {
+                backtrack = Some((after_star, swallowed + 1));
+                p = after_star;
+                s = swallowed + 1;
+            }
+            (None, None) => return false,
+        }
+    }
+
+    // an exhausted string only matches when all that is left of the pattern are stars
+    pattern[p..].iter().all(|byte| *byte == b'*')
+}
+
+// matches a byte against the class that starts at 'start' (right after the '['), returning whether it matched
+// and where the pattern continues after the class; like Redis, an unclosed class ends with the pattern
+fn match_class(pattern: &[u8], start: usize, byte: u8, nocase: bool) -> (bool, usize) {
+    let mut i = start;
+    let negated = pattern.get(i) == Some(&b'^');
+    if negated {
+        i += 1;
+    }
+
+    let mut matched = false;
+    loop {
+        match pattern.get(i) {
+            None => break,
+            Some(b']') => {
+                i += 1;
+                break;
+            }
+            Some(b'\\') if i + 1 < pattern.len() => {
+                matched |= bytes_equal(pattern[i + 1], byte, nocase);
+                i += 2;
+            }
+            Some(&from) if pattern.get(i + 1) == Some(&b'-') && i + 2 < pattern.len() => {
+                let to = pattern[i + 2];
+                let (from, to, byte) = if nocase {
+                    (from.to_ascii_lowercase(), to.to_ascii_lowercase(), byte.to_ascii_lowercase())
+                } else {
+                    (from, to, byte)
+                };
+                // reversed ranges like 'z-a' are accepted too
+                matched |= (from.min(to)..=from.max(to)).contains(&byte);
+                i += 3;
+            }
+            Some(&other) => {
+                matched |= bytes_equal(other, byte, nocase);
+                i += 1;
+            }
+        }
+    }
+
+    (matched != negated, i)
+}
+
+fn bytes_equal(a: u8, b: u8, nocase: bool) -> bool {
+    if nocase { a.eq_ignore_ascii_case(&b) } else { a == b }
+}
+
+#[cfg(test)]
+mod glob_tests {
+    use super::*;
+
+    fn matches(pattern: &str, string: &str) -> bool {
+        glob_match(pattern.as_bytes(), string.as_bytes(), false)
+    }
+
+    #[test]
+    fn matches_literals_and_wildcards() {
+        assert!(matches("hello", "hello"));
+        assert!(!matches("hello", "hell"));
+        assert!(matches("h?llo", "hallo"));
+        assert!(!matches("h?llo", "hllo"));
+        assert!(matches("h*llo", "hllo"));
+        assert!(matches("h*llo", "heeeello"));
+        assert!(matches("*", ""));
+        assert!(matches("user:*:session", "user:42:session"));
+        assert!(!matches("user:*:session", "user:42:sessions"));
+        assert!(matches("*a*b*c", "xxaxxbxxbxxc"));
+        assert!(!matches("*a*b*c", "xxaxxbxxbxxcx"));
+    }
+
+    #[test]
+    fn matches_classes() {
+        assert!(matches("h[ae]llo", "hello"));
+        assert!(!matches("h[ae]llo", "hillo"));
+        assert!(matches("h[^e]llo", "hallo"));
+        assert!(!matches("h[^e]llo", "hello"));
+        assert!(matches("h[a-b]llo", "hbllo"));
+        assert!(matches("h[b-a]llo", "hallo"));
+        assert!(!matches("h[a-b]llo", "hcllo"));
+        assert!(matches("[\\]]", "]"));
+        // an unclosed class ends with the pattern
+        assert!(matches("a[bc", "ac"));
+    }
+
+    #[test]
+    fn matches_escapes() {
+        assert!(matches("h\\*llo", "h*llo"));
+        assert!(!matches("h\\*llo", "hello"));
+        assert!(matches("what\\?", "what?"));
+        assert!(matches("trailing\\", "trailing\\"));
+    }
+
+    #[test]
+    fn matches_ignoring_case() {
+        assert!(glob_match(b"HZ", b"hz", true));
+        assert!(glob_match(b"active-*-[E-F]ffort", b"active-expire-effort", true));
+        assert!(!glob_match(b"HZ", b"hz", false));
+    }
+}
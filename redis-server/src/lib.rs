@@ -1,3 +1,9 @@
 pub mod protocol;
 pub mod engine;
-pub mod command;
\ No newline at end of file
+pub mod command;
+pub mod session;
+pub mod glob;
+pub mod pubsub;
+pub mod blocking;
+pub mod aof;
+pub mod rdb;
@@ -1,12 +1,25 @@
+use coding_challenge_redis_adorow::aof::{self, AppendOnlyFile, FsyncPolicy};
 use coding_challenge_redis_adorow::command::Command;
-use coding_challenge_redis_adorow::engine::StorageEngine;
-use coding_challenge_redis_adorow::protocol::RespObject;
+use coding_challenge_redis_adorow::engine::{StorageEngine, DEFAULT_DATABASES};
+use coding_challenge_redis_adorow::protocol::{RespDecoder, RespObject};
+use coding_challenge_redis_adorow::rdb::{self, SaveRules};
+use coding_challenge_redis_adorow::session::Session;
 
-use std::io::{BufRead, BufReader, Write};
+use std::io::{ErrorKind, Read, Write};
 use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::mpsc::Receiver;
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Duration;
 
+// how much is read from a connection at once; frames larger than this are simply assembled over several reads
+const READ_CHUNK_SIZE: usize = 16 * 1024;
+// how often the connection of a blocked client is checked for having been closed
+const BLOCKED_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+// how often the files are checked for a pending flush to disk, a finished rewrite or save, or a save rule that is due
+const PERSISTENCE_CRON_INTERVAL: Duration = Duration::from_millis(100);
+const DEFAULT_AOF_FILENAME: &str = "appendonly.aof";
 
 // TODO: at the end, should remove the println! for better performance
 
@@ -16,10 +29,38 @@ fn main() -> std::io::Result<()> {
     let mut children = Vec::new();
 
     // todo: need to study more of what can be done with Rust, to make this simpler and more efficient, we're currently locking the whole "storage", but maybe we could get around that
-    let engine = Arc::new(Mutex::new(StorageEngine::new()));
+    let mut engine = StorageEngine::with_databases(databases_argument()?);
+    let aof_enabled = append_only_argument()?;
+    if aof_enabled {
+        // the data is rebuilt before any client gets to see it, and only then are new writes logged
+        let path = PathBuf::from(argument("--appendfilename").unwrap_or_else(|| DEFAULT_AOF_FILENAME.to_string()));
+        let commands = aof::replay(&path, &mut engine)?;
+        println!("Loaded {commands} commands from the append only file {}", path.display());
+        engine.set_aof(Some(AppendOnlyFile::open(path, fsync_argument()?)?));
+    }
+    if let Some(path) = argument("--dbfilename") {
+        engine.rdb_mut().set_path(path);
+    }
+    engine.rdb_mut().set_rules(save_argument()?);
+    if !aof_enabled {
+        // like Redis, the append only file is what is loaded when enabled, as it is the more recent of the two
+        let path = engine.rdb().path().to_path_buf();
+        let keys = rdb::load(&path, &mut engine)?;
+        println!("Loaded {keys} keys from the RDB file {}", path.display());
+    }
+    // what was loaded is already on disk, and does not count towards the save rules
+    let dirty = engine.dirty();
+    engine.rdb_mut().mark_as_saved(dirty);
+    let engine = Arc::new(Mutex::new(engine));
 
     // engine.execute(&Get { key: "a".to_string()});
 
+    let expire_engine = engine.clone();
+    thread::spawn(move || active_expire_loop(expire_engine));
+
+    let persistence_engine = engine.clone();
+    thread::spawn(move || persistence_loop(persistence_engine));
+
     // todo: maybe there's a better handling for the errors here
     // accept connections and process them serially
     // TODO: how to make this happen in parallel in Rust?
@@ -43,15 +84,122 @@ fn main() -> std::io::Result<()> {
     Ok(())
 }
 
+// the value given to an argument, e.g. '--databases 4' (the arguments are named after the directives of redis.conf)
+fn argument(name: &str) -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    args.find(|arg| arg == name)?;
+    Some(args.next().unwrap_or_default())
+}
+
+fn invalid_argument(message: &str) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidInput, message)
+}
+
+// the number of databases, from '--databases <count>'
+fn databases_argument() -> std::io::Result<usize> {
+    match argument("--databases") {
+        None => Ok(DEFAULT_DATABASES),
+        Some(count) => count.parse::<usize>().ok()
+            .filter(|count| *count > 0)
+            .ok_or_else(|| invalid_argument("--databases expects a positive number")),
+    }
+}
+
+// whether writes are logged to the append only file, from '--appendonly yes|no'
+fn append_only_argument() -> std::io::Result<bool> {
+    match argument("--appendonly").as_deref() {
+        None | Some("no") => Ok(false),
+        Some("yes") => Ok(true),
+        Some(_) => Err(invalid_argument("--appendonly expects yes or no")),
+    }
+}
+
+// when the append only file is flushed to disk, from '--appendfsync always|everysec|no'
+fn fsync_argument() -> std::io::Result<FsyncPolicy> {
+    match argument("--appendfsync") {
+        None => Ok(FsyncPolicy::default()),
+        Some(policy) => FsyncPolicy::parse(&policy)
+            .ok_or_else(|| invalid_argument("--appendfsync expects always, everysec or no")),
+    }
+}
+
+// when the data is saved on its own, from '--save "<seconds> <changes> ..."' (an empty string meaning never)
+fn save_argument() -> std::io::Result<SaveRules> {
+    match argument("--save") {
+        None => Ok(SaveRules::default()),
+        Some(rules) => SaveRules::parse(&rules)
+            .ok_or_else(|| invalid_argument("--save expects pairs of seconds and changes, e.g. \"3600 1 300 100\"")),
+    }
+}
+
+// reclaims expired keys that are never accessed again, 'hz' times per second
+fn active_expire_loop(engine: Arc<Mutex<StorageEngine>>) {
+    loop {
+        let hz = match engine.lock() {
+            Ok(engine) => engine.expire_config().hz.max(1),
+            Err(_) => return,
+        };
+        thread::sleep(Duration::from_millis(1000 / u64::from(hz)));
+
+        match engine.lock() {
+            Ok(mut engine) => { engine.active_expire_cycle(); }
+            Err(_) => return,
+        }
+    }
+}
+
+// flushes the append only file to disk every second (with 'everysec'), puts rewrites in place once they are done, and
+// saves the RDB file in the background when a save rule says so
+fn persistence_loop(engine: Arc<Mutex<StorageEngine>>) {
+    loop {
+        thread::sleep(PERSISTENCE_CRON_INTERVAL);
+
+        match engine.lock() {
+            Ok(mut engine) => {
+                if let Some(Err(e)) = engine.aof_mut().map(AppendOnlyFile::cron) {
+                    eprintln!("Error with the append only file: {e}");
+                }
+                if let Err(e) = rdb::cron(&mut engine) {
+                    eprintln!("Error saving the RDB file: {e}");
+                }
+            }
+            Err(_) => return,
+        }
+    }
+}
+
 fn handle_client_multithreaded(
     engine: Arc<Mutex<StorageEngine>>,
-    mut stream: TcpStream,
+    stream: TcpStream,
 ) -> std::io::Result<()> {
-    // keep read-write loop until there's no input
+    let mut session = Session::new();
+
+    // replies and pub/sub messages are written by a thread of their own, as messages come at any time
+    let output = session.take_output().expect("the output of a new session is there to take");
+    let mut writer = stream.try_clone()?;
+    let writer_thread = thread::spawn(move || write_output(&mut writer, output));
 
+    let result = serve_client(&engine, &mut session, stream);
+
+    // however the connection ended, what the client watched and subscribed to goes away with it
+    if let Ok(mut engine) = engine.lock() {
+        session.disconnect(&mut engine);
+    }
+    // the writer is done once nothing can send to the client anymore, after writing whatever was left
+    drop(session);
+    let _ = writer_thread.join();
+    result
+}
+
+fn serve_client(engine: &Mutex<StorageEngine>, session: &mut Session, mut stream: TcpStream) -> std::io::Result<()> {
+    // the decoder keeps whatever part of a frame has not arrived yet, across reads
+    let mut decoder = RespDecoder::new();
+    let mut chunk = [0u8; READ_CHUNK_SIZE];
+
+    // keep read-write loop until there's no input
     loop {
-        let input = read_to_string(&mut stream)?;
-        if input.is_empty() {
+        let read = stream.read(&mut chunk)?;
+        if read == 0 {
             // println!("Empty input, closing connection");
             break;
         }
@@ -60,45 +208,95 @@ fn handle_client_multithreaded(
         // todo: handle not being able to read the address, instead of using 'stream.peer_addr()?'
         //println!("Handling connection from {}", stream.peer_addr()?);
 
-        // TODO: the handling below should probably move into a separate struct/module
+        println!("recv: \"{}\"", chunk[..read].escape_ascii());
+        decoder.feed(&chunk[..read]);
 
-        println!("recv: {:?}", input);
+        // a single read may carry several pipelined commands; their replies are queued up in order, and the writer
+        // sends whatever is queued at once
+        loop {
+            match decoder.next_frame() {
+                Ok(Some(frame)) => {
+                    let reply = handle_frame(engine, session, frame);
+                    // a blocked client gets nothing else done (not even pipelined commands) until it is served
+                    if !session.is_blocked() {
+                        session.send(reply);
+                        if session.is_closing() {
+                            return Ok(());
+                        }
+                    } else if !wait_while_blocked(engine, session, &stream, reply)? {
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    // there is no way to tell where the next frame starts, so the connection is closed, like Redis does
+                    session.send(RespObject::Error(format!("Protocol error: {}", e.message)));
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// waits for a write of another client to serve the blocked one, until it times out (then getting 'timeout_reply') or
+// disconnects; returns whether the client is still there
+fn wait_while_blocked(engine: &Mutex<StorageEngine>, session: &mut Session, stream: &TcpStream, timeout_reply: RespObject) -> std::io::Result<bool> {
+    loop {
+        // the engine is not locked while waiting, or no one could ever write what the client waits for
+        if let Some(reply) = session.wait_until_served(BLOCKED_CHECK_INTERVAL) {
+            session.send(reply);
+            return Ok(true);
+        }
+
+        let disconnected = is_closed(stream)?;
+        if disconnected || session.timed_out() {
+            // the client may have been served while the engine was not locked yet
+            let reply = match engine.lock() {
+                Ok(mut engine) => session.unblock(&mut engine),
+                Err(_) => None,
+            };
+            session.send(reply.unwrap_or(timeout_reply));
+            return Ok(!disconnected);
+        }
+    }
+}
+
+// whether the client closed its end of the connection, without taking anything it sent since
+fn is_closed(stream: &TcpStream) -> std::io::Result<bool> {
+    stream.set_read_timeout(Some(Duration::from_millis(1)))?;
+    let closed = match stream.peek(&mut [0u8; 1]) {
+        Ok(read) => read == 0,
+        Err(e) => !matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut),
+    };
+    stream.set_read_timeout(None)?;
+    Ok(closed)
+}
 
-        let response = input
-            .parse::<RespObject>()
-            .map_err(|e| e.message)
-            .and_then(|request| Command::from(request))
-            //.map(|cmd| { println!("Interpreted as {:?}", cmd); cmd })
-            .map(|command| match engine.lock() {
-                Ok(mut engine) => command.execute_on(&mut engine),
-                Err(_) => RespObject::Error("Unable to acquire lock".to_string()),
-            })
-            .unwrap_or_else(|error_string| RespObject::Error(error_string));
+// writes out what is sent to the client, in order, until nothing can send anymore
+fn write_output(stream: &mut TcpStream, output: Receiver<Vec<u8>>) -> std::io::Result<()> {
+    while let Ok(mut bytes) = output.recv() {
+        bytes.extend(output.try_iter().flatten());
 
-        let response_str = response.to_string();
-        println!("send: {:?}", response_str);
+        println!("send: \"{}\"", bytes.escape_ascii());
 
         // todo: handle IO error
-        stream.write(response_str.as_bytes())?;
+        stream.write_all(&bytes)?;
         stream.flush()?;
     }
 
     Ok(())
 }
 
-// todo: maybe extract this whole reading logic into a struct or else? improve it
-fn read_to_string(stream: &mut TcpStream) -> std::io::Result<String> {
-    let mut reader = BufReader::new(stream);
-    // 'fill_buf' and 'consume' must be used in combination, they are rather low-level
-    // todo: maybe there's a better way to do this (simpler, more performant)
-    let received: Vec<u8> = reader.fill_buf()?.to_vec();
-    // Mark the bytes read as consumed so the buffer will not return them in a subsequent read
-    reader.consume(received.len());
-
-    String::from_utf8(received).map_err(|_| {
-        std::io::Error::new(
-            std::io::ErrorKind::InvalidData,
-            "Couldn't parse received string as utf8",
-        )
-    })
+// TODO: the handling below should probably move into a separate struct/module
+fn handle_frame(engine: &Mutex<StorageEngine>, session: &mut Session, frame: RespObject) -> RespObject {
+    Command::from_request(frame, session)
+        //.map(|cmd| { println!("Interpreted as {:?}", cmd); cmd })
+        .map(|command| match engine.lock() {
+            Ok(mut engine) => command.execute(session, &mut engine),
+            Err(_) => RespObject::Error("Unable to acquire lock".to_string()),
+        })
+        // replies are only adapted to the protocol when sent, as HELLO changes the protocol its own reply is written in
+        .unwrap_or_else(RespObject::Error)
 }
@@ -1,18 +1,40 @@
-use crate::protocol::RespObject::{Array, BulkString, Error, Integer, NullArray, NullBulkString, SimpleString};
-use std::fmt::Display;
+use crate::protocol::RespObject::{Array, Attribute, BigNumber, Boolean, BulkError, BulkString, Double, Error, Integer, Map, Null, NullArray, NullBulkString, Push, Set, SimpleString, VerbatimString};
 use std::str::FromStr;
 
 // todo: should they all be references? should they all own the data?
 // todo: and then: are lifetimes needed (if using refs, probably yes)
-#[derive(Debug, Eq, PartialEq)]
+// bulk strings are binary-safe and carry raw bytes, while simple strings and errors are always text
+// not 'Eq', because RESP3 doubles are floats
+#[derive(Clone, Debug, PartialEq)]
 pub enum RespObject {
     SimpleString(String),
     Error(String),
     Integer(i64),
-    BulkString(String),
+    BulkString(Vec<u8>),
     NullBulkString,
     Array(Vec<RespObject>),
     NullArray,
+    // RESP3 types, only sent to connections that switched protocol with HELLO (see 'into_protocol')
+    Null,
+    Boolean(bool),
+    Double(f64),
+    // arbitrary precision integer, kept as its decimal digits
+    BigNumber(String),
+    BulkError(String),
+    // 'encoding' is always three characters, e.g. 'txt' or 'mkd'
+    VerbatimString { encoding: String, text: Vec<u8> },
+    Map(Vec<(RespObject, RespObject)>),
+    Set(Vec<RespObject>),
+    // out-of-band information about 'value', which is the actual reply
+    Attribute { attributes: Vec<(RespObject, RespObject)>, value: Box<RespObject> },
+    Push(Vec<RespObject>),
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ProtocolVersion {
+    #[default]
+    Resp2,
+    Resp3,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -20,115 +42,453 @@ pub struct RespObjectParseError {
     pub message: String,
 }
 
+impl RespObject {
+    // parses a single, complete object; running out of input is reported as an error here
+    pub fn from_bytes(input: &[u8]) -> Result<RespObject, RespObjectParseError> {
+        let mut input = input;
+
+        parse_(&mut input, 0).map_err(|failure| match failure {
+            ParseFailure::Incomplete => RespObjectParseError { message: String::from("Unexpected end of input") },
+            ParseFailure::Invalid(error) => error,
+        })
+    }
+}
+
 impl FromStr for RespObject {
     type Err = RespObjectParseError;
 
     fn from_str(input: &str) -> Result<Self, Self::Err> {
-        let mut input = &input[..];
+        RespObject::from_bytes(input.as_bytes())
+    }
+}
+
+// ===== Incremental decoding =====
+
+// Accumulates the bytes received on a connection and hands out every complete frame, in the order they arrived.
+// Bytes belonging to a frame that is not complete yet stay buffered until more input is fed.
+#[derive(Debug, Default)]
+pub struct RespDecoder {
+    buffer: Vec<u8>,
+    // the array being received, whose elements are kept as they complete (like Redis does with the arguments of a
+    // request), so that a large one arriving over many reads is not parsed from the start again with each of them
+    partial: Option<PartialArray>,
+}
+
+#[derive(Debug)]
+struct PartialArray {
+    length: usize,
+    elements: Vec<RespObject>,
+    // how many bytes the header and the elements so far took, which are no longer in the buffer
+    consumed: usize,
+}
+
+impl RespDecoder {
+    pub fn new() -> RespDecoder {
+        RespDecoder::default()
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    // Ok(None) means more bytes are needed, which is distinct from the input being malformed.
+    // After an error the buffered input can no longer be trusted, so the connection should be dropped.
+    // Input that does not start with a RESP type character is an inline command (e.g. typed into telnet),
+    // which is handed out as the same Array of BulkStrings a RESP client would have sent.
+    pub fn next_frame(&mut self) -> Result<Option<RespObject>, RespObjectParseError> {
+        let mut input = self.buffer.as_slice();
+        let decoded = decode_frame(&mut input, &mut self.partial);
+        // whatever was parsed goes away at once, rather than shifting the buffer for every element
+        let consumed = self.buffer.len() - input.len();
+        self.buffer.drain(..consumed);
+
+        match decoded {
+            Ok(frame) => Ok(frame),
+            Err(ParseFailure::Incomplete) => Ok(None),
+            Err(ParseFailure::Invalid(error)) => {
+                self.buffer.clear();
+                self.partial = None;
+                Err(error)
+            }
+        }
+    }
+
+    // the bytes of the frame that is not complete yet, including those of the elements already parsed
+    pub fn buffered_len(&self) -> usize {
+        self.buffer.len() + self.partial.as_ref().map_or(0, |array| array.consumed)
+    }
+}
+
+// parses the next frame off the input, going on with the array that was being received (if any); arrays are the
+// only frames kept track of in between, as they are what requests come in
+fn decode_frame(input: &mut &[u8], partial: &mut Option<PartialArray>) -> Result<Option<RespObject>, ParseFailure> {
+    loop {
+        if let Some(array) = partial {
+            while array.elements.len() < array.length {
+                let before = input.len();
+                array.elements.push(parse_complete(input, |input| parse_(input, 1))?);
+                array.consumed += before - input.len();
+            }
+            let elements = partial.take().map(|array| array.elements).unwrap_or_default();
+            return Ok(Some(Array(elements)));
+        }
 
-        parse_(&mut input)
+        let Some(&first) = input.first() else {
+            return Ok(None);
+        };
+        if first == b'*' {
+            let before = input.len();
+            let length = parse_complete(input, |input| {
+                *input = &input[1..];
+                read_aggregate_length(input, 0)
+            })?;
+            if length == -1 {
+                return Ok(Some(NullArray));
+            }
+            // not allocated up front, as the length is whatever the client claims
+            *partial = Some(PartialArray { length: length as usize, elements: Vec::new(), consumed: before - input.len() });
+            continue;
+        }
+
+        let frame = if is_resp_type(first) {
+            parse_complete(input, |input| parse_(input, 0).map(Some))?
+        } else {
+            parse_complete(input, parse_inline)?
+        };
+        // empty inline lines are skipped, without any reply
+        if frame.is_some() {
+            return Ok(frame);
+        }
     }
 }
 
+// runs the parser on the input, which is only moved past what was parsed when a whole object was
+fn parse_complete<T>(
+    input: &mut &[u8],
+    parse: impl FnOnce(&mut &[u8]) -> Result<T, ParseFailure>,
+) -> Result<T, ParseFailure> {
+    let mut attempt = *input;
+    let parsed = parse(&mut attempt)?;
+    *input = attempt;
+    Ok(parsed)
+}
+
 // ===== Parsing (deserialising) logic =====
 
+// same cap Redis applies by default (proto-max-bulk-len), anything longer is treated as malformed
+const MAX_BULK_LENGTH: i64 = 512 * 1024 * 1024;
+pub(crate) const MAX_ARRAY_LENGTH: i64 = i32::MAX as i64;
+// arrays are parsed recursively, so the nesting is bounded to keep a hostile input from overflowing the stack
+const MAX_NESTING_DEPTH: usize = 128;
+// lines (of inline commands, and of headers such as '*3' or '$5') have no length prefix telling how much to wait for,
+// so, like Redis does with PROTO_INLINE_MAX_SIZE, their length is capped
+const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+// running out of input is not an error while decoding a stream: the rest of the frame may still be on its way
+enum ParseFailure {
+    Incomplete,
+    Invalid(RespObjectParseError),
+}
+
+impl From<RespObjectParseError> for ParseFailure {
+    fn from(error: RespObjectParseError) -> Self {
+        ParseFailure::Invalid(error)
+    }
+}
+
 fn parse_(
-    input: &mut &str,
-) -> Result<RespObject, RespObjectParseError> {
-    let c = &input[..1];
+    input: &mut &[u8],
+    depth: usize,
+) -> Result<RespObject, ParseFailure> {
+    let (&c, rest) = input.split_first().ok_or(ParseFailure::Incomplete)?;
 
-    *input = &input[1..];
+    *input = rest;
     match c {
-        "+" => parse_simple_string(input),
-        "-" => parse_error(input),
-        ":" => parse_integer(input),
-        "$" => parse_bulk_string(input),
-        "*" => parse_array(input),
-        _ => Err(RespObjectParseError {
-            message: format!("Unexpected RESP type character: '{c}'"),
-        }),
+        b'+' => parse_simple_string(input),
+        b'-' => parse_error(input),
+        b':' => parse_integer(input),
+        b'$' => parse_bulk_string(input),
+        b'*' => parse_array(input, depth),
+        b'_' => parse_null(input),
+        b'#' => parse_boolean(input),
+        b',' => parse_double(input),
+        b'(' => parse_big_number(input),
+        b'!' => parse_bulk_error(input),
+        b'=' => parse_verbatim_string(input),
+        b'%' => parse_map(input, depth),
+        b'~' => parse_set(input, depth),
+        b'|' => parse_attribute(input, depth),
+        b'>' => parse_push(input, depth),
+        _ => Err(ParseFailure::Invalid(RespObjectParseError {
+            message: format!("Unexpected RESP type character: '{}'", c.escape_ascii()),
+        })),
     }
 }
 
 fn parse_simple_string(
-    input: &mut &str,
-) -> Result<RespObject, RespObjectParseError> {
-    let text = read_until_cr(input)?;
+    input: &mut &[u8],
+) -> Result<RespObject, ParseFailure> {
+    let text = read_text_until_cr(input)?;
     skip_crlf(input)?;
 
     Ok(SimpleString(text))
 }
 
 fn parse_error(
-    input: &mut &str,
-) -> Result<RespObject, RespObjectParseError> {
-    let text = read_until_cr(input)?;
+    input: &mut &[u8],
+) -> Result<RespObject, ParseFailure> {
+    let text = read_text_until_cr(input)?;
     skip_crlf(input)?;
 
     Ok(Error(text))
 }
 
 fn parse_integer(
-    input: &mut &str,
-) -> Result<RespObject, RespObjectParseError> {
-    let text = read_until_cr(input)?;
+    input: &mut &[u8],
+) -> Result<RespObject, ParseFailure> {
+    let text = read_text_until_cr(input)?;
     skip_crlf(input)?;
 
-    text.parse::<i64>()
-        .map_err(|_| RespObjectParseError { message: format!("Failed to parse integer '{text}'") } )
-        .map(|int| Integer(int))
+    let value = text.parse::<i64>()
+        .map_err(|_| RespObjectParseError { message: format!("Failed to parse integer '{text}'") } )?;
+    Ok(Integer(value))
 }
 
 fn parse_bulk_string(
-    input: &mut &str,
-) -> Result<RespObject, RespObjectParseError> {
+    input: &mut &[u8],
+) -> Result<RespObject, ParseFailure> {
     let length = read_length(input)?;
+    if length > MAX_BULK_LENGTH {
+        return Err(RespObjectParseError { message: format!("Bulk string length {length} exceeds the limit of {MAX_BULK_LENGTH}") }.into());
+    }
     let result = match length {
         -1 => NullBulkString,
         _ => {
-            let text = read_until_length(input, length as usize)?;
+            let bytes = read_until_length(input, length as usize)?;
             skip_crlf(input)?;
-            BulkString(text)
+            BulkString(bytes)
         }
     };
     Ok(result)
 }
 
 fn parse_array(
-    input: &mut &str,
-) -> Result<RespObject, RespObjectParseError> {
-    let length = read_length(input)?;
-    let result = match length {
+    input: &mut &[u8],
+    depth: usize,
+) -> Result<RespObject, ParseFailure> {
+    let result = match read_aggregate_length(input, depth)? {
         -1 => NullArray,
-        _ => {
-            let mut array = Vec::new();
-            for _ in 0..length {
-                let resp_object = parse_(input)?;
-                array.push(resp_object);
-            }
-            Array(array)
-        }
+        length => Array(parse_elements(input, length, depth)?),
     };
     Ok(result)
 }
 
-fn read_until_cr(
-    input: &mut &str,
-) -> Result<String, RespObjectParseError> {
-    let end_word_index = input.find('\r').ok_or_else(|| RespObjectParseError { message: String::from("Unexpected end of input") })?;
+fn parse_null(
+    input: &mut &[u8],
+) -> Result<RespObject, ParseFailure> {
+    skip_crlf(input)?;
+
+    Ok(Null)
+}
+
+fn parse_boolean(
+    input: &mut &[u8],
+) -> Result<RespObject, ParseFailure> {
+    let text = read_text_until_cr(input)?;
+    skip_crlf(input)?;
+
+    match text.as_str() {
+        "t" => Ok(Boolean(true)),
+        "f" => Ok(Boolean(false)),
+        _ => Err(RespObjectParseError { message: format!("Failed to parse boolean '{text}'") }.into()),
+    }
+}
+
+fn parse_double(
+    input: &mut &[u8],
+) -> Result<RespObject, ParseFailure> {
+    let text = read_text_until_cr(input)?;
+    skip_crlf(input)?;
+
+    let value = text.parse::<f64>()
+        .map_err(|_| RespObjectParseError { message: format!("Failed to parse double '{text}'") })?;
+    Ok(Double(value))
+}
+
+fn parse_big_number(
+    input: &mut &[u8],
+) -> Result<RespObject, ParseFailure> {
+    let text = read_text_until_cr(input)?;
+    skip_crlf(input)?;
+
+    let digits = text.strip_prefix(['+', '-']).unwrap_or(&text);
+    if digits.is_empty() || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return Err(RespObjectParseError { message: format!("Failed to parse big number '{text}'") }.into());
+    }
+    Ok(BigNumber(text))
+}
+
+fn parse_bulk_error(
+    input: &mut &[u8],
+) -> Result<RespObject, ParseFailure> {
+    let bytes = read_blob(input)?;
+
+    let message = String::from_utf8(bytes)
+        .map_err(|e| RespObjectParseError { message: format!("Expected text but got: '{}'", e.as_bytes().escape_ascii()) })?;
+    Ok(BulkError(message))
+}
+
+fn parse_verbatim_string(
+    input: &mut &[u8],
+) -> Result<RespObject, ParseFailure> {
+    let mut bytes = read_blob(input)?;
+
+    // the payload starts with the encoding and a colon, e.g. 'txt:'
+    if bytes.len() < 4 || bytes[3] != b':' {
+        return Err(RespObjectParseError { message: format!("Missing encoding in verbatim string '{}'", bytes.escape_ascii()) }.into());
+    }
+    let text = bytes.split_off(4);
+    let encoding = String::from_utf8(bytes[..3].to_vec())
+        .map_err(|_| RespObjectParseError { message: format!("Expected text but got: '{}'", bytes[..3].escape_ascii()) })?;
+    Ok(VerbatimString { encoding, text })
+}
+
+fn parse_map(
+    input: &mut &[u8],
+    depth: usize,
+) -> Result<RespObject, ParseFailure> {
+    let length = read_non_null_aggregate_length(input, depth)?;
+
+    Ok(Map(parse_pairs(input, length, depth)?))
+}
+
+fn parse_set(
+    input: &mut &[u8],
+    depth: usize,
+) -> Result<RespObject, ParseFailure> {
+    let length = read_non_null_aggregate_length(input, depth)?;
+
+    Ok(Set(parse_elements(input, length, depth)?))
+}
+
+fn parse_attribute(
+    input: &mut &[u8],
+    depth: usize,
+) -> Result<RespObject, ParseFailure> {
+    let length = read_non_null_aggregate_length(input, depth)?;
+    let attributes = parse_pairs(input, length, depth)?;
+    let value = parse_(input, depth + 1)?;
+
+    Ok(Attribute { attributes, value: Box::new(value) })
+}
+
+fn parse_push(
+    input: &mut &[u8],
+    depth: usize,
+) -> Result<RespObject, ParseFailure> {
+    let length = read_non_null_aggregate_length(input, depth)?;
+
+    Ok(Push(parse_elements(input, length, depth)?))
+}
+
+fn parse_elements(
+    input: &mut &[u8],
+    length: i64,
+    depth: usize,
+) -> Result<Vec<RespObject>, ParseFailure> {
+    let mut elements = Vec::new();
+    for _ in 0..length {
+        elements.push(parse_(input, depth + 1)?);
+    }
+    Ok(elements)
+}
+
+fn parse_pairs(
+    input: &mut &[u8],
+    length: i64,
+    depth: usize,
+) -> Result<Vec<(RespObject, RespObject)>, ParseFailure> {
+    let mut pairs = Vec::new();
+    for _ in 0..length {
+        let key = parse_(input, depth + 1)?;
+        let value = parse_(input, depth + 1)?;
+        pairs.push((key, value));
+    }
+    Ok(pairs)
+}
+
+// aggregates are parsed recursively, so their nesting is checked here too
+fn read_aggregate_length(
+    input: &mut &[u8],
+    depth: usize,
+) -> Result<i64, ParseFailure> {
+    if depth >= MAX_NESTING_DEPTH {
+        return Err(RespObjectParseError { message: format!("Aggregates nested deeper than {MAX_NESTING_DEPTH} levels") }.into());
+    }
+    let length = read_length(input)?;
+    if length > MAX_ARRAY_LENGTH {
+        return Err(RespObjectParseError { message: format!("Aggregate length {length} exceeds the limit of {MAX_ARRAY_LENGTH}") }.into());
+    }
+    Ok(length)
+}
+
+// only RESP2 arrays can be null, RESP3 has its own null type
+fn read_non_null_aggregate_length(
+    input: &mut &[u8],
+    depth: usize,
+) -> Result<i64, ParseFailure> {
+    match read_aggregate_length(input, depth)? {
+        -1 => Err(RespObjectParseError { message: String::from("Only arrays can have a length of -1") }.into()),
+        length => Ok(length),
+    }
+}
+
+// the length-prefixed payload of bulk errors and verbatim strings, which cannot be null
+fn read_blob(
+    input: &mut &[u8],
+) -> Result<Vec<u8>, ParseFailure> {
+    match parse_bulk_string(input)? {
+        BulkString(bytes) => Ok(bytes),
+        _ => Err(RespObjectParseError { message: String::from("Only bulk strings can have a length of -1") }.into()),
+    }
+}
+
+fn read_until_cr<'a>(
+    input: &mut &'a [u8],
+) -> Result<&'a [u8], ParseFailure> {
+    let end_word_index = match input.iter().take(MAX_LINE_LENGTH + 1).position(|byte| *byte == b'\r') {
+        Some(end) => end,
+        None if input.len() <= MAX_LINE_LENGTH => return Err(ParseFailure::Incomplete),
+        None => return Err(RespObjectParseError { message: format!("Line without \\r\\n exceeds the limit of {MAX_LINE_LENGTH} bytes") }.into()),
+    };
 
-    let word = String::from(&input[..end_word_index]);
+    let word = &input[..end_word_index];
 
     *input = &input[end_word_index..];
 
     Ok(word)
 }
 
+// the textual parts of the protocol (simple strings, errors, integers and lengths) must be valid UTF-8
+fn read_text_until_cr(
+    input: &mut &[u8],
+) -> Result<String, ParseFailure> {
+    let word = read_until_cr(input)?;
+
+    let text = String::from_utf8(word.to_vec())
+        .map_err(|_| RespObjectParseError { message: format!("Expected text but got: '{}'", word.escape_ascii()) })?;
+    Ok(text)
+}
+
 fn read_until_length(
-    input: &mut &str,
+    input: &mut &[u8],
     length: usize,
-) -> Result<String, RespObjectParseError> {
-    let word = String::from(&input[..length]);
+) -> Result<Vec<u8>, ParseFailure> {
+    if input.len() < length {
+        return Err(ParseFailure::Incomplete);
+    }
+
+    let word = input[..length].to_vec();
 
     *input = &input[length..];
 
@@ -136,32 +496,35 @@ fn read_until_length(
 }
 
 fn read_length(
-    input: &mut &str,
-) -> Result<i64, RespObjectParseError> {
-    let text = read_until_cr(input)?;
+    input: &mut &[u8],
+) -> Result<i64, ParseFailure> {
+    let text = read_text_until_cr(input)?;
     skip_crlf(input)?;
 
     let length = text
         .parse::<i64>()
-        .map_err(|_| RespObjectParseError { message: format!("Failed to parse length '{text}'"), })
-        .map(|int| int)?;
+        .map_err(|_| RespObjectParseError { message: format!("Failed to parse length '{text}'"), })?;
 
 
     if length < -1 {
-        return Err(RespObjectParseError { message: format!("Expected length to be -1 or non-negative, got: '{text}'") });
+        return Err(RespObjectParseError { message: format!("Expected length to be -1 or non-negative, got: '{text}'") }.into());
     }
     Ok(length)
 }
 
-// just consumes the CRLF (\r\n) characters from the iterator, or fails otherwise
+// just consumes the CRLF (\r\n) characters from the input, or fails otherwise
 fn skip_crlf(
-    input: &mut &str,
-) -> Result<(), RespObjectParseError> {
-    let crlf = &input[..2];
-    if crlf != "\r\n" {
+    input: &mut &[u8],
+) -> Result<(), ParseFailure> {
+    let available = input.len().min(2);
+    let crlf = &input[..available];
+    if crlf != &b"\r\n"[..available] {
         return Err(RespObjectParseError {
-            message: format!("Expected \\r\\n but got something else: {crlf}"),
-        });
+            message: format!("Expected \\r\\n but got something else: {}", crlf.escape_ascii()),
+        }.into());
+    }
+    if available < 2 {
+        return Err(ParseFailure::Incomplete);
     }
 
     *input = &input[2..];
@@ -169,19 +532,229 @@ fn skip_crlf(
     Ok(())
 }
 
-// todo: using ToString/Display for serialisation now for simplicity, may need something better/more performant
-impl Display for RespObject {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let str = match self {
-            SimpleString(value) => format!("+{value}\r\n"),
-            Error(message) => format!("-{message}\r\n"),
-            Integer(value) => format!(":{value}\r\n"),
-            BulkString(value) => format!("${}\r\n{}\r\n", value.len(), value),
-            NullBulkString => "$-1\r\n".to_string(),
-            Array(entries) => format!("*{}\r\n{}", entries.len(), entries.iter().map(|e| e.to_string()).collect::<String>()),
-            NullArray => "*-1\r\n".to_string(),
-        };
-        write!(f, "{}", str)
+// ===== Inline commands =====
+
+fn is_resp_type(c: u8) -> bool {
+    b"+-:$*_#,(!=%~|>".contains(&c)
+}
+
+// same characters C's isspace() accepts, which is what Redis splits inline arguments on
+fn is_inline_space(c: u8) -> bool {
+    matches!(c, b' ' | b'\t' | b'\n' | b'\r' | b'\x0b' | b'\x0c')
+}
+
+// an inline command is a single line, terminated by \n (optionally preceded by \r)
+fn parse_inline(
+    input: &mut &[u8],
+) -> Result<Option<RespObject>, ParseFailure> {
+    let end = match input.iter().position(|byte| *byte == b'\n') {
+        Some(end) if end <= MAX_LINE_LENGTH => end,
+        None if input.len() <= MAX_LINE_LENGTH => return Err(ParseFailure::Incomplete),
+        _ => return Err(RespObjectParseError { message: String::from("too big inline request") }.into()),
+    };
+
+    let line = &input[..end];
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    *input = &input[end + 1..];
+
+    let arguments = split_inline_arguments(line)
+        .ok_or_else(|| RespObjectParseError { message: String::from("unbalanced quotes in request") })?;
+
+    Ok((!arguments.is_empty()).then(|| Array(arguments.into_iter().map(BulkString).collect())))
+}
+
+enum Quoting {
+    Unquoted,
+    Double,
+    Single,
+}
+
+// Splits a line into arguments following the quoting rules of Redis (sdssplitargs):
+// - arguments are separated by whitespace, and quotes can start anywhere in an argument
+// - double quotes understand the escapes \n, \r, \t, \b, \a and \xHH, any other escaped character stands for itself
+// - single quotes only understand \'
+// - a closing quote must be followed by whitespace or the end of the line
+// Returns None when the quotes are unbalanced.
+fn split_inline_arguments(line: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let mut arguments = Vec::new();
+    let mut i = 0;
+
+    loop {
+        while line.get(i).is_some_and(|c| is_inline_space(*c)) {
+            i += 1;
+        }
+        if i == line.len() {
+            return Some(arguments);
+        }
+
+        let mut argument = Vec::new();
+        let mut quoting = Quoting::Unquoted;
+        loop {
+            match quoting {
+                Quoting::Unquoted => match line.get(i) {
+                    None => break,
+                    Some(c) if is_inline_space(*c) => break,
+                    Some(b'"') => quoting = Quoting::Double,
+                    Some(b'\'') => quoting = Quoting::Single,
+                    Some(c) => argument.push(*c),
+                },
+                Quoting::Double => match line.get(i)? {
+                    b'\\' if line.get(i + 1) == Some(&b'x') && hex_byte(line.get(i + 2..i + 4)).is_some() => {
+                        argument.extend(hex_byte(line.get(i + 2..i + 4)));
+                        i += 3;
+                    }
+                    b'\\' if i + 1 < line.len() => {
+                        i += 1;
+                        argument.push(match line[i] {
+                            b'n' => b'\n',
+                            b'r' => b'\r',
+                            b't' => b'\t',
+                            b'b' => 0x08,
+                            b'a' => 0x07,
+                            other => other,
+                        });
+                    }
+                    b'"' => {
+                        if line.get(i + 1).is_some_and(|c| !is_inline_space(*c)) {
+                            return None;
+                        }
+                        i += 1;
+                        break;
+                    }
+                    c => argument.push(*c),
+                },
+                Quoting::Single => match line.get(i)? {
+                    b'\\' if line.get(i + 1) == Some(&b'\'') => {
+                        argument.push(b'\'');
+                        i += 1;
+                    }
+                    b'\'' => {
+                        if line.get(i + 1).is_some_and(|c| !is_inline_space(*c)) {
+                            return None;
+                        }
+                        i += 1;
+                        break;
+                    }
+                    c => argument.push(*c),
+                },
+            }
+            i += 1;
+        }
+        arguments.push(argument);
+    }
+}
+
+// two hexadecimal digits, as in the '\xHH' escape
+fn hex_byte(digits: Option<&[u8]>) -> Option<u8> {
+    u8::from_str_radix(std::str::from_utf8(digits?).ok()?, 16).ok()
+}
+
+// ===== Serialising logic =====
+
+impl RespObject {
+    // todo: may need something more performant, like writing straight into the connection's buffer
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut output = Vec::new();
+        self.write_to(&mut output);
+        output
+    }
+
+    fn write_to(&self, output: &mut Vec<u8>) {
+        match self {
+            SimpleString(value) => output.extend_from_slice(format!("+{value}\r\n").as_bytes()),
+            Error(message) => output.extend_from_slice(format!("-{message}\r\n").as_bytes()),
+            Integer(value) => output.extend_from_slice(format!(":{value}\r\n").as_bytes()),
+            BulkString(value) => {
+                output.extend_from_slice(format!("${}\r\n", value.len()).as_bytes());
+                output.extend_from_slice(value);
+                output.extend_from_slice(b"\r\n");
+            }
+            NullBulkString => output.extend_from_slice(b"$-1\r\n"),
+            Array(entries) => {
+                output.extend_from_slice(format!("*{}\r\n", entries.len()).as_bytes());
+                entries.iter().for_each(|entry| entry.write_to(output));
+            }
+            NullArray => output.extend_from_slice(b"*-1\r\n"),
+            Null => output.extend_from_slice(b"_\r\n"),
+            Boolean(value) => output.extend_from_slice(if *value { b"#t\r\n" } else { b"#f\r\n" }),
+            Double(value) => output.extend_from_slice(format!(",{}\r\n", format_double(*value)).as_bytes()),
+            BigNumber(digits) => output.extend_from_slice(format!("({digits}\r\n").as_bytes()),
+            BulkError(message) => output.extend_from_slice(format!("!{}\r\n{message}\r\n", message.len()).as_bytes()),
+            VerbatimString { encoding, text } => {
+                output.extend_from_slice(format!("={}\r\n{encoding}:", text.len() + 4).as_bytes());
+                output.extend_from_slice(text);
+                output.extend_from_slice(b"\r\n");
+            }
+            Map(pairs) => {
+                output.extend_from_slice(format!("%{}\r\n", pairs.len()).as_bytes());
+                write_pairs(pairs, output);
+            }
+            Set(entries) => {
+                output.extend_from_slice(format!("~{}\r\n", entries.len()).as_bytes());
+                entries.iter().for_each(|entry| entry.write_to(output));
+            }
+            Attribute { attributes, value } => {
+                output.extend_from_slice(format!("|{}\r\n", attributes.len()).as_bytes());
+                write_pairs(attributes, output);
+                value.write_to(output);
+            }
+            Push(entries) => {
+                output.extend_from_slice(format!(">{}\r\n", entries.len()).as_bytes());
+                entries.iter().for_each(|entry| entry.write_to(output));
+            }
+        }
+    }
+
+    // Adapts a reply to what a connection speaking 'version' understands.
+    // Commands may reply with RESP3 types, which RESP2 connections get in their closest RESP2 shape (e.g. a map
+    // becomes a flat array of keys and values), while RESP3 connections get a single null type for both nulls.
+    pub fn into_protocol(self, version: ProtocolVersion) -> RespObject {
+        let convert_all = |entries: Vec<RespObject>| entries.into_iter()
+            .map(|entry| entry.into_protocol(version))
+            .collect::<Vec<_>>();
+        let convert_pairs = |pairs: Vec<(RespObject, RespObject)>| pairs.into_iter()
+            .map(|(key, value)| (key.into_protocol(version), value.into_protocol(version)))
+            .collect::<Vec<_>>();
+
+        match (self, version) {
+            (Array(entries), _) => Array(convert_all(entries)),
+            (NullBulkString | NullArray, ProtocolVersion::Resp3) => Null,
+            (Map(pairs), ProtocolVersion::Resp3) => Map(convert_pairs(pairs)),
+            (Set(entries), ProtocolVersion::Resp3) => Set(convert_all(entries)),
+            (Push(entries), ProtocolVersion::Resp3) => Push(convert_all(entries)),
+            (Attribute { attributes, value }, ProtocolVersion::Resp3) => {
+                Attribute { attributes: convert_pairs(attributes), value: Box::new(value.into_protocol(version)) }
+            }
+            (Null, ProtocolVersion::Resp2) => NullBulkString,
+            (Boolean(value), ProtocolVersion::Resp2) => Integer(value as i64),
+            (Double(value), ProtocolVersion::Resp2) => BulkString(format_double(value).into_bytes()),
+            (BigNumber(digits), ProtocolVersion::Resp2) => BulkString(digits.into_bytes()),
+            (BulkError(message), ProtocolVersion::Resp2) => Error(message),
+            (VerbatimString { text, .. }, ProtocolVersion::Resp2) => BulkString(text),
+            (Map(pairs), ProtocolVersion::Resp2) => Array(pairs.into_iter()
+                .flat_map(|(key, value)| [key.into_protocol(version), value.into_protocol(version)])
+                .collect()),
+            (Set(entries) | Push(entries), ProtocolVersion::Resp2) => Array(convert_all(entries)),
+            // RESP2 has no way to carry attributes, so only the actual reply is kept
+            (Attribute { value, .. }, ProtocolVersion::Resp2) => value.into_protocol(version),
+            (other, _) => other,
+        }
+    }
+}
+
+fn write_pairs(pairs: &[(RespObject, RespObject)], output: &mut Vec<u8>) {
+    pairs.iter().for_each(|(key, value)| {
+        key.write_to(output);
+        value.write_to(output);
+    });
+}
+
+// RESP3 spells the special values as 'inf', '-inf' and 'nan'
+fn format_double(value: f64) -> String {
+    if value.is_nan() {
+        "nan".to_string()
+    } else {
+        value.to_string()
     }
 }
 
@@ -192,7 +765,7 @@ mod deserialization_tests {
     #[test]
     fn parse_simple_string() {
         let result = RespObject::from_str("+Hello, World\r\n");
-        assert_eq!(result, Ok(SimpleString("Hello, World".to_owned())));
+        assert_eq!(result, Ok(SimpleString("Hello, World".into())));
     }
 
     #[test]
@@ -204,7 +777,7 @@ mod deserialization_tests {
     #[test]
     fn parse_error() {
         let result = RespObject::from_str("-Error message\r\n");
-        assert_eq!(result, Ok(Error("Error message".to_owned())));
+        assert_eq!(result, Ok(Error("Error message".into())));
     }
 
     #[test]
@@ -240,19 +813,31 @@ mod deserialization_tests {
     #[test]
     fn parse_empty_bulk_string() {
         let result = RespObject::from_str("$0\r\n\r\n");
-        assert_eq!(result, Ok(BulkString("".to_owned())));
+        assert_eq!(result, Ok(BulkString(vec![])));
     }
 
     #[test]
     fn parse_bulk_string() {
         let result = RespObject::from_str("$6\r\nfoobar\r\n");
-        assert_eq!(result, Ok(BulkString("foobar".to_owned())));
+        assert_eq!(result, Ok(BulkString("foobar".into())));
     }
 
     #[test]
     fn parse_multiline_bulk_string() {
         let result = RespObject::from_str("$8\r\nfoo\r\nbar\r\n");
-        assert_eq!(result, Ok(BulkString("foo\r\nbar".to_owned())));
+        assert_eq!(result, Ok(BulkString("foo\r\nbar".into())));
+    }
+
+    #[test]
+    fn parse_binary_bulk_string() {
+        let result = RespObject::from_bytes(b"$4\r\n\xff\x00\r\xfe\r\n");
+        assert_eq!(result, Ok(BulkString(vec![0xff, 0x00, b'\r', 0xfe])));
+    }
+
+    #[test]
+    fn fail_parse_simple_string_that_is_not_text() {
+        let result = RespObject::from_bytes(b"+\xff\xfe\r\n");
+        assert!(result.is_err());
     }
 
     #[test]
@@ -270,7 +855,7 @@ mod deserialization_tests {
     #[test]
     fn parse_array_example1() {
         let result = RespObject::from_str("*1\r\n$4\r\nping\r\n");
-        assert_eq!(result, Ok(Array(vec![BulkString("ping".to_owned())])));
+        assert_eq!(result, Ok(Array(vec![BulkString("ping".into())])));
     }
 
     #[test]
@@ -279,8 +864,8 @@ mod deserialization_tests {
         assert_eq!(
             result,
             Ok(Array(vec![
-                BulkString("echo".to_owned()),
-                BulkString("hello world".to_owned())
+                BulkString("echo".into()),
+                BulkString("hello world".into())
             ]))
         );
     }
@@ -291,8 +876,8 @@ mod deserialization_tests {
         assert_eq!(
             result,
             Ok(Array(vec![
-                BulkString("get".to_owned()),
-                BulkString("key".to_owned())
+                BulkString("get".into()),
+                BulkString("key".into())
             ]))
         );
     }
@@ -308,6 +893,478 @@ mod deserialization_tests {
         let result = RespObject::from_str("?What is this\r\n");
         assert!(result.is_err());
     }
+
+    #[test]
+    fn parse_null() {
+        let result = RespObject::from_str("_\r\n");
+        assert_eq!(result, Ok(Null));
+    }
+
+    #[test]
+    fn parse_booleans() {
+        assert_eq!(RespObject::from_str("#t\r\n"), Ok(Boolean(true)));
+        assert_eq!(RespObject::from_str("#f\r\n"), Ok(Boolean(false)));
+    }
+
+    #[test]
+    fn fail_parse_boolean_on_unknown_value() {
+        let result = RespObject::from_str("#x\r\n");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn parse_doubles() {
+        assert_eq!(RespObject::from_str(",1.23\r\n"), Ok(Double(1.23)));
+        assert_eq!(RespObject::from_str(",-10\r\n"), Ok(Double(-10.0)));
+        assert_eq!(RespObject::from_str(",1.5e3\r\n"), Ok(Double(1500.0)));
+        assert_eq!(RespObject::from_str(",inf\r\n"), Ok(Double(f64::INFINITY)));
+        assert_eq!(RespObject::from_str(",-inf\r\n"), Ok(Double(f64::NEG_INFINITY)));
+        assert!(matches!(RespObject::from_str(",nan\r\n"), Ok(Double(value)) if value.is_nan()));
+    }
+
+    #[test]
+    fn fail_parse_double_on_non_numerical_input() {
+        let result = RespObject::from_str(",one\r\n");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn parse_big_number() {
+        let result = RespObject::from_str("(-3492890328409238509324850943850943825024385\r\n");
+        assert_eq!(result, Ok(BigNumber("-3492890328409238509324850943850943825024385".into())));
+    }
+
+    #[test]
+    fn fail_parse_big_number_on_non_digits() {
+        assert!(RespObject::from_str("(12a\r\n").is_err());
+        assert!(RespObject::from_str("(-\r\n").is_err());
+    }
+
+    #[test]
+    fn parse_bulk_error() {
+        let result = RespObject::from_str("!21\r\nSYNTAX invalid syntax\r\n");
+        assert_eq!(result, Ok(BulkError("SYNTAX invalid syntax".into())));
+    }
+
+    #[test]
+    fn parse_verbatim_string() {
+        let result = RespObject::from_str("=15\r\ntxt:Some string\r\n");
+        assert_eq!(result, Ok(VerbatimString { encoding: "txt".into(), text: "Some string".into() }));
+    }
+
+    #[test]
+    fn fail_parse_verbatim_string_without_encoding() {
+        let result = RespObject::from_str("=3\r\ntxt\r\n");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn parse_map() {
+        let result = RespObject::from_str("%2\r\n+first\r\n:1\r\n+second\r\n:2\r\n");
+        assert_eq!(
+            result,
+            Ok(Map(vec![
+                (SimpleString("first".into()), Integer(1)),
+                (SimpleString("second".into()), Integer(2)),
+            ]))
+        );
+    }
+
+    #[test]
+    fn parse_set() {
+        let result = RespObject::from_str("~2\r\n$1\r\na\r\n#t\r\n");
+        assert_eq!(result, Ok(Set(vec![BulkString("a".into()), Boolean(true)])));
+    }
+
+    #[test]
+    fn parse_attribute_followed_by_its_value() {
+        let result = RespObject::from_str("|1\r\n+key-popularity\r\n%1\r\n$1\r\na\r\n,0.1923\r\n*1\r\n:2039123\r\n");
+        assert_eq!(
+            result,
+            Ok(Attribute {
+                attributes: vec![(
+                    SimpleString("key-popularity".into()),
+                    Map(vec![(BulkString("a".into()), Double(0.1923))]),
+                )],
+                value: Box::new(Array(vec![Integer(2039123)])),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_push() {
+        let result = RespObject::from_str(">2\r\n+message\r\n$5\r\nhello\r\n");
+        assert_eq!(result, Ok(Push(vec![SimpleString("message".into()), BulkString("hello".into())])));
+    }
+
+    #[test]
+    fn fail_parse_null_map() {
+        let result = RespObject::from_str("%-1\r\n");
+        assert!(result.is_err());
+    }
+}
+
+#[cfg(test)]
+mod decoder_tests {
+    use super::*;
+
+    #[test]
+    fn decode_nothing_from_empty_buffer() {
+        let mut decoder = RespDecoder::new();
+        assert_eq!(decoder.next_frame(), Ok(None));
+    }
+
+    #[test]
+    fn decode_frame_split_across_reads() {
+        let mut decoder = RespDecoder::new();
+
+        decoder.feed(b"*2\r\n$3\r\nget");
+        assert_eq!(decoder.next_frame(), Ok(None));
+        decoder.feed(b"\r\n$3\r");
+        assert_eq!(decoder.next_frame(), Ok(None));
+        decoder.feed(b"\nkey\r\n");
+
+        assert_eq!(
+            decoder.next_frame(),
+            Ok(Some(Array(vec![BulkString("get".into()), BulkString("key".into())])))
+        );
+        assert_eq!(decoder.buffered_len(), 0);
+    }
+
+    #[test]
+    fn decode_frame_fed_byte_by_byte() {
+        let input = b"*2\r\n$4\r\necho\r\n$8\r\nfoo\r\nbar\r\n";
+        let mut decoder = RespDecoder::new();
+
+        for byte in &input[..input.len() - 1] {
+            decoder.feed(&[*byte]);
+            assert_eq!(decoder.next_frame(), Ok(None));
+        }
+        decoder.feed(&input[input.len() - 1..]);
+
+        assert_eq!(
+            decoder.next_frame(),
+            Ok(Some(Array(vec![BulkString("echo".into()), BulkString("foo\r\nbar".into())])))
+        );
+    }
+
+    #[test]
+    fn decode_pipelined_frames_in_order() {
+        let mut decoder = RespDecoder::new();
+        decoder.feed(b"*1\r\n$4\r\nping\r\n:42\r\n+OK\r\n$5\r\nhel");
+
+        assert_eq!(decoder.next_frame(), Ok(Some(Array(vec![BulkString("ping".into())]))));
+        assert_eq!(decoder.next_frame(), Ok(Some(Integer(42))));
+        assert_eq!(decoder.next_frame(), Ok(Some(SimpleString("OK".into()))));
+        assert_eq!(decoder.next_frame(), Ok(None));
+        assert_eq!(decoder.buffered_len(), 7);
+
+        decoder.feed(b"lo\r\n");
+        assert_eq!(decoder.next_frame(), Ok(Some(BulkString("hello".into()))));
+        assert_eq!(decoder.next_frame(), Ok(None));
+    }
+
+    #[test]
+    fn decode_array_keeps_the_elements_received_so_far() {
+        let mut decoder = RespDecoder::new();
+
+        decoder.feed(b"*3\r\n$3\r\nset\r\n$1\r\nk\r\n$5\r\nva");
+        assert_eq!(decoder.next_frame(), Ok(None));
+        // only the incomplete element is left to parse, but it all still counts as buffered
+        assert_eq!(decoder.buffer, b"$5\r\nva");
+        assert_eq!(decoder.buffered_len(), 26);
+
+        decoder.feed(b"lue\r\n*0\r\n*-1\r\n");
+        assert_eq!(decoder.next_frame(), Ok(Some(Array(vec![BulkString("set".into()), BulkString("k".into()), BulkString("value".into())]))));
+        assert_eq!(decoder.next_frame(), Ok(Some(Array(vec![]))));
+        assert_eq!(decoder.next_frame(), Ok(Some(NullArray)));
+        assert_eq!(decoder.buffered_len(), 0);
+    }
+
+    #[test]
+    fn fail_decode_on_header_line_that_is_too_long() {
+        let mut decoder = RespDecoder::new();
+        decoder.feed(b"*1\r\n$");
+        decoder.feed(&vec![b'1'; MAX_LINE_LENGTH]);
+        assert_eq!(decoder.next_frame(), Ok(None));
+
+        decoder.feed(b"1");
+        assert!(decoder.next_frame().is_err());
+        assert_eq!(decoder.buffered_len(), 0);
+    }
+
+    #[test]
+    fn fail_decode_on_malformed_input() {
+        let mut decoder = RespDecoder::new();
+        decoder.feed(b"$abc\r\n");
+
+        assert!(decoder.next_frame().is_err());
+        assert_eq!(decoder.buffered_len(), 0);
+    }
+
+    #[test]
+    fn fail_decode_on_bulk_string_without_trailing_crlf() {
+        let mut decoder = RespDecoder::new();
+        decoder.feed(b"$3\r\nfooXY");
+
+        assert!(decoder.next_frame().is_err());
+    }
+
+    #[test]
+    fn fail_decode_on_cr_not_followed_by_lf() {
+        let mut decoder = RespDecoder::new();
+        decoder.feed(b":42\rX");
+
+        assert!(decoder.next_frame().is_err());
+    }
+
+    fn decode_inline(input: &[u8]) -> Result<Option<RespObject>, RespObjectParseError> {
+        let mut decoder = RespDecoder::new();
+        decoder.feed(input);
+        decoder.next_frame()
+    }
+
+    fn arguments(values: &[&[u8]]) -> Option<RespObject> {
+        Some(Array(values.iter().map(|value| BulkString(value.to_vec())).collect()))
+    }
+
+    #[test]
+    fn decode_inline_command() {
+        assert_eq!(decode_inline(b"PING\r\n"), Ok(arguments(&[b"PING"])));
+        assert_eq!(decode_inline(b"set  key\tvalue\n"), Ok(arguments(&[b"set", b"key", b"value"])));
+    }
+
+    #[test]
+    fn decode_inline_command_waits_for_end_of_line() {
+        let mut decoder = RespDecoder::new();
+        decoder.feed(b"get ke");
+        assert_eq!(decoder.next_frame(), Ok(None));
+
+        decoder.feed(b"y\r\n*1\r\n$4\r\nping\r\n");
+        assert_eq!(decoder.next_frame(), Ok(arguments(&[b"get", b"key"])));
+        assert_eq!(decoder.next_frame(), Ok(arguments(&[b"ping"])));
+    }
+
+    #[test]
+    fn decode_inline_command_skips_empty_lines() {
+        let mut decoder = RespDecoder::new();
+        decoder.feed(b"\r\n   \n\r\nping\r\n\r\n");
+
+        assert_eq!(decoder.next_frame(), Ok(arguments(&[b"ping"])));
+        assert_eq!(decoder.next_frame(), Ok(None));
+        assert_eq!(decoder.buffered_len(), 0);
+    }
+
+    #[test]
+    fn decode_inline_command_with_double_quotes() {
+        assert_eq!(decode_inline(b"set \"hello world\" \"\"\n"), Ok(arguments(&[b"set", b"hello world", b""])));
+        assert_eq!(
+            decode_inline(b"echo \"a\\nb\\tc\\\"d\\\\e\\x41\\xzz\"\n"),
+            Ok(arguments(&[b"echo", b"a\nb\tc\"d\\eAxzz"]))
+        );
+        assert_eq!(decode_inline(b"echo \"\\xff\\x00\"\n"), Ok(arguments(&[b"echo", b"\xff\x00"])));
+    }
+
+    #[test]
+    fn decode_inline_command_with_single_quotes() {
+        assert_eq!(decode_inline(b"echo 'it\\'s \"raw\" \\n'\n"), Ok(arguments(&[b"echo", b"it's \"raw\" \\n"])));
+    }
+
+    #[test]
+    fn decode_inline_command_with_quotes_inside_argument() {
+        assert_eq!(decode_inline(b"echo foo\"bar baz\"\n"), Ok(arguments(&[b"echo", b"foobar baz"])));
+    }
+
+    #[test]
+    fn fail_decode_inline_command_with_unbalanced_quotes() {
+        assert!(decode_inline(b"echo \"unterminated\n").is_err());
+        assert!(decode_inline(b"echo 'unterminated\n").is_err());
+        // a closing quote must be followed by a space
+        assert!(decode_inline(b"echo \"closed\"too-early\n").is_err());
+    }
+
+    #[test]
+    fn fail_decode_inline_command_that_is_too_long() {
+        let mut decoder = RespDecoder::new();
+        decoder.feed(&vec![b'a'; MAX_LINE_LENGTH]);
+        assert_eq!(decoder.next_frame(), Ok(None));
+
+        decoder.feed(b"a");
+        assert!(decoder.next_frame().is_err());
+    }
+
+    #[test]
+    fn fail_parse_on_truncated_bulk_string() {
+        let result = RespObject::from_str("$6\r\nfoo");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn fail_parse_on_empty_input() {
+        let result = RespObject::from_bytes(b"");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn fail_parse_on_lone_cr() {
+        let result = RespObject::from_bytes(b"+OK\r");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn fail_parse_on_multi_byte_character_cut_short() {
+        // 'é' is two bytes in UTF-8, the length below cuts it in half
+        let result = RespObject::from_bytes("$2\r\naé\r\n".as_bytes());
+        assert!(result.is_err());
+
+        let result = RespObject::from_bytes(b"+a\xc3\r\n");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn fail_parse_on_bulk_string_longer_than_limit() {
+        let mut decoder = RespDecoder::new();
+        decoder.feed(b"$9223372036854775807\r\n");
+
+        assert!(decoder.next_frame().is_err());
+    }
+
+    #[test]
+    fn fail_parse_on_length_that_overflows() {
+        let result = RespObject::from_bytes(b"*99999999999999999999999\r\n");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn fail_parse_on_arrays_nested_too_deep() {
+        let input = "*1\r\n".repeat(100_000);
+
+        let mut decoder = RespDecoder::new();
+        decoder.feed(input.as_bytes());
+
+        assert!(decoder.next_frame().is_err());
+    }
+
+    #[test]
+    fn parse_arrays_nested_within_limit() {
+        let input = format!("{}:1\r\n", "*1\r\n".repeat(MAX_NESTING_DEPTH));
+
+        let mut result = RespObject::from_str(&input).unwrap();
+        for _ in 0..MAX_NESTING_DEPTH {
+            result = match result {
+                Array(mut entries) => entries.remove(0),
+                other => panic!("Expected an array, got {other:?}"),
+            };
+        }
+        assert_eq!(result, Integer(1));
+    }
+}
+
+#[cfg(test)]
+mod parser_property_tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn resp_object() -> impl Strategy<Value = RespObject> {
+        let text = "[^\r\n]*";
+        let leaf = prop_oneof![
+            text.prop_map(SimpleString),
+            text.prop_map(Error),
+            any::<i64>().prop_map(Integer),
+            prop::collection::vec(any::<u8>(), 0..64).prop_map(BulkString),
+            Just(NullBulkString),
+            Just(NullArray),
+            Just(Null),
+            any::<bool>().prop_map(Boolean),
+            // NaN is left out, as it never equals itself
+            prop_oneof![any::<f64>().prop_filter("not NaN", |value| !value.is_nan()), Just(f64::INFINITY), Just(f64::NEG_INFINITY)].prop_map(Double),
+            "-?[0-9]{1,60}".prop_map(BigNumber),
+            ".*".prop_map(BulkError),
+            ("[a-z]{3}", prop::collection::vec(any::<u8>(), 0..64))
+                .prop_map(|(encoding, text)| VerbatimString { encoding, text }),
+        ];
+        leaf.prop_recursive(4, 64, 8, |inner| {
+            let pairs = prop::collection::vec((inner.clone(), inner.clone()), 0..4);
+            prop_oneof![
+                prop::collection::vec(inner.clone(), 0..8).prop_map(Array),
+                pairs.clone().prop_map(Map),
+                prop::collection::vec(inner.clone(), 0..8).prop_map(Set),
+                prop::collection::vec(inner.clone(), 0..8).prop_map(Push),
+                (pairs, inner).prop_map(|(attributes, value)| Attribute { attributes, value: Box::new(value) }),
+            ]
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn parsing_arbitrary_bytes_never_panics(input in prop::collection::vec(any::<u8>(), 0..256)) {
+            let _ = RespObject::from_bytes(&input);
+
+            let mut decoder = RespDecoder::new();
+            decoder.feed(&input);
+            while let Ok(Some(_)) = decoder.next_frame() {}
+        }
+
+        #[test]
+        fn parsing_protocol_like_bytes_never_panics(
+            input in prop::collection::vec(prop::sample::select(b"+-:$*_#,(!=%~|>\r\n-019aZ\xc3\xa9".to_vec()), 0..256)
+        ) {
+            let _ = RespObject::from_bytes(&input);
+
+            let mut decoder = RespDecoder::new();
+            decoder.feed(&input);
+            while let Ok(Some(_)) = decoder.next_frame() {}
+        }
+
+        #[test]
+        fn written_objects_parse_back(object in resp_object()) {
+            let bytes = object.to_bytes();
+
+            prop_assert_eq!(RespObject::from_bytes(&bytes), Ok(object));
+        }
+
+        #[test]
+        fn truncated_objects_need_more_bytes(object in resp_object(), cut in any::<prop::sample::Index>()) {
+            let bytes = object.to_bytes();
+            let truncated = &bytes[..cut.index(bytes.len())];
+
+            prop_assert!(RespObject::from_bytes(truncated).is_err());
+
+            let mut decoder = RespDecoder::new();
+            decoder.feed(truncated);
+            prop_assert_eq!(decoder.next_frame(), Ok(None));
+        }
+
+        #[test]
+        fn pipelined_objects_decode_in_order_whatever_the_split(
+            objects in prop::collection::vec(resp_object(), 1..8),
+            cut in any::<prop::sample::Index>(),
+        ) {
+            let bytes: Vec<u8> = objects.iter().flat_map(RespObject::to_bytes).collect();
+            let (first, second) = bytes.split_at(cut.index(bytes.len() + 1));
+
+            let mut decoder = RespDecoder::new();
+            let mut decoded = Vec::new();
+            for chunk in [first, second] {
+                decoder.feed(chunk);
+                while let Some(frame) = decoder.next_frame().unwrap() {
+                    decoded.push(frame);
+                }
+            }
+
+            prop_assert_eq!(decoded, objects);
+            prop_assert_eq!(decoder.buffered_len(), 0);
+        }
+
+        #[test]
+        fn corrupted_objects_never_panic(object in resp_object(), position in any::<prop::sample::Index>(), byte in any::<u8>()) {
+            let mut bytes = object.to_bytes();
+            let position = position.index(bytes.len());
+            bytes[position] = byte;
+
+            let _ = RespObject::from_bytes(&bytes);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -316,85 +1373,211 @@ mod serialization_tests {
 
     #[test]
     fn write_simple_string() {
-        let result = SimpleString("Hello, World".to_owned()).to_string();
-        assert_eq!(result, "+Hello, World\r\n");
+        let result = SimpleString("Hello, World".into()).to_bytes();
+        assert_eq!(result, b"+Hello, World\r\n");
     }
 
     #[test]
     fn write_error() {
-        let result = Error("Error message".to_owned()).to_string();
-        assert_eq!(result, "-Error message\r\n");
+        let result = Error("Error message".into()).to_bytes();
+        assert_eq!(result, b"-Error message\r\n");
     }
 
     #[test]
     fn write_integer() {
-        let result = Integer(42).to_string();
-        assert_eq!(result, ":42\r\n");
+        let result = Integer(42).to_bytes();
+        assert_eq!(result, b":42\r\n");
     }
 
     #[test]
     fn write_negative_integer() {
-        let result = Integer(-10).to_string();
-        assert_eq!(result, ":-10\r\n");
+        let result = Integer(-10).to_bytes();
+        assert_eq!(result, b":-10\r\n");
     }
 
     #[test]
     fn write_empty_bulk_string() {
-        let result = BulkString("".to_owned()).to_string();
-        assert_eq!(result, "$0\r\n\r\n");
+        let result = BulkString(vec![]).to_bytes();
+        assert_eq!(result, b"$0\r\n\r\n");
     }
 
     #[test]
     fn write_bulk_string() {
-        let result = BulkString("foobar".to_owned()).to_string();
-        assert_eq!(result, "$6\r\nfoobar\r\n");
+        let result = BulkString("foobar".into()).to_bytes();
+        assert_eq!(result, b"$6\r\nfoobar\r\n");
     }
 
     #[test]
     fn write_multiline_bulk_string() {
-        let result = BulkString("foo\r\nbar".to_owned()).to_string();
-        assert_eq!(result, "$8\r\nfoo\r\nbar\r\n");
+        let result = BulkString("foo\r\nbar".into()).to_bytes();
+        assert_eq!(result, b"$8\r\nfoo\r\nbar\r\n");
+    }
+
+    #[test]
+    fn write_binary_bulk_string() {
+        let result = BulkString(vec![0xff, 0x00, b'\n']).to_bytes();
+        assert_eq!(result, b"$3\r\n\xff\x00\n\r\n");
     }
 
     #[test]
     fn write_null_bulk_string() {
-        let result = NullBulkString.to_string();
-        assert_eq!(result, "$-1\r\n");
+        let result = NullBulkString.to_bytes();
+        assert_eq!(result, b"$-1\r\n");
     }
 
     #[test]
     fn write_empty_array() {
-        let result = Array(vec![]).to_string();
-        assert_eq!(result, "*0\r\n");
+        let result = Array(vec![]).to_bytes();
+        assert_eq!(result, b"*0\r\n");
     }
 
     #[test]
     fn write_array_example1() {
-        let result = Array(vec![BulkString("ping".to_owned())]).to_string();
-        assert_eq!(result, "*1\r\n$4\r\nping\r\n");
+        let result = Array(vec![BulkString("ping".into())]).to_bytes();
+        assert_eq!(result, b"*1\r\n$4\r\nping\r\n");
     }
 
     #[test]
     fn write_array_example2() {
         let result = Array(vec![
-            BulkString("echo".to_owned()),
-            BulkString("hello world".to_owned())
-        ]).to_string();
-        assert_eq!(result, "*2\r\n$4\r\necho\r\n$11\r\nhello world\r\n");
+            BulkString("echo".into()),
+            BulkString("hello world".into())
+        ]).to_bytes();
+        assert_eq!(result, b"*2\r\n$4\r\necho\r\n$11\r\nhello world\r\n");
     }
 
     #[test]
     fn write_array_example3() {
         let result = Array(vec![
-            BulkString("get".to_owned()),
-            BulkString("key".to_owned())
-        ]).to_string();
-        assert_eq!(result, "*2\r\n$3\r\nget\r\n$3\r\nkey\r\n");
+            BulkString("get".into()),
+            BulkString("key".into())
+        ]).to_bytes();
+        assert_eq!(result, b"*2\r\n$3\r\nget\r\n$3\r\nkey\r\n");
     }
 
     #[test]
     fn write_null_array() {
-        let result = NullArray.to_string();
-        assert_eq!(result, "*-1\r\n");
+        let result = NullArray.to_bytes();
+        assert_eq!(result, b"*-1\r\n");
+    }
+
+    #[test]
+    fn write_null() {
+        assert_eq!(Null.to_bytes(), b"_\r\n");
+    }
+
+    #[test]
+    fn write_booleans() {
+        assert_eq!(Boolean(true).to_bytes(), b"#t\r\n");
+        assert_eq!(Boolean(false).to_bytes(), b"#f\r\n");
+    }
+
+    #[test]
+    fn write_doubles() {
+        assert_eq!(Double(1.23).to_bytes(), b",1.23\r\n");
+        assert_eq!(Double(10.0).to_bytes(), b",10\r\n");
+        assert_eq!(Double(f64::INFINITY).to_bytes(), b",inf\r\n");
+        assert_eq!(Double(f64::NEG_INFINITY).to_bytes(), b",-inf\r\n");
+        assert_eq!(Double(f64::NAN).to_bytes(), b",nan\r\n");
+    }
+
+    #[test]
+    fn write_big_number() {
+        assert_eq!(BigNumber("3492890328409238509324850943850943825024385".into()).to_bytes(), b"(3492890328409238509324850943850943825024385\r\n");
+    }
+
+    #[test]
+    fn write_bulk_error() {
+        assert_eq!(BulkError("SYNTAX invalid syntax".into()).to_bytes(), b"!21\r\nSYNTAX invalid syntax\r\n");
+    }
+
+    #[test]
+    fn write_verbatim_string() {
+        let result = VerbatimString { encoding: "txt".into(), text: "Some string".into() }.to_bytes();
+        assert_eq!(result, b"=15\r\ntxt:Some string\r\n");
+    }
+
+    #[test]
+    fn write_map() {
+        let result = Map(vec![(SimpleString("first".into()), Integer(1)), (SimpleString("second".into()), Integer(2))]).to_bytes();
+        assert_eq!(result, b"%2\r\n+first\r\n:1\r\n+second\r\n:2\r\n");
+    }
+
+    #[test]
+    fn write_set() {
+        let result = Set(vec![BulkString("a".into()), Boolean(true)]).to_bytes();
+        assert_eq!(result, b"~2\r\n$1\r\na\r\n#t\r\n");
+    }
+
+    #[test]
+    fn write_attribute_before_its_value() {
+        let result = Attribute {
+            attributes: vec![(SimpleString("ttl".into()), Integer(100))],
+            value: Box::new(BulkString("a".into())),
+        }.to_bytes();
+        assert_eq!(result, b"|1\r\n+ttl\r\n:100\r\n$1\r\na\r\n");
+    }
+
+    #[test]
+    fn write_push() {
+        let result = Push(vec![SimpleString("message".into()), BulkString("hello".into())]).to_bytes();
+        assert_eq!(result, b">2\r\n+message\r\n$5\r\nhello\r\n");
+    }
+}
+
+#[cfg(test)]
+mod protocol_version_tests {
+    use super::*;
+
+    #[test]
+    fn resp2_flattens_maps_into_arrays() {
+        let map = Map(vec![
+            (BulkString("name".into()), BulkString("Jane".into())),
+            (BulkString("age".into()), Map(vec![(BulkString("years".into()), Integer(42))])),
+        ]);
+
+        assert_eq!(
+            map.into_protocol(ProtocolVersion::Resp2),
+            Array(vec![
+                BulkString("name".into()), BulkString("Jane".into()),
+                BulkString("age".into()), Array(vec![BulkString("years".into()), Integer(42)]),
+            ])
+        );
+    }
+
+    #[test]
+    fn resp2_gets_closest_shape_of_resp3_types() {
+        let resp2 = |object: RespObject| object.into_protocol(ProtocolVersion::Resp2);
+
+        assert_eq!(resp2(Null), NullBulkString);
+        assert_eq!(resp2(Boolean(true)), Integer(1));
+        assert_eq!(resp2(Double(1.5)), BulkString("1.5".into()));
+        assert_eq!(resp2(Double(f64::INFINITY)), BulkString("inf".into()));
+        assert_eq!(resp2(BigNumber("123".into())), BulkString("123".into()));
+        assert_eq!(resp2(BulkError("SYNTAX oops".into())), Error("SYNTAX oops".into()));
+        assert_eq!(resp2(VerbatimString { encoding: "txt".into(), text: "hi".into() }), BulkString("hi".into()));
+        assert_eq!(resp2(Set(vec![Null])), Array(vec![NullBulkString]));
+        assert_eq!(resp2(Push(vec![Boolean(false)])), Array(vec![Integer(0)]));
+        assert_eq!(
+            resp2(Attribute { attributes: vec![(Null, Null)], value: Box::new(Integer(1)) }),
+            Integer(1)
+        );
+    }
+
+    #[test]
+    fn resp2_leaves_resp2_types_untouched() {
+        let reply = Array(vec![SimpleString("OK".into()), NullBulkString, NullArray, Error("oops".into())]);
+
+        assert_eq!(reply.clone().into_protocol(ProtocolVersion::Resp2), reply);
+    }
+
+    #[test]
+    fn resp3_uses_a_single_null() {
+        let reply = Array(vec![NullBulkString, NullArray, Map(vec![(BulkString("a".into()), NullBulkString)])]);
+
+        assert_eq!(
+            reply.into_protocol(ProtocolVersion::Resp3),
+            Array(vec![Null, Null, Map(vec![(BulkString("a".into()), Null)])])
+        );
     }
 }
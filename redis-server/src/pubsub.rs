@@ -0,0 +1,163 @@
+use crate::glob::glob_match;
+use crate::protocol::{ProtocolVersion, RespObject};
+use crate::protocol::RespObject::{BulkString, Push};
+
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+
+/// Keeps track of which clients subscribed to which channels and patterns, and delivers what is published to them.
+///
+/// Messages do not go through the request/response cycle of the subscribers: they are serialized right away (in the
+/// protocol of each subscriber) and handed over to its connection, which writes them out after whatever it was
+/// already sending.
+#[derive(Default)]
+pub struct Broker {
+    // channel -> client id -> subscriber
+    channels: HashMap<Vec<u8>, HashMap<u64, Subscriber>>,
+    // pattern -> client id -> subscriber
+    patterns: HashMap<Vec<u8>, HashMap<u64, Subscriber>>,
+}
+
+/// A client that subscribed to something, as reached from other connections
+#[derive(Debug, Clone)]
+pub struct Subscriber {
+    outbox: Sender<Vec<u8>>,
+    protocol: ProtocolVersion,
+}
+
+impl Subscriber {
+    pub fn new(outbox: Sender<Vec<u8>>, protocol: ProtocolVersion) -> Subscriber {
+        Subscriber { outbox, protocol }
+    }
+
+    fn deliver(&self, message: RespObject) {
+        // a client that is gone cannot receive anything, and it unsubscribes from everything as it goes
+        let _ = self.outbox.send(message.into_protocol(self.protocol).to_bytes());
+    }
+}
+
+impl Broker {
+    pub fn new() -> Broker {
+        Broker::default()
+    }
+
+    pub fn subscribe(&mut self, channel: &[u8], client_id: u64, subscriber: Subscriber) {
+        self.channels.entry(channel.to_vec()).or_default().insert(client_id, subscriber);
+    }
+
+    pub fn unsubscribe(&mut self, channel: &[u8], client_id: u64) {
+        remove_subscriber(&mut self.channels, channel, client_id);
+    }
+
+    pub fn psubscribe(&mut self, pattern: &[u8], client_id: u64, subscriber: Subscriber) {
+        self.patterns.entry(pattern.to_vec()).or_default().insert(client_id, subscriber);
+    }
+
+    pub fn punsubscribe(&mut self, pattern: &[u8], client_id: u64) {
+        remove_subscriber(&mut self.patterns, pattern, client_id);
+    }
+
+    /// Sends the message to the subscribers of the channel, and to those of the patterns that match it.
+    /// Returns how many deliveries there were, a client subscribed to several matching patterns counting once per pattern.
+    pub fn publish(&self, channel: &[u8], message: &[u8]) -> usize {
+        let mut receivers = 0;
+
+        for subscriber in self.channels.get(channel).into_iter().flat_map(HashMap::values) {
+            subscriber.deliver(Push(vec![
+                BulkString(b"message".to_vec()), BulkString(channel.to_vec()), BulkString(message.to_vec()),
+            ]));
+            receivers += 1;
+        }
+
+        for (pattern, subscribers) in &self.patterns {
+            if !glob_match(pattern, channel, false) {
+                continue;
+            }
+            for subscriber in subscribers.values() {
+                subscriber.deliver(Push(vec![
+                    BulkString(b"pmessage".to_vec()), BulkString(pattern.clone()), BulkString(channel.to_vec()), BulkString(message.to_vec()),
+                ]));
+                receivers += 1;
+            }
+        }
+        receivers
+    }
+
+    /// The channels with at least one subscriber, optionally only those that match a pattern, as PUBSUB CHANNELS.
+    pub fn channels(&self, pattern: Option<&[u8]>) -> Vec<Vec<u8>> {
+        self.channels.keys()
+            .filter(|channel| pattern.is_none_or(|pattern| glob_match(pattern, channel, false)))
+            .cloned()
+            .collect()
+    }
+
+    pub fn subscriber_count(&self, channel: &[u8]) -> usize {
+        self.channels.get(channel).map_or(0, HashMap::len)
+    }
+
+    /// How many distinct patterns clients subscribed to, as PUBSUB NUMPAT.
+    pub fn pattern_count(&self) -> usize {
+        self.patterns.len()
+    }
+
+    /// Has messages reach the client in the protocol it switched to.
+    pub fn set_protocol(&mut self, client_id: u64, protocol: ProtocolVersion) {
+        for subscribers in self.channels.values_mut().chain(self.patterns.values_mut()) {
+            if let Some(subscriber) = subscribers.get_mut(&client_id) {
+                subscriber.protocol = protocol;
+            }
+        }
+    }
+}
+
+// channels and patterns without subscribers are dropped, so that only active ones are listed
+fn remove_subscriber(subscriptions: &mut HashMap<Vec<u8>, HashMap<u64, Subscriber>>, name: &[u8], client_id: u64) {
+    if let Some(subscribers) = subscriptions.get_mut(name) {
+        subscribers.remove(&client_id);
+        if subscribers.is_empty() {
+            subscriptions.remove(name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod broker_tests {
+    use super::*;
+    use crate::protocol::RespObject::Array;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn publishes_to_channel_and_pattern_subscribers() {
+        let mut broker = Broker::new();
+        let (first, first_messages) = channel();
+        let (second, second_messages) = channel();
+        broker.subscribe(b"news", 1, Subscriber::new(first, ProtocolVersion::Resp2));
+        broker.psubscribe(b"n*", 2, Subscriber::new(second.clone(), ProtocolVersion::Resp3));
+        broker.psubscribe(b"*s", 2, Subscriber::new(second, ProtocolVersion::Resp3));
+
+        assert_eq!(broker.publish(b"news", b"hi"), 3);
+        assert_eq!(broker.publish(b"nothing", b"hi"), 1);
+        assert_eq!(broker.publish(b"other", b"hi"), 0);
+
+        let message = Array(vec![BulkString("message".into()), BulkString("news".into()), BulkString("hi".into())]);
+        assert_eq!(first_messages.try_iter().collect::<Vec<_>>(), vec![message.to_bytes()]);
+        assert_eq!(second_messages.try_iter().count(), 3);
+    }
+
+    #[test]
+    fn lists_only_channels_with_subscribers() {
+        let mut broker = Broker::new();
+        let (outbox, _messages) = channel();
+        broker.subscribe(b"a", 1, Subscriber::new(outbox.clone(), ProtocolVersion::Resp2));
+        broker.subscribe(b"a", 2, Subscriber::new(outbox.clone(), ProtocolVersion::Resp2));
+        broker.subscribe(b"b", 1, Subscriber::new(outbox, ProtocolVersion::Resp2));
+        assert_eq!(broker.subscriber_count(b"a"), 2);
+
+        broker.unsubscribe(b"b", 1);
+        broker.unsubscribe(b"a", 1);
+        assert_eq!(broker.channels(None), vec![b"a".to_vec()]);
+        assert_eq!(broker.channels(Some(b"b*")), Vec::<Vec<u8>>::new());
+        assert_eq!(broker.subscriber_count(b"a"), 1);
+        assert_eq!(broker.subscriber_count(b"b"), 0);
+    }
+}
@@ -0,0 +1,383 @@
+use crate::engine::{Snapshot, StorageEngine};
+
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+use std::time::SystemTime;
+
+pub const DEFAULT_RDB_FILENAME: &str = "dump.rdb";
+// the rules Redis saves by when it is given none
+const DEFAULT_SAVE_RULES: [SaveRule; 3] = [
+    SaveRule { seconds: 3600, changes: 1 },
+    SaveRule { seconds: 300, changes: 100 },
+    SaveRule { seconds: 60, changes: 10000 },
+];
+// how long the rules wait to try again after a background save failed, like CONFIG_BGSAVE_RETRY_DELAY of Redis
+const RETRY_DELAY_SECONDS: u64 = 5;
+
+/// A 'save <seconds> <changes>' rule: the data is saved in the background once at least 'changes' changes were made
+/// to it and 'seconds' went by since it was last saved.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct SaveRule {
+    pub seconds: u64,
+    pub changes: u64,
+}
+
+/// The save rules, as the 'save' config holds them: pairs of seconds and changes, e.g. "3600 1 300 100"
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct SaveRules(pub Vec<SaveRule>);
+
+impl SaveRules {
+    /// Parses rules like "3600 1 300 100"; no rules at all (an empty string) means never saving on its own.
+    pub fn parse(rules: &str) -> Option<SaveRules> {
+        let numbers = rules.split_whitespace()
+            .map(|number| number.parse::<u64>().ok())
+            .collect::<Option<Vec<u64>>>()?;
+        if numbers.len() % 2 != 0 {
+            return None;
+        }
+        Some(SaveRules(numbers.chunks(2)
+            .map(|rule| SaveRule { seconds: rule[0], changes: rule[1] })
+            .collect()))
+    }
+}
+
+impl Default for SaveRules {
+    fn default() -> Self {
+        SaveRules(DEFAULT_SAVE_RULES.to_vec())
+    }
+}
+
+impl Display for SaveRules {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let rules: Vec<String> = self.0.iter().map(|rule| format!("{} {}", rule.seconds, rule.changes)).collect();
+        write!(f, "{}", rules.join(" "))
+    }
+}
+
+/// The RDB file: a point-in-time snapshot of all the data in a compact binary form (see 'Snapshot::encode'), which
+/// is loaded on startup.
+///
+/// SAVE writes it right away, while BGSAVE (and the save rules) have a thread of its own write it from a snapshot,
+/// so that clients are served meanwhile. Either way, it is written to a temporary file that only takes the place of
+/// the old one once complete, so that a crash never leaves a half-written file behind.
+pub struct RdbFile {
+    path: PathBuf,
+    rules: SaveRules,
+    // the dirty counter of the engine as of the data of the last successful save, to tell how many changes it misses
+    saved_dirty: u64,
+    // unix times in seconds, of the last successful save (as LASTSAVE reports it) and of the last attempt
+    last_save: u64,
+    last_attempt: u64,
+    last_save_ok: bool,
+    background_save: Option<BackgroundSave>,
+}
+
+// A BGSAVE in progress
+struct BackgroundSave {
+    // the dirty counter of the engine when the snapshot was taken
+    dirty: u64,
+    writer: JoinHandle<io::Result<()>>,
+}
+
+impl RdbFile {
+    pub fn new(path: impl Into<PathBuf>) -> RdbFile {
+        let now = unix_time_seconds();
+        RdbFile {
+            path: path.into(),
+            rules: SaveRules::default(),
+            saved_dirty: 0,
+            last_save: now,
+            last_attempt: now,
+            last_save_ok: true,
+            background_save: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn set_path(&mut self, path: impl Into<PathBuf>) {
+        self.path = path.into();
+    }
+
+    pub fn rules(&self) -> &SaveRules {
+        &self.rules
+    }
+
+    pub fn set_rules(&mut self, rules: SaveRules) {
+        self.rules = rules;
+    }
+
+    pub fn last_save(&self) -> u64 {
+        self.last_save
+    }
+
+    pub fn last_save_ok(&self) -> bool {
+        self.last_save_ok
+    }
+
+    pub fn is_saving(&self) -> bool {
+        self.background_save.is_some()
+    }
+
+    /// How many changes the saved data misses, given the current dirty counter of the engine.
+    pub fn changes_since_save(&self, dirty: u64) -> u64 {
+        dirty - self.saved_dirty
+    }
+
+    /// Counts the data as saved, as it is right after being loaded on startup.
+    pub fn mark_as_saved(&mut self, dirty: u64) {
+        self.saved_dirty = dirty;
+    }
+
+    /// Writes the snapshot to the file, before returning.
+    pub fn save(&mut self, snapshot: Snapshot, dirty: u64) -> io::Result<()> {
+        let saved = write_file(&self.path, snapshot);
+        self.saved(dirty, saved.is_ok());
+        saved
+    }
+
+    /// Starts writing the snapshot to the file in the background, unless that is already happening. Returns whether
+    /// it started.
+    pub fn start_background_save(&mut self, snapshot: Snapshot, dirty: u64) -> bool {
+        if self.background_save.is_some() {
+            return false;
+        }
+        let path = self.path.clone();
+        let writer = thread::spawn(move || write_file(&path, snapshot));
+        self.background_save = Some(BackgroundSave { dirty, writer });
+        true
+    }
+
+    /// Whether a save rule says that it is time to save, at the given unix time in seconds.
+    pub fn is_save_due(&self, dirty: u64, now: u64) -> bool {
+        let changes = self.changes_since_save(dirty);
+        let since_save = now.saturating_sub(self.last_save);
+        // a failed save is not retried right away, or a full disk would have the server write all the time
+        let may_retry = self.last_save_ok || now.saturating_sub(self.last_attempt) >= RETRY_DELAY_SECONDS;
+
+        self.background_save.is_none() && may_retry
+            && self.rules.0.iter().any(|rule| changes >= rule.changes && since_save >= rule.seconds)
+    }
+
+    /// Takes note of how a background save went, once it is done.
+    pub fn finish_background_save(&mut self) -> io::Result<()> {
+        if !self.background_save.as_ref().is_some_and(|save| save.writer.is_finished()) {
+            return Ok(());
+        }
+        let Some(BackgroundSave { dirty, writer }) = self.background_save.take() else {
+            return Ok(());
+        };
+        let saved = writer.join()
+            .unwrap_or_else(|_| Err(io::Error::other("the background save thread panicked")));
+        self.saved(dirty, saved.is_ok());
+        saved
+    }
+
+    fn saved(&mut self, dirty: u64, ok: bool) {
+        let now = unix_time_seconds();
+        self.last_attempt = now;
+        self.last_save_ok = ok;
+        if ok {
+            self.saved_dirty = dirty;
+            self.last_save = now;
+        }
+    }
+}
+
+// writes a temporary file first, which is only renamed to the actual one once it is complete and on disk
+fn write_file(path: &Path, snapshot: Snapshot) -> io::Result<()> {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    let temp_path = path.with_file_name(name);
+
+    let written = File::create(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(&snapshot.encode())?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&temp_path, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    written
+}
+
+/// Loads the data of the RDB file at 'path', returning how many keys there were (leaving out those that expired in
+/// the meantime). A missing file is just an empty one, while a file that does not match its checksum is refused.
+pub fn load(path: &Path, engine: &mut StorageEngine) -> io::Result<usize> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let snapshot = Snapshot::decode(&bytes)
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, format!("Bad file format reading the RDB file: {e}")))?;
+    engine.load_snapshot(snapshot)
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+}
+
+/// What is due every now and then: taking note of a finished background save, and starting one when a save rule
+/// says so.
+pub fn cron(engine: &mut StorageEngine) -> io::Result<()> {
+    let finished = engine.rdb_mut().finish_background_save();
+
+    let dirty = engine.dirty();
+    if engine.rdb().is_save_due(dirty, unix_time_seconds()) {
+        let snapshot = engine.snapshot();
+        engine.rdb_mut().start_background_save(snapshot, dirty);
+    }
+    finished
+}
+
+fn unix_time_seconds() -> u64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+#[cfg(test)]
+mod rdb_tests {
+    use super::*;
+    use crate::command::Command;
+    use crate::protocol::RespObject;
+    use crate::protocol::RespObject::{Array, BulkString};
+    use crate::session::Session;
+    use mock_instant::thread_local::MockClock;
+    use std::time::Duration;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("rdb-{}-{name}.rdb", std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn execute(session: &mut Session, engine: &mut StorageEngine, arguments: &[&str]) -> RespObject {
+        let arguments = arguments.iter().map(|argument| BulkString(argument.as_bytes().to_vec())).collect();
+        Command::from(Array(arguments)).unwrap().execute(session, engine)
+    }
+
+    #[test]
+    fn parses_and_prints_save_rules() {
+        assert_eq!(SaveRules::parse("3600 1  300 100"), Some(SaveRules(vec![
+            SaveRule { seconds: 3600, changes: 1 }, SaveRule { seconds: 300, changes: 100 },
+        ])));
+        assert_eq!(SaveRules::parse(""), Some(SaveRules(vec![])));
+        assert_eq!(SaveRules::parse("3600"), None);
+        assert_eq!(SaveRules::parse("3600 -1"), None);
+        assert_eq!(SaveRules::default().to_string(), "3600 1 300 100 60 10000");
+    }
+
+    #[test]
+    fn saves_when_a_rule_says_so() {
+        let mut rdb = RdbFile::new(temp_path("rules"));
+        rdb.set_rules(SaveRules::parse("60 10 5 100").unwrap());
+        let start = rdb.last_save();
+
+        assert!(!rdb.is_save_due(9, start + 60));
+        assert!(rdb.is_save_due(10, start + 60));
+        assert!(!rdb.is_save_due(99, start + 59));
+        assert!(rdb.is_save_due(100, start + 5));
+
+        rdb.mark_as_saved(100);
+        assert!(!rdb.is_save_due(109, start + 60));
+        rdb.set_rules(SaveRules(vec![]));
+        assert!(!rdb.is_save_due(1000, start + 3600));
+    }
+
+    #[test]
+    fn saves_and_loads_all_the_data() {
+        let path = temp_path("roundtrip");
+        let mut engine = StorageEngine::new();
+        engine.rdb_mut().set_path(&path);
+        let mut session = Session::new();
+
+        execute(&mut session, &mut engine, &["set", "string", "v"]);
+        execute(&mut session, &mut engine, &["set", "expiring", "v", "ex", "100"]);
+        execute(&mut session, &mut engine, &["set", "short-lived", "v", "px", "100"]);
+        execute(&mut session, &mut engine, &["rpush", "list", "a", "b"]);
+        execute(&mut session, &mut engine, &["sadd", "set", "a"]);
+        execute(&mut session, &mut engine, &["hset", "hash", "f", "v"]);
+        execute(&mut session, &mut engine, &["zadd", "zset", "0.1", "a", "+inf", "b"]);
+        execute(&mut session, &mut engine, &["xadd", "stream", "1-1", "f", "v"]);
+        execute(&mut session, &mut engine, &["xgroup", "create", "stream", "group", "0"]);
+        execute(&mut session, &mut engine, &["xreadgroup", "group", "group", "alice", "streams", "stream", ">"]);
+        execute(&mut session, &mut engine, &["select", "15"]);
+        execute(&mut session, &mut engine, &["set", "last", "v"]);
+        assert_eq!(engine.rdb().changes_since_save(engine.dirty()), 11);
+
+        assert_eq!(execute(&mut session, &mut engine, &["save"]), RespObject::SimpleString("OK".to_string()));
+        assert_eq!(engine.rdb().changes_since_save(engine.dirty()), 0);
+
+        // the key that expired in the meantime is left out
+        MockClock::advance(Duration::from_secs(1));
+        MockClock::advance_system_time(Duration::from_secs(1));
+        let mut engine = StorageEngine::new();
+        assert_eq!(load(&path, &mut engine).unwrap(), 8);
+        assert_eq!(engine.keys(b"*").len(), 7);
+        let mut session = Session::new();
+        assert_eq!(execute(&mut session, &mut engine, &["lrange", "list", "0", "-1"]), Array(vec![BulkString("a".into()), BulkString("b".into())]));
+        assert_eq!(execute(&mut session, &mut engine, &["zscore", "zset", "a"]), BulkString("0.1".into()));
+        assert_eq!(execute(&mut session, &mut engine, &["zscore", "zset", "b"]), BulkString("inf".into()));
+        assert_eq!(execute(&mut session, &mut engine, &["hget", "hash", "f"]), BulkString("v".into()));
+        assert_eq!(execute(&mut session, &mut engine, &["sismember", "set", "a"]), RespObject::Integer(1));
+        assert!(matches!(execute(&mut session, &mut engine, &["ttl", "expiring"]), RespObject::Integer(99)));
+        assert_eq!(execute(&mut session, &mut engine, &["ttl", "string"]), RespObject::Integer(-1));
+        assert_eq!(execute(&mut session, &mut engine, &["xpending", "stream", "group"]), Array(vec![
+            RespObject::Integer(1), BulkString("1-1".into()), BulkString("1-1".into()),
+            Array(vec![Array(vec![BulkString("alice".into()), BulkString("1".into())])]),
+        ]));
+        execute(&mut session, &mut engine, &["select", "15"]);
+        assert_eq!(execute(&mut session, &mut engine, &["get", "last"]), BulkString("v".into()));
+
+        // with fewer databases, the data does not fit
+        let error = load(&path, &mut StorageEngine::with_databases(4)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn refuses_a_corrupt_file() {
+        let path = temp_path("corrupt");
+        let mut engine = StorageEngine::new();
+        engine.set(b"key".to_vec(), b"value".to_vec(), None).unwrap();
+        RdbFile::new(&path).save(engine.snapshot(), engine.dirty()).unwrap();
+
+        let mut bytes = fs::read(&path).unwrap();
+        let last = bytes.len() - 10;
+        bytes[last] ^= 1;
+        fs::write(&path, bytes).unwrap();
+
+        let error = load(&path, &mut StorageEngine::new()).unwrap_err();
+        assert_eq!(error.to_string(), "Bad file format reading the RDB file: the checksum does not match, the file is corrupt");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn saves_in_the_background() {
+        let path = temp_path("background");
+        let mut engine = StorageEngine::new();
+        engine.rdb_mut().set_path(&path);
+        let mut session = Session::new();
+        execute(&mut session, &mut engine, &["set", "a", "1"]);
+
+        assert_eq!(execute(&mut session, &mut engine, &["bgsave"]), RespObject::SimpleString("Background saving started".to_string()));
+        // until the cron takes note of it being done, the save is still going on
+        assert_eq!(execute(&mut session, &mut engine, &["bgsave"]), RespObject::Error("Background save already in progress".to_string()));
+        // written after the snapshot was taken, so still to save
+        execute(&mut session, &mut engine, &["set", "b", "1"]);
+        while engine.rdb().is_saving() {
+            cron(&mut engine).unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(engine.rdb().last_save_ok());
+        assert_eq!(engine.rdb().changes_since_save(engine.dirty()), 1);
+
+        let mut engine = StorageEngine::new();
+        assert_eq!(load(&path, &mut engine).unwrap(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+}
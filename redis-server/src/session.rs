@@ -0,0 +1,262 @@
+use crate::command::Command;
+use crate::engine::StorageEngine;
+use crate::protocol::{ProtocolVersion, RespObject};
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::{Duration, Instant};
+
+// ids are handed out in connection order, starting at 1, like Redis does
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
+// State that belongs to a single client connection, rather than to the storage shared by all of them
+#[derive(Debug)]
+pub struct Session {
+    id: u64,
+    protocol: ProtocolVersion,
+    client_name: Option<Vec<u8>>,
+    // the database the client works on, as chosen with SELECT
+    db: usize,
+    // set between MULTI and EXEC/DISCARD
+    transaction: Option<Transaction>,
+    // the keys the client WATCHes: the database, the key, and the version it had when watched
+    watched_keys: Vec<(usize, Vec<u8>, u64)>,
+    // the channels and patterns the client subscribed to
+    channels: HashSet<Vec<u8>>,
+    patterns: HashSet<Vec<u8>>,
+    // everything written to the client goes through here, replies as well as pub/sub messages, which keeps them in order
+    outbox: Sender<Vec<u8>>,
+    output: Option<Receiver<Vec<u8>>>,
+    // set while the client waits in a blocking command (e.g. BLPOP)
+    blocked: Option<Blocked>,
+    // set by QUIT, for the connection to be closed once the reply is out
+    closing: bool,
+}
+
+// A client waiting for another one to write what its blocking command pops, which then sends over the reply
+#[derive(Debug)]
+struct Blocked {
+    reply: Receiver<RespObject>,
+    // when it gives up waiting; never, for a timeout of 0 (or one that is out of reach)
+    deadline: Option<Instant>,
+}
+
+// The commands a client sends after MULTI, which are only run on EXEC
+#[derive(Debug, Default)]
+pub struct Transaction {
+    pub commands: Vec<Command>,
+    // set when a command could not be queued, in which case EXEC refuses to run the others
+    pub failed: bool,
+}
+
+impl Session {
+    pub fn new() -> Session {
+        let (outbox, output) = channel();
+        Session {
+            id: NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed),
+            protocol: ProtocolVersion::default(),
+            client_name: None,
+            db: 0,
+            transaction: None,
+            watched_keys: vec![],
+            channels: HashSet::new(),
+            patterns: HashSet::new(),
+            outbox,
+            output: Some(output),
+            blocked: None,
+            closing: false,
+        }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    // the protocol replies to this client must be written in, as negotiated with HELLO
+    pub fn protocol(&self) -> ProtocolVersion {
+        self.protocol
+    }
+
+    pub fn set_protocol(&mut self, protocol: ProtocolVersion) {
+        self.protocol = protocol;
+    }
+
+    pub fn client_name(&self) -> Option<&[u8]> {
+        self.client_name.as_deref()
+    }
+
+    pub fn set_client_name(&mut self, client_name: Vec<u8>) {
+        self.client_name = Some(client_name);
+    }
+
+    pub fn db(&self) -> usize {
+        self.db
+    }
+
+    pub fn select_db(&mut self, db: usize) {
+        self.db = db;
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.transaction.is_some()
+    }
+
+    pub fn begin_transaction(&mut self) {
+        self.transaction = Some(Transaction::default());
+    }
+
+    pub fn queue_command(&mut self, command: Command) {
+        if let Some(transaction) = &mut self.transaction {
+            transaction.commands.push(command);
+        }
+    }
+
+    // dooms the transaction in progress, if any, so that EXEC aborts
+    pub fn fail_transaction(&mut self) {
+        if let Some(transaction) = &mut self.transaction {
+            transaction.failed = true;
+        }
+    }
+
+    pub fn take_transaction(&mut self) -> Option<Transaction> {
+        self.transaction.take()
+    }
+
+    pub fn is_watching(&self, db: usize, key: &[u8]) -> bool {
+        self.watched_keys.iter().any(|(watched_db, watched_key, _)| *watched_db == db && watched_key == key)
+    }
+
+    pub fn watch(&mut self, db: usize, key: Vec<u8>, version: u64) {
+        self.watched_keys.push((db, key, version));
+    }
+
+    pub fn watched_keys(&self) -> &[(usize, Vec<u8>, u64)] {
+        &self.watched_keys
+    }
+
+    // stops watching all keys, which happens on EXEC, DISCARD, UNWATCH and when the client disconnects
+    pub fn unwatch_all(&mut self, engine: &mut StorageEngine) {
+        for (db, key, _) in self.watched_keys.drain(..) {
+            engine.unwatch(db, &key);
+        }
+    }
+
+    // what is sent to the client, for its connection to write out; can only be taken once
+    pub fn take_output(&mut self) -> Option<Receiver<Vec<u8>>> {
+        self.output.take()
+    }
+
+    // where others can send to the client, e.g. the messages of its subscriptions
+    pub fn outbox(&self) -> Sender<Vec<u8>> {
+        self.outbox.clone()
+    }
+
+    // sends a reply (or a message) to the client, in the protocol it uses
+    pub fn send(&self, object: RespObject) {
+        // nothing is reading anymore when the connection is closing, at which point there is no one to tell either
+        let _ = self.outbox.send(object.into_protocol(self.protocol).to_bytes());
+    }
+
+    // how many channels and patterns the client subscribed to
+    pub fn subscription_count(&self) -> usize {
+        self.channels.len() + self.patterns.len()
+    }
+
+    // with RESP2, a client with subscriptions can only manage them, as its connection is busy carrying messages
+    pub fn in_subscribed_mode(&self) -> bool {
+        self.protocol == ProtocolVersion::Resp2 && self.subscription_count() > 0
+    }
+
+    pub fn channels(&self) -> Vec<Vec<u8>> {
+        self.channels.iter().cloned().collect()
+    }
+
+    pub fn patterns(&self) -> Vec<Vec<u8>> {
+        self.patterns.iter().cloned().collect()
+    }
+
+    // these return whether the subscriptions changed
+    pub fn add_channel(&mut self, channel: &[u8]) -> bool {
+        self.channels.insert(channel.to_vec())
+    }
+
+    pub fn remove_channel(&mut self, channel: &[u8]) -> bool {
+        self.channels.remove(channel)
+    }
+
+    pub fn add_pattern(&mut self, pattern: &[u8]) -> bool {
+        self.patterns.insert(pattern.to_vec())
+    }
+
+    pub fn remove_pattern(&mut self, pattern: &[u8]) -> bool {
+        self.patterns.remove(pattern)
+    }
+
+    // has the client wait for the reply of a blocking command, as sent by whoever serves it; no timeout waits forever,
+    // as does one too far away for the clock to tell when it is due
+    pub fn block(&mut self, reply: Receiver<RespObject>, timeout: Option<Duration>) {
+        self.blocked = Some(Blocked { reply, deadline: timeout.and_then(|timeout| Instant::now().checked_add(timeout)) });
+    }
+
+    pub fn is_blocked(&self) -> bool {
+        self.blocked.is_some()
+    }
+
+    pub fn timed_out(&self) -> bool {
+        self.blocked.as_ref()
+            .and_then(|blocked| blocked.deadline)
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    // waits up to 'limit' (but not past the timeout) for the client to be served, returning the reply if it was
+    pub fn wait_until_served(&mut self, limit: Duration) -> Option<RespObject> {
+        let blocked = self.blocked.as_ref()?;
+        let limit = blocked.deadline.map_or(limit, |deadline| limit.min(deadline.saturating_duration_since(Instant::now())));
+        let reply = blocked.reply.recv_timeout(limit).ok()?;
+        self.blocked = None;
+        Some(reply)
+    }
+
+    // stops waiting (e.g. on timeout), returning the reply if the client happened to be served in the meantime
+    pub fn unblock(&mut self, engine: &mut StorageEngine) -> Option<RespObject> {
+        let blocked = self.blocked.take()?;
+        engine.blocked_clients_mut().unblock(self.id);
+        blocked.reply.try_recv().ok()
+    }
+
+    pub fn close(&mut self) {
+        self.closing = true;
+    }
+
+    pub fn is_closing(&self) -> bool {
+        self.closing
+    }
+
+    // brings the connection back to how it was when the client connected (RESET), though it keeps its id
+    pub fn reset(&mut self, engine: &mut StorageEngine) {
+        self.transaction = None;
+        self.disconnect(engine);
+        self.db = 0;
+        self.protocol = ProtocolVersion::default();
+        self.client_name = None;
+    }
+
+    // releases what the client holds in the engine, once its connection is gone
+    pub fn disconnect(&mut self, engine: &mut StorageEngine) {
+        self.unblock(engine);
+        self.unwatch_all(engine);
+        for channel in self.channels.drain() {
+            engine.broker_mut().unsubscribe(&channel, self.id);
+        }
+        for pattern in self.patterns.drain() {
+            engine.broker_mut().punsubscribe(&pattern, self.id);
+        }
+    }
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session::new()
+    }
+}